@@ -0,0 +1,956 @@
+use crate::packets::BARK_BANDS;
+use realfft::{num_complex::Complex, num_traits::Float, FftNum, RealFftPlanner, RealToComplex};
+use std::{
+    cmp::Ordering, collections::VecDeque, f64::consts::PI, ops::Range, str::FromStr, sync::Arc,
+};
+
+pub const DEFAULT_SAMPLE_SIZE: usize = 2048;
+pub const DEFAULT_NOVELTY_BUFFER_SIZE: usize = 200;
+pub const COMPRESSION_CONST: f64 = 1000.0;
+pub const DEFAULT_SHORT_TERM_NOVELTY_SIZE: usize = 50;
+/// Amount of novelty frames whose median gives the adaptive onset threshold.
+pub const ONSET_MEDIAN_SIZE: usize = 16;
+/// How much the novelty must exceed the local median to be an onset.
+pub const ONSET_THRESHOLD_RATIO: f64 = 1.5;
+/// Part of the short term novelty peak added to the threshold, so the noise floor
+/// isn't full of onsets.
+pub const ONSET_THRESHOLD_OFFSET: f64 = 0.1;
+/// Minimum amount of frames between two onsets.
+pub const ONSET_MIN_INTERVAL: usize = 3;
+/// Range of frequencies in Hz folded into the chroma, from C2 to C7.
+pub const CHROMA_FREQUENCIES: (f64, f64) = (65.4, 2093.0);
+/// How many times the average a pitch class must weigh to be the dominant one.
+pub const CHROMA_CLARITY: f64 = 1.5;
+/// Edges in Hz of the critical bands of the Bark scale.
+pub const BARK_EDGES: [f64; BARK_BANDS + 1] = [
+    0.0, 100.0, 200.0, 300.0, 400.0, 510.0, 630.0, 770.0, 920.0, 1080.0, 1270.0, 1480.0, 1720.0,
+    2000.0, 2320.0, 2700.0, 3150.0, 3700.0, 4400.0, 5300.0, 6400.0, 7700.0, 9500.0, 12000.0,
+    15500.0,
+];
+/// Below this RMS amplitude (about -60dBFS) a frame is considered silent.
+pub const SILENCE_THRESHOLD: f64 = 0.001;
+/// The analysis expects this sample rate.
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// Frequency weighting applied to the spectrum before its compression.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Weighting {
+    Flat,
+    /// Follows the sensitivity of our ears, so sub-bass rumble and ultrasonics are mostly
+    /// ignored.
+    A,
+}
+
+impl Weighting {
+    /// Amplitude gain at `frequency` in Hz.
+    fn gain(self, frequency: f64) -> f64 {
+        match self {
+            Self::Flat => 1.0,
+            Self::A => {
+                let f2 = frequency * frequency;
+                let response = 12194f64.powi(2) * f2 * f2
+                    / ((f2 + 20.6f64.powi(2))
+                        * ((f2 + 107.7f64.powi(2)) * (f2 + 737.9f64.powi(2))).sqrt()
+                        * (f2 + 12194f64.powi(2)));
+                // Normalized to 0dB at 1kHz
+                response * 10f64.powf(2.0 / 20.0)
+            }
+        }
+    }
+}
+
+impl FromStr for Weighting {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "flat" => Ok(Self::Flat),
+            "a" => Ok(Self::A),
+            _ => Err("Unknown weighting !".into()),
+        }
+    }
+}
+
+/// A second order filter with the coefficients of the audio EQ cookbook, run in the
+/// direct form I on both channels.
+#[derive(Clone)]
+struct Biquad<T> {
+    b: [T; 3],
+    a: [T; 2],
+    /// Last two inputs then outputs of each channel.
+    state: [[T; 4]; 2],
+}
+
+impl<T: AudioFloat> Biquad<T> {
+    /// Butterworth, it doesn't ring at the cutoff.
+    const Q: f64 = std::f64::consts::FRAC_1_SQRT_2;
+
+    fn highpass(cutoff: f64) -> Self {
+        let (cos, alpha) = Self::angle(cutoff);
+        Self::new(
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    fn lowpass(cutoff: f64) -> Self {
+        let (cos, alpha) = Self::angle(cutoff);
+        Self::new(
+            [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    fn angle(cutoff: f64) -> (f64, f64) {
+        // Above the Nyquist frequency the filter is unstable
+        let nyquist = SAMPLE_RATE as f64 / 2.0;
+        let omega = 2.0 * PI * cutoff.max(1.0).min(nyquist * 0.99) / SAMPLE_RATE as f64;
+        (omega.cos(), omega.sin() / (2.0 * Self::Q))
+    }
+
+    /// Normalized by `a[0]`.
+    fn new(b: [f64; 3], a: [f64; 3]) -> Self {
+        Self {
+            b: [float(b[0] / a[0]), float(b[1] / a[0]), float(b[2] / a[0])],
+            a: [float(a[1] / a[0]), float(a[2] / a[0])],
+            state: [[T::zero(); 4]; 2],
+        }
+    }
+
+    fn process(&mut self, channel: usize, x: T) -> T {
+        let [x1, x2, y1, y2] = self.state[channel];
+        let y = self.b[0] * x + self.b[1] * x1 + self.b[2] * x2 - self.a[0] * y1 - self.a[1] * y2;
+        self.state[channel] = [x, x1, y, y1];
+        y
+    }
+}
+
+/// Gain of a range of frequencies in the novelty, to make it follow the kicks more than
+/// the hi-hats for example.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct NoveltyWeight {
+    /// In Hz.
+    pub low: f64,
+    pub high: f64,
+    pub gain: f64,
+}
+
+impl NoveltyWeight {
+    fn contains(&self, frequency: f64) -> bool {
+        frequency >= self.low && frequency <= self.high
+    }
+}
+
+impl FromStr for NoveltyWeight {
+    type Err = String;
+
+    /// Parses `low-high=gain`, like `40-150=4`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.rsplitn(2, '=');
+        let (gain, range) = match (parts.next(), parts.next()) {
+            (Some(gain), Some(range)) => (gain.trim(), range),
+            _ => return Err("Expected a weight like 40-150=4 !".into()),
+        };
+        let gain = match gain.parse::<f64>() {
+            Ok(gain) if gain >= 0.0 => gain,
+            _ => return Err(format!("Invalid gain `{}` !", gain)),
+        };
+
+        let mut bounds = range
+            .splitn(2, '-')
+            .map(|bound| bound.trim().parse::<f64>());
+        match (bounds.next(), bounds.next()) {
+            (Some(Ok(low)), Some(Ok(high))) if low < high => Ok(Self { low, high, gain }),
+            _ => Err(format!("Invalid frequency range `{}` !", range)),
+        }
+    }
+}
+
+/// Floating point type the analysis runs with, `f32` about halves the work and memory
+/// needed for weak machines, `f64` is more precise.
+pub trait AudioFloat: FftNum + Float {}
+
+impl<T: FftNum + Float> AudioFloat for T {}
+
+/// Convert a constant to the type of the analysis.
+#[inline]
+fn float<T: AudioFloat>(value: f64) -> T {
+    T::from_f64(value).expect("Not representable")
+}
+
+/// `f64` by default because TUI graphs expect f64 anyway, and we can usually afford it.
+pub struct AudioProcessor<T: AudioFloat = f64> {
+    sample_size: usize,
+
+    fft_planner: RealFftPlanner<T>,
+    fft: Arc<dyn RealToComplex<T>>,
+
+    window: Vec<T>,
+    highpass: Option<f64>,
+    lowpass: Option<f64>,
+    filters: Vec<Biquad<T>>,
+    compression: f64,
+    weighting: Weighting,
+    /// Weighting of each bin, with the FFT normalization and the L/R average.
+    weights: Vec<T>,
+    peak_half_life: Option<f64>,
+    peak_decay: T,
+    peak_input: T,
+    peak_output: T,
+    peak_delta: T,
+    peaks: Vec<T>,
+
+    silence_duration: Option<f64>,
+    /// Amount of silent frames in a row after which the audio is considered stopped.
+    silence_frames: usize,
+    silent_frames: usize,
+    /// -1 when everything is on the left, 1 on the right.
+    balance: T,
+
+    input: Vec<T>,
+    raw_data_left: Vec<T>,
+    raw_data_right: Vec<T>,
+    fft_scratch: Vec<Complex<T>>,
+    fft_data_left: Vec<Complex<T>>,
+    fft_data_right: Vec<Complex<T>>,
+
+    spectrum: Vec<T>,
+    output: Vec<T>,
+    prev_output: Vec<T>,
+
+    /// Pitch class of each bin, if it is in [CHROMA_FREQUENCIES].
+    pitch_classes: Vec<Option<u8>>,
+    chroma: [T; 12],
+
+    mel_band_count: Option<usize>,
+    mel_filterbank: Option<MelFilterbank<T>>,
+    bands: Vec<T>,
+    prev_bands: Vec<T>,
+
+    /// Bins of each Bark band, at least one.
+    bark_bins: Vec<Range<usize>>,
+    bark: [T; BARK_BANDS],
+    bark_peaks: [T; BARK_BANDS],
+
+    novelty_weights: Vec<NoveltyWeight>,
+    /// Gain of each bin or mel band in the novelty, empty when they all count the same.
+    novelty_gains: Vec<T>,
+
+    novelty_curve: VecDeque<T>,
+    short_term_novelty_size: usize,
+
+    onset_threshold: f64,
+    onset: bool,
+    frames_since_onset: usize,
+    median_scratch: Vec<T>,
+}
+
+impl<T: AudioFloat> Default for AudioProcessor<T> {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_SAMPLE_SIZE,
+            DEFAULT_NOVELTY_BUFFER_SIZE,
+            DEFAULT_SHORT_TERM_NOVELTY_SIZE,
+        )
+    }
+}
+
+impl<T: AudioFloat> AudioProcessor<T> {
+    /// Create a new [AudioProcessor].
+    /// It will automatically create and manage the buffers required for the analysis.
+    pub fn new(
+        sample_size: usize, novelty_buffer_size: usize, short_term_novelty_size: usize,
+    ) -> Self {
+        assert!(short_term_novelty_size <= novelty_buffer_size);
+        let mut fft_planner = RealFftPlanner::new();
+        let fft = fft_planner.plan_fft_forward(sample_size);
+
+        let mut processor = Self {
+            sample_size,
+
+            fft_planner,
+            fft,
+
+            window: vec![],
+            highpass: None,
+            lowpass: None,
+            filters: vec![],
+            compression: COMPRESSION_CONST,
+            weighting: Weighting::Flat,
+            weights: vec![],
+            peak_half_life: None,
+            peak_decay: T::one(),
+            peak_input: T::zero(),
+            peak_output: T::zero(),
+            peak_delta: T::zero(),
+            peaks: vec![],
+
+            silence_duration: None,
+            silence_frames: 0,
+            silent_frames: 0,
+            balance: T::zero(),
+
+            input: vec![],
+            raw_data_left: vec![],
+            raw_data_right: vec![],
+            fft_scratch: vec![],
+            fft_data_left: vec![],
+            fft_data_right: vec![],
+
+            spectrum: vec![],
+            output: vec![],
+            prev_output: vec![],
+
+            pitch_classes: vec![],
+            chroma: [T::zero(); 12],
+
+            mel_band_count: None,
+            mel_filterbank: None,
+            bands: vec![],
+            prev_bands: vec![],
+
+            bark_bins: vec![],
+            bark: [T::zero(); BARK_BANDS],
+            bark_peaks: [T::zero(); BARK_BANDS],
+
+            novelty_weights: vec![],
+            novelty_gains: vec![],
+
+            novelty_curve: {
+                let mut queue = VecDeque::with_capacity(novelty_buffer_size);
+                queue.resize(novelty_buffer_size, T::zero());
+                queue
+            },
+            short_term_novelty_size,
+
+            onset_threshold: ONSET_THRESHOLD_RATIO,
+            onset: false,
+            frames_since_onset: 0,
+            median_scratch: Vec::with_capacity(ONSET_MEDIAN_SIZE),
+        };
+        processor.recreate_fft();
+        processor
+    }
+
+    pub fn sample_size(&self) -> usize {
+        self.sample_size
+    }
+
+    pub fn set_sample_size(&mut self, sample_size: usize) {
+        self.sample_size = sample_size;
+        self.recreate_fft();
+    }
+
+    pub fn compression(&self) -> f64 {
+        self.compression
+    }
+
+    /// The higher the compression, the more importance the little values of the spectrum get.
+    pub fn set_compression(&mut self, compression: f64) {
+        self.compression = compression;
+    }
+
+    /// Make the peaks lose half of their value every `half_life` seconds, so a single loud
+    /// transient doesn't squash everything that follows. They never decay with `None`.
+    pub fn set_peak_half_life(&mut self, half_life: Option<f64>) {
+        self.peak_half_life = half_life;
+        self.recreate_fft();
+    }
+
+    /// Consider the audio stopped after `duration` seconds of silence, never with `None`.
+    pub fn set_silence_duration(&mut self, duration: Option<f64>) {
+        self.silence_duration = duration.filter(|&duration| duration > 0.0);
+        self.recreate_fft();
+    }
+
+    /// Filter the signal before the FFT, cutoffs in Hz. The high-pass removes the DC offset
+    /// and the rumble, the low-pass the hiss of cheap capture devices.
+    pub fn set_filters(&mut self, highpass: Option<f64>, lowpass: Option<f64>) {
+        self.highpass = highpass;
+        self.lowpass = lowpass;
+        self.recreate_fft();
+    }
+
+    pub fn set_weighting(&mut self, weighting: Weighting) {
+        self.weighting = weighting;
+        self.recreate_fft();
+    }
+
+    /// Group the spectrum into this many bands evenly spaced on the mel scale, the novelty
+    /// is then computed on the bands instead of every bin.
+    pub fn set_mel_bands(&mut self, band_count: Option<usize>) {
+        self.mel_band_count = band_count;
+        self.recreate_fft();
+    }
+
+    /// Weight ranges of frequencies in the novelty, the first range containing a bin or the
+    /// center of a mel band gives its gain, the others count as usual.
+    pub fn set_novelty_weights(&mut self, weights: Vec<NoveltyWeight>) {
+        self.novelty_weights = weights;
+        self.recreate_fft();
+    }
+
+    pub fn short_term_novelty_size(&self) -> usize {
+        self.short_term_novelty_size
+    }
+
+    /// Clamped between 1 and the size of the novelty buffer.
+    pub fn set_short_term_novelty_size(&mut self, size: usize) {
+        self.short_term_novelty_size = size.clamp(1, self.novelty_curve.len());
+    }
+
+    pub fn onset_threshold(&self) -> f64 {
+        self.onset_threshold
+    }
+
+    /// How many times above the recent median the novelty must be to be an onset.
+    pub fn set_onset_threshold(&mut self, ratio: f64) {
+        self.onset_threshold = ratio;
+    }
+
+    pub fn input(&mut self) -> &mut [T] {
+        &mut self.input
+    }
+
+    pub fn peak_input(&self) -> T {
+        self.peak_input
+    }
+
+    pub fn peak_output(&self) -> T {
+        self.peak_output
+    }
+
+    pub fn peak_delta(&self) -> T {
+        self.peak_delta
+    }
+
+    pub fn peaks(&self) -> &[T] {
+        &self.peaks
+    }
+
+    pub fn output(&self) -> &[T] {
+        &self.output[1..]
+    }
+
+    /// Energy of the right channel against the left one, from -1 when everything is on
+    /// the left to 1 on the right. 0 in mono or in silence.
+    pub fn balance(&self) -> T {
+        self.balance
+    }
+
+    /// Whether the audio has been silent for long enough, like when the music is paused.
+    pub fn is_silent(&self) -> bool {
+        self.silence_duration.is_some() && self.silent_frames >= self.silence_frames
+    }
+
+    /// Energy of each pitch class, starting from C, normalized so the strongest one is 1.
+    pub fn chroma(&self) -> &[T; 12] {
+        &self.chroma
+    }
+
+    /// The pitch class standing out of the chroma (0 being C), if any.
+    pub fn dominant_pitch_class(&self) -> Option<u8> {
+        let mean = self
+            .chroma
+            .iter()
+            .fold(T::zero(), |sum, &energy| sum + energy)
+            / float(12.0);
+        self.chroma
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(Ordering::Equal))
+            .filter(|&(_, &energy)| {
+                energy > T::zero() && energy >= float::<T>(CHROMA_CLARITY) * mean
+            })
+            .map(|(pitch_class, _)| pitch_class as u8)
+    }
+
+    /// The compressed energy of each mel band, empty if they are disabled.
+    pub fn bands(&self) -> &[T] {
+        &self.bands
+    }
+
+    /// The compressed energy of each Bark band, from the lowest.
+    pub fn bark_bands(&self) -> &[T; BARK_BANDS] {
+        &self.bark
+    }
+
+    /// The recent peak of each Bark band, fading like the other peaks.
+    pub fn bark_peaks(&self) -> &[T; BARK_BANDS] {
+        &self.bark_peaks
+    }
+
+    pub fn novelty_curve(&self) -> impl Iterator<Item = T> + '_ {
+        self.novelty_curve.iter().copied()
+    }
+
+    pub fn novelty(&self) -> T {
+        self.novelty_curve.back().copied().unwrap_or_else(T::zero)
+    }
+
+    /// Whether an onset (a note or a hit) was detected, it is always the previous frame
+    /// as it must be a local maximum of the novelty.
+    pub fn is_onset(&self) -> bool {
+        self.onset
+    }
+
+    pub fn novelty_peak(&self) -> T {
+        self.novelty_curve
+            .iter()
+            .copied()
+            .max_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal))
+            .unwrap_or_else(T::zero)
+    }
+
+    pub fn novelty_peak_short_term(&self) -> T {
+        self.novelty_curve
+            .iter()
+            .skip(self.novelty_curve.len() - self.short_term_novelty_size)
+            .copied()
+            .max_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal))
+            .unwrap_or_else(T::zero)
+    }
+
+    /// Novelty of the bins between `low` and `high` Hz only, on the bins even with mel bands.
+    pub fn novelty_between(&self, low: f64, high: f64) -> T {
+        let bin_width = SAMPLE_RATE as f64 / self.sample_size as f64;
+        let first = ((low / bin_width).ceil() as usize).min(self.output.len());
+        let last = ((high / bin_width).floor() as usize + 1)
+            .min(self.output.len())
+            .max(first);
+
+        let novelty = positive_delta_sum(&self.output[first..last], &self.prev_output[first..last]);
+        // Amplified like the whole novelty
+        novelty * novelty
+    }
+}
+
+impl<T: AudioFloat> AudioProcessor<T> {
+    /// Plan FFT and create buffers and window of the correct sizes.
+    fn recreate_fft(&mut self) {
+        self.fft = self.fft_planner.plan_fft_forward(self.sample_size);
+
+        self.raw_data_left = self.fft.make_input_vec();
+        self.raw_data_right = self.fft.make_input_vec();
+
+        self.fft_scratch = self.fft.make_scratch_vec();
+
+        self.fft_data_left = self.fft.make_output_vec();
+        self.fft_data_right = self.fft.make_output_vec();
+
+        self.input = vec![T::zero(); self.raw_data_left.len() + self.raw_data_right.len()];
+        self.spectrum = vec![T::zero(); self.fft_data_left.len()];
+        self.output = vec![T::zero(); self.fft_data_left.len()];
+        self.prev_output = vec![T::zero(); self.output.len()];
+
+        let bin_width = SAMPLE_RATE as f64 / self.sample_size as f64;
+        let scale_coeff = 1.0 / (self.fft_data_left.len() as f64).sqrt() / 2.0;
+        self.weights = (0..self.spectrum.len())
+            .map(|bin| float(self.weighting.gain(bin as f64 * bin_width) * scale_coeff))
+            .collect();
+
+        self.pitch_classes = (0..self.spectrum.len())
+            .map(|bin| {
+                let frequency = bin as f64 * bin_width;
+                if frequency < CHROMA_FREQUENCIES.0 || frequency > CHROMA_FREQUENCIES.1 {
+                    return None;
+                }
+                // MIDI note number, A4 is 440Hz and 69
+                let note = (12.0 * (frequency / 440.0).log2() + 69.0).round() as i64;
+                Some(note.rem_euclid(12) as u8)
+            })
+            .collect();
+
+        self.mel_filterbank = self
+            .mel_band_count
+            .map(|count| MelFilterbank::new(count, self.spectrum.len(), self.sample_size));
+        self.bands = vec![T::zero(); self.mel_band_count.unwrap_or(0)];
+        self.prev_bands = vec![T::zero(); self.bands.len()];
+
+        // Without the DC offset, the lowest bands are narrower than a bin with small samples
+        let bin_count = self.output.len();
+        self.bark_bins = BARK_EDGES
+            .windows(2)
+            .map(|edges| {
+                let start = ((edges[0] / bin_width).ceil() as usize)
+                    .max(1)
+                    .min(bin_count - 1);
+                let end = ((edges[1] / bin_width).ceil() as usize)
+                    .min(bin_count)
+                    .max(start + 1);
+                start..end
+            })
+            .collect();
+        self.bark = [T::zero(); BARK_BANDS];
+        self.bark_peaks = [T::zero(); BARK_BANDS];
+
+        self.novelty_gains = if self.novelty_weights.is_empty() {
+            vec![]
+        } else {
+            let frequencies: Vec<f64> = match self.mel_filterbank.as_ref() {
+                Some(filterbank) => filterbank.centers.clone(),
+                None => (0..self.output.len())
+                    .map(|bin| bin as f64 * bin_width)
+                    .collect(),
+            };
+            let weights = &self.novelty_weights;
+            frequencies
+                .into_iter()
+                .map(|frequency| {
+                    float(
+                        weights
+                            .iter()
+                            .find(|weight| weight.contains(frequency))
+                            .map_or(1.0, |weight| weight.gain),
+                    )
+                })
+                .collect()
+        };
+
+        self.peaks = vec![T::zero(); self.output.len()];
+
+        self.filters = self
+            .highpass
+            .map(Biquad::highpass)
+            .into_iter()
+            .chain(self.lowpass.map(Biquad::lowpass))
+            .collect();
+
+        // Applied once per frame
+        let frame_duration = self.sample_size as f64 / SAMPLE_RATE as f64;
+        self.peak_decay = float(
+            self.peak_half_life
+                .filter(|&half_life| half_life > 0.0)
+                .map_or(1.0, |half_life| 0.5f64.powf(frame_duration / half_life)),
+        );
+        self.silence_frames = self
+            .silence_duration
+            .map_or(0, |duration| (duration / frame_duration).ceil() as usize);
+
+        // Hann window
+        self.window = (0..self.raw_data_left.len())
+            .into_iter()
+            .map(|i| {
+                float(0.5 * (1.0 - (2.0 * PI * i as f64 / (self.sample_size as f64 - 1.0)).cos()))
+            })
+            .collect();
+    }
+
+    pub fn process(&mut self) {
+        let compression: T = float(self.compression);
+
+        // Save output
+        self.prev_output.copy_from_slice(&self.output);
+        self.prev_bands.copy_from_slice(&self.bands);
+
+        // Let the peaks fade
+        self.peak_input = self.peak_input * self.peak_decay;
+        self.peak_output = self.peak_output * self.peak_decay;
+        self.peak_delta = self.peak_delta * self.peak_decay;
+        for peak in self.peaks.iter_mut().chain(self.bark_peaks.iter_mut()) {
+            *peak = *peak * self.peak_decay;
+        }
+
+        // The filters keep their state from the previous frame, the frames follow each other
+        for filter in self.filters.iter_mut() {
+            for samples in self.input.chunks_exact_mut(2) {
+                samples[0] = filter.process(0, samples[0]);
+                samples[1] = filter.process(1, samples[1]);
+            }
+        }
+
+        // Separate stereo channels and apply window
+        let (mut left_sum, mut right_sum) = (T::zero(), T::zero());
+        for (i, samples) in self.input.chunks_exact_mut(2).enumerate() {
+            left_sum = left_sum + samples[0] * samples[0];
+            right_sum = right_sum + samples[1] * samples[1];
+
+            // Also modify input so we can see the window being applied in the visualisation
+            samples[0] = samples[0] * self.window[i];
+            samples[1] = samples[1] * self.window[i];
+
+            self.raw_data_left[i] = samples[0];
+            self.raw_data_right[i] = samples[1];
+
+            // Update peak
+            self.peak_input = self.peak_input.max(samples[0]).max(samples[1]);
+        }
+
+        // Silence detection, on the signal before the window
+        let square_sum = left_sum + right_sum;
+        let rms = (square_sum / float(self.input.len() as f64)).sqrt();
+        if rms < float(SILENCE_THRESHOLD) {
+            self.silent_frames = self.silent_frames.saturating_add(1);
+            self.balance = T::zero();
+        } else {
+            self.silent_frames = 0;
+            self.balance = (right_sum - left_sum) / square_sum;
+        }
+
+        // Process
+        // We unwrap because we now that the buffers are of the correct length
+        self.fft
+            .process_with_scratch(
+                &mut self.raw_data_left,
+                &mut self.fft_data_left,
+                &mut self.fft_scratch,
+            )
+            .unwrap();
+        self.fft
+            .process_with_scratch(
+                &mut self.raw_data_right,
+                &mut self.fft_data_right,
+                &mut self.fft_scratch,
+            )
+            .unwrap();
+
+        // Build output
+        // Each step is its own pass over plain slices so they can be vectorized
+        // Normalize, weight and average L/R, without hypot which can't be vectorized
+        for (((val, left), right), &weight) in self
+            .spectrum
+            .iter_mut()
+            .zip(self.fft_data_left.iter())
+            .zip(self.fft_data_right.iter())
+            .zip(self.weights.iter())
+        {
+            *val = ((left.re * left.re + left.im * left.im).sqrt()
+                + (right.re * right.re + right.im * right.im).sqrt())
+                * weight;
+        }
+
+        // Logarithmic compression
+        for (output, &val) in self.output.iter_mut().zip(self.spectrum.iter()) {
+            *output = (compression * val).ln_1p();
+        }
+
+        // Record peaks
+        let peak_output = track_peaks(&self.output, &mut self.peaks);
+        self.peak_output = self.peak_output.max(peak_output);
+
+        // Bark bands, the mean of their compressed bins so the wide ones don't dominate
+        for ((band, peak), bins) in self
+            .bark
+            .iter_mut()
+            .zip(self.bark_peaks.iter_mut())
+            .zip(self.bark_bins.iter())
+        {
+            let sum = self.output[bins.clone()]
+                .iter()
+                .fold(T::zero(), |sum, &val| sum + val);
+            *band = sum / float(bins.len() as f64);
+            *peak = peak.max(*band);
+        }
+
+        // Chroma, the energy of the bins folded into a single octave
+        self.chroma = [T::zero(); 12];
+        for (pitch_class, &val) in self.pitch_classes.iter().zip(self.spectrum.iter()) {
+            if let Some(pitch_class) = pitch_class {
+                let energy = &mut self.chroma[*pitch_class as usize];
+                *energy = *energy + val * val;
+            }
+        }
+        let max = self.chroma.iter().copied().fold(T::zero(), T::max);
+        if max > T::zero() {
+            for energy in self.chroma.iter_mut() {
+                *energy = *energy / max;
+            }
+        }
+
+        // Mel bands, summed before the compression like the bins
+        if let Some(filterbank) = self.mel_filterbank.as_ref() {
+            filterbank.apply(&self.spectrum, &mut self.bands);
+            for band in self.bands.iter_mut() {
+                *band = (compression * *band).ln_1p();
+            }
+        }
+
+        // Novelty curve
+        let (current, previous) = if self.mel_filterbank.is_some() {
+            (&self.bands, &self.prev_bands)
+        } else {
+            (&self.output, &self.prev_output)
+        };
+        let mut novelty = if self.novelty_gains.is_empty() {
+            positive_delta_sum(current, previous)
+        } else {
+            weighted_positive_delta_sum(current, previous, &self.novelty_gains)
+        };
+        // Amplify data
+        novelty = novelty * novelty;
+
+        self.novelty_curve.pop_front();
+        self.novelty_curve.push_back(novelty);
+
+        self.detect_onset();
+    }
+
+    /// Peak picking on the novelty curve with a threshold following its moving median.
+    fn detect_onset(&mut self) {
+        self.onset = false;
+        self.frames_since_onset = self.frames_since_onset.saturating_add(1);
+
+        let len = self.novelty_curve.len();
+        if len < 3 {
+            return;
+        }
+        let current = self.novelty_curve[len - 1];
+        let candidate = self.novelty_curve[len - 2];
+        let before = self.novelty_curve[len - 3];
+
+        // Median of the frames up to the candidate, selected in place without allocating
+        let window = ONSET_MEDIAN_SIZE.min(len - 1);
+        self.median_scratch.clear();
+        self.median_scratch.extend(
+            self.novelty_curve
+                .iter()
+                .skip(len - 1 - window)
+                .take(window),
+        );
+        let (_, &mut median, _) = self
+            .median_scratch
+            .select_nth_unstable_by(window / 2, |a, b| {
+                a.partial_cmp(b).unwrap_or(Ordering::Equal)
+            });
+
+        let threshold = float::<T>(self.onset_threshold) * median
+            + float::<T>(ONSET_THRESHOLD_OFFSET) * self.novelty_peak_short_term();
+        if candidate > before
+            && candidate >= current
+            && candidate > threshold
+            && self.frames_since_onset > ONSET_MIN_INTERVAL
+        {
+            self.onset = true;
+            self.frames_since_onset = 0;
+        }
+    }
+}
+
+/// Amount of independent accumulators in the reductions, so they use every SIMD lane
+/// (the compiler can't reorder float additions by itself).
+const LANES: usize = 8;
+
+/// Sum of the increases from `previous` to `current`, the half-wave rectified flux.
+fn positive_delta_sum<T: AudioFloat>(current: &[T], previous: &[T]) -> T {
+    let len = current.len().min(previous.len());
+    let split = len - len % LANES;
+
+    let mut lanes = [T::zero(); LANES];
+    for (current, previous) in current[..split]
+        .chunks_exact(LANES)
+        .zip(previous[..split].chunks_exact(LANES))
+    {
+        for ((lane, &current), &previous) in lanes.iter_mut().zip(current).zip(previous) {
+            *lane = *lane + (current - previous).max(T::zero());
+        }
+    }
+
+    let tail = current[split..len]
+        .iter()
+        .zip(previous[split..len].iter())
+        .fold(T::zero(), |sum, (&current, &previous)| {
+            sum + (current - previous).max(T::zero())
+        });
+    lanes.iter().fold(tail, |sum, &lane| sum + lane)
+}
+
+/// Like [positive_delta_sum] with a gain for each value, not worth the lanes as it is
+/// rarely used.
+fn weighted_positive_delta_sum<T: AudioFloat>(current: &[T], previous: &[T], gains: &[T]) -> T {
+    current
+        .iter()
+        .zip(previous.iter())
+        .zip(gains.iter())
+        .fold(T::zero(), |sum, ((&current, &previous), &gain)| {
+            sum + (current - previous).max(T::zero()) * gain
+        })
+}
+
+/// Raise the `peaks` reached by `values`, returns the maximum of `values`.
+fn track_peaks<T: AudioFloat>(values: &[T], peaks: &mut [T]) -> T {
+    let len = values.len().min(peaks.len());
+    let split = len - len % LANES;
+
+    let mut lanes = [T::zero(); LANES];
+    for (values, peaks) in values[..split]
+        .chunks_exact(LANES)
+        .zip(peaks[..split].chunks_exact_mut(LANES))
+    {
+        for ((lane, peak), &value) in lanes.iter_mut().zip(peaks.iter_mut()).zip(values) {
+            *peak = peak.max(value);
+            *lane = lane.max(value);
+        }
+    }
+
+    let mut max = lanes.iter().copied().fold(T::zero(), T::max);
+    for (&value, peak) in values[split..len].iter().zip(peaks[split..len].iter_mut()) {
+        *peak = peak.max(value);
+        max = max.max(value);
+    }
+    max
+}
+
+/// Triangular filters evenly spaced on the mel scale from 0Hz to the Nyquist frequency,
+/// each one is a weighted sum of a few bins of the spectrum.
+struct MelFilterbank<T> {
+    /// First bin and weights of each filter.
+    filters: Vec<(usize, Vec<T>)>,
+    /// Center frequency of each filter, in Hz.
+    centers: Vec<f64>,
+}
+
+impl<T: AudioFloat> MelFilterbank<T> {
+    fn new(band_count: usize, bin_count: usize, sample_size: usize) -> Self {
+        let hz_to_mel = |hz: f64| 2595.0 * (1.0 + hz / 700.0).log10();
+        let mel_to_hz = |mel: f64| 700.0 * (10f64.powf(mel / 2595.0) - 1.0);
+
+        let bin_width = SAMPLE_RATE as f64 / sample_size as f64;
+        let max_mel = hz_to_mel(SAMPLE_RATE as f64 / 2.0);
+
+        // Edges of the triangles, in fractional bins
+        let edges: Vec<f64> = (0..band_count + 2)
+            .map(|i| mel_to_hz(max_mel * i as f64 / (band_count + 1) as f64) / bin_width)
+            .collect();
+
+        let filters = edges
+            .windows(3)
+            .map(|edge| {
+                let (low, center, high) = (edge[0], edge[1], edge[2]);
+                let start = low.ceil() as usize;
+                let end = (high.floor() as usize).min(bin_count - 1);
+                let weights: Vec<f64> = (start..=end)
+                    .map(|bin| {
+                        let bin = bin as f64;
+                        if bin <= center {
+                            (bin - low) / (center - low)
+                        } else {
+                            (high - bin) / (high - center)
+                        }
+                    })
+                    .collect();
+
+                // The lowest bands can be narrower than a bin
+                if weights.iter().all(|&weight| weight <= 0.0) {
+                    ((center.round() as usize).min(bin_count - 1), vec![T::one()])
+                } else {
+                    (start, weights.into_iter().map(float).collect())
+                }
+            })
+            .collect();
+        let centers = edges[1..=band_count]
+            .iter()
+            .map(|center| center * bin_width)
+            .collect();
+
+        Self { filters, centers }
+    }
+
+    fn apply(&self, spectrum: &[T], bands: &mut [T]) {
+        for (band, (start, weights)) in bands.iter_mut().zip(self.filters.iter()) {
+            *band = spectrum[*start..]
+                .iter()
+                .zip(weights.iter())
+                .fold(T::zero(), |sum, (&bin, &weight)| sum + bin * weight);
+        }
+    }
+}
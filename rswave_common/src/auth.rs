@@ -0,0 +1,45 @@
+use hmac::{Hmac, Mac, NewMac};
+use sha2::{Digest, Sha256};
+use std::{convert::Infallible, fmt, str::FromStr};
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const MAC_SIZE: usize = 32;
+
+/// Pre-shared key between the remote and the server, derived from a passphrase.
+#[derive(Copy, Clone)]
+pub struct Psk([u8; 32]);
+
+impl Psk {
+    fn hmac(&self) -> HmacSha256 {
+        HmacSha256::new_varkey(&self.0).expect("HMAC accepts any key size")
+    }
+
+    pub fn sign(&self, data: &[u8]) -> [u8; MAC_SIZE] {
+        let mut hmac = self.hmac();
+        hmac.update(data);
+        hmac.finalize().into_bytes().into()
+    }
+
+    /// Constant time comparison of the MAC.
+    pub fn verify(&self, data: &[u8], mac: &[u8]) -> bool {
+        let mut hmac = self.hmac();
+        hmac.update(data);
+        hmac.verify(mac).is_ok()
+    }
+}
+
+impl FromStr for Psk {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(Sha256::digest(s.as_bytes()).into()))
+    }
+}
+
+impl fmt::Debug for Psk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Don't leak it in logs
+        f.write_str("Psk(..)")
+    }
+}
@@ -0,0 +1,103 @@
+//! Turns packets into datagrams and back, with buffers reused from one packet to the next.
+
+use crate::{frame, transport::Transport};
+use bytecheck::CheckBytes;
+use rkyv::{
+    check_archive,
+    ser::{serializers::WriteSerializer, Serializer},
+    validation::{ArchiveContext, CheckArchiveError},
+    Aligned, Archive, Archived, Serialize,
+};
+use std::{io, net::SocketAddr};
+
+/// The largest UDP payload that fits in an Ethernet frame, the packets are kept smaller by
+/// [MAX_ZONES](crate::packets::MAX_ZONES) and the other limits of the packets.
+pub const MAX_DATAGRAM_SIZE: usize = 1472;
+
+/// Why a received packet isn't a valid `T`.
+pub type CheckError<T> = CheckArchiveError<<Archived<T> as CheckBytes<ArchiveContext>>::Error>;
+
+pub struct PacketCodec {
+    send_buffer: Vec<u8>,
+    /// The archives must be aligned to be checked in place.
+    recv_buffer: Aligned<[u8; MAX_DATAGRAM_SIZE]>,
+    /// Length of the last received packet without its trailer, and the position of its root.
+    /// Empty if it was corrupted.
+    received: (usize, usize),
+}
+
+impl Default for PacketCodec {
+    fn default() -> Self {
+        Self {
+            send_buffer: Vec::new(),
+            recv_buffer: Aligned([0; MAX_DATAGRAM_SIZE]),
+            received: (0, 0),
+        }
+    }
+}
+
+impl PacketCodec {
+    /// Serialize and seal `item`, the datagram is valid until the next one. Fails if it is
+    /// too big to be received whole.
+    pub fn encode(&mut self, item: &impl Serialize<WriteSerializer<Vec<u8>>>) -> io::Result<&[u8]> {
+        let mut buffer = std::mem::take(&mut self.send_buffer);
+        buffer.clear();
+
+        let mut serializer = WriteSerializer::new(buffer);
+        let root = serializer.serialize_value(item)?;
+
+        self.send_buffer = serializer.into_inner();
+        frame::seal(&mut self.send_buffer, root);
+        if self.send_buffer.len() > MAX_DATAGRAM_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Packet of {} bytes, over the {} of a datagram",
+                    self.send_buffer.len(),
+                    MAX_DATAGRAM_SIZE
+                ),
+            ));
+        }
+        Ok(&self.send_buffer)
+    }
+
+    /// Receive one datagram from the only peer, returns whether it is intact.
+    pub fn recv(&mut self, socket: &mut impl Transport) -> io::Result<bool> {
+        let len = socket.recv(self.recv_buffer.as_mut())?;
+        Ok(self.open(len))
+    }
+
+    /// Receive one datagram from anyone, returns its sender and whether it is intact.
+    pub fn recv_from(&mut self, socket: &mut impl Transport) -> io::Result<(SocketAddr, bool)> {
+        let (len, address) = socket.recv_from(self.recv_buffer.as_mut())?;
+        Ok((address, self.open(len)))
+    }
+
+    fn open(&mut self, len: usize) -> bool {
+        match frame::open(&self.recv_buffer.as_ref()[..len]) {
+            Some((packet, root)) => {
+                self.received = (packet.len(), root);
+                true
+            }
+            None => {
+                self.received = (0, 0);
+                false
+            }
+        }
+    }
+
+    /// Length of the last received packet, 0 if it was corrupted.
+    #[inline]
+    pub fn received_len(&self) -> usize {
+        self.received.0
+    }
+
+    /// Validate the last received packet as a `T`, without copying it.
+    pub fn check<T: Archive>(&self) -> Result<&Archived<T>, CheckError<T>>
+    where
+        Archived<T>: CheckBytes<ArchiveContext>,
+    {
+        let (len, root) = self.received;
+        check_archive::<T>(&self.recv_buffer.as_ref()[..len], root)
+    }
+}
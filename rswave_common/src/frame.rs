@@ -0,0 +1,35 @@
+//! Every datagram is a serialized packet followed by a small trailer
+//! made of the position of the root object and a CRC32 of everything before it.
+
+use std::convert::TryInto;
+
+pub const TRAILER_SIZE: usize = 8;
+
+/// Append the trailer to a serialized packet whose root object is at `root`.
+pub fn seal(buffer: &mut Vec<u8>, root: usize) {
+    buffer.extend_from_slice(&(root as u32).to_le_bytes());
+    let checksum = crc32fast::hash(buffer);
+    buffer.extend_from_slice(&checksum.to_le_bytes());
+}
+
+/// Check the integrity of a received datagram.
+/// Returns the serialized packet and the position of its root object,
+/// or nothing if the datagram is corrupted.
+pub fn open(datagram: &[u8]) -> Option<(&[u8], usize)> {
+    if datagram.len() < TRAILER_SIZE {
+        return None;
+    }
+
+    let (content, checksum) = datagram.split_at(datagram.len() - 4);
+    if crc32fast::hash(content) != u32::from_le_bytes(checksum.try_into().ok()?) {
+        return None;
+    }
+
+    let (packet, root) = content.split_at(content.len() - 4);
+    let root = u32::from_le_bytes(root.try_into().ok()?) as usize;
+    if root > packet.len() {
+        return None;
+    }
+
+    Some((packet, root))
+}
@@ -1,5 +1,24 @@
 pub use rkyv;
+use std::time::Duration;
 
 pub const MAGIC: u8 = 0x42;
+/// Bumped every time the packets change, both ends must agree on it.
+pub const PROTOCOL_VERSION: u8 = 13;
 
+/// Maximum time without sending anything before the remote sends a heartbeat.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// Time without hearing from the remote after which the server considers it gone.
+pub const PEER_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[cfg(feature = "audio")]
+pub mod audio;
+pub mod auth;
+pub mod codec;
+pub mod frame;
+pub mod logging;
 pub mod packets;
+#[cfg(feature = "transport_quic")]
+pub mod quic;
+pub mod sequence;
+pub mod tcp;
+pub mod transport;
@@ -0,0 +1,43 @@
+use std::{fs::File, io, str::FromStr, sync::Mutex};
+use tracing_subscriber::{fmt::writer::BoxMakeWriter, EnvFilter};
+
+/// How the logs are printed, the same for both ends.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable lines.
+    Text,
+    /// One JSON object per line, with the fields of the current spans.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err("Unknown log format !".into()),
+        }
+    }
+}
+
+/// Install the global subscriber, the verbosity is controlled by `RUST_LOG`.
+/// Logs go to stderr unless a `file` is given.
+/// The records of the dependencies that still use `log` are collected too.
+pub fn init(format: LogFormat, file: Option<File>) {
+    let (writer, ansi) = match file {
+        Some(file) => (BoxMakeWriter::new(Mutex::new(file)), false),
+        None => (BoxMakeWriter::new(io::stderr), true),
+    };
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(writer)
+        .with_ansi(ansi);
+
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
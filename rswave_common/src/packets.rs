@@ -1,20 +1,87 @@
-use crate::MAGIC;
+use crate::{
+    auth::{Psk, MAC_SIZE},
+    MAGIC, PROTOCOL_VERSION,
+};
 use bytecheck::CheckBytes;
 use rkyv::{Archive, Deserialize, Serialize};
+use std::{
+    fmt,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Value of [NoveltyModeData::pitch_class] when no pitch stands out.
+pub const NO_PITCH_CLASS: u8 = u8::MAX;
+/// Amount of critical bands of the Bark scale sent in [DataMode::Bands].
+pub const BARK_BANDS: usize = 24;
+/// Most colors in a [PaletteData].
+pub const MAX_PALETTE_COLORS: usize = 8;
+/// Most zones in a [NoveltyModeData], so the packet stays in a datagram.
+pub const MAX_ZONES: usize = 8;
+/// Longest name of a [ZoneData], in bytes.
+pub const MAX_ZONE_NAME: usize = 32;
+
+/// Clock of the timestamps of the data packets, in microseconds since the Unix epoch.
+/// The server doesn't need to agree on it, it only looks at how it moves.
+pub fn timestamp_us() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_micros() as u64)
+}
 
 #[derive(Debug, Copy, Clone, Archive, Serialize, Deserialize)]
 #[archive(derive(Debug, CheckBytes))]
 pub struct HelloPacket {
     pub magic: u8,
+    /// [PROTOCOL_VERSION] of the sender, not covered by the MAC.
+    pub version: u8,
     pub random: u8,
+    /// Makes every signed hello different, to detect replays.
+    pub nonce: u64,
+    /// HMAC of the rest of the packet with the pre-shared key, zeroed without key.
+    pub mac: [u8; MAC_SIZE],
+    /// Filled by the server in its answer so the remote doesn't send faster than the LEDs.
+    /// Not covered by the MAC.
+    pub led_update_period_ms: u32,
+    /// Filled by the remote, how long after the `timestamp_us` of the data the servers
+    /// should show it so they all show it at the same time. 0 to show it on arrival.
+    /// Not covered by the MAC.
+    pub sync_delay_ms: u32,
 }
 
 impl Default for HelloPacket {
     fn default() -> Self {
         Self {
             magic: MAGIC,
+            version: PROTOCOL_VERSION,
             random: rand::random(),
+            nonce: rand::random(),
+            mac: [0; MAC_SIZE],
+            led_update_period_ms: 0,
+            sync_delay_ms: 0,
+        }
+    }
+}
+
+impl HelloPacket {
+    pub fn new(psk: Option<&Psk>) -> Self {
+        let mut hello = Self::default();
+        if let Some(psk) = psk {
+            hello.mac = psk.sign(&hello.signed_bytes());
         }
+        hello
+    }
+
+    fn signed_bytes(&self) -> [u8; 10] {
+        let mut bytes = [0; 10];
+        bytes[0] = self.magic;
+        bytes[1] = self.random;
+        bytes[2..].copy_from_slice(&self.nonce.to_le_bytes());
+        bytes
+    }
+
+    pub fn verify(&self, psk: &Psk) -> bool {
+        psk.verify(&self.signed_bytes(), &self.mac)
     }
 }
 
@@ -23,11 +90,18 @@ impl Default for HelloPacket {
 pub enum DataMode {
     Novelty,
     NoveltyBeats,
+    /// Only the beats and the tempo, a few packets per second at most.
+    BeatsOnly,
+    /// The novelty and the beats along with the energy of the Bark bands, for the effects
+    /// spreading the spectrum over the strip.
+    Bands,
 }
 
 #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
 #[archive(derive(Debug, CheckBytes))]
 pub struct SetModePacket {
+    /// Numbered like every control packet.
+    pub sequence: u32,
     pub mode: DataMode,
 }
 
@@ -35,6 +109,11 @@ pub struct SetModePacket {
 #[archive(derive(Debug, CheckBytes))]
 pub enum NoveltyModePacket {
     Data(NoveltyModeData),
+    Palette(PaletteData),
+    /// Sent when there is no data to send, to let the server know we are alive.
+    Heartbeat,
+    Standby(StandbyData),
+    Command(CommandData),
     Abort,
     Goodbye(GoodbyeData),
 }
@@ -42,14 +121,82 @@ pub enum NoveltyModePacket {
 #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
 #[archive(derive(Debug, CheckBytes))]
 pub struct NoveltyModeData {
+    /// Incremented for every data packet, in any mode.
+    pub sequence: u32,
+    pub value: f64,
+    pub peak: f64,
+    /// An onset was detected since the last data packet.
+    pub onset: bool,
+    /// Dominant pitch class of the audio, 0 being C, or [NO_PITCH_CLASS].
+    pub pitch_class: u8,
+    /// Stereo balance of the audio, from -1 on the left to 1 on the right.
+    pub balance: f32,
+    /// When it was sent, see [timestamp_us].
+    pub timestamp_us: u64,
+    /// Sent to the zones of the server with these names instead of `value`, the other
+    /// zones get `value`.
+    pub zones: Vec<ZoneData>,
+}
+
+/// Novelty of a part of the spectrum, for a zone of the strip.
+#[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+#[archive(derive(Debug, CheckBytes))]
+pub struct ZoneData {
+    pub name: String,
     pub value: f64,
     pub peak: f64,
 }
 
+/// Read in place from the received datagram, no need to deserialize.
+impl ArchivedNoveltyModeData {
+    /// The novelty relative to its recent peak, zero before any peak.
+    #[inline]
+    pub fn normalized_novelty(&self) -> f64 {
+        if self.peak > 0.0 {
+            self.value / self.peak
+        } else {
+            0.0
+        }
+    }
+
+    /// Dominant pitch class, 0 being C.
+    #[inline]
+    pub fn pitch_class(&self) -> Option<u8> {
+        if self.pitch_class == NO_PITCH_CLASS {
+            None
+        } else {
+            Some(self.pitch_class % 12)
+        }
+    }
+
+    /// Between -1 and 1 whatever was received.
+    #[inline]
+    pub fn balance(&self) -> f64 {
+        (self.balance as f64).max(-1.0).min(1.0)
+    }
+
+    /// The name of each zone with its novelty relative to its recent peak.
+    pub fn zones(&self) -> impl Iterator<Item = (&str, f64)> + '_ {
+        self.zones.iter().map(|zone| {
+            let novelty = if zone.peak > 0.0 {
+                zone.value / zone.peak
+            } else {
+                0.0
+            };
+            (zone.name.as_str(), novelty)
+        })
+    }
+}
+
 #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
 #[archive(derive(Debug, CheckBytes))]
 pub enum NoveltyBeatsModePacket {
     Data(NoveltyBeatsModeData),
+    Palette(PaletteData),
+    Features(FeaturesData),
+    Heartbeat,
+    Standby(StandbyData),
+    Command(CommandData),
     Abort,
     Goodbye(GoodbyeData),
 }
@@ -59,6 +206,205 @@ pub enum NoveltyBeatsModePacket {
 pub struct NoveltyBeatsModeData {
     pub novelty: NoveltyModeData,
     pub beat: bool,
+    /// Loudness of the current spotify segment, normalized between 0 and 1.
+    pub loudness: f64,
+}
+
+#[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+#[archive(derive(Debug, CheckBytes))]
+pub enum BeatsOnlyModePacket {
+    Beat(BeatData),
+    Palette(PaletteData),
+    Heartbeat,
+    Standby(StandbyData),
+    Command(CommandData),
+    Abort,
+    Goodbye(GoodbyeData),
+}
+
+#[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+#[archive(derive(Debug, CheckBytes))]
+pub struct BeatData {
+    /// Numbered like the data packets of the other modes.
+    pub sequence: u32,
+    /// Beats per minute, 0 when unknown.
+    pub tempo: f64,
+    /// When it was sent, see [timestamp_us].
+    pub timestamp_us: u64,
+}
+
+#[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+#[archive(derive(Debug, CheckBytes))]
+pub struct PaletteData {
+    /// Numbered like every control packet.
+    pub sequence: u32,
+    /// RGB colors, the most important one first. They are also the keys of a gradient,
+    /// evenly spread from the first to the last, for the runners filling the strip with it.
+    /// At most [MAX_PALETTE_COLORS].
+    pub colors: Vec<[u8; 3]>,
+}
+
+impl ArchivedPaletteData {
+    #[inline]
+    pub fn colors(&self) -> &[[u8; 3]] {
+        &self.colors
+    }
+}
+
+#[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+#[archive(derive(Debug, CheckBytes))]
+pub enum BandsModePacket {
+    Data(BandsModeData),
+    Palette(PaletteData),
+    Heartbeat,
+    Standby(StandbyData),
+    Command(CommandData),
+    Abort,
+    Goodbye(GoodbyeData),
+}
+
+#[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+#[archive(derive(Debug, CheckBytes))]
+pub struct BandsModeData {
+    pub novelty: NoveltyModeData,
+    pub beat: bool,
+    /// Energy of each Bark band from the lowest, relative to its recent peak which is 255.
+    pub bands: [u8; BARK_BANDS],
+}
+
+impl ArchivedBandsModeData {
+    /// Between 0 and 1.
+    pub fn bands(&self) -> [f32; BARK_BANDS] {
+        let mut bands = [0.0; BARK_BANDS];
+        for (band, &energy) in bands.iter_mut().zip(self.bands.iter()) {
+            *band = energy as f32 / u8::MAX as f32;
+        }
+        bands
+    }
+}
+
+/// The character of the track according to spotify, for the ambient runners to follow.
+#[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+#[archive(derive(Debug, CheckBytes))]
+pub struct FeaturesData {
+    /// Numbered like every control packet.
+    pub sequence: u32,
+    /// Between 0 and 1.
+    pub danceability: f32,
+    /// Between 0 and 1.
+    pub energy: f32,
+}
+
+/// The audio went silent or came back, the server shows its standby in the meantime.
+#[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+#[archive(derive(Debug, CheckBytes))]
+pub struct StandbyData {
+    /// Numbered like every control packet.
+    pub sequence: u32,
+    pub silent: bool,
+}
+
+/// An order for the server, it can be sent in any mode.
+#[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+#[archive(derive(Debug, CheckBytes))]
+pub struct CommandData {
+    /// Numbered like every control packet.
+    pub sequence: u32,
+    pub command: Command,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Archive, Serialize, Deserialize)]
+#[archive(derive(Debug, CheckBytes))]
+pub enum Command {
+    /// Overall brightness of the strip.
+    Brightness(u8),
+    /// Index of the runner to show, wraps around.
+    Runner(u8),
+    /// Speed of the standby rainbow.
+    StandbySpeed(f32),
+    /// Blank the strip and start the current runner over.
+    Reset,
+    /// Show the look saved on the server with this id.
+    Preset(u8),
+    /// Save what the server shows as the preset with this id.
+    SavePreset(u8),
+    /// Which beats reach the runners.
+    BeatDivision(BeatDivision),
+    /// A beat right now, on top of the ones of the analysis.
+    Beat,
+    /// Show this RGB color, like a palette of a single color.
+    Color([u8; 3]),
+    /// Flash the whole strip in white for this many milliseconds.
+    Strobe(u16),
+}
+
+impl From<&ArchivedCommand> for Command {
+    fn from(command: &ArchivedCommand) -> Self {
+        match command {
+            ArchivedCommand::Brightness(brightness) => Self::Brightness(*brightness),
+            ArchivedCommand::Runner(index) => Self::Runner(*index),
+            ArchivedCommand::StandbySpeed(speed) => Self::StandbySpeed(*speed),
+            ArchivedCommand::Reset => Self::Reset,
+            ArchivedCommand::Preset(id) => Self::Preset(*id),
+            ArchivedCommand::SavePreset(id) => Self::SavePreset(*id),
+            ArchivedCommand::BeatDivision(division) => Self::BeatDivision(division.into()),
+            ArchivedCommand::Beat => Self::Beat,
+            ArchivedCommand::Color(color) => Self::Color(*color),
+            ArchivedCommand::Strobe(duration_ms) => Self::Strobe(*duration_ms),
+        }
+    }
+}
+
+/// Which beats the runners see, fast tracks are tiring with a flash on every beat and slow
+/// ones feel dead.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Archive, Serialize, Deserialize)]
+#[archive(derive(Debug, CheckBytes))]
+pub enum BeatDivision {
+    /// Every other beat.
+    Half,
+    Normal,
+    /// Another beat halfway between each of them.
+    Double,
+}
+
+impl BeatDivision {
+    /// Half, normal, double and back to half.
+    pub fn next(self) -> Self {
+        match self {
+            Self::Half => Self::Normal,
+            Self::Normal => Self::Double,
+            Self::Double => Self::Half,
+        }
+    }
+}
+
+impl Default for BeatDivision {
+    fn default() -> Self {
+        Self::Normal
+    }
+}
+
+impl From<&ArchivedBeatDivision> for BeatDivision {
+    fn from(division: &ArchivedBeatDivision) -> Self {
+        match division {
+            ArchivedBeatDivision::Half => Self::Half,
+            ArchivedBeatDivision::Normal => Self::Normal,
+            ArchivedBeatDivision::Double => Self::Double,
+        }
+    }
+}
+
+impl FromStr for BeatDivision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "half" => Ok(Self::Half),
+            "normal" => Ok(Self::Normal),
+            "double" => Ok(Self::Double),
+            _ => Err("Unknown beat division !".into()),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
@@ -68,10 +414,50 @@ pub struct GoodbyeData {
     pub force: bool,
 }
 
+impl ArchivedGoodbyeData {
+    /// Whether it really is a goodbye and not garbage.
+    #[inline]
+    pub fn is_valid(&self) -> bool {
+        self.magic == MAGIC
+    }
+}
+
 #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
 #[archive(derive(Debug, CheckBytes))]
 pub enum AckPacket {
-    Ok,
+    /// Contains the sequence number of the acknowledged data.
+    Ok(u32),
+    /// Contains the sequence number of the acknowledged control packet (mode, palette and
+    /// standby), they are sent again until acknowledged.
+    Control(u32),
     Quit,
-    Abort,
+    Abort(AbortReason),
+}
+
+/// Why the server doesn't want to hear from the remote anymore.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Archive, Serialize, Deserialize)]
+#[archive(derive(Debug, CheckBytes))]
+pub enum AbortReason {
+    /// The packet doesn't make sense in the current mode.
+    InvalidPacket,
+    UnsupportedMode,
+    /// The remote speaks another [PROTOCOL_VERSION].
+    BadVersion,
+    /// Too many handshakes at once.
+    RateLimited,
+    /// Another remote is connected and the server only takes one.
+    Busy,
+}
+
+/// The remote only sees the archived reason.
+impl fmt::Display for ArchivedAbortReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::InvalidPacket => "invalid packet",
+            Self::UnsupportedMode => "unsupported mode",
+            Self::BadVersion => "protocol version mismatch, update rswave on both ends",
+            Self::RateLimited => "too many handshakes, try again later",
+            Self::Busy => "busy with another remote",
+        })
+    }
 }
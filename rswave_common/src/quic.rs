@@ -0,0 +1,344 @@
+//! QUIC transport, running on its own runtime thread.
+//! Control packets go through a reliable stream while the analysis data uses
+//! unreliable datagrams. The connection also survives address changes of the remote.
+
+use futures_util::StreamExt;
+use quinn::{
+    ClientConfig, Endpoint, Incoming, NewConnection, RecvStream, SendStream, ServerConfig,
+};
+use std::{
+    error::Error,
+    io,
+    net::{SocketAddr, ToSocketAddrs},
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio1::sync::mpsc as async_mpsc;
+
+/// Name used in the certificate, it isn't verified anyway.
+const SERVER_NAME: &str = "rswave";
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(1);
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+/// How long to wait for the first connection of a client.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+enum Command {
+    Send { data: Vec<u8>, reliable: bool },
+    Close,
+}
+
+/// Behaves like a connected UDP socket, one connection at a time.
+pub struct QuicSocket {
+    incoming: mpsc::Receiver<Vec<u8>>,
+    /// Message received while waiting for one, handed out by the next `recv`.
+    peeked: Mutex<Option<Vec<u8>>>,
+    commands: async_mpsc::UnboundedSender<Command>,
+    peer: Arc<Mutex<Option<SocketAddr>>>,
+    nonblocking: bool,
+    read_timeout: Option<Duration>,
+}
+
+/// The runtime side of a `QuicSocket`.
+struct Link {
+    incoming: mpsc::Sender<Vec<u8>>,
+    commands: async_mpsc::UnboundedReceiver<Command>,
+    peer: Arc<Mutex<Option<SocketAddr>>>,
+}
+
+impl QuicSocket {
+    fn new() -> (Self, Link) {
+        let (incoming_sender, incoming) = mpsc::channel();
+        let (commands, commands_receiver) = async_mpsc::unbounded_channel();
+        let peer = Arc::new(Mutex::new(None));
+
+        let socket = Self {
+            incoming,
+            peeked: Mutex::new(None),
+            commands,
+            peer: peer.clone(),
+            nonblocking: false,
+            read_timeout: None,
+        };
+        let link = Link {
+            incoming: incoming_sender,
+            commands: commands_receiver,
+            peer,
+        };
+        (socket, link)
+    }
+
+    /// Accept connections on `port`, with a new self signed certificate.
+    pub fn server(port: u16) -> io::Result<Self> {
+        let certificate =
+            rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_string()]).map_err(other)?;
+        let key = rustls::PrivateKey(certificate.serialize_private_key_der());
+        let certificate = rustls::Certificate(certificate.serialize_der().map_err(other)?);
+        let config = ServerConfig::with_single_cert(vec![certificate], key).map_err(other)?;
+
+        let runtime = new_runtime()?;
+        let (endpoint, incoming) = {
+            let _guard = runtime.enter();
+            Endpoint::server(config, SocketAddr::new([0, 0, 0, 0].into(), port))?
+        };
+
+        let (socket, link) = Self::new();
+        std::thread::Builder::new()
+            .name("QUIC Server Thread".into())
+            .spawn(move || {
+                let _endpoint = endpoint;
+                runtime.block_on(link.serve(incoming));
+            })?;
+
+        Ok(socket)
+    }
+
+    /// Connect to `address`, reconnecting automatically when the connection is lost.
+    /// The certificate of the server isn't verified, use a pre-shared key to authenticate it.
+    pub fn client(address: &str) -> io::Result<Self> {
+        let address = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| other("Unknown address"))?;
+
+        let crypto = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(SkipServerVerification))
+            .with_no_client_auth();
+        let mut config = ClientConfig::new(Arc::new(crypto));
+        Arc::get_mut(&mut config.transport)
+            .unwrap()
+            .keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+
+        let runtime = new_runtime()?;
+        let mut endpoint = {
+            let _guard = runtime.enter();
+            Endpoint::client(SocketAddr::new([0, 0, 0, 0].into(), 0))?
+        };
+        endpoint.set_default_client_config(config);
+
+        let (socket, link) = Self::new();
+        std::thread::Builder::new()
+            .name("QUIC Client Thread".into())
+            .spawn(move || runtime.block_on(link.connect(endpoint, address)))?;
+
+        // Give it a chance to be usable right away
+        let start = Instant::now();
+        while socket.peer().is_none() && start.elapsed() < CONNECT_TIMEOUT {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        Ok(socket)
+    }
+
+    pub fn peer(&self) -> Option<SocketAddr> {
+        *self.peer.lock().unwrap()
+    }
+
+    pub fn send(&self, buf: &[u8], reliable: bool) -> io::Result<usize> {
+        if self.peer().is_none() {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+
+        self.commands
+            .send(Command::Send {
+                data: buf.to_vec(),
+                reliable,
+            })
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    /// Like UDP, the message is truncated if the buffer is too small.
+    pub fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let peeked = self.peeked.lock().unwrap().take();
+        let message = if let Some(message) = peeked {
+            message
+        } else if self.nonblocking {
+            self.incoming.try_recv().map_err(|err| match err {
+                mpsc::TryRecvError::Empty => io::ErrorKind::WouldBlock.into(),
+                mpsc::TryRecvError::Disconnected => io::Error::from(io::ErrorKind::BrokenPipe),
+            })?
+        } else if let Some(timeout) = self.read_timeout {
+            self.incoming
+                .recv_timeout(timeout)
+                .map_err(|err| match err {
+                    mpsc::RecvTimeoutError::Timeout => io::ErrorKind::TimedOut.into(),
+                    mpsc::RecvTimeoutError::Disconnected => {
+                        io::Error::from(io::ErrorKind::BrokenPipe)
+                    }
+                })?
+        } else {
+            self.incoming
+                .recv()
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?
+        };
+
+        let len = message.len().min(buf.len());
+        buf[..len].copy_from_slice(&message[..len]);
+        Ok(len)
+    }
+
+    /// Block until a message is available or the `timeout` expires, without consuming it.
+    pub fn wait_readable(&self, timeout: Duration) -> io::Result<bool> {
+        let mut peeked = self.peeked.lock().unwrap();
+        if peeked.is_some() {
+            return Ok(true);
+        }
+
+        match self.incoming.recv_timeout(timeout) {
+            Ok(message) => {
+                peeked.replace(message);
+                Ok(true)
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => Ok(false),
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                Err(io::Error::from(io::ErrorKind::BrokenPipe))
+            }
+        }
+    }
+
+    pub fn set_nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking = nonblocking;
+    }
+
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    /// Close the current connection, a server will then accept the next one.
+    pub fn close(&mut self) {
+        let _ = self.commands.send(Command::Close);
+        // Forget what was left from this connection
+        self.peeked.lock().unwrap().take();
+        while self.incoming.try_recv().is_ok() {}
+    }
+}
+
+impl Link {
+    async fn serve(mut self, mut incoming: Incoming) {
+        while let Some(connecting) = incoming.next().await {
+            if let Ok(connection) = connecting.await {
+                if !self.run(connection, None).await {
+                    break;
+                }
+            }
+        }
+    }
+
+    async fn connect(mut self, endpoint: Endpoint, address: SocketAddr) {
+        loop {
+            if let Ok(connecting) = endpoint.connect(address, SERVER_NAME) {
+                if let Ok(connection) = connecting.await {
+                    if let Ok(stream) = connection.connection.open_bi().await {
+                        if !self.run(connection, Some(stream)).await {
+                            break;
+                        }
+                    }
+                }
+            }
+
+            tokio1::time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+
+    /// Pump the messages of a connection until it is closed.
+    /// Returns false if the socket itself is gone.
+    async fn run(
+        &mut self, connection: NewConnection, stream: Option<(SendStream, RecvStream)>,
+    ) -> bool {
+        let NewConnection {
+            connection,
+            mut bi_streams,
+            mut datagrams,
+            ..
+        } = connection;
+
+        // The client opens the stream, the server waits for it
+        let (mut send, mut recv) = match stream {
+            Some(stream) => stream,
+            None => match bi_streams.next().await {
+                Some(Ok(stream)) => stream,
+                _ => return true,
+            },
+        };
+
+        // Whatever was sent before can't be meant for this connection
+        while self.commands.try_recv().is_ok() {}
+        *self.peer.lock().unwrap() = Some(connection.remote_address());
+
+        // Messages on the stream are prefixed by their length
+        let incoming = self.incoming.clone();
+        let mut stream_reader = tokio1::spawn(async move {
+            let mut len = [0; 2];
+            while recv.read_exact(&mut len).await.is_ok() {
+                let mut message = vec![0; u16::from_le_bytes(len) as usize];
+                if recv.read_exact(&mut message).await.is_err() || incoming.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let incoming = self.incoming.clone();
+        let datagram_reader = tokio1::spawn(async move {
+            while let Some(Ok(datagram)) = datagrams.next().await {
+                if incoming.send(datagram.to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let socket_alive = loop {
+            tokio1::select! {
+                command = self.commands.recv() => match command {
+                    Some(Command::Send { data, reliable: true }) => {
+                        let len = (data.len() as u16).to_le_bytes();
+                        if send.write_all(&len).await.is_err() || send.write_all(&data).await.is_err() {
+                            break true;
+                        }
+                    }
+                    Some(Command::Send { data, reliable: false }) => {
+                        // Dropped if it can't be sent, like UDP would
+                        let _ = connection.send_datagram(data.into());
+                    }
+                    Some(Command::Close) => {
+                        connection.close(0u32.into(), b"Goodbye");
+                        break true;
+                    }
+                    None => {
+                        connection.close(0u32.into(), b"Goodbye");
+                        break false;
+                    }
+                },
+                _ = &mut stream_reader => break true,
+            }
+        };
+
+        *self.peer.lock().unwrap() = None;
+        stream_reader.abort();
+        datagram_reader.abort();
+        socket_alive
+    }
+}
+
+fn new_runtime() -> io::Result<tokio1::runtime::Runtime> {
+    tokio1::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+}
+
+fn other<E: Into<Box<dyn Error + Send + Sync>>>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err)
+}
+
+/// The server uses a self signed certificate that can't be verified.
+struct SkipServerVerification;
+
+impl rustls::client::ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self, _end_entity: &rustls::Certificate, _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName, _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8], _now: std::time::SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
@@ -0,0 +1,64 @@
+/// Follows the sequence numbers of received packets to detect losses and reordering.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SequenceTracker {
+    next: Option<u32>,
+    received: u64,
+    lost: u64,
+    out_of_order: u64,
+}
+
+impl SequenceTracker {
+    /// Forget everything, for when the peer starts over.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn record(&mut self, sequence: u32) {
+        self.received += 1;
+
+        let next = match self.next {
+            Some(next) => next,
+            None => {
+                self.next = Some(sequence.wrapping_add(1));
+                return;
+            }
+        };
+
+        // Interpret the difference as signed to survive wrapping
+        let diff = sequence.wrapping_sub(next) as i32;
+        if diff >= 0 {
+            // Everything in between is missing, for now
+            self.lost += diff as u64;
+            self.next = Some(sequence.wrapping_add(1));
+        } else {
+            // It was already counted as lost but it is only late
+            self.out_of_order += 1;
+            self.lost = self.lost.saturating_sub(1);
+        }
+    }
+
+    #[inline]
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    #[inline]
+    pub fn lost(&self) -> u64 {
+        self.lost
+    }
+
+    #[inline]
+    pub fn out_of_order(&self) -> u64 {
+        self.out_of_order
+    }
+
+    /// Ratio of packets lost, between 0 and 1.
+    pub fn loss_ratio(&self) -> f64 {
+        let total = self.received + self.lost;
+        if total == 0 {
+            0.0
+        } else {
+            self.lost as f64 / total as f64
+        }
+    }
+}
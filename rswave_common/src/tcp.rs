@@ -0,0 +1,293 @@
+//! TCP transport for networks that drop UDP, each message is prefixed by its length.
+//! The streams are always non blocking underneath, blocking is emulated by polling them.
+
+use crate::transport::Transport;
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+/// How long to sleep between two polls when blocking.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+/// How long to wait for a server, the remote is stuck in the meantime.
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+/// Bytes waiting to be sent at most, a peer this far behind isn't reading anymore.
+const MAX_UNSENT: usize = 64 * 1024;
+
+struct Connection {
+    stream: TcpStream,
+    peer: SocketAddr,
+    /// What was read of the next messages.
+    buffer: Vec<u8>,
+    /// The end of the messages the socket didn't take yet.
+    unsent: Vec<u8>,
+}
+
+impl Connection {
+    fn new(stream: TcpStream, peer: SocketAddr) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        // The messages are tiny and must go out right away
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            peer,
+            buffer: Vec::new(),
+            unsent: Vec::new(),
+        })
+    }
+
+    fn connect(address: &str) -> io::Result<Self> {
+        let mut last_err = io::Error::from(io::ErrorKind::AddrNotAvailable);
+        for peer in address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&peer, CONNECT_TIMEOUT) {
+                Ok(stream) => return Self::new(stream, peer),
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+
+    fn send(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() > u16::MAX as usize {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+
+        if self.unsent.len() + buf.len() + 2 > MAX_UNSENT {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.unsent
+            .extend_from_slice(&(buf.len() as u16).to_le_bytes());
+        self.unsent.extend_from_slice(buf);
+        self.flush()?;
+        Ok(buf.len())
+    }
+
+    /// Write what the socket takes, the rest is kept for later so the messages are never cut.
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.unsent.is_empty() {
+            match self.stream.write(&self.unsent) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(len) => {
+                    self.unsent.drain(..len);
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// The next complete message, if it arrived.
+    fn poll(&mut self) -> io::Result<Option<Vec<u8>>> {
+        self.flush()?;
+        loop {
+            if self.buffer.len() >= 2 {
+                let len = u16::from_le_bytes([self.buffer[0], self.buffer[1]]) as usize;
+                if self.buffer.len() >= len + 2 {
+                    let message = self.buffer[2..len + 2].to_vec();
+                    self.buffer.drain(..len + 2);
+                    return Ok(Some(message));
+                }
+            }
+
+            let mut chunk = [0; 512];
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(io::ErrorKind::ConnectionAborted.into()),
+                Ok(len) => self.buffer.extend_from_slice(&chunk[..len]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+enum Role {
+    /// Connected again on the next send once the connection is lost.
+    Client {
+        address: String,
+        connection: Option<Connection>,
+    },
+    Server {
+        listener: TcpListener,
+        connections: Vec<Connection>,
+    },
+}
+
+pub struct TcpTransport {
+    role: Role,
+    /// Message received while waiting for one, handed out by the next `recv`.
+    peeked: Option<(Vec<u8>, SocketAddr)>,
+    nonblocking: bool,
+    read_timeout: Option<Duration>,
+}
+
+impl TcpTransport {
+    /// Accept connections on `port`, from any number of remotes.
+    pub fn server(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(SocketAddr::new([0, 0, 0, 0].into(), port))?;
+        listener.set_nonblocking(true)?;
+
+        Ok(Self::new(Role::Server {
+            listener,
+            connections: Vec::new(),
+        }))
+    }
+
+    /// Connects on the first send, so a server that is down doesn't stop the others.
+    pub fn client(address: &str) -> io::Result<Self> {
+        Ok(Self::new(Role::Client {
+            address: address.to_string(),
+            connection: None,
+        }))
+    }
+
+    fn new(role: Role) -> Self {
+        Self {
+            role,
+            peeked: None,
+            nonblocking: false,
+            read_timeout: None,
+        }
+    }
+
+    /// Look once for a message, without blocking.
+    fn poll(&mut self) -> io::Result<Option<(Vec<u8>, SocketAddr)>> {
+        if let Some(message) = self.peeked.take() {
+            return Ok(Some(message));
+        }
+
+        match &mut self.role {
+            Role::Client { connection, .. } => {
+                let res = match connection.as_mut() {
+                    Some(alive) => alive
+                        .poll()
+                        .map(|message| message.map(|message| (message, alive.peer))),
+                    None => Err(io::ErrorKind::NotConnected.into()),
+                };
+                if res.is_err() {
+                    connection.take();
+                }
+                res
+            }
+            Role::Server {
+                listener,
+                connections,
+            } => {
+                loop {
+                    match listener.accept() {
+                        Ok((stream, peer)) => connections.push(Connection::new(stream, peer)?),
+                        Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                        Err(err) => return Err(err),
+                    }
+                }
+
+                let mut i = 0;
+                while i < connections.len() {
+                    match connections[i].poll() {
+                        Ok(Some(message)) => return Ok(Some((message, connections[i].peer))),
+                        Ok(None) => i += 1,
+                        // The remote left
+                        Err(_) => {
+                            connections.remove(i);
+                        }
+                    }
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    /// Wait for a message like a std socket would.
+    fn recv_message(&mut self) -> io::Result<(Vec<u8>, SocketAddr)> {
+        let deadline = self.read_timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            if let Some(message) = self.poll()? {
+                return Ok(message);
+            }
+
+            if self.nonblocking {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send(&mut self, buf: &[u8], _reliable: bool) -> io::Result<usize> {
+        match &mut self.role {
+            Role::Client {
+                address,
+                connection,
+            } => {
+                if connection.is_none() {
+                    *connection = Some(Connection::connect(address)?);
+                }
+                let res = connection.as_mut().unwrap().send(buf);
+                if res.is_err() {
+                    connection.take();
+                }
+                res
+            }
+            Role::Server { .. } => Err(io::ErrorKind::NotConnected.into()),
+        }
+    }
+
+    fn send_to(&mut self, buf: &[u8], peer: SocketAddr, reliable: bool) -> io::Result<usize> {
+        match &mut self.role {
+            Role::Client { .. } => self.send(buf, reliable),
+            Role::Server { connections, .. } => connections
+                .iter_mut()
+                .find(|connection| connection.peer == peer)
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?
+                .send(buf),
+        }
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.recv_from(buf).map(|(len, _)| len)
+    }
+
+    fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let (message, peer) = self.recv_message()?;
+        let len = message.len().min(buf.len());
+        buf[..len].copy_from_slice(&message[..len]);
+        Ok((len, peer))
+    }
+
+    fn forget(&mut self, peer: SocketAddr) {
+        if let Role::Server { connections, .. } = &mut self.role {
+            connections.retain(|connection| connection.peer != peer);
+        }
+    }
+
+    fn wait_readable(&mut self, timeout: Duration) -> io::Result<bool> {
+        self.nonblocking = true;
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(message) = self.poll()? {
+                self.peeked = Some(message);
+                return Ok(true);
+            }
+
+            if Instant::now() >= deadline {
+                return Ok(false);
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        self.nonblocking = nonblocking;
+        Ok(())
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.read_timeout = timeout;
+        Ok(())
+    }
+}
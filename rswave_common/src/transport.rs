@@ -0,0 +1,234 @@
+#[cfg(feature = "transport_quic")]
+use crate::quic::QuicSocket;
+use crate::tcp::TcpTransport;
+use std::{
+    io,
+    net::{SocketAddr, UdpSocket},
+    str::FromStr,
+    time::Duration,
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TransportKind {
+    Udp,
+    Tcp,
+    #[cfg(feature = "transport_quic")]
+    Quic,
+}
+
+impl FromStr for TransportKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "udp" => Ok(Self::Udp),
+            "tcp" => Ok(Self::Tcp),
+            #[cfg(feature = "transport_quic")]
+            "quic" => Ok(Self::Quic),
+            _ => Err("Unknown transport !".into()),
+        }
+    }
+}
+
+/// Moves the serialized packets between the remote and the server, one message at a time
+/// like datagrams. Errors and blocking behave like with a std socket.
+pub trait Transport {
+    /// Control packets should be `reliable`, it is only a hint for transports that support it.
+    fn send(&mut self, buf: &[u8], reliable: bool) -> io::Result<usize>;
+
+    /// Send to a specific peer, for the server.
+    fn send_to(&mut self, buf: &[u8], peer: SocketAddr, reliable: bool) -> io::Result<usize>;
+
+    /// The message is truncated if the buffer is too small.
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Receive from anyone, for the server.
+    fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+
+    /// The server is done with `peer`, close the connection if there is one.
+    fn forget(&mut self, _peer: SocketAddr) {}
+
+    /// Block until something can be received or the `timeout` expires, like a `poll` on
+    /// a single socket. Returns whether something can be received.
+    /// Meant for event loops, the transport is left in non blocking mode.
+    fn wait_readable(&mut self, timeout: Duration) -> io::Result<bool>;
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()>;
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Transport for UdpSocket {
+    fn send(&mut self, buf: &[u8], _reliable: bool) -> io::Result<usize> {
+        UdpSocket::send(self, buf)
+    }
+
+    fn send_to(&mut self, buf: &[u8], peer: SocketAddr, _reliable: bool) -> io::Result<usize> {
+        UdpSocket::send_to(self, buf, peer)
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        UdpSocket::recv(self, buf)
+    }
+
+    fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        UdpSocket::recv_from(self, buf)
+    }
+
+    fn wait_readable(&mut self, timeout: Duration) -> io::Result<bool> {
+        // Peeking in blocking mode is the only way to wait with the std socket
+        UdpSocket::set_nonblocking(self, false)?;
+        let previous_timeout = self.read_timeout()?;
+        // A zero timeout is refused
+        UdpSocket::set_read_timeout(self, Some(timeout.max(Duration::from_millis(1))))?;
+
+        let res = match self.peek_from(&mut [0; 1]) {
+            Ok(_) => Ok(true),
+            Err(err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut =>
+            {
+                Ok(false)
+            }
+            // Some platforms complain that the datagram doesn't fit, it is still there
+            Err(_) => Ok(true),
+        };
+
+        UdpSocket::set_read_timeout(self, previous_timeout)?;
+        UdpSocket::set_nonblocking(self, true)?;
+        res
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        UdpSocket::set_nonblocking(self, nonblocking)
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        UdpSocket::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(feature = "transport_quic")]
+impl Transport for QuicSocket {
+    fn send(&mut self, buf: &[u8], reliable: bool) -> io::Result<usize> {
+        QuicSocket::send(self, buf, reliable)
+    }
+
+    /// There is only one peer at a time.
+    fn send_to(&mut self, buf: &[u8], _peer: SocketAddr, reliable: bool) -> io::Result<usize> {
+        QuicSocket::send(self, buf, reliable)
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        QuicSocket::recv(self, buf)
+    }
+
+    fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let len = QuicSocket::recv(self, buf)?;
+        let peer = self
+            .peer()
+            .unwrap_or_else(|| SocketAddr::new([0, 0, 0, 0].into(), 0));
+        Ok((len, peer))
+    }
+
+    fn forget(&mut self, peer: SocketAddr) {
+        if self.peer() == Some(peer) {
+            self.close();
+        }
+    }
+
+    fn wait_readable(&mut self, timeout: Duration) -> io::Result<bool> {
+        QuicSocket::set_nonblocking(self, true);
+        QuicSocket::wait_readable(self, timeout)
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        QuicSocket::set_nonblocking(self, nonblocking);
+        Ok(())
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        QuicSocket::set_read_timeout(self, timeout);
+        Ok(())
+    }
+}
+
+/// The transport chosen at runtime, UDP unless configured otherwise.
+pub enum TransportSocket {
+    Udp(UdpSocket),
+    Tcp(TcpTransport),
+    #[cfg(feature = "transport_quic")]
+    Quic(QuicSocket),
+}
+
+impl TransportSocket {
+    /// Listen on `port` for the server.
+    pub fn server(kind: TransportKind, port: u16) -> io::Result<Self> {
+        match kind {
+            TransportKind::Udp => Ok(Self::Udp(UdpSocket::bind(SocketAddr::new(
+                [0, 0, 0, 0].into(),
+                port,
+            ))?)),
+            TransportKind::Tcp => Ok(Self::Tcp(TcpTransport::server(port)?)),
+            #[cfg(feature = "transport_quic")]
+            TransportKind::Quic => Ok(Self::Quic(QuicSocket::server(port)?)),
+        }
+    }
+
+    /// Connect to the server at `address` for the remote.
+    pub fn client(kind: TransportKind, address: &str) -> io::Result<Self> {
+        match kind {
+            TransportKind::Udp => {
+                let socket = UdpSocket::bind("0.0.0.0:0")?;
+                socket.connect(address)?;
+                Ok(Self::Udp(socket))
+            }
+            TransportKind::Tcp => Ok(Self::Tcp(TcpTransport::client(address)?)),
+            #[cfg(feature = "transport_quic")]
+            TransportKind::Quic => Ok(Self::Quic(QuicSocket::client(address)?)),
+        }
+    }
+
+    fn inner(&mut self) -> &mut dyn Transport {
+        match self {
+            Self::Udp(socket) => socket,
+            Self::Tcp(socket) => socket,
+            #[cfg(feature = "transport_quic")]
+            Self::Quic(socket) => socket,
+        }
+    }
+}
+
+impl Transport for TransportSocket {
+    fn send(&mut self, buf: &[u8], reliable: bool) -> io::Result<usize> {
+        self.inner().send(buf, reliable)
+    }
+
+    fn send_to(&mut self, buf: &[u8], peer: SocketAddr, reliable: bool) -> io::Result<usize> {
+        self.inner().send_to(buf, peer, reliable)
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner().recv(buf)
+    }
+
+    fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.inner().recv_from(buf)
+    }
+
+    fn forget(&mut self, peer: SocketAddr) {
+        self.inner().forget(peer)
+    }
+
+    fn wait_readable(&mut self, timeout: Duration) -> io::Result<bool> {
+        self.inner().wait_readable(timeout)
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        self.inner().set_nonblocking(nonblocking)
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner().set_read_timeout(timeout)
+    }
+}
@@ -1,44 +1,235 @@
+#[cfg(feature = "source_file")]
+use crate::file::FileSource;
+#[cfg(feature = "source_jack")]
+use crate::jack::JackSource;
+#[cfg(feature = "midi")]
+use crate::midi::MidiTrigger;
+#[cfg(feature = "source_mpris")]
+use crate::mpris::MprisTracker;
+#[cfg(feature = "source_pipewire")]
+use crate::pipewire::PipeWireSource;
+#[cfg(feature = "source_librespot")]
+use crate::spotify_connect::SpotifyConnect;
 use crate::{
-    audio::{AudioProcessor, COMPRESSION_CONST},
-    net::NetHandler,
-    spotify::SpotifyTracker,
-    Opt,
+    audio::{AudioProcessor, SAMPLE_RATE},
+    context::ContextPresets,
+    from_i16,
+    genre::GenreRunners,
+    mpd::{MpdFifo, MpdTracker},
+    net::{Analysis, ConnectionState, NetHandler},
+    preview::LedPreview,
+    pulse,
+    record::Recorder,
+    resample::{ChannelSelection, SampleSink},
+    spotify::{PlayerSource, SpotifyAuth, SpotifyTracker},
+    tap::TapTempo,
+    tempo::TempoTracker,
+    to_f64,
+    zone::ZoneTracker,
+    Opt, Sample, TrackSource,
 };
 use anyhow::{anyhow, Result};
 use cpal::{
     traits::{DeviceTrait, HostTrait, StreamTrait},
-    SampleFormat, SampleRate, Stream,
+    SampleFormat, Stream,
+};
+use crossterm::{
+    event::{self, Event, KeyCode, KeyModifiers},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use parking_lot::Mutex;
 use ringbuf::{Consumer, RingBuffer};
-use rswave_common::packets::DataMode;
+use rswave_common::packets::{BeatDivision, Command, DataMode};
 use std::{
     io::{stdout, Stdout},
     sync::Arc,
     time::{Duration, Instant},
 };
-use structopt::StructOpt;
+use tracing::{error, info, warn};
 use tui::{
     backend::CrosstermBackend,
     layout::{Alignment, Constraint, Direction, Layout},
     style::{Color, Modifier, Style},
     symbols::Marker,
     text::{Span, Spans},
-    widgets::{Axis, Block, Borders, Chart, Dataset, Gauge, GraphType, Paragraph},
+    widgets::{Axis, BarChart, Block, Borders, Chart, Dataset, Gauge, GraphType, Paragraph},
     Terminal,
 };
 
+pub(crate) enum AudioSource {
+    Device(cpal::Device),
+    /// An output device recorded through WASAPI loopback.
+    #[cfg(windows)]
+    Loopback(cpal::Device),
+    MpdFifo(MpdFifo),
+    #[cfg(feature = "source_librespot")]
+    SpotifyConnect(SpotifyConnect),
+    #[cfg(feature = "source_pipewire")]
+    PipeWire(PipeWireSource),
+    #[cfg(feature = "source_jack")]
+    Jack(JackSource),
+    #[cfg(feature = "source_file")]
+    File(FileSource),
+}
+
 pub(crate) struct AudioHolder {
-    device: cpal::Device,
+    source: AudioSource,
     stream: Option<Stream>,
-    consumer: Option<Consumer<f64>>,
-    pub(crate) processor: AudioProcessor,
+    consumer: Option<Consumer<Sample>>,
+    pub(crate) processor: AudioProcessor<Sample>,
+}
+
+impl AudioSource {
+    /// Start collecting samples from this source.
+    /// There is a stream to play only when reading from a device.
+    pub(crate) fn open_stream(
+        &self, sample_size: usize, channels: ChannelSelection,
+    ) -> Result<(Option<Stream>, Consumer<Sample>)> {
+        // Ring buffer 4 times as large as the sample size, so we can store a total of 2 frames of 2 channels
+        let (prod, cons) = RingBuffer::new(sample_size * 4).split();
+
+        let (device, config) = match self {
+            AudioSource::Device(device) => (device, device.default_input_config()?),
+            // Input streams of output devices are loopback streams
+            #[cfg(windows)]
+            AudioSource::Loopback(device) => (device, device.default_output_config()?),
+            AudioSource::MpdFifo(fifo) => {
+                fifo.attach(prod);
+                return Ok((None, cons));
+            }
+            #[cfg(feature = "source_librespot")]
+            AudioSource::SpotifyConnect(connect) => {
+                // Samples are pushed directly from librespot, there is no stream to manage
+                connect.attach(prod);
+                return Ok((None, cons));
+            }
+            #[cfg(feature = "source_pipewire")]
+            AudioSource::PipeWire(pipewire) => {
+                pipewire.attach(prod);
+                return Ok((None, cons));
+            }
+            #[cfg(feature = "source_jack")]
+            AudioSource::Jack(jack) => {
+                jack.attach(prod);
+                return Ok((None, cons));
+            }
+            #[cfg(feature = "source_file")]
+            AudioSource::File(file) => {
+                file.attach(prod);
+                return Ok((None, cons));
+            }
+        };
+
+        let mut sink = SampleSink::new(
+            prod,
+            config.sample_rate().0,
+            config.channels() as usize,
+            channels,
+        )?;
+
+        let reader = match config.sample_format() {
+            SampleFormat::I16 => device.build_input_stream(
+                &config.into(),
+                move |data: &[i16], _| {
+                    sink.push(&mut data.iter().copied().map(from_i16));
+                },
+                |e| error!("CPAL Error: {:?}", e),
+            ),
+            SampleFormat::U16 => device.build_input_stream(
+                &config.into(),
+                move |data: &[u16], _| {
+                    sink.push(
+                        &mut data
+                            .iter()
+                            .copied()
+                            .map(|sample| from_i16((sample ^ 0x8000) as i16)),
+                    );
+                },
+                |e| error!("CPAL Error: {:?}", e),
+            ),
+            SampleFormat::F32 => device.build_input_stream(
+                &config.into(),
+                move |data: &[f32], _| {
+                    sink.push(&mut data.iter().copied().map(|sample| sample as Sample));
+                },
+                |e| error!("CPAL Error: {:?}", e),
+            ),
+        }?;
+
+        Ok((Some(reader), cons))
+    }
+}
+
+/// Factor applied to the compression by the `+` and `-` keys.
+const COMPRESSION_STEP: f64 = 1.25;
+/// Added to the onset threshold by the `t` and `T` keys.
+const ONSET_THRESHOLD_STEP: f64 = 0.1;
+/// Added to the brightness of the servers by the `b` and `B` keys.
+const BRIGHTNESS_STEP: u8 = 32;
+/// Factor applied to the standby speed of the servers by the `s` and `S` keys.
+const STANDBY_SPEED_STEP: f32 = 1.5;
+/// Colors sent by the keys `1` to `9` in the trigger mode.
+const TRIGGER_COLORS: [[u8; 3]; 9] = [
+    [255, 0, 0],
+    [255, 96, 0],
+    [255, 200, 0],
+    [0, 255, 0],
+    [0, 255, 200],
+    [0, 64, 255],
+    [128, 0, 255],
+    [255, 0, 160],
+    [255, 255, 255],
+];
+/// Strobe of each press of `s` in the trigger mode in ms, holding the key keeps it going.
+const TRIGGER_STROBE_MS: u16 = 250;
+/// Number of bars of the spectrum bar chart.
+const SPECTRUM_BARS: usize = 16;
+/// Range of the spectrum bar chart in Hz, split in bands of equal width on a log scale.
+const SPECTRUM_BARS_FREQUENCIES: (f64, f64) = (40.0, 16000.0);
+
+/// Settings of the servers changed from the TUI.
+struct ServerCommands {
+    brightness: u8,
+    runner: u8,
+    standby_speed: f32,
+    beat_division: BeatDivision,
+}
+
+/// Same defaults as the server.
+impl Default for ServerCommands {
+    fn default() -> Self {
+        Self {
+            brightness: u8::MAX,
+            runner: 0,
+            standby_speed: 1.0,
+            beat_division: BeatDivision::Normal,
+        }
+    }
 }
 
 pub struct App {
     pub(crate) opt: Opt,
     pub(crate) audio: AudioHolder,
     tui: Option<Terminal<CrosstermBackend<Stdout>>>,
+    /// The capture is paused from the TUI.
+    paused: bool,
+    /// Some parameters were changed from the TUI.
+    tuned: bool,
+    /// The keys trigger beats, colors and strobes on the servers.
+    trigger: bool,
+    preview: LedPreview,
+    /// What was last asked to the servers, starting from their defaults.
+    commands: ServerCommands,
+    recorder: Option<Recorder>,
+    zones: ZoneTracker,
+    context_presets: ContextPresets,
+    genre_runners: GenreRunners,
+    /// Finds the beats spotify doesn't know, if enabled.
+    tempo: Option<TempoTracker>,
+    tap: TapTempo,
+    #[cfg(feature = "midi")]
+    midi: Option<MidiTrigger>,
 
     pub(crate) spotify: Option<SpotifyTracker>,
     pub(crate) net: Option<NetHandler>,
@@ -50,73 +241,77 @@ pub struct App {
 }
 
 impl App {
-    pub async fn new() -> Result<Arc<Mutex<Self>>> {
-        let opt: Opt = Opt::from_args();
-
-        // Check options
-        match (opt.spotify_id.as_ref(), opt.spotify_secret.as_ref()) {
-            (Some(_), Some(_)) | (None, None) => {}
-            _ => {
-                return Err(anyhow!(
-                    "You must provide --spotify-id and --spotify-secret or neither !"
-                ))
-            }
-        }
+    pub async fn new(opt: Opt) -> Result<Arc<Mutex<Self>>> {
+        let zones = ZoneTracker::new(opt.zone.clone())?;
 
         // Init audio
-        let audio_device = {
-            let host = cpal::default_host();
-            if let Some(hint) = opt.device_hint.as_ref() {
-                host.input_devices()?
-                    .find(|device| device.name().map(|n| n.contains(hint)).unwrap_or(false))
-                    .ok_or(anyhow!("Can't find a device satisfying the hint"))?
-            } else {
-                host.default_input_device()
-                    .ok_or(anyhow!("No default device found"))?
-            }
-        };
+        let audio_source = Self::make_audio_source(&opt)?;
 
-        // Init spotify
-        let spotify = if let (Some(id), Some(secret)) =
-            (opt.spotify_id.as_ref(), opt.spotify_secret.as_ref())
-        {
-            Some(SpotifyTracker::new(id, secret, opt.spotify_auth_fresh).await?)
-        } else {
-            None
-        };
-
-        // Init net
-        let net = if let Some(addr) = opt.address.as_ref() {
-            let mut net = NetHandler::new(addr)?;
-            net.handshake(if spotify.is_some() {
-                DataMode::NoveltyBeats
-            } else {
-                DataMode::Novelty
-            })?;
-            Some(net)
-        } else {
-            None
-        };
+        // Init spotify and net
+        let spotify = Self::make_spotify(&opt).await?;
+        let net = Self::make_net(&opt, spotify.is_some())?;
 
         // Init TUI
         let tui = if opt.no_tui {
             None
         } else {
+            // Keys are read one by one and the shell is left untouched
+            enable_raw_mode()?;
+            execute!(stdout(), EnterAlternateScreen)?;
             let mut tui = Terminal::new(CrosstermBackend::new(stdout()))?;
             // Clear terminal just before creating the app
             tui.clear()?;
+            tui.hide_cursor()?;
             Some(tui)
         };
 
+        let recorder = opt.record.as_deref().map(Recorder::new).transpose()?;
+        let context_presets = ContextPresets::new(opt.context_preset.clone());
+        let genre_runners = GenreRunners::new(opt.genre_runner.clone(), opt.genre_effects);
+
+        let mut processor = AudioProcessor::<Sample>::default();
+        processor.set_compression(opt.spectrum_compression);
+        processor.set_onset_threshold(opt.onset_threshold);
+        processor.set_peak_half_life(Some(opt.peak_half_life));
+        processor.set_silence_duration(Some(opt.silence_standby));
+        processor.set_filters(opt.highpass, opt.lowpass);
+        processor.set_weighting(opt.weighting);
+        processor.set_mel_bands(opt.mel_bands);
+        processor.set_novelty_weights(opt.novelty_weight.clone());
+        let tempo = if spotify.is_some() || opt.tempo_tracking {
+            Some(TempoTracker::new(processor.sample_size()))
+        } else {
+            None
+        };
+        #[cfg(feature = "midi")]
+        let midi = opt
+            .midi
+            .as_deref()
+            .map(|name| MidiTrigger::open(name, opt.midi_brightness_cc))
+            .transpose()?;
+
         Ok(Arc::new(Mutex::new(Self {
             opt,
             audio: AudioHolder {
-                device: audio_device,
+                source: audio_source,
                 stream: None,
                 consumer: None,
-                processor: Default::default(),
+                processor,
             },
             tui,
+            paused: false,
+            tuned: false,
+            trigger: false,
+            preview: LedPreview::default(),
+            commands: ServerCommands::default(),
+            recorder,
+            zones,
+            context_presets,
+            genre_runners,
+            tempo,
+            tap: TapTempo::new(),
+            #[cfg(feature = "midi")]
+            midi,
             spotify,
             net,
             run_time: Duration::from_millis(0),
@@ -126,6 +321,142 @@ impl App {
         })))
     }
 
+    pub(crate) async fn make_spotify(opt: &Opt) -> Result<Option<SpotifyTracker>> {
+        match (opt.spotify_id.as_ref(), opt.spotify_secret.as_ref()) {
+            (Some(_), Some(_)) | (None, None) => {}
+            _ => {
+                return Err(anyhow!(
+                    "You must provide --spotify-id and --spotify-secret or neither !"
+                ))
+            }
+        }
+
+        let spotify = if let (Some(id), Some(secret)) =
+            (opt.spotify_id.as_ref(), opt.spotify_secret.as_ref())
+        {
+            let player = match opt.source {
+                TrackSource::Spotify => None,
+                #[cfg(feature = "source_mpris")]
+                TrackSource::Mpris => Some(PlayerSource::Mpris(MprisTracker::new(
+                    opt.player_hint.clone(),
+                )?)),
+                TrackSource::Mpd => {
+                    Some(PlayerSource::Mpd(MpdTracker::new(opt.mpd_address.clone())?))
+                }
+            };
+            let mut spotify =
+                SpotifyTracker::new(id, secret, &SpotifyAuth::from(opt), player).await?;
+            if opt.genre_effects || !opt.genre_runner.is_empty() {
+                spotify.enable_genres();
+            }
+            spotify.set_palette_source(opt.palette_source);
+            Some(spotify)
+        } else if opt.source != TrackSource::Spotify {
+            return Err(anyhow!(
+                "The track analysis needs --spotify-id and --spotify-secret !"
+            ));
+        } else {
+            None
+        };
+
+        Ok(spotify)
+    }
+
+    /// The beats are only sent `with_spotify`, with the tempo tracking or MIDI.
+    pub(crate) fn make_net(opt: &Opt, with_spotify: bool) -> Result<Option<NetHandler>> {
+        if opt.address.is_empty() {
+            return Ok(None);
+        }
+
+        let mut net = NetHandler::new(&opt.address, opt.transport, opt.psk)?;
+        // A single server has nothing to be in sync with
+        if opt.address.len() > 1 {
+            net.set_sync_delay(Duration::from_millis(opt.sync_delay));
+        }
+        net.set_segment_blend(opt.segment_blend);
+        net.handshake(match (with_spotify || opt.finds_beats(), opt.beats_only) {
+            _ if opt.bands => DataMode::Bands,
+            (true, true) => DataMode::BeatsOnly,
+            (true, false) => DataMode::NoveltyBeats,
+            (false, _) => DataMode::Novelty,
+        })?;
+        Ok(Some(net))
+    }
+
+    pub(crate) fn make_audio_source(opt: &Opt) -> Result<AudioSource> {
+        #[cfg(feature = "source_librespot")]
+        if let Some(name) = opt.librespot_name.as_ref() {
+            let (username, password) = match (
+                opt.librespot_username.as_ref(),
+                opt.librespot_password.as_ref(),
+            ) {
+                (Some(username), Some(password)) => (username, password),
+                _ => {
+                    return Err(anyhow!(
+                        "You must provide --librespot-username and --librespot-password !"
+                    ))
+                }
+            };
+
+            return Ok(AudioSource::SpotifyConnect(SpotifyConnect::new(
+                name.clone(),
+                username.clone(),
+                password.clone(),
+            )?));
+        }
+
+        #[cfg(feature = "source_file")]
+        if let Some(path) = opt.input_file.as_ref() {
+            return Ok(AudioSource::File(FileSource::new(
+                path.clone(),
+                opt.channels,
+            )?));
+        }
+
+        #[cfg(feature = "source_jack")]
+        if let Some(name) = opt.jack_name.as_ref() {
+            return Ok(AudioSource::Jack(JackSource::new(name)?));
+        }
+
+        #[cfg(feature = "source_pipewire")]
+        if opt.pipewire {
+            return Ok(AudioSource::PipeWire(PipeWireSource::new(
+                opt.pipewire_target.clone(),
+            )?));
+        }
+
+        if let Some(path) = opt.mpd_fifo.as_ref() {
+            return Ok(AudioSource::MpdFifo(MpdFifo::new(path.clone())?));
+        }
+
+        let host = cpal::default_host();
+        #[cfg(windows)]
+        if opt.capture_output {
+            let device = host
+                .default_output_device()
+                .ok_or(anyhow!("No default output device found"))?;
+            return Ok(AudioSource::Loopback(device));
+        }
+
+        let device = if opt.capture_output {
+            let monitor = pulse::default_monitor()?;
+            info!("Capturing the output through {}", monitor);
+            // Read by the PulseAudio plugin when the device is opened
+            std::env::set_var(pulse::SOURCE_ENV, monitor);
+            host.input_devices()?
+                .find(|device| device.name().map_or(false, |n| n == pulse::PULSE_DEVICE))
+                .ok_or(anyhow!("Can't find the PulseAudio device"))?
+        } else if let Some(hint) = opt.device_hint.as_ref() {
+            host.input_devices()?
+                .find(|device| device.name().map(|n| n.contains(hint)).unwrap_or(false))
+                .ok_or(anyhow!("Can't find a device satisfying the hint"))?
+        } else {
+            host.default_input_device()
+                .ok_or(anyhow!("No default device found"))?
+        };
+        Ok(AudioSource::Device(device))
+    }
+
     pub fn recreate_audio_stream(&mut self) -> Result<()> {
         // Drop previous stuff
         {
@@ -133,74 +464,250 @@ impl App {
             self.audio.consumer.take();
         }
 
-        let config = self.audio.device.default_input_config()?;
-        assert_eq!(
-            config.sample_rate(),
-            SampleRate(44100),
-            "Only 44100Hz sample rate supported !"
-        );
-        assert_eq!(config.channels(), 2, "Only stereo is supported !");
-
-        // Ring buffer 4 times as large as the sample size, so we can store a total of 2 frames of 2 channels
-        let (mut prod, cons) = RingBuffer::new(self.audio.processor.sample_size() * 4).split();
-
-        let reader = match config.sample_format() {
-            SampleFormat::I16 => self.audio.device.build_input_stream(
-                &config.into(),
-                move |data: &[i16], _| {
-                    prod.push_iter(&mut data.iter().copied().map(|sample| sample as f64));
-                },
-                |e| eprintln!("CPAL Error: {:?}", e),
-            ),
-            SampleFormat::U16 => self.audio.device.build_input_stream(
-                &config.into(),
-                move |data: &[u16], _| {
-                    prod.push_iter(
-                        &mut data
-                            .iter()
-                            .copied()
-                            .map(|sample| sample as f64 / u16::max_value() as f64 - 0.5),
-                    );
-                },
-                |e| eprintln!("CPAL Error: {:?}", e),
-            ),
-            SampleFormat::F32 => self.audio.device.build_input_stream(
-                &config.into(),
-                move |data: &[f32], _| {
-                    prod.push_iter(&mut data.iter().copied().map(|sample| sample as f64));
-                },
-                |e| eprintln!("CPAL Error: {:?}", e),
-            ),
-        }?;
-
-        self.audio.stream = Some(reader);
-        self.audio.consumer = Some(cons);
+        let (stream, consumer) = self
+            .audio
+            .source
+            .open_stream(self.audio.processor.sample_size(), self.opt.channels)?;
+        self.audio.stream = stream;
+        self.audio.consumer = Some(consumer);
 
         Ok(())
     }
 
     pub fn start_recording(&mut self) -> Result<()> {
-        if let None = self.audio.stream {
+        if let None = self.audio.consumer {
             self.recreate_audio_stream()?;
         }
 
-        self.audio.stream.as_ref().unwrap().play()?;
+        if let Some(stream) = self.audio.stream.as_ref() {
+            stream.play()?;
+        }
         Ok(())
     }
 }
 
 impl App {
     pub fn can_run(&self) -> bool {
-        self.audio.consumer.as_ref().map_or(false, |cons| {
-            cons.len() > self.audio.processor.sample_size() * 2
-        })
+        !self.paused
+            && self.audio.consumer.as_ref().map_or(false, |cons| {
+                cons.len() > self.audio.processor.sample_size() * 2
+            })
+    }
+
+    /// Handle the keys pressed since the last call, returns false when asked to quit.
+    pub fn handle_input(&mut self) -> Result<bool> {
+        if self.tui.is_none() {
+            return Ok(true);
+        }
+
+        while event::poll(Duration::from_millis(0))? {
+            let key = match event::read()? {
+                Event::Key(key) => key,
+                _ => continue,
+            };
+
+            match key.code {
+                // The raw mode doesn't turn it into a signal
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(false)
+                }
+                code if self.trigger => self.trigger_key(code),
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+                KeyCode::Char('l') => {
+                    self.trigger = true;
+                    info!("Trigger mode on");
+                }
+                KeyCode::Char('p') | KeyCode::Char(' ') => self.toggle_pause()?,
+                KeyCode::Char('a') => {
+                    self.opt.no_ack = !self.opt.no_ack;
+                    info!("ACK checks {}", if self.opt.no_ack { "off" } else { "on" });
+                }
+                KeyCode::Char('m') => self.switch_mode(),
+                KeyCode::Tab => self.tap(),
+                KeyCode::Backspace => {
+                    self.tap.forget();
+                    info!("Tapped tempo forgotten");
+                }
+                KeyCode::Char('+') => self.scale_compression(COMPRESSION_STEP),
+                KeyCode::Char('-') => self.scale_compression(1.0 / COMPRESSION_STEP),
+                KeyCode::Char('[') => self.resize_short_term_novelty(-1),
+                KeyCode::Char(']') => self.resize_short_term_novelty(1),
+                KeyCode::Char('t') => self.shift_onset_threshold(-ONSET_THRESHOLD_STEP),
+                KeyCode::Char('T') => self.shift_onset_threshold(ONSET_THRESHOLD_STEP),
+                KeyCode::Char('b') => {
+                    let brightness = self.commands.brightness.saturating_sub(BRIGHTNESS_STEP);
+                    self.send_command(Command::Brightness(brightness));
+                }
+                KeyCode::Char('B') => {
+                    let brightness = self.commands.brightness.saturating_add(BRIGHTNESS_STEP);
+                    self.send_command(Command::Brightness(brightness));
+                }
+                KeyCode::Char('n') => {
+                    self.send_command(Command::Runner(self.commands.runner.wrapping_add(1)))
+                }
+                KeyCode::Char('s') => self.send_command(Command::StandbySpeed(
+                    self.commands.standby_speed / STANDBY_SPEED_STEP,
+                )),
+                KeyCode::Char('S') => self.send_command(Command::StandbySpeed(
+                    self.commands.standby_speed * STANDBY_SPEED_STEP,
+                )),
+                KeyCode::Char('x') => self.send_command(Command::Reset),
+                KeyCode::Char('d') => {
+                    self.send_command(Command::BeatDivision(self.commands.beat_division.next()))
+                }
+                KeyCode::Char(digit @ '1'..='9') => {
+                    self.send_command(Command::Preset(digit as u8 - b'0'))
+                }
+                KeyCode::F(slot @ 1..=9) => self.send_command(Command::SavePreset(slot)),
+                _ => {}
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Like a tiny lighting console.
+    fn trigger_key(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::Char('l') | KeyCode::Esc => {
+                self.trigger = false;
+                info!("Trigger mode off");
+            }
+            KeyCode::Char(' ') | KeyCode::Enter => self.send_command(Command::Beat),
+            KeyCode::Char(digit @ '1'..='9') => self.send_command(Command::Color(
+                TRIGGER_COLORS[(digit as u8 - b'1') as usize],
+            )),
+            KeyCode::Char('s') => self.send_command(Command::Strobe(TRIGGER_STROBE_MS)),
+            _ => {}
+        }
+    }
+
+    fn toggle_pause(&mut self) -> Result<()> {
+        self.paused = !self.paused;
+        info!("Capture {}", if self.paused { "paused" } else { "resumed" });
+
+        if let Some(stream) = self.audio.stream.as_ref() {
+            if self.paused {
+                stream.pause()?;
+            } else {
+                stream.play()?;
+            }
+        }
+
+        // Forget what the other sources pushed in the meantime
+        if !self.paused {
+            if let Some(consumer) = self.audio.consumer.as_mut() {
+                let len = consumer.len();
+                consumer.discard(len);
+            }
+        }
+        Ok(())
+    }
+
+    /// The beats are only sent with the novelty in the NoveltyBeats mode, switch to it.
+    fn tap(&mut self) {
+        self.tap.tap(Instant::now());
+
+        let net = match self.net.as_mut() {
+            Some(net) => net,
+            None => return,
+        };
+        if net.mode() == Some(DataMode::Novelty) {
+            info!(
+                "Switching to {:?} for the tapped beats",
+                DataMode::NoveltyBeats
+            );
+            if let Err(err) = net.switch_mode(DataMode::NoveltyBeats) {
+                warn!("Failed to switch mode: {}", err);
+            }
+        }
+    }
+
+    /// Cycle between sending the novelty, the novelty and the beats, only the beats, or the
+    /// Bark bands.
+    fn switch_mode(&mut self) {
+        let net = match self.net.as_mut() {
+            Some(net) => net,
+            None => return,
+        };
+
+        let mode = match net.mode() {
+            Some(DataMode::Novelty) => DataMode::NoveltyBeats,
+            Some(DataMode::NoveltyBeats) => DataMode::BeatsOnly,
+            Some(DataMode::BeatsOnly) => DataMode::Bands,
+            _ => DataMode::Novelty,
+        };
+        info!("Switching to {:?}", mode);
+        // The servers that can't be reached are retried in the background
+        if let Err(err) = net.switch_mode(mode) {
+            warn!("Failed to switch mode: {}", err);
+        }
+    }
+
+    fn send_command(&mut self, command: Command) {
+        let net = match self.net.as_mut() {
+            Some(net) => net,
+            None => return,
+        };
+
+        info!("Sending {:?}", command);
+        match command {
+            Command::Brightness(brightness) => self.commands.brightness = brightness,
+            Command::Runner(index) => self.commands.runner = index,
+            Command::StandbySpeed(speed) => self.commands.standby_speed = speed,
+            Command::BeatDivision(division) => self.commands.beat_division = division,
+            Command::Reset
+            | Command::Preset(_)
+            | Command::SavePreset(_)
+            | Command::Beat
+            | Command::Color(_)
+            | Command::Strobe(_) => {}
+        }
+        net.send_command(command);
+    }
+
+    fn scale_compression(&mut self, factor: f64) {
+        let processor = &mut self.audio.processor;
+        processor.set_compression(processor.compression() * factor);
+        self.opt.spectrum_compression = processor.compression();
+        self.tuned = true;
+    }
+
+    fn resize_short_term_novelty(&mut self, delta: isize) {
+        let processor = &mut self.audio.processor;
+        let size = processor.short_term_novelty_size() as isize + delta;
+        processor.set_short_term_novelty_size(size.max(1) as usize);
+        self.opt.novelty_size_st = processor.short_term_novelty_size();
+        self.tuned = true;
+    }
+
+    fn shift_onset_threshold(&mut self, delta: f64) {
+        let processor = &mut self.audio.processor;
+        processor.set_onset_threshold((processor.onset_threshold() + delta).max(1.0));
+        self.opt.onset_threshold = processor.onset_threshold();
+        self.tuned = true;
+    }
+
+    /// The parameters changed from the TUI as arguments, to use them next time.
+    fn tuned_arguments(&self) -> String {
+        format!(
+            "--spectrum-compression {:.0} --novelty-size-st {} --onset-threshold {:.1}",
+            self.opt.spectrum_compression, self.opt.novelty_size_st, self.opt.onset_threshold
+        )
+    }
+
+    /// To call when there is nothing to run, so the server doesn't think we are gone.
+    pub fn keepalive(&mut self) {
+        if let Some(net) = self.net.as_mut() {
+            net.keepalive();
+        }
     }
 
     pub async fn run_once(&mut self) -> Result<()> {
         let start = Instant::now();
         self.spare_time = start.duration_since(self.last_run_end);
 
-        if let None = self.audio.stream {
+        if let None = self.audio.consumer {
             self.recreate_audio_stream()?;
         }
 
@@ -219,15 +726,44 @@ impl App {
         if let Some(spotify) = self.spotify.as_mut() {
             spotify.refresh_current_track().await;
             spotify.advance_beat();
+            spotify.advance_segment();
+
+            let track = spotify.current_track().map(|(track, _)| track);
+            let preset = self.context_presets.update(track);
+            let runner = self
+                .genre_runners
+                .update(track, spotify.genres())
+                .filter(|_| !self.context_presets.is_active());
+            if let Some(preset) = preset {
+                self.send_command(Command::Preset(preset));
+            }
+            if let Some(runner) = runner {
+                self.send_command(Command::Runner(runner));
+            }
+        }
+
+        let mut analysis = Analysis::new(&self.audio.processor, self.spotify.as_ref());
+        analysis.zones = self.zones.update(&self.audio.processor);
+        if let Some(tempo) = self.tempo.as_mut() {
+            tempo.fill_in(&mut analysis, self.spotify.as_ref());
+        }
+        self.tap.fill_in(&mut analysis, Instant::now());
+        #[cfg(feature = "midi")]
+        if let Some(midi) = self.midi.as_mut() {
+            for command in midi.fill_in(&mut analysis) {
+                self.send_command(command);
+            }
+        }
+        self.preview.update(&analysis);
+        if let Some(recorder) = self.recorder.as_mut() {
+            if let Err(err) = recorder.record(&analysis, self.audio.processor.bands()) {
+                warn!("Failed to record the analysis: {}", err);
+            }
         }
 
         // Send to remote and acknowledge
         if let Some(net) = self.net.as_mut() {
-            net.send_current_data(
-                &self.audio.processor,
-                self.spotify.as_ref(),
-                self.opt.no_ack,
-            )?;
+            net.send_current_data(&analysis, self.opt.no_ack)?;
         }
 
         // Time
@@ -252,7 +788,7 @@ impl App {
             .input()
             .chunks_exact(2)
             .enumerate()
-            .map(|(i, slice)| (i as f64, (slice[0] + slice[1]) / 2.0))
+            .map(|(i, slice)| (i as f64, to_f64(slice[0] + slice[1]) / 2.0))
             .collect::<Vec<_>>();
 
         let fft_data = self
@@ -262,39 +798,80 @@ impl App {
             .iter()
             .copied()
             .enumerate()
-            .map(|(i, sample)| (i as f64, sample))
+            .map(|(i, sample)| (i as f64, to_f64(sample)))
             .collect::<Vec<_>>();
 
-        let last_novelty = self.audio.processor.novelty();
+        let spectrum_bars = spectrum_bars(
+            self.audio.processor.output(),
+            self.audio.processor.sample_size(),
+            to_f64(self.audio.processor.peak_output()),
+        );
+
+        let last_novelty = to_f64(self.audio.processor.novelty());
         let novelty_data = self
             .audio
             .processor
             .novelty_curve()
             .enumerate()
-            .map(|(i, val)| (i as f64, val))
+            .map(|(i, val)| (i as f64, to_f64(val)))
             .collect::<Vec<(f64, f64)>>();
 
         // Some max for display
 
-        let max_data = self.audio.processor.peak_input() * 1.1;
-        let max_fft = self.audio.processor.peak_output() * 1.2;
-        let max_novelty = self.audio.processor.novelty_peak();
+        let max_data = to_f64(self.audio.processor.peak_input()) * 1.1;
+        let max_fft = to_f64(self.audio.processor.peak_output()) * 1.2;
+        let max_novelty = to_f64(self.audio.processor.novelty_peak());
+
+        let compression = self.audio.processor.compression();
+        let novelty_size_st = self.audio.processor.short_term_novelty_size();
+        let onset_threshold = self.audio.processor.onset_threshold();
+        let tapped_tempo = self.tap.tempo();
+        let paused = self.paused;
+        let trigger = self.trigger;
+        let ack = !self.opt.no_ack;
+        let mode = self.net.as_ref().and_then(|net| net.mode());
+        let net_stats = self.net.as_ref().map(|net| (net.stats(), net.rtt()));
 
         let run_time_micros = self.run_time.as_micros();
         let draw_time_micros = self.draw_time.as_micros();
         let spare_time_millis = self.spare_time.as_millis();
+        let servers = self
+            .net
+            .as_ref()
+            .map(|net| {
+                net.servers()
+                    .iter()
+                    .map(|server| {
+                        (
+                            server.address(),
+                            server.state(),
+                            server.last_error(),
+                            *server.ack_stats(),
+                            *server.rtt(),
+                            server.send_period(),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        let preview_color = self
+            .preview
+            .color(self.spotify.as_ref().and_then(|s| s.palette()));
+        let preview_standby = self.preview.is_silent();
 
         // Spotify info
-        let (spotify_online, current_track, tempo, is_beat) =
+        let (spotify_online, current_track, tempo, is_beat, loudness) =
             if let Some(spotify) = self.spotify.as_ref() {
                 (
                     true,
                     spotify.current_track(),
                     spotify.tempo(),
                     spotify.is_beat(),
+                    spotify.segment_loudness(),
                 )
             } else {
-                (false, None, f32::NAN, false)
+                (false, None, f32::NAN, false, 0.0)
             };
 
         tui.draw(|frame| {
@@ -343,8 +920,8 @@ impl App {
                     .block(
                         Block::default()
                             .title(format!(
-                                " STFT Data Magnitude - Compression: {} - {} samples ",
-                                COMPRESSION_CONST,
+                                " STFT Data Magnitude - Compression: {:.0} - {} samples ",
+                                compression,
                                 fft_data.len()
                             ))
                             .borders(Borders::ALL),
@@ -353,6 +930,35 @@ impl App {
                     .y_axis(Axis::default().bounds([0.0, max_fft]))
             };
 
+            let fft_layout = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(60), Constraint::Percentage(40)].as_ref())
+                .split(graph_layout[1]);
+
+            let spectrum_bar_chart = {
+                let data = spectrum_bars
+                    .iter()
+                    .map(|(label, value)| (label.as_str(), *value))
+                    .collect::<Vec<_>>();
+                // Fill the width, borders included
+                let bar_width = (fft_layout[1].width.saturating_sub(2) / SPECTRUM_BARS as u16)
+                    .saturating_sub(1)
+                    .max(1);
+
+                BarChart::default()
+                    .block(
+                        Block::default()
+                            .title(" Spectrum Bands ")
+                            .borders(Borders::ALL),
+                    )
+                    .data(&data)
+                    .bar_width(bar_width)
+                    .bar_gap(1)
+                    .max(100)
+                    .bar_style(Style::default().fg(Color::LightBlue))
+                    .value_style(Style::default().fg(Color::Black).bg(Color::LightBlue))
+            };
+
             let novelty_graph = {
                 let novelty_dataset = Dataset::default()
                     .marker(Marker::Braille)
@@ -377,8 +983,11 @@ impl App {
                 .direction(Direction::Vertical)
                 .constraints(
                     [
-                        Constraint::Length(3),
+                        // One line per server
+                        Constraint::Length(8 + servers.len().max(1) as u16),
+                        Constraint::Length(4),
                         Constraint::Length(4),
+                        Constraint::Length(3),
                         Constraint::Min(1),
                     ]
                     .as_ref(),
@@ -388,7 +997,7 @@ impl App {
             let bold = Style::default().add_modifier(Modifier::BOLD);
 
             let status = {
-                let text = vec![Spans::from(vec![
+                let mut text = vec![Spans::from(vec![
                     Span::styled(" Process time: ", bold),
                     Span::raw(format!("{:3}us", run_time_micros)),
                     Span::styled(" | Draw time: ", bold),
@@ -404,11 +1013,139 @@ impl App {
                     },
                 ])];
 
+                text.push(Spans::from(vec![
+                    Span::styled(" Capture: ", bold),
+                    if paused {
+                        Span::styled(
+                            "Paused",
+                            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+                        )
+                    } else {
+                        Span::raw("Running")
+                    },
+                    Span::styled(" | ACK: ", bold),
+                    Span::raw(if ack { "On" } else { "Off" }),
+                ]));
+                text.push(Spans::from(vec![
+                    Span::styled(" Short term novelty: ", bold),
+                    Span::raw(format!("{}", novelty_size_st)),
+                    Span::styled(" | Onset threshold: ", bold),
+                    Span::raw(format!("{:.1}", onset_threshold)),
+                    Span::styled(" | Tapped tempo: ", bold),
+                    Span::raw(format!("{:.1}", tapped_tempo)),
+                ]));
+                text.push(Spans::from(vec![
+                    Span::styled(" Keys: ", bold),
+                    Span::raw("q quit | p pause | a ACK | m mode | +/- compression"),
+                ]));
+                text.push(Spans::from(vec![Span::raw(
+                    "       [/] short term novelty | t/T onset threshold",
+                )]));
+                text.push(Spans::from(vec![Span::raw(
+                    "       Tab tap the tempo | Backspace forget it",
+                )]));
+                text.push(Spans::from(vec![Span::raw(
+                    "       Servers: b/B brightness | n next runner | s/S standby speed | x reset",
+                )]));
+                text.push(Spans::from(vec![Span::raw(
+                    "       1-9 recall preset | F1-F9 save preset | d beat division | l trigger mode",
+                )]));
+                if trigger {
+                    text.push(Spans::from(vec![
+                        Span::styled(
+                            " Trigger mode: ",
+                            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+                        ),
+                        Span::raw("Space beat | 1-9 color | s strobe | l/Esc leave"),
+                    ]));
+                }
+
+                if servers.is_empty() {
+                    text.push(Spans::from(vec![
+                        Span::styled(" Server: ", bold),
+                        Span::raw("Disabled"),
+                    ]));
+                }
+
+                for (address, state, last_error, stats, rtt, led_update_period) in servers.iter() {
+                    text.push(Spans::from(vec![
+                        Span::styled(format!(" Server {}: ", address), bold),
+                        match state {
+                            ConnectionState::Connected => Span::styled(
+                                "Connected",
+                                Style::default()
+                                    .fg(Color::Green)
+                                    .add_modifier(Modifier::BOLD),
+                            ),
+                            ConnectionState::Reconnecting { attempts } => Span::styled(
+                                match last_error {
+                                    Some(err) => {
+                                        format!("Reconnecting ({} attempts, {})", attempts, err)
+                                    }
+                                    None => format!("Reconnecting ({} attempts)", attempts),
+                                },
+                                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+                            ),
+                        },
+                        Span::styled(" | Loss: ", bold),
+                        Span::raw(format!("{:5.2}%", stats.loss_ratio() * 100.0)),
+                        Span::styled(" | Out of order: ", bold),
+                        Span::raw(format!("{}", stats.out_of_order())),
+                        Span::styled(" | RTT: ", bold),
+                        match rtt.rtt() {
+                            // Slower than the LEDs, lag is coming from the network
+                            Some(latency) if latency > *led_update_period => Span::styled(
+                                format!(
+                                    "{:5.1}ms ± {:4.1}ms",
+                                    latency.as_secs_f64() * 1000.0,
+                                    rtt.jitter().as_secs_f64() * 1000.0
+                                ),
+                                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+                            ),
+                            Some(latency) => Span::raw(format!(
+                                "{:5.1}ms ± {:4.1}ms",
+                                latency.as_secs_f64() * 1000.0,
+                                rtt.jitter().as_secs_f64() * 1000.0
+                            )),
+                            None => Span::raw("-"),
+                        },
+                    ]));
+                }
+
                 Paragraph::new(text)
                     .block(Block::default().title(" Status ").borders(Borders::ALL))
                     .alignment(Alignment::Left)
             };
 
+            let network = {
+                let text = match net_stats {
+                    Some((stats, rtt)) => vec![
+                        Spans::from(vec![
+                            Span::styled(" Mode: ", bold),
+                            Span::raw(mode.map_or("-".to_owned(), |mode| format!("{:?}", mode))),
+                            Span::styled(" | RTT: ", bold),
+                            Span::raw(match rtt {
+                                Some(rtt) => format!("{:.1}ms", rtt.as_secs_f64() * 1000.0),
+                                None => "-".to_owned(),
+                            }),
+                        ]),
+                        Spans::from(vec![
+                            Span::styled(" Sent: ", bold),
+                            Span::raw(format!("{}", stats.packets_sent)),
+                            Span::styled(" | ACKs: ", bold),
+                            Span::raw(format!("{}", stats.acks_received)),
+                            Span::styled(" | Retransmits: ", bold),
+                            Span::raw(format!("{}", stats.retransmits)),
+                        ]),
+                    ],
+                    None => vec![Spans::from(vec![Span::styled(" Disabled", bold)])],
+                };
+
+                Paragraph::new(text)
+                    .block(Block::default().title(" Network ").borders(Borders::ALL))
+                    .alignment(Alignment::Left)
+            };
+
             let novelty_bar = {
                 Gauge::default()
                     .block(Block::default().title(" Novelty ").borders(Borders::ALL))
@@ -416,9 +1153,27 @@ impl App {
                     .ratio((last_novelty / max_novelty).min(1.0))
             };
 
-            let spotify_status_text = if let Some((playing, progress)) = current_track {
-                let full_track = playing.item.as_ref().unwrap();
-                let duration = full_track.duration_ms;
+            let led_preview = {
+                let [r, g, b] = preview_color;
+                // One block per 2 columns, borders excluded
+                let leds = (output_data_layout[3].width.saturating_sub(2) / 2) as usize;
+                let strip = (0..leds)
+                    .map(|_| Span::styled("\u{2588} ", Style::default().fg(Color::Rgb(r, g, b))))
+                    .collect::<Vec<_>>();
+
+                Paragraph::new(Spans::from(strip)).block(
+                    Block::default()
+                        .title(if preview_standby {
+                            " LED Preview - Standby "
+                        } else {
+                            " LED Preview "
+                        })
+                        .borders(Borders::ALL),
+                )
+            };
+
+            let spotify_status_text = if let Some((track, progress)) = current_track {
+                let duration = track.duration_ms;
                 vec![
                     Spans::from(vec![
                         Span::styled(" Status: ", bold),
@@ -431,20 +1186,11 @@ impl App {
                     ]),
                     Spans::from(vec![
                         Span::styled(" Current track: ", bold),
-                        Span::raw(format!(
-                            "{} - {}",
-                            &full_track.name, &full_track.artists[0].name
-                        )),
+                        Span::raw(format!("{} - {}", &track.name, &track.artist)),
                     ]),
                     Spans::from(vec![
                         Span::styled(" Current track ID: ", bold),
-                        Span::raw(
-                            full_track
-                                .id
-                                .as_ref()
-                                .map(|s| s.as_str())
-                                .unwrap_or("Unknown ID"),
-                        ),
+                        Span::raw(track.spotify_id.as_deref().unwrap_or("Unknown ID")),
                     ]),
                     Spans::from(vec![
                         Span::styled(" Time: ", bold),
@@ -460,6 +1206,10 @@ impl App {
                         Span::styled(" Tempo: ", bold),
                         Span::raw(format!("{:.2}", tempo)),
                     ]),
+                    Spans::from(vec![
+                        Span::styled(" Segment loudness: ", bold),
+                        Span::raw(format!("{:.2}", loudness)),
+                    ]),
                     Spans::from(vec![
                         Span::styled(" New Beat: ", bold),
                         if is_beat {
@@ -501,11 +1251,14 @@ impl App {
                 .block(Block::default().title(" Spotify ").borders(Borders::ALL));
 
             frame.render_widget(raw_graph, graph_layout[0]);
-            frame.render_widget(fft_graph, graph_layout[1]);
+            frame.render_widget(fft_graph, fft_layout[0]);
+            frame.render_widget(spectrum_bar_chart, fft_layout[1]);
             frame.render_widget(novelty_graph, graph_layout[2]);
             frame.render_widget(status, output_data_layout[0]);
-            frame.render_widget(novelty_bar, output_data_layout[1]);
-            frame.render_widget(spotify_status_widget, output_data_layout[2]);
+            frame.render_widget(network, output_data_layout[1]);
+            frame.render_widget(novelty_bar, output_data_layout[2]);
+            frame.render_widget(led_preview, output_data_layout[3]);
+            frame.render_widget(spotify_status_widget, output_data_layout[4]);
         })
         .unwrap();
 
@@ -513,7 +1266,23 @@ impl App {
         self.last_run_end = Instant::now();
     }
 
+    /// Give the terminal back to the shell, nothing can be drawn afterwards.
+    fn restore_terminal(&mut self) -> Result<()> {
+        if let Some(mut tui) = self.tui.take() {
+            disable_raw_mode()?;
+            execute!(tui.backend_mut(), LeaveAlternateScreen)?;
+            tui.show_cursor()?;
+        }
+        Ok(())
+    }
+
     pub fn cleanup(&mut self) -> Result<()> {
+        self.restore_terminal()?;
+        if self.tuned {
+            // Once the terminal is back so it stays visible
+            println!("Tuned parameters: {}", self.tuned_arguments());
+        }
+
         if let Some(audio) = self.audio.stream.as_ref() {
             audio.pause()?;
         }
@@ -525,3 +1294,48 @@ impl App {
         Ok(())
     }
 }
+
+/// Group the spectrum in log-spaced bands, as a percentage of the peak, labelled by their
+/// center frequency.
+fn spectrum_bars(output: &[Sample], sample_size: usize, peak: f64) -> Vec<(String, u64)> {
+    if output.is_empty() {
+        return vec![];
+    }
+
+    // The first bin (DC) isn't in the output
+    let bin_width = SAMPLE_RATE as f64 / sample_size as f64;
+    let bin = |freq: f64| ((freq / bin_width).round() as usize).clamp(1, output.len()) - 1;
+
+    let (low, high) = SPECTRUM_BARS_FREQUENCIES;
+    let ratio = (high / low).powf(1.0 / SPECTRUM_BARS as f64);
+
+    (0..SPECTRUM_BARS)
+        .map(|i| {
+            let start = low * ratio.powi(i as i32);
+            let end = start * ratio;
+            // Low bands can be narrower than a bin
+            let first = bin(start);
+            let last = bin(end).max(first + 1).min(output.len());
+
+            let band = &output[first..last];
+            let mean = band.iter().copied().map(to_f64).sum::<f64>() / band.len().max(1) as f64;
+            let value = if peak > 0.0 { mean / peak * 100.0 } else { 0.0 };
+
+            let center = (start * end).sqrt();
+            let label = if center < 1000.0 {
+                format!("{:.0}", center)
+            } else {
+                format!("{:.0}k", center / 1000.0)
+            };
+
+            (label, value.round().min(100.0) as u64)
+        })
+        .collect()
+}
+
+impl Drop for App {
+    fn drop(&mut self) {
+        // Don't leave the terminal in raw mode if something failed
+        let _ = self.restore_terminal();
+    }
+}
@@ -1,7 +1,76 @@
-use crate::Opt;
+use crate::{
+    app::App as BlockingApp,
+    async_app::{
+        audio_collector::AudioCollector,
+        audio_processor::Processor,
+        errors::{Result, RsWaveError, SpotifyError},
+        net::NetHandler,
+        stats::StatsLogger,
+    },
+    Opt,
+};
+use std::future::Future;
+use tracing::info;
 
-pub struct App {}
+/// Same as the blocking app, without the TUI.
+pub struct App {
+    collector: AudioCollector,
+    processor: Processor,
+    net: Option<NetHandler>,
+    stats: Option<StatsLogger>,
+}
 
 impl App {
-    pub fn new(opt: Opt) {}
+    pub async fn new(opt: Opt) -> Result<Self> {
+        let spotify = BlockingApp::make_spotify(&opt)
+            .await
+            .map_err(SpotifyError::Init)?;
+        let net = NetHandler::new(&opt, spotify.is_some()).await?;
+        let (collector, consumer) = AudioCollector::new(opt.clone(), opt.sample_size).await?;
+        let processor = Processor::new(&opt, consumer, spotify).map_err(RsWaveError::Record)?;
+
+        Ok(Self {
+            collector,
+            processor,
+            net,
+            stats: StatsLogger::new(opt.stats_interval),
+        })
+    }
+
+    /// Collect, process and send until `shutdown` completes.
+    pub async fn run(&mut self, shutdown: impl Future<Output = ()>) -> Result<()> {
+        tokio::pin!(shutdown);
+
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => return Ok(()),
+                analysis = self.processor.next() => {
+                    let analysis = match analysis {
+                        Some(analysis) => analysis,
+                        None => continue,
+                    };
+
+                    if let Some(stats) = self.stats.as_mut() {
+                        stats.record(&analysis);
+                        stats.log_if_due(self.net.as_ref().map(NetHandler::snapshot));
+                    }
+                    if let Some(net) = self.net.as_ref() {
+                        for command in self.processor.take_commands() {
+                            info!("Sending {:?}", command);
+                            net.send_command(command)?;
+                        }
+                        net.send(analysis.into_owned())?;
+                    }
+                }
+            }
+        }
+    }
+
+    pub async fn stop(self) -> Result<()> {
+        let collector_res = self.collector.stop().await;
+        if let Some(net) = self.net {
+            net.stop().await?;
+        }
+        Ok(collector_res?)
+    }
 }
@@ -1,150 +1,84 @@
 use crate::{
+    app::App,
     async_app::errors::{AudioCollectorError, ResultAudioCollector as Result},
-    Opt,
-};
-use cpal::{
-    traits::{DeviceTrait, HostTrait, StreamTrait},
-    Device, SampleFormat, SampleRate, StreamConfig, SupportedStreamConfig,
-};
-use log::debug;
-use ringbuf::{Consumer, Producer, RingBuffer};
-use std::{
-    collections::VecDeque,
-    sync::{Arc, Barrier},
+    Opt, Sample,
 };
+use cpal::traits::StreamTrait;
+use ringbuf::Consumer;
+use std::time::Duration;
 use tokio::{
     sync::{oneshot, oneshot::error::TryRecvError},
     task,
-    task::JoinHandle,
 };
 
+/// How often the collecting task checks if it needs to stop.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
 pub struct AudioCollector {
-    pub(crate) handle: task::JoinHandle<()>,
-    pub(crate) stop_signal: oneshot::Sender<bool>,
-    pub(crate) consumer: Consumer<f64>,
+    pub(crate) handle: task::JoinHandle<Result<()>>,
+    pub(crate) stop_signal: oneshot::Sender<()>,
 }
 
 impl AudioCollector {
-    pub fn new(opt: Opt) -> Result<AudioCollector> {
-        let host = cpal::default_host();
-
-        // Choose device
-        let device = if let Some(hint) = opt.device_hint.as_ref() {
-            host.input_devices()?
-                .find(|device| device.name().map_or(false, |name| name.contains(hint)))
-        } else {
-            host.default_input_device()
-        }
-        .ok_or(AudioCollectorError::AudioDeviceNotFound)?;
-
-        // Get config and check if we can handle it
-        let config = device.default_input_config()?;
-        if config.sample_rate() != SampleRate(44100) {
-            return Err(AudioCollectorError::UnsupportedSampleRate).into();
-        } else if config.channels() != 2 {
-            return Err(AudioCollectorError::NotStereoDevice).into();
-        }
-
-        // Ring buffer for buffering (lol) samples, 2 times the sample size so we don't lose any
-        let buffer = RingBuffer::new(opt.sample_size * 2);
-        let (prod, cons) = buffer.split();
-        // Vanilla barrier because cpal isn't async
-        let buffer_barrier = Arc::new(Barrier::new(2));
-
-        // Setup collecting task
+    /// Returns the collector and the end of the buffer to read the samples from.
+    pub async fn new(opt: Opt, sample_size: usize) -> Result<(AudioCollector, Consumer<Sample>)> {
         let (stop_signal, stop_recv) = oneshot::channel();
+        let (consumer_sender, consumer_recv) = oneshot::channel();
 
-        let handle = tokio::task::spawn_blocking(move || {
-            Self::run(
-                device,
-                config,
-                opt.sample_size,
-                prod,
-                buffer_barrier,
-                stop_recv,
-            );
-        });
+        // The source and its stream aren't `Send`, they live on the collecting task
+        let handle =
+            task::spawn_blocking(move || Self::run(opt, sample_size, consumer_sender, stop_recv));
 
-        // Ok we have everything
-        Ok(AudioCollector {
-            handle,
-            stop_signal,
-            consumer: cons,
-        })
+        match consumer_recv.await {
+            Ok(consumer) => Ok((
+                AudioCollector {
+                    handle,
+                    stop_signal,
+                },
+                consumer,
+            )),
+            // Failed before being ready
+            Err(_) => Err(handle
+                .await
+                .map_err(|_| AudioCollectorError::FailedToStopTask)?
+                .err()
+                .unwrap_or(AudioCollectorError::Stopped)),
+        }
     }
 
     fn run(
-        device: Device, config: SupportedStreamConfig, sample_size: usize, mut prod: Producer<f64>,
-        buffer_barrier: Arc<Barrier>, mut stop: oneshot::Receiver<bool>,
-    ) {
-        let buffer_barrier_clone = buffer_barrier.clone();
+        opt: Opt, sample_size: usize, consumer_sender: oneshot::Sender<Consumer<Sample>>,
+        mut stop: oneshot::Receiver<()>,
+    ) -> Result<()> {
+        let source = App::make_audio_source(&opt).map_err(AudioCollectorError::OpenSource)?;
+        let (stream, consumer) = source
+            .open_stream(sample_size, opt.channels)
+            .map_err(AudioCollectorError::OpenSource)?;
 
-        // Create reader here because it isn't `Send`
-        let stream = match config.sample_format() {
-            SampleFormat::I16 => {
-                device.build_input_stream(
-                    &config.into(),
-                    move |data: &[i16], _| {
-                        prod.push_iter(&mut data.iter().copied().map(|sample| sample as f64));
-                        if prod.len() >= sample_size {
-                            // Notify we are ready to send this batch
-                            buffer_barrier_clone.wait();
-                        }
-                    },
-                    move |err| panic!(err),
-                )
-            }
-            SampleFormat::U16 => {
-                device.build_input_stream(
-                    &config.into(),
-                    move |data: &[u16], _| {
-                        prod.push_iter(
-                            &mut data
-                                .iter()
-                                .copied()
-                                .map(|sample| sample as f64 - u16::max_value() as f64 / 2.0),
-                        );
-                        if prod.len() >= sample_size {
-                            // Notify we are ready to send this batch
-                            buffer_barrier_clone.wait();
-                        }
-                    },
-                    |err| panic!(err),
-                )
-            }
-            SampleFormat::F32 => {
-                device.build_input_stream(
-                    &config.into(),
-                    move |data: &[f32], _| {
-                        prod.push_iter(&mut data.iter().copied().map(|sample| sample as f64));
-                        if prod.len() >= sample_size {
-                            // Notify we are ready to send this batch
-                            buffer_barrier_clone.wait();
-                        }
-                    },
-                    |err| panic!(err),
-                )
-            }
+        if let Some(stream) = stream.as_ref() {
+            stream.play()?;
+        }
+        if consumer_sender.send(consumer).is_err() {
+            // Nobody to collect for
+            return Ok(());
         }
-        .expect("Failed to create audio stream");
 
-        stream.play().expect("Failed to start playing audio stream");
+        // Keep the source alive until told otherwise
         while let Err(TryRecvError::Empty) = stop.try_recv() {
-            // Wait for buffer to fill up
-            buffer_barrier.wait();
+            std::thread::sleep(STOP_POLL_INTERVAL);
         }
-        stream.pause().expect("Failed to pause audio stream");
-    }
 
-    pub async fn stop(self) -> Result<()> {
-        // If we can't send the signal it means that the
-        // other has been dropped, so we don't need to await it
-        if let Ok(_) = self.stop_signal.send(true) {
-            self.handle
-                .await
-                .map_err(|_| AudioCollectorError::FailedToStopTask)?;
+        if let Some(stream) = stream.as_ref() {
+            stream.pause()?;
         }
-
         Ok(())
     }
+
+    pub async fn stop(self) -> Result<()> {
+        // If we can't send the signal it means that the task is already over
+        let _ = self.stop_signal.send(());
+        self.handle
+            .await
+            .map_err(|_| AudioCollectorError::FailedToStopTask)?
+    }
 }
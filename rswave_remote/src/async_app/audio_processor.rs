@@ -1,148 +1,118 @@
-use crate::Opt;
-use realfft::{num_complex::Complex, RealFftPlanner, RealToComplex};
-use std::{collections::VecDeque, f64::consts::PI, sync::Arc};
-
-pub struct AudioProcessor {
-    sample_size: usize,
-    novelty_size_st: usize,
-    compression: f64,
-
-    fft_planner: RealFftPlanner<f64>,
-    fft: Arc<dyn RealToComplex<f64>>,
-
-    window: Box<[f64]>,
-    input: Box<[f64]>,
-    // Stereo L/R
-    raw_data: (Box<[f64]>, Box<[f64]>),
-    fft_scratch: Box<[Complex<f64>]>,
-    // Stereo L/R
-    fft_data: (Box<[Complex<f64>]>, Box<[Complex<f64>]>),
-
-    peak_input: f64,
-    output: Box<[f64]>,
-    prev_output: Box<[f64]>,
-
-    novelty_curve: VecDeque<f64>,
+#[cfg(feature = "midi")]
+use crate::midi::MidiTrigger;
+use crate::{
+    audio::AudioProcessor, context::ContextPresets, genre::GenreRunners, net::Analysis,
+    record::Recorder, spotify::SpotifyTracker, tempo::TempoTracker, zone::ZoneTracker, Opt, Sample,
+};
+use anyhow::Result;
+use ringbuf::Consumer;
+use rswave_common::packets::Command;
+use std::time::Duration;
+use tracing::warn;
+
+/// How long to wait for the buffer to fill up.
+const IDLE_DELAY: Duration = Duration::from_millis(10);
+
+/// Turns the collected samples into something to send.
+pub struct Processor {
+    processor: AudioProcessor<Sample>,
+    consumer: Consumer<Sample>,
+    spotify: Option<SpotifyTracker>,
+    recorder: Option<Recorder>,
+    context_presets: ContextPresets,
+    genre_runners: GenreRunners,
+    zones: ZoneTracker,
+    /// Finds the beats spotify doesn't know, if enabled.
+    tempo: Option<TempoTracker>,
+    #[cfg(feature = "midi")]
+    midi: Option<MidiTrigger>,
+    /// For the servers because the track changed, not taken yet.
+    commands: Vec<Command>,
 }
 
-impl AudioProcessor {
-    pub fn new(opt: Opt) -> Self {
-        assert!(
-            opt.novelty_size >= opt.novelty_size_st,
-            "Novelty size but be >= Short term novelty size !"
-        );
-
-        let mut fft_planner = RealFftPlanner::new();
-        let fft = fft_planner.plan_fft_forward(opt.sample_size);
-
-        let raw_data = (
-            fft.make_input_vec().into_boxed_slice(),
-            fft.make_input_vec().into_boxed_slice(),
-        );
-        let fft_scratch = fft.make_scratch_vec().into_boxed_slice();
-        let fft_data = (
-            fft.make_output_vec().into_boxed_slice(),
-            fft.make_output_vec().into_boxed_slice(),
-        );
-
-        let input = vec![0.0; raw_data.0.len() + raw_data.1.len()].into_boxed_slice();
-        let output = vec![0.0; fft_data.0.len()].into_boxed_slice();
-        let prev_output = vec![0.0; output.len()].into_boxed_slice();
-
-        // Hann window
-        let window = (0..raw_data.0.len())
-            .into_iter()
-            .map(|i| 0.5 * (1.0 - (2.0 * PI * i as f64 / (opt.sample_size as f64 - 1.0)).cos()))
-            .collect::<Vec<_>>()
-            .into_boxed_slice();
-
-        let mut novelty_curve = VecDeque::with_capacity(opt.novelty_size);
-        novelty_curve.resize(opt.novelty_size, 0.0);
-
-        Self {
-            sample_size: opt.sample_size,
-            novelty_size_st: opt.novelty_size_st,
-            compression: opt.spectrum_compression,
-
-            fft_planner,
-            fft,
-
-            window,
-            input,
-            raw_data,
-            fft_scratch,
-            fft_data,
-
-            peak_input: 0.0,
-            output,
-            prev_output,
-
-            novelty_curve,
-        }
+impl Processor {
+    pub fn new(
+        opt: &Opt, consumer: Consumer<Sample>, spotify: Option<SpotifyTracker>,
+    ) -> Result<Self> {
+        let mut processor =
+            AudioProcessor::new(opt.sample_size, opt.novelty_size, opt.novelty_size_st);
+        processor.set_compression(opt.spectrum_compression);
+        processor.set_onset_threshold(opt.onset_threshold);
+        processor.set_peak_half_life(Some(opt.peak_half_life));
+        processor.set_silence_duration(Some(opt.silence_standby));
+        processor.set_filters(opt.highpass, opt.lowpass);
+        processor.set_weighting(opt.weighting);
+        processor.set_mel_bands(opt.mel_bands);
+        processor.set_novelty_weights(opt.novelty_weight.clone());
+
+        Ok(Self {
+            processor,
+            consumer,
+            spotify,
+            recorder: opt.record.as_deref().map(Recorder::new).transpose()?,
+            context_presets: ContextPresets::new(opt.context_preset.clone()),
+            genre_runners: GenreRunners::new(opt.genre_runner.clone(), opt.genre_effects),
+            zones: ZoneTracker::new(opt.zone.clone())?,
+            tempo: if spotify.is_some() || opt.tempo_tracking {
+                Some(TempoTracker::new(opt.sample_size))
+            } else {
+                None
+            },
+            #[cfg(feature = "midi")]
+            midi: opt
+                .midi
+                .as_deref()
+                .map(|name| MidiTrigger::open(name, opt.midi_brightness_cc))
+                .transpose()?,
+            commands: Vec::new(),
+        })
     }
 
-    /// Probably the most important function of the program.
-    pub fn process(&mut self) {
-        // Save output
-        self.prev_output.copy_from_slice(&self.output);
-
-        // Separate channels and apply window
-        for (i, samples) in self.input.chunks_exact_mut(2).enumerate() {
-            // Apply window to the input so we can see it in the visualization
-            samples[0] *= self.window[i];
-            samples[1] *= self.window[i];
-
-            self.raw_data.0[i] = samples[0];
-            self.raw_data.1[i] = samples[0];
-
-            // Update peak
-            self.peak_input = self.peak_input.max(samples[0]).max(samples[1]);
+    /// Process the next frame, waits a bit if there isn't enough samples yet.
+    pub async fn next(&mut self) -> Option<Analysis> {
+        // 2 channels
+        if self.consumer.len() < self.processor.sample_size() * 2 {
+            tokio::time::delay_for(IDLE_DELAY).await;
+            return None;
         }
 
-        // Process
-        // We can unwrap because it errors only if the buffers aren't of the correct size
-        self.fft
-            .process_with_scratch(
-                &mut self.raw_data.0,
-                &mut self.fft_data.0,
-                &mut self.fft_scratch,
-            )
-            .unwrap();
-        self.fft
-            .process_with_scratch(
-                &mut self.raw_data.1,
-                &mut self.fft_data.1,
-                &mut self.fft_scratch,
-            )
-            .unwrap();
-
-        // Post-process spectrum
-        let scale_coeff = 1.0 / (self.fft_data.0.len() as f64).sqrt();
-        for (i, (left, right)) in self
-            .fft_data
-            .0
-            .iter()
-            .zip(self.fft_data.1.iter())
-            .enumerate()
-        {
-            // Normalize values them combine them
-            // Average L/R
-            let mut val = (left.scale(scale_coeff).norm() + right.scale(scale_coeff).norm()) / 2.0;
-
-            // Logarithmic compression
-            val = (self.compression * val).ln_1p();
+        self.consumer.pop_slice(self.processor.input());
+        self.processor.process();
+
+        if let Some(spotify) = self.spotify.as_mut() {
+            spotify.refresh_current_track().await;
+            spotify.advance_beat();
+            spotify.advance_segment();
+
+            let track = spotify.current_track().map(|(track, _)| track);
+            if let Some(preset) = self.context_presets.update(track) {
+                self.commands.push(Command::Preset(preset));
+            }
+            let runner = self.genre_runners.update(track, spotify.genres());
+            if let Some(runner) = runner.filter(|_| !self.context_presets.is_active()) {
+                self.commands.push(Command::Runner(runner));
+            }
         }
 
-        // Novelty curve
-        let novelty = self
-            .output
-            .iter()
-            .copied()
-            .enumerate()
-            .map(|(i, val)| (val - self.prev_output[i]).max(0.0))
-            .sum();
+        let mut analysis = Analysis::new(&self.processor, self.spotify.as_ref());
+        analysis.zones = self.zones.update(&self.processor);
+        if let Some(tempo) = self.tempo.as_mut() {
+            tempo.fill_in(&mut analysis, self.spotify.as_ref());
+        }
+        #[cfg(feature = "midi")]
+        if let Some(midi) = self.midi.as_mut() {
+            self.commands.extend(midi.fill_in(&mut analysis));
+        }
+        if let Some(recorder) = self.recorder.as_mut() {
+            if let Err(err) = recorder.record(&analysis, self.processor.bands()) {
+                warn!("Failed to record the analysis: {}", err);
+            }
+        }
+        Some(analysis)
+    }
 
-        self.novelty_curve.pop_front();
-        self.novelty_curve.push_back(novelty);
+    /// What to tell the servers since the track changed or from MIDI, if anything.
+    pub fn take_commands(&mut self) -> Vec<Command> {
+        std::mem::take(&mut self.commands)
     }
 }
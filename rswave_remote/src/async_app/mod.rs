@@ -1,8 +1,13 @@
+//! Headless pipeline where every stage runs on its own: the audio is collected on a
+//! blocking task, processed on the main task and sent to the servers from a blocking task.
+//! Only used with `--no-tui`, the TUI stays on the blocking [App](crate::app::App) which
+//! draws between two frames anyway.
+
 pub mod app;
 pub mod audio_collector;
 pub mod audio_processor;
 pub mod net;
-pub mod spotify;
+pub mod stats;
 
 pub mod errors {
     use thiserror::Error;
@@ -20,37 +25,39 @@ pub mod errors {
         SpotifyError(#[from] SpotifyError),
         #[error(transparent)]
         NetError(#[from] NetError),
+        #[error("Failed to create the recording: {0}")]
+        Record(anyhow::Error),
     }
 
     #[derive(Debug, Error)]
     pub enum AudioCollectorError {
-        #[error("Can't find audio device !")]
-        AudioDeviceNotFound,
-        #[error(transparent)]
-        CpalDevicesError(#[from] cpal::DevicesError),
-        #[error("Unsupported sample rate, only 44100 Hz is supported !")]
-        UnsupportedSampleRate,
-        #[error("Only stereo devices are supported !")]
-        NotStereoDevice,
+        #[error("Failed to open audio source: {0}")]
+        OpenSource(anyhow::Error),
         #[error(transparent)]
-        CpalDefaultStreamConfigError(#[from] cpal::DefaultStreamConfigError),
+        CpalPlayStreamError(#[from] cpal::PlayStreamError),
         #[error(transparent)]
-        CpalStreamError(#[from] cpal::StreamError),
-        #[error(transparent)]
-        CpalBuildStreamError(#[from] cpal::BuildStreamError),
+        CpalPauseStreamError(#[from] cpal::PauseStreamError),
+        #[error("Audio collector stopped unexpectedly !")]
+        Stopped,
         #[error("Failed to stop audio collector !")]
         FailedToStopTask,
     }
 
     #[derive(Debug, Error)]
     pub enum SpotifyError {
-        #[error("Unable to get spotify access token !")]
-        UnableToGetAccessToken,
+        #[error("Failed to initialize spotify: {0}")]
+        Init(anyhow::Error),
     }
 
     #[derive(Debug, Error)]
     pub enum NetError {
-        #[error("Hey")]
-        Hey,
+        #[error("Failed to reach the servers: {0}")]
+        Connect(anyhow::Error),
+        #[error("Failed to talk to the servers: {0}")]
+        Send(anyhow::Error),
+        #[error("Net task stopped unexpectedly !")]
+        Stopped,
+        #[error("Failed to stop net task !")]
+        FailedToStopTask,
     }
 }
@@ -1,38 +1,134 @@
-use crate::async_app::errors::ResultNet as Result;
-use rswave_common::rkyv::Aligned;
-use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
-use tokio::net::UdpSocket;
+use crate::{
+    app::App,
+    async_app::errors::{NetError, ResultNet as Result},
+    net::{Analysis, NetHandler as Servers, NetStats},
+    Opt,
+};
+use parking_lot::Mutex;
+use rswave_common::{packets::Command, HEARTBEAT_INTERVAL};
+use std::sync::{mpsc, Arc};
+use tokio::task;
 
+/// Counters of the servers, copied after every send.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NetSnapshot {
+    pub stats: NetStats,
+    /// Data packets whose ACK never came.
+    pub lost: u64,
+}
+
+/// What the net task sends.
+enum Outgoing {
+    /// The latest analysis is waiting in its slot.
+    Analysis,
+    Command(Command),
+}
+
+/// Only the latest analysis is worth sending, older ones are dropped if the servers are slow.
+type LatestAnalysis = Arc<Mutex<Option<Analysis<'static>>>>;
+
+/// Sends the analysis to the servers from a blocking task, the sockets being blocking ones.
 pub struct NetHandler {
-    serialize_scratch: Option<Box<[u8]>>,
-    deserialize_scratch: Aligned<[u8; 128]>,
+    handle: task::JoinHandle<Result<()>>,
+    /// Commands are never dropped, the analysis only wakes the task when its slot was empty.
+    sender: mpsc::Sender<Outgoing>,
+    latest: LatestAnalysis,
+    snapshot: Arc<Mutex<NetSnapshot>>,
 }
 
 impl NetHandler {
-    pub async fn new(address: &str) -> Result<Self> {
-        let socket = UdpSocket::bind((Ipv4Addr::new(0, 0, 0, 0), 0))
+    /// Returns nothing when there is no server to talk to.
+    pub async fn new(opt: &Opt, with_spotify: bool) -> Result<Option<Self>> {
+        let handshake_opt = opt.clone();
+        let servers = task::spawn_blocking(move || App::make_net(&handshake_opt, with_spotify))
             .await
-            .expect("Invalid socket addr, should never happen");
-        let (mut recv, send) = socket.split();
+            .map_err(|_| NetError::Stopped)?
+            .map_err(NetError::Connect)?;
 
-        let recv_task = tokio::task::spawn(async move {
-            let mut deserialize_scratch = Aligned([0; 128]);
+        let servers = match servers {
+            Some(servers) => servers,
+            None => return Ok(None),
+        };
 
-            loop {
-                let len = recv
-                    .recv(deserialize_scratch.as_mut())
-                    .await
-                    .expect("Recv call failed, should never happen");
-            }
+        let (sender, receiver) = mpsc::channel();
+        let no_ack = opt.no_ack;
+        let latest = Arc::new(Mutex::new(None));
+        let task_latest = latest.clone();
+        let snapshot = Arc::new(Mutex::new(NetSnapshot::default()));
+        let task_snapshot = snapshot.clone();
+        let handle = task::spawn_blocking(move || {
+            Self::run(servers, receiver, task_latest, no_ack, task_snapshot)
         });
 
-        Ok(Self {
-            serialize_scratch: None,
-            deserialize_scratch: Aligned([0; 128]),
-        })
+        Ok(Some(Self {
+            handle,
+            sender,
+            latest,
+            snapshot,
+        }))
     }
 
-    fn start(&mut self) {}
+    fn run(
+        mut servers: Servers, receiver: mpsc::Receiver<Outgoing>, latest: LatestAnalysis,
+        no_ack: bool, snapshot: Arc<Mutex<NetSnapshot>>,
+    ) -> Result<()> {
+        let res = loop {
+            match receiver.recv_timeout(HEARTBEAT_INTERVAL) {
+                Ok(Outgoing::Analysis) => {
+                    let analysis = latest.lock().take();
+                    if let Some(analysis) = analysis {
+                        if let Err(err) = servers.send_current_data(&analysis, no_ack) {
+                            break Err(NetError::Send(err));
+                        }
+                    }
+                }
+                Ok(Outgoing::Command(command)) => servers.send_command(command),
+                // Nothing to send, don't let the servers think we are gone
+                Err(mpsc::RecvTimeoutError::Timeout) => servers.keepalive(),
+                // Nothing will ever be sent again
+                Err(mpsc::RecvTimeoutError::Disconnected) => break Ok(()),
+            }
+
+            *snapshot.lock() = NetSnapshot {
+                stats: servers.stats(),
+                lost: servers.lost(),
+            };
+        };
 
-    async fn handle_recv() {}
+        servers.stop(false).map_err(NetError::Send)?;
+        res
+    }
+
+    /// Replaces the analysis not sent yet, if any. Fails if the net task is over, [stop] tells
+    /// why.
+    pub fn send(&self, analysis: Analysis<'static>) -> Result<()> {
+        // The net task holds the other reference until it's over
+        if Arc::strong_count(&self.latest) < 2 {
+            return Err(NetError::Stopped);
+        }
+        let stale = self.latest.lock().replace(analysis);
+        if stale.is_none() {
+            self.sender
+                .send(Outgoing::Analysis)
+                .map_err(|_| NetError::Stopped)?;
+        }
+        Ok(())
+    }
+
+    /// Fails if the net task is over, [stop] tells why.
+    pub fn send_command(&self, command: Command) -> Result<()> {
+        self.sender
+            .send(Outgoing::Command(command))
+            .map_err(|_| NetError::Stopped)
+    }
+
+    pub fn snapshot(&self) -> NetSnapshot {
+        *self.snapshot.lock()
+    }
+
+    /// Say goodbye to the servers.
+    pub async fn stop(self) -> Result<()> {
+        drop(self.sender);
+        self.handle.await.map_err(|_| NetError::FailedToStopTask)?
+    }
 }
@@ -0,0 +1,91 @@
+use crate::{async_app::net::NetSnapshot, net::Analysis};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Sums up what happened since the last line, to follow a remote running without TUI.
+pub struct StatsLogger {
+    interval: Duration,
+    last_log: Instant,
+    frames: u64,
+    novelty_sum: f64,
+    novelty_peak: f64,
+    beats: u64,
+    onsets: u64,
+    last_net: NetSnapshot,
+}
+
+impl StatsLogger {
+    /// Log every `interval` seconds, never with 0, NaN or infinity.
+    pub fn new(interval: f64) -> Option<Self> {
+        if !interval.is_finite() || interval <= 0.0 {
+            return None;
+        }
+
+        Some(Self {
+            // A Duration can't hold the biggest floats
+            interval: Duration::from_secs_f64(interval.min(u32::MAX as f64)),
+            last_log: Instant::now(),
+            frames: 0,
+            novelty_sum: 0.0,
+            novelty_peak: 0.0,
+            beats: 0,
+            onsets: 0,
+            last_net: NetSnapshot::default(),
+        })
+    }
+
+    pub fn record(&mut self, analysis: &Analysis) {
+        self.frames += 1;
+        self.novelty_sum += analysis.novelty;
+        self.novelty_peak = self.novelty_peak.max(analysis.novelty_peak);
+        self.beats += analysis.beat as u64;
+        self.onsets += analysis.onset as u64;
+    }
+
+    pub fn log_if_due(&mut self, net: Option<NetSnapshot>) {
+        let elapsed = self.last_log.elapsed();
+        if elapsed < self.interval {
+            return;
+        }
+
+        let novelty = if self.frames > 0 {
+            self.novelty_sum / self.frames as f64
+        } else {
+            0.0
+        };
+
+        match net {
+            Some(net) => {
+                let sent = net.stats.packets_sent - self.last_net.stats.packets_sent;
+                // Lost ACKs are forgotten when reconnecting
+                let dropped = net.lost.saturating_sub(self.last_net.lost);
+                info!(
+                    "Last {:.0}s: novelty {:.2} (peak {:.2}), {} beats, {} onsets, {:.1} packets/s, {} dropped",
+                    elapsed.as_secs_f64(),
+                    novelty,
+                    self.novelty_peak,
+                    self.beats,
+                    self.onsets,
+                    sent as f64 / elapsed.as_secs_f64(),
+                    dropped
+                );
+                self.last_net = net;
+            }
+            None => info!(
+                "Last {:.0}s: novelty {:.2} (peak {:.2}), {} beats, {} onsets",
+                elapsed.as_secs_f64(),
+                novelty,
+                self.novelty_peak,
+                self.beats,
+                self.onsets
+            ),
+        }
+
+        self.last_log = Instant::now();
+        self.frames = 0;
+        self.novelty_sum = 0.0;
+        self.novelty_peak = 0.0;
+        self.beats = 0;
+        self.onsets = 0;
+    }
+}
@@ -0,0 +1,234 @@
+//! Sends made up novelty and beats to servers, to try them and their runners without
+//! any audio or spotify.
+
+use anyhow::{anyhow, Result};
+use rswave_common::{
+    auth::Psk,
+    logging,
+    logging::LogFormat,
+    packets::{DataMode, BARK_BANDS},
+    transport::TransportKind,
+};
+use rswave_remote::{
+    net::{Analysis, NetHandler},
+    record::{read_recording, RecordedFrame},
+};
+use std::{
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use structopt::StructOpt;
+use tracing::info;
+
+/// Same pace as the analysis of the real remote, 2048 samples at 44100Hz.
+const FRAME_PERIOD: Duration = Duration::from_micros(46_440);
+
+#[derive(Clone, Debug, StructOpt)]
+struct Opt {
+    /// Address of the server, can be given several times.
+    #[structopt(short = "a", long, required = true)]
+    address: Vec<String>,
+
+    /// What to send.
+    /// Possible values: sine, random, script (a file recorded with `--record`, looped).
+    #[structopt(short, long, default_value = "sine")]
+    pattern: FakePattern,
+
+    /// The recording played by the `script` pattern.
+    #[structopt(long, required_if("pattern", "script"))]
+    script: Option<PathBuf>,
+
+    /// Beats per minute, 0 to send no beat at all.
+    #[structopt(long, default_value = "120")]
+    tempo: f64,
+
+    /// Period of the sine in seconds.
+    #[structopt(long, default_value = "4")]
+    period: f64,
+
+    /// How long the servers wait before showing the data, in milliseconds, to be in sync.
+    /// Only used with several servers.
+    #[structopt(long, default_value = "50")]
+    sync_delay: u64,
+
+    /// Possible values: udp, tcp, quic (requires the `transport_quic` feature).
+    #[structopt(long, default_value = "udp")]
+    transport: TransportKind,
+
+    /// Passphrase shared with the server, required if the server has one.
+    #[structopt(long, env = "RSWAVE_PSK", hide_env_values = true)]
+    psk: Option<Psk>,
+
+    /// Don't check the ACKs of the servers.
+    #[structopt(long)]
+    no_ack: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum FakePattern {
+    Sine,
+    Random,
+    Script,
+}
+
+impl FromStr for FakePattern {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sine" => Ok(Self::Sine),
+            "random" => Ok(Self::Random),
+            "script" => Ok(Self::Script),
+            _ => Err(anyhow!("Unknown pattern !")),
+        }
+    }
+}
+
+/// Xorshift, good enough for blinking lights.
+struct Random(u64);
+
+impl Random {
+    fn next_f64(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Makes up one frame after the other.
+struct Generator {
+    opt: Opt,
+    script: Vec<RecordedFrame>,
+    random: Random,
+    last_beat: f64,
+}
+
+impl Generator {
+    fn new(opt: Opt) -> Result<Self> {
+        // `required_if` only matches the exact spelling of the pattern
+        let script = match (opt.pattern, opt.script.as_ref()) {
+            (FakePattern::Script, Some(path)) => {
+                let script = read_recording(path)?;
+                if script.is_empty() {
+                    return Err(anyhow!("The script is empty !"));
+                }
+                script
+            }
+            (FakePattern::Script, None) => {
+                return Err(anyhow!("The script pattern needs a `--script` !"))
+            }
+            _ => Vec::new(),
+        };
+
+        Ok(Self {
+            opt,
+            script,
+            random: Random(0x2545_f491_4f6c_dd1d),
+            last_beat: f64::NEG_INFINITY,
+        })
+    }
+
+    /// The frame to send `time` seconds after the start.
+    fn frame(&mut self, time: f64) -> Analysis<'static> {
+        if self.opt.pattern == FakePattern::Script {
+            // Loop over the script
+            let duration = self.script.last().unwrap().time + FRAME_PERIOD.as_secs_f64();
+            let time = time % duration;
+            let index = self
+                .script
+                .iter()
+                .rposition(|frame| frame.time <= time)
+                .unwrap_or(0);
+            return self.script[index].to_analysis();
+        }
+
+        let beat = self.opt.tempo > 0.0 && time - self.last_beat >= 60.0 / self.opt.tempo;
+        if beat {
+            self.last_beat = time;
+        }
+
+        let novelty = match self.opt.pattern {
+            FakePattern::Sine => {
+                0.5 + 0.5 * (2.0 * std::f64::consts::PI * time / self.opt.period).sin()
+            }
+            _ => self.random.next_f64(),
+        };
+
+        Analysis {
+            novelty,
+            novelty_peak: 1.0,
+            beat,
+            onset: beat || novelty > 0.9,
+            pitch_class: None,
+            balance: 0.0,
+            loudness: novelty,
+            segments: false,
+            tempo: self.opt.tempo,
+            palette: None,
+            features: None,
+            silent: false,
+            zones: Vec::new(),
+            bands: [0; BARK_BANDS],
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let opt = Opt::from_args();
+    logging::init(LogFormat::Text, None);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let ctrl_c_stop = stop.clone();
+    tokio::task::spawn(async move {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to wait for Ctrl+C");
+        ctrl_c_stop.store(true, Ordering::Relaxed);
+    });
+
+    // The sockets are blocking ones
+    tokio::task::spawn_blocking(move || run(opt, &stop)).await?
+}
+
+fn run(opt: Opt, stop: &AtomicBool) -> Result<()> {
+    let mut net = NetHandler::new(&opt.address, opt.transport, opt.psk)?;
+    if opt.address.len() > 1 {
+        net.set_sync_delay(Duration::from_millis(opt.sync_delay));
+    }
+    net.handshake(if opt.tempo > 0.0 || opt.pattern == FakePattern::Script {
+        DataMode::NoveltyBeats
+    } else {
+        DataMode::Novelty
+    })?;
+    info!("Sending the {:?} pattern", opt.pattern);
+
+    let no_ack = opt.no_ack;
+    let mut generator = Generator::new(opt)?;
+    let start = Instant::now();
+    let mut next_frame = start;
+    let res = loop {
+        if stop.load(Ordering::Relaxed) {
+            break Ok(());
+        }
+
+        let analysis = generator.frame(start.elapsed().as_secs_f64());
+        if let Err(err) = net.send_current_data(&analysis, no_ack) {
+            break Err(err);
+        }
+
+        next_frame += FRAME_PERIOD;
+        if let Some(remaining) = next_frame.checked_duration_since(Instant::now()) {
+            std::thread::sleep(remaining);
+        }
+    };
+
+    net.stop(false)?;
+    res
+}
@@ -11,10 +11,16 @@ use rswave_common::{
 fn main() {
     let packet = NoveltyBeatsModePacket::Data(NoveltyBeatsModeData {
         novelty: NoveltyModeData {
+            sequence: 0,
             value: 0.0,
             peak: 0.0,
+            onset: false,
+            pitch_class: 0,
+            timestamp_us: 0,
+            zones: Vec::new(),
         },
         beat: false,
+        loudness: 0.0,
     });
 
     let mut serializer = WriteSerializer::new(Vec::new());
@@ -0,0 +1,99 @@
+use crate::spotify::TrackInfo;
+use anyhow::{anyhow, Error};
+use std::str::FromStr;
+use tracing::info;
+
+/// A spotify playlist, album or artist shown with a preset of the servers.
+#[derive(Clone, Debug)]
+pub struct ContextPreset {
+    /// Without the `spotify:playlist:` part of the URI.
+    pub id: String,
+    pub preset: u8,
+}
+
+impl FromStr for ContextPreset {
+    type Err = Error;
+
+    /// Parses `id=preset`, the id can also be a whole URI like `spotify:playlist:<id>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.rsplitn(2, '=');
+        let (preset, id) = match (parts.next(), parts.next()) {
+            (Some(preset), Some(id)) => (preset.trim(), strip_uri(id.trim())),
+            _ => {
+                return Err(anyhow!(
+                    "Expected a playlist or an artist like <id>=<preset> !"
+                ))
+            }
+        };
+        if id.is_empty() {
+            return Err(anyhow!("Missing the id of the playlist or the artist !"));
+        }
+
+        Ok(Self {
+            id: id.to_string(),
+            preset: preset
+                .parse()
+                .map_err(|_| anyhow!("Invalid preset `{}` !", preset))?,
+        })
+    }
+}
+
+/// `spotify:playlist:<id>` and `https://open.spotify.com/playlist/<id>` both give the id.
+fn strip_uri(uri: &str) -> &str {
+    let uri = uri.split('?').next().unwrap_or(uri);
+    uri.rsplit(|c| c == ':' || c == '/').next().unwrap_or(uri)
+}
+
+/// Tells which preset to recall when the playlist or the artist changes.
+pub struct ContextPresets {
+    mappings: Vec<ContextPreset>,
+    /// The id matched by the current track, if any.
+    current: Option<String>,
+}
+
+impl ContextPresets {
+    pub fn new(mappings: Vec<ContextPreset>) -> Self {
+        Self {
+            mappings,
+            current: None,
+        }
+    }
+
+    /// The playlist is looked up before the artist. Returns the preset to recall, only when
+    /// the track moves to another mapping so the servers can still be changed by hand in
+    /// the meantime.
+    pub fn update(&mut self, track: Option<&TrackInfo>) -> Option<u8> {
+        if self.mappings.is_empty() {
+            return None;
+        }
+
+        let mapping = track.and_then(|track| {
+            let ids = [track.context_uri.as_deref(), track.artist_id.as_deref()];
+            ids.iter()
+                .flatten()
+                .find_map(|&id| self.find(strip_uri(id)))
+                .cloned()
+        });
+        let id = mapping.as_ref().map(|mapping| mapping.id.clone());
+        if id == self.current {
+            return None;
+        }
+        self.current = id;
+
+        let mapping = mapping?;
+        info!(
+            "Now playing {}, recalling preset {}",
+            mapping.id, mapping.preset
+        );
+        Some(mapping.preset)
+    }
+
+    /// The current track is mapped to a preset.
+    pub fn is_active(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn find(&self, id: &str) -> Option<&ContextPreset> {
+        self.mappings.iter().find(|mapping| mapping.id == id)
+    }
+}
@@ -0,0 +1,173 @@
+use crate::{
+    resample::{ChannelSelection, SampleSink},
+    Sample,
+};
+use anyhow::{anyhow, Result};
+use claxon::FlacReader;
+use hound::{SampleFormat, WavReader};
+use parking_lot::Mutex;
+use ringbuf::Producer;
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+use tracing::{error, info};
+
+/// Frames decoded at once.
+const CHUNK_FRAMES: usize = 1024;
+/// How long to wait for a buffer to be attached.
+const IDLE_DELAY: Duration = Duration::from_millis(10);
+
+enum Decoder {
+    Wav(WavReader<BufReader<File>>),
+    Flac(FlacReader<File>),
+}
+
+impl Decoder {
+    /// The format is guessed from the extension, WAV unless it is `.flac`.
+    fn open(path: &Path) -> Result<Self> {
+        let is_flac = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map_or(false, |ext| ext.eq_ignore_ascii_case("flac"));
+
+        if is_flac {
+            Ok(Self::Flac(FlacReader::open(path)?))
+        } else {
+            Ok(Self::Wav(WavReader::open(path)?))
+        }
+    }
+
+    /// Sample rate and amount of channels.
+    fn format(&self) -> (u32, usize) {
+        match self {
+            Self::Wav(reader) => {
+                let spec = reader.spec();
+                (spec.sample_rate, spec.channels as usize)
+            }
+            Self::Flac(reader) => {
+                let info = reader.streaminfo();
+                (info.sample_rate, info.channels as usize)
+            }
+        }
+    }
+
+    /// Decode up to `count` interleaved samples between -1 and 1, nothing is left at the end.
+    fn read(&mut self, count: usize, samples: &mut Vec<Sample>) -> Result<()> {
+        samples.clear();
+        match self {
+            Self::Wav(reader) => {
+                let spec = reader.spec();
+                match spec.sample_format {
+                    SampleFormat::Float => {
+                        for sample in reader.samples::<f32>().take(count) {
+                            samples.push(sample? as Sample);
+                        }
+                    }
+                    SampleFormat::Int => {
+                        let scale = (1u64 << (spec.bits_per_sample - 1)) as Sample;
+                        for sample in reader.samples::<i32>().take(count) {
+                            samples.push(sample? as Sample / scale);
+                        }
+                    }
+                }
+            }
+            Self::Flac(reader) => {
+                let scale = (1u64 << (reader.streaminfo().bits_per_sample - 1)) as Sample;
+                for sample in reader.samples().take(count) {
+                    samples.push(sample? as Sample / scale);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Audio source reading a WAV or FLAC file in real time, like if it was played.
+pub struct FileSource {
+    sink: Arc<Mutex<Option<SampleSink>>>,
+    sample_rate: u32,
+    channels: usize,
+    selection: ChannelSelection,
+    _thread: JoinHandle<()>,
+}
+
+impl FileSource {
+    pub fn new(path: PathBuf, selection: ChannelSelection) -> Result<Self> {
+        let decoder = Decoder::open(&path)?;
+        let (sample_rate, channels) = decoder.format();
+        if channels == 0 || sample_rate == 0 {
+            return Err(anyhow!("{} has no audio !", path.display()));
+        }
+        info!(
+            "Reading {} ({}Hz, {} channels)",
+            path.display(),
+            sample_rate,
+            channels
+        );
+
+        let sink = Arc::new(Mutex::new(None));
+        let thread_sink = sink.clone();
+        let thread = std::thread::Builder::new()
+            .name("File Reader Thread".into())
+            .spawn(
+                move || match Self::read_file(decoder, sample_rate, channels, &thread_sink) {
+                    Ok(()) => info!("End of {}", path.display()),
+                    Err(err) => error!("Failed to read {}: {}", path.display(), err),
+                },
+            )?;
+
+        Ok(Self {
+            sink,
+            sample_rate,
+            channels,
+            selection,
+            _thread: thread,
+        })
+    }
+
+    fn read_file(
+        mut decoder: Decoder, sample_rate: u32, channels: usize, sink: &Mutex<Option<SampleSink>>,
+    ) -> Result<()> {
+        let mut samples = Vec::with_capacity(CHUNK_FRAMES * channels);
+
+        // Don't start before someone listens
+        while sink.lock().is_none() {
+            std::thread::sleep(IDLE_DELAY);
+        }
+
+        let start = Instant::now();
+        let mut frames = 0u64;
+        loop {
+            decoder.read(CHUNK_FRAMES * channels, &mut samples)?;
+            if samples.is_empty() {
+                return Ok(());
+            }
+
+            if let Some(sink) = sink.lock().as_mut() {
+                sink.push(&mut samples.iter().copied());
+            }
+
+            // Wait until this chunk would have been played
+            frames += (samples.len() / channels) as u64;
+            let played = Duration::from_secs_f64(frames as f64 / sample_rate as f64);
+            if let Some(remaining) = played.checked_sub(start.elapsed()) {
+                std::thread::sleep(remaining);
+            }
+        }
+    }
+
+    /// Replace the buffer to which the samples will be pushed.
+    pub fn attach(&self, producer: Producer<Sample>) {
+        match SampleSink::new(producer, self.sample_rate, self.channels, self.selection) {
+            Ok(sink) => {
+                self.sink.lock().replace(sink);
+            }
+            Err(err) => error!("Can't use the file: {}", err),
+        }
+    }
+}
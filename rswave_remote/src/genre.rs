@@ -0,0 +1,102 @@
+use crate::spotify::TrackInfo;
+use anyhow::{anyhow, Error};
+use std::str::FromStr;
+use tracing::info;
+
+/// Used with `--genre-effects`, after the ones given by the user. The runners of the
+/// servers are 0 for the strobe, 1 for the color changes, 2 for the white flashes, 3 for the
+/// spectrum, then 4 and 5 for the effects following the layout of the LEDs.
+const DEFAULT_MAPPINGS: &[(&str, u8)] = &[
+    ("techno", 0),
+    ("house", 0),
+    ("edm", 0),
+    ("electro", 0),
+    ("dubstep", 0),
+    ("trance", 0),
+    ("drum and bass", 0),
+    ("metal", 0),
+    ("classical", 1),
+    ("ambient", 1),
+    ("jazz", 1),
+    ("folk", 1),
+    ("acoustic", 1),
+    ("piano", 1),
+    ("lo-fi", 1),
+    ("soul", 1),
+    ("rock", 2),
+    ("punk", 2),
+    ("hip hop", 2),
+    ("rap", 2),
+    ("pop", 2),
+];
+
+/// The runner shown for the tracks of the artists with a genre containing `genre`.
+#[derive(Clone, Debug)]
+pub struct GenreRunner {
+    pub genre: String,
+    pub runner: u8,
+}
+
+impl FromStr for GenreRunner {
+    type Err = Error;
+
+    /// Parses `genre=runner`, like `techno=0`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.rsplitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(runner), Some(genre)) if !genre.trim().is_empty() => Ok(Self {
+                genre: genre.trim().to_lowercase(),
+                runner: runner
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid runner `{}` !", runner))?,
+            }),
+            _ => Err(anyhow!("Expected a genre like techno=0 !")),
+        }
+    }
+}
+
+/// Picks the runner of each new track from the genres of its artist.
+pub struct GenreRunners {
+    mappings: Vec<GenreRunner>,
+    /// Key of the last track looked at.
+    current: Option<String>,
+}
+
+impl GenreRunners {
+    /// With `defaults`, a few common genres are mapped after the given ones.
+    pub fn new(mut mappings: Vec<GenreRunner>, defaults: bool) -> Self {
+        if defaults {
+            mappings.extend(DEFAULT_MAPPINGS.iter().map(|&(genre, runner)| GenreRunner {
+                genre: genre.to_string(),
+                runner,
+            }));
+        }
+
+        Self {
+            mappings,
+            current: None,
+        }
+    }
+
+    /// Returns the runner to show once per track, if one of its `genres` is known. The
+    /// mappings are tried in order, the genres of spotify are as precise as `german techno`.
+    pub fn update(&mut self, track: Option<&TrackInfo>, genres: &[String]) -> Option<u8> {
+        let track = track?;
+        if self.current.as_deref() == Some(track.key.as_str()) {
+            return None;
+        }
+        self.current = Some(track.key.clone());
+
+        let mapping = self.mappings.iter().find(|mapping| {
+            genres
+                .iter()
+                .any(|genre| genre.to_lowercase().contains(&mapping.genre))
+        })?;
+        info!(
+            "Showing runner {} for the {} of {}",
+            mapping.runner, mapping.genre, track.artist
+        );
+        Some(mapping.runner)
+    }
+}
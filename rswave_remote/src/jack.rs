@@ -0,0 +1,77 @@
+use crate::{
+    resample::{ChannelSelection, SampleSink},
+    Sample,
+};
+use ::jack::{
+    AsyncClient, AudioIn, Client, ClientOptions, ClosureProcessHandler, Control, ProcessScope,
+};
+use anyhow::Result;
+use parking_lot::Mutex;
+use ringbuf::Producer;
+use std::sync::Arc;
+use tracing::{error, info};
+
+type ProcessFn = Box<dyn FnMut(&Client, &ProcessScope) -> Control + Send>;
+
+/// Audio source exposing two JACK input ports, to be wired to any output of the graph
+/// (a mixer, a DJ software...).
+pub struct JackSource {
+    sink: Arc<Mutex<Option<SampleSink>>>,
+    sample_rate: u32,
+    _client: AsyncClient<(), ClosureProcessHandler<ProcessFn>>,
+}
+
+impl JackSource {
+    /// Register the client as `name`, its ports are `<name>:in_left` and `<name>:in_right`.
+    pub fn new(name: &str) -> Result<Self> {
+        let (client, _) = Client::new(name, ClientOptions::NO_START_SERVER)?;
+        let sample_rate = client.sample_rate() as u32;
+
+        let left = client.register_port("in_left", AudioIn::default())?;
+        let right = client.register_port("in_right", AudioIn::default())?;
+        info!(
+            "JACK ports registered as {}:in_left and {}:in_right",
+            name, name
+        );
+
+        let sink = Arc::new(Mutex::new(None));
+        let process_sink: Arc<Mutex<Option<SampleSink>>> = sink.clone();
+        let process: ProcessFn = Box::new(move |_: &Client, scope: &ProcessScope| {
+            // Don't block the realtime thread if the buffer is being replaced
+            if let Some(mut sink) = process_sink.try_lock() {
+                if let Some(sink) = sink.as_mut() {
+                    // Interleave the channels
+                    sink.push(
+                        &mut left
+                            .as_slice(scope)
+                            .iter()
+                            .zip(right.as_slice(scope))
+                            .flat_map(|(&left, &right)| {
+                                std::iter::once(left as Sample)
+                                    .chain(std::iter::once(right as Sample))
+                            }),
+                    );
+                }
+            }
+            Control::Continue
+        });
+
+        let client = client.activate_async((), ClosureProcessHandler::new(process))?;
+
+        Ok(Self {
+            sink,
+            sample_rate,
+            _client: client,
+        })
+    }
+
+    /// Replace the buffer to which the samples will be pushed.
+    pub fn attach(&self, producer: Producer<Sample>) {
+        match SampleSink::new(producer, self.sample_rate, 2, ChannelSelection::Downmix) {
+            Ok(sink) => {
+                self.sink.lock().replace(sink);
+            }
+            Err(err) => error!("Can't resample from {}Hz: {}", self.sample_rate, err),
+        }
+    }
+}
@@ -1,33 +1,175 @@
+use crate::{
+    audio::{NoveltyWeight, Weighting},
+    context::ContextPreset,
+    genre::GenreRunner,
+    resample::ChannelSelection,
+    zone::ZoneBand,
+};
+use anyhow::anyhow;
+use rswave_common::{auth::Psk, logging::LogFormat, transport::TransportKind};
+use std::{path::PathBuf, str::FromStr};
 use structopt::StructOpt;
 
+pub use rswave_common::audio;
+
+/// Type of the samples through the whole audio pipeline.
+#[cfg(not(feature = "f32"))]
+pub type Sample = f64;
+/// Type of the samples through the whole audio pipeline.
+#[cfg(feature = "f32")]
+pub type Sample = f32;
+
+/// The TUI and the packets always work with f64.
+#[inline]
+#[allow(clippy::useless_conversion)]
+pub fn to_f64(sample: Sample) -> f64 {
+    sample.into()
+}
+
+/// The pipeline works with samples between -1 and 1, like the float formats.
+#[inline]
+pub fn from_i16(sample: i16) -> Sample {
+    sample as Sample / 32768.0
+}
+
 pub mod app;
 pub mod async_app;
-pub mod audio;
+pub mod context;
+#[cfg(feature = "source_file")]
+pub mod file;
+pub mod genre;
+#[cfg(feature = "source_jack")]
+pub mod jack;
+#[cfg(feature = "midi")]
+pub mod midi;
+pub mod mpd;
+#[cfg(feature = "source_mpris")]
+pub mod mpris;
 pub mod net;
+pub mod palette;
+#[cfg(feature = "source_pipewire")]
+pub mod pipewire;
+pub mod preview;
+pub mod pulse;
+pub mod record;
+pub mod replay;
+pub mod resample;
 pub mod spotify;
+#[cfg(feature = "source_librespot")]
+pub mod spotify_connect;
+pub mod tap;
+pub mod tempo;
+pub mod zone;
 
 #[derive(Clone, Debug, StructOpt)]
 pub struct Opt {
-    /// Address to bind to.
+    /// Address of the server, can be given several times to drive multiple servers at once.
     #[structopt(short = "a", long)]
-    pub address: Option<String>,
+    pub address: Vec<String>,
 
     /// A pattern to help take the right device.
     /// Enabling this means disabling the manual selection of device.
     #[structopt(short, long)]
     pub device_hint: Option<String>,
 
+    /// Capture what the computer is playing instead of an input device, through the monitor
+    /// of the default PulseAudio (or PipeWire) sink, or WASAPI loopback on Windows.
+    #[structopt(long, conflicts_with = "device_hint")]
+    pub capture_output: bool,
+
+    /// Capture from PipeWire directly instead of an ALSA device, by default what the default
+    /// sink plays (requires the `source_pipewire` feature).
+    #[cfg(feature = "source_pipewire")]
+    #[structopt(long)]
+    pub pipewire: bool,
+
+    /// Only capture this node with PipeWire, its name or serial (see `pw-cli ls Node`).
+    /// Use the stream of the music player so notification sounds are ignored.
+    #[cfg(feature = "source_pipewire")]
+    #[structopt(long, requires = "pipewire")]
+    pub pipewire_target: Option<String>,
+
+    /// Register a JACK client with this name and analyse what is wired to its ports
+    /// instead of capturing an audio device (requires the `source_jack` feature).
+    #[cfg(feature = "source_jack")]
+    #[structopt(long)]
+    pub jack_name: Option<String>,
+
+    /// Analyse a WAV or FLAC file, read in real time like if it was played, instead of
+    /// capturing an audio device (requires the `source_file` feature).
+    #[cfg(feature = "source_file")]
+    #[structopt(long)]
+    pub input_file: Option<PathBuf>,
+
+    /// Which channels of the device to analyse, either `downmix` to average all of them
+    /// into stereo or a pair of channels starting from 0, like `2,3`.
+    #[structopt(long, default_value = "downmix")]
+    pub channels: ChannelSelection,
+
     /// Sample size for audio.
     /// It isn't recommended to change it at all but if you
     /// do so make sure that it is a power of two.
     #[structopt(short, long, default_value = "2048")]
     pub sample_size: usize,
 
+    /// Act as a Spotify Connect device with this name and analyse its audio
+    /// directly instead of capturing an audio device.
+    #[cfg(feature = "source_librespot")]
+    #[structopt(long)]
+    pub librespot_name: Option<String>,
+
+    /// Spotify username of the Spotify Connect device.
+    #[cfg(feature = "source_librespot")]
+    #[structopt(long, env)]
+    pub librespot_username: Option<String>,
+
+    /// Spotify password of the Spotify Connect device.
+    #[cfg(feature = "source_librespot")]
+    #[structopt(long, env)]
+    pub librespot_password: Option<String>,
+
     /// Compression to use for the logarithmic compression of the spectrum.
     /// The higher the value, the more importance the little values will get.
     #[structopt(long, default_value = "1000")]
     pub spectrum_compression: f64,
 
+    /// Time in seconds for the tracked peaks of the signal and spectrum to lose half of
+    /// their value, so one loud transient doesn't flatten everything after it.
+    /// 0 keeps them forever.
+    #[structopt(long, default_value = "10")]
+    pub peak_half_life: f64,
+
+    /// Seconds of silence after which the servers are told to show their standby,
+    /// until the music resumes. 0 never tells them.
+    #[structopt(long, default_value = "5")]
+    pub silence_standby: f64,
+
+    /// Cutoff in Hz of a high-pass filter applied before the analysis, something like 30
+    /// removes the DC offset and the rumble of cheap capture devices.
+    #[structopt(long)]
+    pub highpass: Option<f64>,
+
+    /// Cutoff in Hz of a low-pass filter applied before the analysis, against the hiss.
+    #[structopt(long)]
+    pub lowpass: Option<f64>,
+
+    /// Frequency weighting of the spectrum, `a` follows the sensitivity of our ears so
+    /// inaudible rumble doesn't drive the lights.
+    /// Possible values: flat, a.
+    #[structopt(long, default_value = "flat")]
+    pub weighting: Weighting,
+
+    /// Group the spectrum into this many mel bands and compute the novelty on them,
+    /// which follows what we hear better than the raw bins. Something like 40 works well.
+    #[structopt(long)]
+    pub mel_bands: Option<usize>,
+
+    /// Gain of a frequency range in Hz in the novelty, like `40-150=4` to follow the kicks
+    /// of a track full of hi-hats. Can be given several times, the first range containing
+    /// a frequency is used.
+    #[structopt(long)]
+    pub novelty_weight: Vec<NoveltyWeight>,
+
     /// Buffer size for the novelty curve.
     /// This is mainly to have a pretty curve to look at.
     /// However it must always be superior or equal to the short term
@@ -41,15 +183,142 @@ pub struct Opt {
     #[structopt(long, default_value = "50")]
     pub novelty_size_st: usize,
 
+    /// How many times above the recent median the novelty must be to be an onset.
+    /// Lower it to detect more notes and hits.
+    #[structopt(long, default_value = "1.5")]
+    pub onset_threshold: f64,
+
+    /// Send the novelty of a frequency range in Hz to the zone of the servers with this name,
+    /// like `floor:20-250` for the bass. Can be given up to 8 times, the other zones get the
+    /// novelty of the whole spectrum.
+    #[structopt(long)]
+    pub zone: Vec<ZoneBand>,
+
     /// Disable the TUI.
     #[structopt(short = "t", long)]
     pub no_tui: bool,
 
+    /// Write the analysis of every frame to this file, as JSON lines if it ends with `.jsonl`
+    /// or as CSV otherwise.
+    #[structopt(long)]
+    pub record: Option<PathBuf>,
+
+    /// Send a recording made with `--record` to the servers, with its original timing,
+    /// instead of analysing any audio.
+    #[structopt(long)]
+    pub replay: Option<PathBuf>,
+
+    /// Without TUI, log a summary of the analysis and the network every this many seconds.
+    /// 0 to disable.
+    #[structopt(long, default_value = "60")]
+    pub stats_interval: f64,
+
+    /// How to print the logs, the verbosity is set with `RUST_LOG`.
+    /// Possible values: text, json.
+    #[structopt(long, default_value = "text")]
+    pub log_format: LogFormat,
+
+    /// Write the logs to this file instead of stderr.
+    /// The TUI owns the terminal, so they go to `rswave_remote.log` in the temporary
+    /// directory by default when it is enabled.
+    #[structopt(long)]
+    pub log_file: Option<PathBuf>,
+
+    /// How to talk with the server, it must use the same.
+    /// Possible values: udp, tcp, quic (requires the `transport_quic` feature).
+    #[structopt(long, default_value = "udp")]
+    pub transport: TransportKind,
+
+    /// Passphrase shared with the server, required if the server has one.
+    #[structopt(long, env = "RSWAVE_PSK", hide_env_values = true)]
+    pub psk: Option<Psk>,
+
     /// Disable ACK checks, this also means that if the remote goes down
     /// we won't be notified and will continue sending data
     #[structopt(long)]
     pub no_ack: bool,
 
+    /// How long the servers wait before showing the data, in milliseconds, so that several
+    /// servers show it at the same time whatever their latency. Only used with several servers,
+    /// 0 to show the data as soon as it arrives.
+    #[structopt(long, default_value = "50")]
+    pub sync_delay: u64,
+
+    /// Part of the loudness of the spotify segments in the novelty sent, between 0 and 1, so
+    /// the intensity holds up when the microphone picks up the room. Only with spotify.
+    #[structopt(long, default_value = "0")]
+    pub segment_blend: f64,
+
+    /// Only send the beats and the tempo to the servers, a few packets per second at most.
+    /// For remotes on a battery or a flaky link, requires spotify.
+    #[structopt(long)]
+    pub beats_only: bool,
+
+    /// Send the energy of 24 Bark bands along with the novelty and the beats, for the
+    /// effects of the servers spreading the spectrum over the strip.
+    #[structopt(long, conflicts_with = "beats-only")]
+    pub bands: bool,
+
+    /// Find the beats in the audio without spotify, they are less accurate than the ones of
+    /// spotify. With spotify, this is only done for the tracks it doesn't have.
+    #[structopt(long)]
+    pub tempo_tracking: bool,
+
+    /// Take beats from a MIDI controller or a DAW, on the first input whose name contains
+    /// this (any input when empty): the notes and the clock are beats, the program changes
+    /// select the runner of the servers (requires the `midi` feature).
+    #[cfg(feature = "midi")]
+    #[structopt(long)]
+    pub midi: Option<String>,
+
+    /// The control change setting the brightness of the servers with `--midi`.
+    #[cfg(feature = "midi")]
+    #[structopt(long, default_value = "7")]
+    pub midi_brightness_cc: u8,
+
+    /// Where to get the currently playing track from.
+    /// Possible values: spotify, mpris (requires the `source_mpris` feature), mpd.
+    /// The track analysis always comes from spotify.
+    #[structopt(long, default_value = "spotify")]
+    pub source: TrackSource,
+
+    /// Where the colors sent to the servers come from, only with spotify.
+    /// Possible values: album for the album art, features for a gradient from the energy
+    /// and the danceability of the track.
+    #[structopt(long, default_value = "album")]
+    pub palette_source: PaletteSource,
+
+    /// Recall a preset of the servers when a spotify playlist, album or artist starts playing,
+    /// `<id>=<preset>` like `37i9dQZF1DX4WYpdgoIcn6=2`, the id can also be its URI or link.
+    /// Can be given several times, only works with the spotify source.
+    #[structopt(long)]
+    pub context_preset: Vec<ContextPreset>,
+
+    /// Show a runner of the servers picked from the genres of the artist on each new track,
+    /// like techno for the strobe and classical for the color changes.
+    /// The preset of `--context-preset` wins.
+    #[structopt(long)]
+    pub genre_effects: bool,
+
+    /// The runner for a genre, tried before the ones of `--genre-effects`, `<genre>=<runner>`
+    /// like `german techno=2`. Can be given several times.
+    #[structopt(long)]
+    pub genre_runner: Vec<GenreRunner>,
+
+    /// A pattern to help find the right MPRIS player, otherwise the active one is used.
+    #[cfg(feature = "source_mpris")]
+    #[structopt(long)]
+    pub player_hint: Option<String>,
+
+    /// Address of the MPD server.
+    #[structopt(long, default_value = "127.0.0.1:6600")]
+    pub mpd_address: String,
+
+    /// Analyse the audio written by an MPD fifo output instead of capturing an audio device.
+    /// The output must be configured with `format "44100:16:2"`.
+    #[structopt(long, parse(from_os_str))]
+    pub mpd_fifo: Option<PathBuf>,
+
     /// Maximum interval between calls to the spotify API to check for
     /// the currently playing track.
     /// Too much requests will be rate limited so stay reasonable.
@@ -69,4 +338,70 @@ pub struct Opt {
     /// instead ask the user to log in again.
     #[structopt(long)]
     pub spotify_auth_fresh: bool,
+
+    /// Don't try to open a browser to log in, print the authorization URL instead
+    /// and paste the URL you were redirected to once logged in on any device.
+    #[structopt(long)]
+    pub spotify_headless: bool,
+
+    /// Authorization code obtained by logging in on another device.
+    /// This implies --spotify-headless and bypasses the cached token.
+    #[structopt(long)]
+    pub auth_code: Option<String>,
+
+    /// Redirect URI registered in the spotify dashboard for this app.
+    /// It doesn't need to be reachable, the code will be in the URL.
+    #[structopt(long, default_value = "http://localhost/")]
+    pub spotify_redirect_uri: String,
+}
+
+impl Opt {
+    /// The beats come from somewhere even without spotify.
+    pub fn finds_beats(&self) -> bool {
+        #[cfg(feature = "midi")]
+        if self.midi.is_some() {
+            return true;
+        }
+        self.tempo_tracking
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TrackSource {
+    Spotify,
+    #[cfg(feature = "source_mpris")]
+    Mpris,
+    Mpd,
+}
+
+impl FromStr for TrackSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "spotify" => Ok(Self::Spotify),
+            #[cfg(feature = "source_mpris")]
+            "mpris" => Ok(Self::Mpris),
+            "mpd" => Ok(Self::Mpd),
+            _ => Err(anyhow!("Unknown track source !")),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PaletteSource {
+    Album,
+    Features,
+}
+
+impl FromStr for PaletteSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "album" => Ok(Self::Album),
+            "features" => Ok(Self::Features),
+            _ => Err(anyhow!("Unknown palette source !")),
+        }
+    }
 }
@@ -1,44 +1,116 @@
 use anyhow::bail;
-use rswave_remote::app::App;
-use std::time::Duration;
+use rswave_common::logging;
+use rswave_remote::{app::App, async_app::app::App as HeadlessApp, replay, Opt};
+use std::{
+    fs::OpenOptions,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use structopt::StructOpt;
 use tokio::sync::oneshot::error::TryRecvError;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let app = App::new().await?;
+    let opt: Opt = Opt::from_args();
+    init_logging(&opt)?;
+
+    if let Some(path) = opt.replay.clone() {
+        return run_replay(opt, path).await;
+    }
+
+    if opt.no_tui {
+        return run_headless(opt).await;
+    }
+
+    let app = App::new(opt).await?;
     let mut app = app.lock();
 
+    // Still needed when the signal doesn't come from the keyboard
     let (sender, mut ctrl_c_receiver) = tokio::sync::oneshot::channel();
-    let ctrl_c_handle = tokio::task::spawn(async move {
+    tokio::task::spawn(async move {
         tokio::signal::ctrl_c()
             .await
             .expect("Failed to wait for Ctrl+C");
-        sender.send(true).expect("Failed to send Ctrl+C ok");
+        // Nobody to tell if we quit with a key
+        let _ = sender.send(true);
     });
 
     app.start_recording()?;
 
     loop {
         match ctrl_c_receiver.try_recv() {
-            Err(TryRecvError::Empty) => {
-                // Ok, continue the loop
-                if app.can_run() {
-                    app.run_once().await?;
-                    app.draw();
-                } else {
-                    tokio::time::delay_for(Duration::from_millis(10)).await;
-                }
-            }
+            Err(TryRecvError::Empty) => {}
             Ok(true) => {
                 // We need to exit
                 break;
             }
             _ => bail!("Something went wrong waiting for Ctrl+C !"),
         }
-    }
 
-    ctrl_c_handle.await?;
+        if !app.handle_input()? {
+            break;
+        }
+
+        if app.can_run() {
+            app.run_once().await?;
+            app.draw();
+        } else {
+            app.keepalive();
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+        }
+    }
 
     app.cleanup()?;
     Ok(())
 }
+
+/// Anything printed on the terminal would mess up the TUI, the logs go to a file instead.
+fn init_logging(opt: &Opt) -> anyhow::Result<()> {
+    let path = match opt.log_file.clone() {
+        Some(path) => Some(path),
+        None if !opt.no_tui => Some(std::env::temp_dir().join("rswave_remote.log")),
+        None => None,
+    };
+
+    let file = match path {
+        Some(path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+        None => None,
+    };
+    logging::init(opt.log_format, file);
+    Ok(())
+}
+
+/// Without TUI, every stage of the analysis runs on its own task.
+async fn run_headless(opt: Opt) -> anyhow::Result<()> {
+    let mut app = HeadlessApp::new(opt).await?;
+
+    let res = app
+        .run(async {
+            tokio::signal::ctrl_c()
+                .await
+                .expect("Failed to wait for Ctrl+C");
+        })
+        .await;
+
+    app.stop().await?;
+    Ok(res?)
+}
+
+/// No audio and no TUI, only the recording and the servers.
+async fn run_replay(opt: Opt, path: PathBuf) -> anyhow::Result<()> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let ctrl_c_stop = stop.clone();
+    tokio::task::spawn(async move {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to wait for Ctrl+C");
+        ctrl_c_stop.store(true, Ordering::Relaxed);
+    });
+
+    // The sockets are blocking ones
+    tokio::task::spawn_blocking(move || replay::replay(&opt, &path, &stop)).await?
+}
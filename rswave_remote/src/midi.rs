@@ -0,0 +1,173 @@
+use crate::net::Analysis;
+use anyhow::{anyhow, Result};
+use midir::{Ignore, MidiInput, MidiInputConnection};
+use rswave_common::packets::Command;
+use std::{
+    sync::mpsc::{self, Receiver, Sender},
+    time::Instant,
+};
+use tracing::info;
+
+/// Pulses of the MIDI clock per beat.
+const CLOCK_PULSES_PER_BEAT: u32 = 24;
+
+enum MidiEvent {
+    /// A note was played.
+    Beat,
+    /// A pulse of the clock.
+    Clock(Instant),
+    /// The clock starts from the first beat.
+    Start,
+    Continue,
+    Stop,
+    Runner(u8),
+    Brightness(u8),
+}
+
+/// Beats, runners and brightness from a MIDI controller or a DAW: the notes and the clock
+/// are beats, the program changes select the runner and a control change sets the
+/// brightness.
+pub struct MidiTrigger {
+    events: Receiver<MidiEvent>,
+    /// The clock is counted between its start and its stop.
+    running: bool,
+    pulses: u32,
+    last_clock_beat: Option<Instant>,
+    /// Beats per minute of the clock, 0 when unknown.
+    tempo: f64,
+}
+
+impl MidiTrigger {
+    /// Listen to the first input whose name contains `name`, any input if it is empty.
+    pub fn open(name: &str, brightness_cc: u8) -> Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let (ready_sender, ready) = mpsc::channel();
+        let name = name.to_string();
+
+        // The connection stays on its own thread, it can't always be sent to another one
+        std::thread::Builder::new()
+            .name("MIDI Thread".into())
+            .spawn(move || {
+                let connection = Self::connect(&name, brightness_cc, sender);
+                let failed = connection.is_err();
+                let _ = ready_sender.send(connection.map(|_| ()));
+                if failed {
+                    return;
+                }
+                // Keep the connection alive with the remote
+                loop {
+                    std::thread::park();
+                }
+            })?;
+
+        ready
+            .recv()
+            .map_err(|_| anyhow!("The MIDI thread died !"))??;
+        Ok(Self {
+            events,
+            running: true,
+            pulses: 0,
+            last_clock_beat: None,
+            tempo: 0.0,
+        })
+    }
+
+    fn connect(
+        name: &str, brightness_cc: u8, sender: Sender<MidiEvent>,
+    ) -> Result<MidiInputConnection<()>> {
+        let mut input =
+            MidiInput::new("rswave").map_err(|err| anyhow!("Failed to open MIDI: {}", err))?;
+        // The clock is needed
+        input.ignore(Ignore::SysexAndActiveSense);
+
+        let port = input
+            .ports()
+            .into_iter()
+            .find(|port| {
+                input
+                    .port_name(port)
+                    .map_or(false, |port_name| port_name.contains(name))
+            })
+            .ok_or_else(|| anyhow!("No MIDI input matching `{}` !", name))?;
+        let port_name = input.port_name(&port)?;
+        info!("Listening to the MIDI input {}", port_name);
+
+        input
+            .connect(
+                &port,
+                "rswave-in",
+                move |_, message, _| {
+                    if let Some(event) = Self::parse(message, brightness_cc) {
+                        let _ = sender.send(event);
+                    }
+                },
+                (),
+            )
+            .map_err(|err| anyhow!("Failed to connect to {}: {}", port_name, err))
+    }
+
+    fn parse(message: &[u8], brightness_cc: u8) -> Option<MidiEvent> {
+        // The channel doesn't matter
+        let kind = message.first()? & 0xF0;
+        match *message {
+            [0xF8] => Some(MidiEvent::Clock(Instant::now())),
+            [0xFA] => Some(MidiEvent::Start),
+            [0xFB] => Some(MidiEvent::Continue),
+            [0xFC] => Some(MidiEvent::Stop),
+            // A note on with a null velocity is a note off
+            [_, _, velocity] if kind == 0x90 && velocity > 0 => Some(MidiEvent::Beat),
+            // From 0 to 127
+            [_, controller, value] if kind == 0xB0 && controller == brightness_cc => {
+                Some(MidiEvent::Brightness(value << 1 | value >> 6))
+            }
+            [_, program] if kind == 0xC0 => Some(MidiEvent::Runner(program)),
+            _ => None,
+        }
+    }
+
+    /// Add the beats received since the last frame to the analysis, the tempo of the clock
+    /// wins. Returns the commands for the servers.
+    pub fn fill_in(&mut self, analysis: &mut Analysis) -> Vec<Command> {
+        // A fader sends a message for every step, only where it stopped is sent
+        let mut runner = None;
+        let mut brightness = None;
+        for event in self.events.try_iter() {
+            match event {
+                MidiEvent::Beat => analysis.beat = true,
+                MidiEvent::Clock(at) if self.running => {
+                    if self.pulses % CLOCK_PULSES_PER_BEAT == 0 {
+                        analysis.beat = true;
+                        if let Some(last) = self.last_clock_beat {
+                            self.tempo = 60.0 / (at - last).as_secs_f64();
+                        }
+                        self.last_clock_beat = Some(at);
+                    }
+                    self.pulses += 1;
+                }
+                MidiEvent::Clock(_) => {}
+                MidiEvent::Start => {
+                    self.running = true;
+                    self.pulses = 0;
+                    self.last_clock_beat = None;
+                }
+                MidiEvent::Continue => self.running = true,
+                MidiEvent::Stop => {
+                    self.running = false;
+                    self.last_clock_beat = None;
+                    self.tempo = 0.0;
+                }
+                MidiEvent::Runner(index) => runner = Some(index),
+                MidiEvent::Brightness(level) => brightness = Some(level),
+            }
+        }
+
+        if self.tempo > 0.0 {
+            analysis.tempo = self.tempo;
+        }
+        runner
+            .map(Command::Runner)
+            .into_iter()
+            .chain(brightness.map(Command::Brightness))
+            .collect()
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{from_i16, spotify::TrackInfo, Sample};
+use ::mpd::{Client, State};
+use anyhow::Result;
+use parking_lot::Mutex;
+use ringbuf::Producer;
+use std::{fs::File, io::Read, path::PathBuf, sync::Arc, thread::JoinHandle, time::Duration};
+use tracing::error;
+
+/// Reads the current track from an MPD server.
+pub struct MpdTracker {
+    address: String,
+    client: Option<Client>,
+}
+
+impl MpdTracker {
+    pub fn new(address: String) -> Result<Self> {
+        let client = Client::connect(address.as_str())?;
+        Ok(Self {
+            address,
+            client: Some(client),
+        })
+    }
+
+    /// Query the server for its current song, reconnect if the connection was lost.
+    pub fn current_track(&mut self) -> Option<TrackInfo> {
+        if self.client.is_none() {
+            self.client = Client::connect(self.address.as_str()).ok();
+        }
+        let client = self.client.as_mut()?;
+
+        let (status, song) = match (client.status(), client.currentsong()) {
+            (Ok(status), Ok(song)) => (status, song?),
+            _ => {
+                // Try again from scratch next time
+                self.client = None;
+                return None;
+            }
+        };
+
+        if let State::Stop = status.state {
+            return None;
+        }
+
+        Some(TrackInfo {
+            spotify_id: None,
+            name: song.title.clone().unwrap_or_else(|| song.file.clone()),
+            artist: song.artist.clone().unwrap_or_default(),
+            artist_id: None,
+            context_uri: None,
+            duration_ms: status
+                .duration
+                .or(song.duration)
+                .map_or(0, |duration| duration.as_millis() as u32),
+            progress_ms: status
+                .elapsed
+                .map_or(0, |elapsed| elapsed.as_millis() as u32),
+            album_art_url: None,
+            key: song.file,
+        })
+    }
+}
+
+/// Audio source reading the raw PCM written by an MPD fifo output.
+/// The output must be configured with `format "44100:16:2"`.
+pub struct MpdFifo {
+    producer: Arc<Mutex<Option<Producer<Sample>>>>,
+    _thread: JoinHandle<()>,
+}
+
+impl MpdFifo {
+    pub fn new(path: PathBuf) -> Result<Self> {
+        let producer = Arc::new(Mutex::new(None));
+        let thread_producer = producer.clone();
+
+        let thread = std::thread::Builder::new()
+            .name("MPD Fifo Thread".into())
+            .spawn(move || loop {
+                // Opening blocks until MPD opens its side
+                match File::open(&path) {
+                    Ok(fifo) => Self::read_fifo(fifo, &thread_producer),
+                    Err(err) => error!("Failed to open MPD fifo: {}", err),
+                }
+
+                // Wait for a bit and retry
+                std::thread::sleep(Duration::from_millis(500));
+            })?;
+
+        Ok(Self {
+            producer,
+            _thread: thread,
+        })
+    }
+
+    fn read_fifo(mut fifo: File, producer: &Mutex<Option<Producer<Sample>>>) {
+        let mut buffer = [0u8; 4096];
+
+        // Fails when MPD closes the fifo
+        while let Ok(()) = fifo.read_exact(&mut buffer) {
+            if let Some(producer) = producer.lock().as_mut() {
+                // Signed 16 bits little endian
+                producer.push_iter(
+                    &mut buffer
+                        .chunks_exact(2)
+                        .map(|bytes| from_i16(i16::from_le_bytes([bytes[0], bytes[1]]))),
+                );
+            }
+        }
+    }
+
+    /// Replace the buffer to which the samples will be pushed.
+    pub fn attach(&self, producer: Producer<Sample>) {
+        self.producer.lock().replace(producer);
+    }
+}
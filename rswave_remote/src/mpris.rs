@@ -0,0 +1,85 @@
+use crate::spotify::TrackInfo;
+use ::mpris::{PlaybackStatus, Player, PlayerFinder};
+use anyhow::Result;
+
+/// Reads the current track from any MPRIS compatible media player.
+pub struct MprisTracker {
+    finder: PlayerFinder,
+    player: Option<Player>,
+    player_hint: Option<String>,
+}
+
+impl MprisTracker {
+    /// Without a hint, the most active player will be used.
+    pub fn new(player_hint: Option<String>) -> Result<Self> {
+        Ok(Self {
+            finder: PlayerFinder::new()?,
+            player: None,
+            player_hint,
+        })
+    }
+
+    /// Query the player for its current track, look for another player if it is gone.
+    pub fn current_track(&mut self) -> Option<TrackInfo> {
+        if self
+            .player
+            .as_ref()
+            .map_or(true, |player| !player.is_running())
+        {
+            self.player = self.find_player();
+        }
+        let player = self.player.as_ref()?;
+
+        match player.get_playback_status() {
+            Ok(PlaybackStatus::Playing) | Ok(PlaybackStatus::Paused) => {}
+            _ => return None,
+        }
+
+        let metadata = player.get_metadata().ok()?;
+        let name = metadata.title()?.to_string();
+        let artist = metadata
+            .artists()
+            .and_then(|artists| artists.first().map(|artist| artist.to_string()))
+            .unwrap_or_default();
+        let track_id = metadata.track_id().map(String::from);
+
+        // The spotify client exposes its IDs, no need to search the track later
+        let spotify_id = track_id
+            .as_deref()
+            .and_then(|id| id.strip_prefix("/com/spotify/track/"))
+            .or_else(|| {
+                metadata
+                    .url()
+                    .and_then(|url| url.strip_prefix("https://open.spotify.com/track/"))
+            })
+            .map(String::from);
+
+        Some(TrackInfo {
+            key: track_id.unwrap_or_else(|| format!("{} - {}", name, artist)),
+            spotify_id,
+            duration_ms: metadata
+                .length()
+                .map_or(0, |length| length.as_millis() as u32),
+            progress_ms: player
+                .get_position()
+                .map_or(0, |position| position.as_millis() as u32),
+            album_art_url: metadata.art_url().map(String::from),
+            name,
+            artist,
+            artist_id: None,
+            context_uri: None,
+        })
+    }
+
+    fn find_player(&self) -> Option<Player> {
+        if let Some(hint) = self.player_hint.as_ref() {
+            let hint = hint.to_lowercase();
+            self.finder.find_all().ok()?.into_iter().find(|player| {
+                player.identity().to_lowercase().contains(&hint)
+                    || player.bus_name().to_lowercase().contains(&hint)
+            })
+        } else {
+            self.finder.find_active().ok()
+        }
+    }
+}
@@ -1,155 +1,960 @@
-use crate::{audio::AudioProcessor, spotify::SpotifyTracker};
+use crate::{
+    audio::AudioProcessor,
+    spotify::{SpotifyTracker, TrackFeatures},
+    to_f64, Sample,
+};
 use anyhow::{anyhow, Result};
 use rswave_common::{
+    auth::Psk,
+    codec::PacketCodec,
     packets::{
-        AckPacket, DataMode, GoodbyeData, HelloPacket, NoveltyBeatsModeData,
-        NoveltyBeatsModePacket, NoveltyModeData, NoveltyModePacket, SetModePacket,
-    },
-    rkyv::{
-        archived_value,
-        ser::{serializers::WriteSerializer, Serializer},
-        Aligned, Archived, Serialize,
+        timestamp_us, AckPacket, BandsModeData, BandsModePacket, BeatData, BeatsOnlyModePacket,
+        Command, CommandData, DataMode, FeaturesData, GoodbyeData, HelloPacket,
+        NoveltyBeatsModeData, NoveltyBeatsModePacket, NoveltyModeData, NoveltyModePacket,
+        PaletteData, SetModePacket, StandbyData, ZoneData, BARK_BANDS, MAX_PALETTE_COLORS,
+        NO_PITCH_CLASS,
     },
-    MAGIC,
+    rkyv::{ser::serializers::WriteSerializer, Archived, Serialize},
+    sequence::SequenceTracker,
+    transport::{Transport, TransportKind, TransportSocket},
+    HEARTBEAT_INTERVAL, MAGIC,
 };
-use std::net::UdpSocket;
+use std::{
+    borrow::Cow,
+    io::ErrorKind,
+    time::{Duration, Instant},
+};
+use tracing::{error, instrument, warn};
+
+/// How long to wait for an answer of the server before considering it gone.
+const RECV_TIMEOUT: Duration = Duration::from_millis(500);
+/// How long to wait for the ACK of a control packet before sending it again.
+const CONTROL_ACK_TIMEOUT: Duration = Duration::from_millis(150);
+/// Sending a control packet this many times without ACK means the server is gone.
+const CONTROL_ATTEMPTS: u32 = 4;
+const MIN_BACKOFF: Duration = Duration::from_millis(250);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+/// How long to wait for the ACK of the data packet just sent, so that the RTT is measured
+/// when it arrives and not at the next send. A late ACK is read with the next packet.
+const DATA_ACK_WAIT: Duration = Duration::from_millis(5);
+/// Amount of data packets remembered to match them with their ACK.
+const IN_FLIGHT_SIZE: usize = 64;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connected,
+    /// The connection was lost, `attempts` handshakes failed since then.
+    Reconnecting {
+        attempts: u32,
+    },
+}
+
+/// The error to report when the server answers something else than the expected ACK.
+fn unexpected_answer(ack: &Archived<AckPacket>) -> anyhow::Error {
+    match ack {
+        Archived::<AckPacket>::Abort(reason) => anyhow!("Aborted by the server: {}", reason),
+        Archived::<AckPacket>::Quit => anyhow!("The server quit !"),
+        _ => anyhow!("Unexpected answer: {:?}", ack),
+    }
+}
+
+/// Everything sent to the servers for one frame of audio.
+#[derive(Debug, Clone)]
+pub struct Analysis<'a> {
+    pub novelty: f64,
+    pub novelty_peak: f64,
+    pub beat: bool,
+    pub onset: bool,
+    /// Dominant pitch class, 0 being C.
+    pub pitch_class: Option<u8>,
+    /// From -1 on the left to 1 on the right.
+    pub balance: f64,
+    /// Between 0 and 1.
+    pub loudness: f64,
+    /// The loudness follows the segments of the track, it can be blended with the novelty.
+    pub segments: bool,
+    /// Beats per minute, 0 when unknown.
+    pub tempo: f64,
+    pub palette: Option<Cow<'a, [[u8; 3]]>>,
+    pub features: Option<TrackFeatures>,
+    /// The audio has been silent for a while.
+    pub silent: bool,
+    /// Novelty of the frequency ranges of `--zone`.
+    pub zones: Vec<ZoneData>,
+    /// Energy of the Bark bands relative to their peaks, 255 being the peak.
+    pub bands: [u8; BARK_BANDS],
+}
+
+impl<'a> Analysis<'a> {
+    /// Be sure to have processed the audio and advanced spotify before.
+    pub fn new(audio: &AudioProcessor<Sample>, spotify: Option<&'a SpotifyTracker>) -> Self {
+        Self {
+            novelty: to_f64(audio.novelty()),
+            novelty_peak: to_f64(audio.novelty_peak_short_term()),
+            beat: spotify.map_or(false, |s| s.is_beat()),
+            onset: audio.is_onset(),
+            pitch_class: audio.dominant_pitch_class(),
+            balance: to_f64(audio.balance()),
+            loudness: spotify.map_or(0.0, |s| s.segment_loudness() as f64),
+            segments: spotify.map_or(false, |s| s.has_segments()),
+            tempo: spotify
+                .map(|s| s.tempo())
+                .filter(|&tempo| tempo < f32::MAX)
+                .map_or(0.0, |tempo| tempo as f64),
+            palette: spotify.and_then(|s| s.palette()).map(Cow::Borrowed),
+            features: spotify.and_then(|s| s.features()),
+            silent: audio.is_silent(),
+            zones: Vec::new(),
+            bands: Self::bark_bands(audio),
+        }
+    }
+
+    fn bark_bands(audio: &AudioProcessor<Sample>) -> [u8; BARK_BANDS] {
+        let mut bands = [0; BARK_BANDS];
+        for ((band, &energy), &peak) in bands
+            .iter_mut()
+            .zip(audio.bark_bands())
+            .zip(audio.bark_peaks())
+        {
+            let peak = to_f64(peak);
+            if peak > 0.0 {
+                *band = (to_f64(energy) / peak * u8::MAX as f64) as u8;
+            }
+        }
+        bands
+    }
+
+    /// To send it to another thread.
+    pub fn into_owned(self) -> Analysis<'static> {
+        Analysis {
+            palette: self.palette.map(|palette| Cow::Owned(palette.into_owned())),
+            ..self
+        }
+    }
+}
+
+/// Counters of what went through the network, they survive the reconnections.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NetStats {
+    /// Every packet, data or control.
+    pub packets_sent: u64,
+    pub acks_received: u64,
+    /// Control packets without ACK and hellos sent again while trying to reconnect.
+    /// Data packets are never sent twice.
+    pub retransmits: u64,
+}
+
+impl std::ops::Add for NetStats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            packets_sent: self.packets_sent + other.packets_sent,
+            acks_received: self.acks_received + other.acks_received,
+            retransmits: self.retransmits + other.retransmits,
+        }
+    }
+}
 
-pub struct NetHandler {
-    socket: UdpSocket,
+/// Smoothed round-trip time and its variation, computed like TCP does (RFC 6298).
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RttEstimator {
+    rtt: Option<Duration>,
+    jitter: Duration,
+}
+
+impl RttEstimator {
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn record(&mut self, sample: Duration) {
+        match self.rtt {
+            Some(rtt) => {
+                let diff = if rtt > sample {
+                    rtt - sample
+                } else {
+                    sample - rtt
+                };
+                self.jitter = (self.jitter * 3 + diff) / 4;
+                self.rtt = Some((rtt * 7 + sample) / 8);
+            }
+            None => {
+                self.rtt = Some(sample);
+                self.jitter = sample / 2;
+            }
+        }
+    }
+
+    /// None until the first ACK.
+    #[inline]
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    #[inline]
+    pub fn jitter(&self) -> Duration {
+        self.jitter
+    }
+}
+
+/// The connection to one server.
+pub struct Server<T: Transport = TransportSocket> {
+    address: String,
+    socket: T,
+    psk: Option<Psk>,
     mode: DataMode,
     stopped: bool,
+    sent_palette: Option<Vec<[u8; 3]>>,
+    sent_features: Option<TrackFeatures>,
+    /// Whether the server was told that the audio is silent.
+    sent_silent: bool,
+
+    state: ConnectionState,
+    /// Why the connection was lost the last time.
+    last_error: Option<String>,
+    /// LED update period of the server, no need to send more often.
+    send_period: Duration,
+    /// Asked to the server in the handshake.
+    sync_delay: Duration,
+    /// Part of the loudness of the segments in the novelty sent.
+    segment_blend: f64,
+    last_data: Instant,
+    /// Analysis aggregated while waiting for the next send.
+    pending_novelty: Option<f64>,
+    pending_beat: bool,
+    pending_onset: bool,
+    pending_bands: Option<[u8; BARK_BANDS]>,
+    backoff: Duration,
+    next_attempt: Instant,
+    last_send: Instant,
 
-    serialize_scratch: Option<Vec<u8>>,
-    deserialize_scratch: Aligned<[u8; 128]>,
+    next_sequence: u32,
+    next_control_sequence: u32,
+    acks: SequenceTracker,
+    /// Send time of the last data packets, indexed by sequence number.
+    in_flight: [Option<(u32, Instant)>; IN_FLIGHT_SIZE],
+    rtt: RttEstimator,
+    stats: NetStats,
+
+    codec: PacketCodec,
 }
 
-impl NetHandler {
-    pub fn new(address: &str) -> Result<Self> {
-        let socket = UdpSocket::bind("0.0.0.0:0")?;
+impl Server {
+    fn new(address: &str, transport: TransportKind, psk: Option<Psk>) -> Result<Self> {
+        Self::with_transport(address, TransportSocket::client(transport, address)?, psk)
+    }
+}
+
+impl<T: Transport> Server<T> {
+    /// `address` only names the server in the logs.
+    fn with_transport(address: &str, mut socket: T, psk: Option<Psk>) -> Result<Self> {
         socket.set_nonblocking(false)?;
-        socket.connect(address)?;
+        socket.set_read_timeout(Some(RECV_TIMEOUT))?;
 
         Ok(Self {
+            address: address.to_string(),
             socket,
+            psk,
             mode: DataMode::Novelty,
             stopped: false,
-            serialize_scratch: Some(Vec::new()),
-            deserialize_scratch: Aligned([0; 128]),
+            sent_palette: None,
+            sent_features: None,
+            sent_silent: false,
+            state: ConnectionState::Connected,
+            last_error: None,
+            send_period: Duration::from_millis(0),
+            sync_delay: Duration::from_millis(0),
+            segment_blend: 0.0,
+            last_data: Instant::now(),
+            pending_novelty: None,
+            pending_beat: false,
+            pending_onset: false,
+            pending_bands: None,
+            backoff: MIN_BACKOFF,
+            next_attempt: Instant::now(),
+            last_send: Instant::now(),
+            next_sequence: 0,
+            next_control_sequence: 0,
+            acks: SequenceTracker::default(),
+            in_flight: [None; IN_FLIGHT_SIZE],
+            rtt: RttEstimator::default(),
+            stats: NetStats::default(),
+            codec: PacketCodec::default(),
         })
     }
 
-    fn serialize_send(&mut self, item: &impl Serialize<WriteSerializer<Vec<u8>>>) -> Result<()> {
-        if let Some(scratch) = &mut self.serialize_scratch {
-            scratch.clear();
+    /// Control packets should be `reliable`, when the transport supports it.
+    fn serialize_send(
+        &mut self, item: &impl Serialize<WriteSerializer<Vec<u8>>>, reliable: bool,
+    ) -> Result<()> {
+        let datagram = self.codec.encode(item)?;
+        self.socket.send(datagram, reliable)?;
+        self.last_send = Instant::now();
+        self.stats.packets_sent += 1;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip(self), fields(server = %self.address))]
+    fn handshake(&mut self, mode: DataMode) -> Result<()> {
+        let mut hello = HelloPacket::new(self.psk.as_ref());
+        hello.sync_delay_ms = self.sync_delay.as_millis() as u32;
+
+        self.serialize_send(&hello, true)?;
+
+        if !self.codec.recv(&mut self.socket)? {
+            return Err(anyhow!("Corrupted hello !"));
+        }
+        let remote_hello = match self.codec.check::<HelloPacket>() {
+            Ok(remote_hello) => remote_hello,
+            // Refused by the server
+            Err(err) => {
+                return Err(match self.codec.check::<AckPacket>() {
+                    Ok(ack) => unexpected_answer(ack),
+                    Err(_) => anyhow!("Check archive failed: {}", err),
+                });
+            }
+        };
+
+        if hello.magic != remote_hello.magic || hello.random != remote_hello.random {
+            return Err(anyhow!("Handshake failed !"));
+        }
+        self.send_period = Duration::from_millis(remote_hello.led_update_period_ms as u64);
+
+        // The server doesn't know anything about us anymore
+        self.sent_palette = None;
+        self.sent_features = None;
+        self.sent_silent = false;
+        self.next_control_sequence = 0;
+        self.next_sequence = 0;
+        self.acks.reset();
+        self.in_flight = [None; IN_FLIGHT_SIZE];
+        self.rtt.reset();
+
+        self.mode = mode;
+        let mode = SetModePacket {
+            sequence: self.next_control_sequence(),
+            mode,
+        };
+        self.send_control(&mode, mode.sequence)
+    }
+
+    fn next_control_sequence(&mut self) -> u32 {
+        let sequence = self.next_control_sequence;
+        self.next_control_sequence = self.next_control_sequence.wrapping_add(1);
+        sequence
+    }
+
+    /// Send a control packet numbered `sequence` until the server acknowledges it.
+    /// Fails when the server seems to be gone. This blocks the caller while waiting for the
+    /// ACK, up to [CONTROL_ATTEMPTS] times [CONTROL_ACK_TIMEOUT] (600ms) when the server
+    /// doesn't answer, so the control packets must stay rare.
+    fn send_control(
+        &mut self, item: &impl Serialize<WriteSerializer<Vec<u8>>>, sequence: u32,
+    ) -> Result<()> {
+        for attempt in 0..CONTROL_ATTEMPTS {
+            if attempt > 0 {
+                self.stats.retransmits += 1;
+            }
+
+            self.serialize_send(item, true)?;
+            if self.wait_control_ack(sequence)? {
+                return Ok(());
+            }
+        }
+
+        Err(anyhow!("No ACK for control packet {} !", sequence))
+    }
+
+    /// Returns whether the control packet `sequence` was acknowledged in time.
+    /// The ACKs of the data received in the meantime are recorded.
+    fn wait_control_ack(&mut self, sequence: u32) -> Result<bool> {
+        let deadline = Instant::now() + CONTROL_ACK_TIMEOUT;
+        let res = loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_millis(0) {
+                break Ok(false);
+            }
+            self.socket.set_read_timeout(Some(remaining))?;
+
+            match self.codec.recv(&mut self.socket) {
+                Ok(_) => match self.codec.check::<AckPacket>().ok() {
+                    Some(Archived::<AckPacket>::Control(acked)) if *acked == sequence => {
+                        break Ok(true)
+                    }
+                    Some(Archived::<AckPacket>::Ok(acked)) => self.record_ack(*acked),
+                    // Late ACKs of a packet sent twice, or corrupted
+                    Some(Archived::<AckPacket>::Control(_)) | None => {}
+                    Some(ack) => break Err(unexpected_answer(ack)),
+                },
+                Err(err)
+                    if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut =>
+                {
+                    break Ok(false)
+                }
+                Err(err) => break Err(anyhow!(err)),
+            }
+        };
+
+        self.socket.set_read_timeout(Some(RECV_TIMEOUT))?;
+        res
+    }
+
+    #[inline]
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    #[inline]
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    /// Statistics about the acknowledgments of the data packets, which means that losses
+    /// on the way back are counted too.
+    #[inline]
+    pub fn ack_stats(&self) -> &SequenceTracker {
+        &self.acks
+    }
+
+    /// How often data is sent, as asked by the server.
+    #[inline]
+    pub fn send_period(&self) -> Duration {
+        self.send_period
+    }
+
+    /// Latency measured with the ACKs of the data packets.
+    #[inline]
+    pub fn rtt(&self) -> &RttEstimator {
+        &self.rtt
+    }
+
+    #[inline]
+    pub fn stats(&self) -> &NetStats {
+        &self.stats
+    }
+
+    /// Why the connection was lost the last time, if it was.
+    #[inline]
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    fn connection_lost(&mut self, err: &anyhow::Error) {
+        self.last_error = Some(err.to_string());
+        self.state = ConnectionState::Reconnecting { attempts: 0 };
+        self.backoff = MIN_BACKOFF;
+        self.next_attempt = Instant::now() + self.backoff;
+    }
+
+    /// Try a new handshake if the backoff delay is over, doubling it on failure.
+    fn try_reconnect(&mut self) {
+        let attempts = match self.state {
+            ConnectionState::Connected => return,
+            ConnectionState::Reconnecting { attempts } => attempts,
+        };
+
+        let now = Instant::now();
+        if now < self.next_attempt {
+            return;
+        }
+
+        self.stats.retransmits += 1;
+        if let Err(err) = self.handshake(self.mode) {
+            self.last_error = Some(err.to_string());
+            self.state = ConnectionState::Reconnecting {
+                attempts: attempts + 1,
+            };
+            self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+            self.next_attempt = now + self.backoff;
         } else {
-            self.serialize_scratch = Some(Vec::new());
+            self.state = ConnectionState::Connected;
         }
+    }
 
-        let mut serializer = WriteSerializer::new(self.serialize_scratch.take().unwrap());
-        serializer.serialize_value(item)?;
+    /// Send the data to the server, if it isn't reachable the connection
+    /// is silently re-established in the background.
+    fn send_current_data(&mut self, analysis: &Analysis, no_ack: bool) -> Result<()> {
+        if let ConnectionState::Reconnecting { .. } = self.state {
+            self.try_reconnect();
+            return Ok(());
+        }
+
+        // Don't lose the peaks and beats of the frames that aren't sent
+        let novelty = self.intensity(analysis);
+        self.pending_novelty = Some(self.pending_novelty.map_or(novelty, |n| n.max(novelty)));
+        self.pending_beat |= analysis.beat;
+        self.pending_onset |= analysis.onset;
+        let mut bands = self.pending_bands.unwrap_or(analysis.bands);
+        for (band, &energy) in bands.iter_mut().zip(analysis.bands.iter()) {
+            *band = (*band).max(energy);
+        }
+        self.pending_bands = Some(bands);
+        if self.last_data.elapsed() < self.send_period {
+            return Ok(());
+        }
+        // Only the beats are sent, the heartbeats keep the connection alive in the meantime
+        if self.mode == DataMode::BeatsOnly
+            && !self.pending_beat
+            && analysis.silent == self.sent_silent
+        {
+            return Ok(());
+        }
 
-        let buff = serializer.into_inner();
-        self.socket.send(&buff)?;
+        if let Err(err) = self.send_data(analysis, no_ack) {
+            self.connection_lost(&err);
+        }
 
-        self.serialize_scratch.replace(buff);
         Ok(())
     }
 
-    pub fn handshake(&mut self, mode: DataMode) -> Result<()> {
-        let hello = HelloPacket::default();
+    fn send_command(&mut self, command: Command) -> Result<()> {
+        let sequence = self.next_control_sequence();
+        let command = CommandData { sequence, command };
+        match self.mode {
+            DataMode::Novelty => self.send_control(&NoveltyModePacket::Command(command), sequence),
+            DataMode::NoveltyBeats => {
+                self.send_control(&NoveltyBeatsModePacket::Command(command), sequence)
+            }
+            DataMode::BeatsOnly => {
+                self.send_control(&BeatsOnlyModePacket::Command(command), sequence)
+            }
+            DataMode::Bands => self.send_control(&BandsModePacket::Command(command), sequence),
+        }
+    }
 
-        self.serialize_send(&hello)?;
+    /// Send a heartbeat if nothing was sent for a while.
+    fn keepalive(&mut self) {
+        if let ConnectionState::Reconnecting { .. } = self.state {
+            self.try_reconnect();
+            return;
+        }
 
-        self.socket
-            .recv(self.deserialize_scratch.as_mut())
-            .expect("Failed to receive");
-        let remote_hello =
-            unsafe { archived_value::<HelloPacket>(self.deserialize_scratch.as_mut(), 0) };
+        if self.last_send.elapsed() < HEARTBEAT_INTERVAL {
+            return;
+        }
 
-        if hello.magic != remote_hello.magic || hello.random != remote_hello.random {
-            return Err(anyhow!("Handshake failed !"));
+        let res = match self.mode {
+            DataMode::Novelty => self.serialize_send(&NoveltyModePacket::Heartbeat, false),
+            DataMode::NoveltyBeats => {
+                self.serialize_send(&NoveltyBeatsModePacket::Heartbeat, false)
+            }
+            DataMode::BeatsOnly => self.serialize_send(&BeatsOnlyModePacket::Heartbeat, false),
+            DataMode::Bands => self.serialize_send(&BandsModePacket::Heartbeat, false),
+        };
+        if let Err(err) = res {
+            self.connection_lost(&err);
         }
+    }
 
-        self.mode = mode;
-        let mode = SetModePacket { mode };
-        self.serialize_send(&mode)?;
-        Ok(())
+    /// The novelty blended with the loudness of the segments, which the noise of the room
+    /// can't fool. Both are relative to the peak of the novelty.
+    fn intensity(&self, analysis: &Analysis) -> f64 {
+        if !analysis.segments || self.segment_blend <= 0.0 || analysis.novelty_peak <= 0.0 {
+            return analysis.novelty;
+        }
+
+        let novelty = analysis.novelty / analysis.novelty_peak;
+        let blended = novelty * (1.0 - self.segment_blend) + analysis.loudness * self.segment_blend;
+        blended * analysis.novelty_peak
     }
 
-    pub fn send_current_data(
-        &mut self, audio: &AudioProcessor, spotify: Option<&SpotifyTracker>, no_ack: bool,
-    ) -> Result<()> {
+    #[instrument(level = "trace", skip(self, analysis), fields(server = %self.address))]
+    fn send_data(&mut self, analysis: &Analysis, no_ack: bool) -> Result<()> {
         let novelty_data = NoveltyModeData {
-            value: audio.novelty(),
-            peak: audio.novelty_peak_short_term(),
+            sequence: self.next_sequence,
+            value: self.pending_novelty.take().unwrap_or(analysis.novelty),
+            peak: analysis.novelty_peak,
+            onset: std::mem::take(&mut self.pending_onset),
+            pitch_class: analysis.pitch_class.unwrap_or(NO_PITCH_CLASS),
+            balance: analysis.balance as f32,
+            timestamp_us: timestamp_us(),
+            // Only the last frame, the zones are secondary
+            zones: analysis.zones.clone(),
         };
+        let beat = std::mem::take(&mut self.pending_beat);
+        let bands = self.pending_bands.take().unwrap_or(analysis.bands);
+        self.last_data = Instant::now();
+
+        if analysis.silent != self.sent_silent {
+            let standby = StandbyData {
+                sequence: self.next_control_sequence(),
+                silent: analysis.silent,
+            };
+            let sequence = standby.sequence;
+            match self.mode {
+                DataMode::Novelty => {
+                    self.send_control(&NoveltyModePacket::Standby(standby), sequence)?
+                }
+                DataMode::NoveltyBeats => {
+                    self.send_control(&NoveltyBeatsModePacket::Standby(standby), sequence)?
+                }
+                DataMode::BeatsOnly => {
+                    self.send_control(&BeatsOnlyModePacket::Standby(standby), sequence)?
+                }
+                DataMode::Bands => {
+                    self.send_control(&BandsModePacket::Standby(standby), sequence)?
+                }
+            }
+            self.sent_silent = analysis.silent;
+        }
+
+        // Only send the palette when it changes
+        if let Some(palette) = analysis.palette.as_deref() {
+            let palette = &palette[..palette.len().min(MAX_PALETTE_COLORS)];
+            if self.sent_palette.as_deref() != Some(palette) {
+                let palette_data = PaletteData {
+                    sequence: self.next_control_sequence(),
+                    colors: palette.to_vec(),
+                };
+                let sequence = palette_data.sequence;
+                match self.mode {
+                    DataMode::Novelty => {
+                        self.send_control(&NoveltyModePacket::Palette(palette_data), sequence)?
+                    }
+                    DataMode::NoveltyBeats => {
+                        self.send_control(&NoveltyBeatsModePacket::Palette(palette_data), sequence)?
+                    }
+                    DataMode::BeatsOnly => {
+                        self.send_control(&BeatsOnlyModePacket::Palette(palette_data), sequence)?
+                    }
+                    DataMode::Bands => {
+                        self.send_control(&BandsModePacket::Palette(palette_data), sequence)?
+                    }
+                }
+                self.sent_palette = Some(palette.to_vec());
+            }
+        }
 
         match self.mode {
             DataMode::Novelty => {
                 let packet = NoveltyModePacket::Data(novelty_data);
-                self.serialize_send(&packet)?;
+                self.serialize_send(&packet, false)?;
             }
             DataMode::NoveltyBeats => {
+                if let Some(features) = analysis.features {
+                    if self.sent_features != Some(features) {
+                        let sequence = self.next_control_sequence();
+                        let packet = NoveltyBeatsModePacket::Features(FeaturesData {
+                            sequence,
+                            danceability: features.danceability,
+                            energy: features.energy,
+                        });
+                        self.send_control(&packet, sequence)?;
+                        self.sent_features = Some(features);
+                    }
+                }
+
                 let packet = NoveltyBeatsModePacket::Data(NoveltyBeatsModeData {
                     novelty: novelty_data,
-                    beat: spotify.as_ref().map(|s| s.is_beat()).unwrap_or(false),
+                    beat,
+                    loudness: analysis.loudness,
                 });
-                self.serialize_send(&packet)?;
+                self.serialize_send(&packet, false)?;
+            }
+            DataMode::BeatsOnly => {
+                // Maybe only the standby changed
+                if !beat {
+                    return Ok(());
+                }
+
+                let packet = BeatsOnlyModePacket::Beat(BeatData {
+                    sequence: self.next_sequence,
+                    tempo: analysis.tempo,
+                    timestamp_us: novelty_data.timestamp_us,
+                });
+                self.serialize_send(&packet, false)?;
+            }
+            DataMode::Bands => {
+                let packet = BandsModePacket::Data(BandsModeData {
+                    novelty: novelty_data,
+                    beat,
+                    bands,
+                });
+                self.serialize_send(&packet, false)?;
             }
         }
 
-        /*if !no_ack {
-            self.check_ack()?;
-        }*/
+        let sequence = self.next_sequence;
+        self.in_flight[sequence as usize % IN_FLIGHT_SIZE] = Some((sequence, Instant::now()));
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+
+        if !no_ack {
+            self.wait_data_ack(sequence)?;
+        }
 
         Ok(())
     }
 
-    /*fn check_ack(&mut self) -> Result<()> {
-        self.socket.recv(self.deserialize_scratch.as_mut())?;
-        let archived = unsafe { archived_value::<AckPacket>(self.deserialize_scratch.as_ref(), 0) };
-        if let ArchivedAckPacket::Ok = archived {
-            Ok(())
-        } else {
-            Err(anyhow!("Server quit/abort !"))
+    fn record_ack(&mut self, sequence: u32) {
+        self.acks.record(sequence);
+        self.stats.acks_received += 1;
+
+        let slot = &mut self.in_flight[sequence as usize % IN_FLIGHT_SIZE];
+        if let Some((sent_sequence, sent_at)) = *slot {
+            // The slot may have been reused already if the ACK is really late
+            if sent_sequence == sequence {
+                self.rtt.record(sent_at.elapsed());
+                *slot = None;
+            }
+        }
+    }
+
+    /// Read the ACKs until the one of the data packet `sequence` comes, for
+    /// [DATA_ACK_WAIT] at most.
+    fn wait_data_ack(&mut self, sequence: u32) -> Result<()> {
+        let deadline = Instant::now() + DATA_ACK_WAIT;
+        loop {
+            self.check_acks()?;
+            let slot = self.in_flight[sequence as usize % IN_FLIGHT_SIZE];
+            let acked = slot.map_or(true, |(sent_sequence, _)| sent_sequence != sequence);
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if acked || remaining == Duration::from_millis(0) {
+                return Ok(());
+            }
+            if !self.socket.wait_readable(remaining)? {
+                // Left non blocking by the wait
+                self.socket.set_nonblocking(false)?;
+                return Ok(());
+            }
+        }
+    }
+
+    /// Read all the ACKs received so far, without blocking.
+    fn check_acks(&mut self) -> Result<()> {
+        self.socket.set_nonblocking(true)?;
+        let res = loop {
+            match self.codec.recv(&mut self.socket) {
+                Ok(_) => match self.codec.check::<AckPacket>().ok() {
+                    Some(Archived::<AckPacket>::Ok(sequence)) => self.record_ack(*sequence),
+                    // Late ACKs of a control packet sent twice, or corrupted
+                    Some(Archived::<AckPacket>::Control(_)) | None => {}
+                    Some(ack) => break Err(unexpected_answer(ack)),
+                },
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break Ok(()),
+                Err(err) => break Err(anyhow!(err)),
+            }
+        };
+        self.socket.set_nonblocking(false)?;
+        res
+    }
+
+    fn stop(&mut self, force: bool) -> Result<()> {
+        if let ConnectionState::Reconnecting { .. } = self.state {
+            // Nobody to say goodbye to
+            self.stopped = true;
+            return Ok(());
         }
-    }*/
 
-    pub fn stop(&mut self, force: bool) -> Result<()> {
         match self.mode {
             DataMode::Novelty => {
                 let packet = NoveltyModePacket::Goodbye(GoodbyeData {
                     magic: MAGIC,
                     force,
                 });
-                self.serialize_send(&packet)?;
+                self.serialize_send(&packet, true)?;
             }
             DataMode::NoveltyBeats => {
                 let packet = NoveltyBeatsModePacket::Goodbye(GoodbyeData {
                     magic: MAGIC,
                     force,
                 });
-                self.serialize_send(&packet)?;
+                self.serialize_send(&packet, true)?;
+            }
+            DataMode::BeatsOnly => {
+                let packet = BeatsOnlyModePacket::Goodbye(GoodbyeData {
+                    magic: MAGIC,
+                    force,
+                });
+                self.serialize_send(&packet, true)?;
+            }
+            DataMode::Bands => {
+                let packet = BandsModePacket::Goodbye(GoodbyeData {
+                    magic: MAGIC,
+                    force,
+                });
+                self.serialize_send(&packet, true)?;
             }
         }
 
-        self.socket.recv(self.deserialize_scratch.as_mut())?;
-        let archived: &Archived<AckPacket> =
-            unsafe { archived_value::<AckPacket>(self.deserialize_scratch.as_ref(), 0) };
-        if let Archived::<AckPacket>::Quit = archived {
-            self.stopped = true;
-            Ok(())
-        } else {
-            println!("{:?}", archived);
-            Err(anyhow!("Something went wrong somewhere !"))
+        loop {
+            self.codec.recv(&mut self.socket)?;
+            match self.codec.check::<AckPacket>().ok() {
+                Some(Archived::<AckPacket>::Quit) => {
+                    self.stopped = true;
+                    return Ok(());
+                }
+                // ACKs of data still in flight, or corrupted datagrams
+                Some(Archived::<AckPacket>::Ok(_))
+                | Some(Archived::<AckPacket>::Control(_))
+                | None => {}
+                Some(ack) => return Err(unexpected_answer(ack)),
+            }
         }
     }
 }
 
-impl Drop for NetHandler {
+impl<T: Transport> Drop for Server<T> {
     fn drop(&mut self) {
         if !self.stopped {
-            eprintln!("Forgot to stop NetHandler !");
+            warn!("Forgot to stop the connection to {} !", self.address);
+        }
+    }
+}
+
+/// Sends the same data to every server, so they all stay in sync.
+pub struct NetHandler<T: Transport = TransportSocket> {
+    servers: Vec<Server<T>>,
+}
+
+impl NetHandler {
+    /// The servers are only reached by [NetHandler::handshake], one being down doesn't fail.
+    pub fn new(addresses: &[String], transport: TransportKind, psk: Option<Psk>) -> Result<Self> {
+        let servers = addresses
+            .iter()
+            .map(|address| Server::new(address, transport, psk))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { servers })
+    }
+}
+
+impl<T: Transport> NetHandler<T> {
+    /// Talk to servers through already connected transports, named by their address.
+    pub fn with_transports(transports: Vec<(String, T)>, psk: Option<Psk>) -> Result<Self> {
+        let servers = transports
+            .into_iter()
+            .map(|(address, socket)| Server::with_transport(&address, socket, psk))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { servers })
+    }
+
+    /// Ask the servers to show the data `delay` after it is sent, so they all show it at the
+    /// same time whatever their latency. Takes effect at the next handshake.
+    pub fn set_sync_delay(&mut self, delay: Duration) {
+        for server in self.servers.iter_mut() {
+            server.sync_delay = delay;
+        }
+    }
+
+    /// Mix the loudness of the spotify segments into the novelty, from 0 (only the novelty)
+    /// to 1 (only the loudness).
+    pub fn set_segment_blend(&mut self, blend: f64) {
+        for server in self.servers.iter_mut() {
+            server.segment_blend = blend.max(0.0).min(1.0);
+        }
+    }
+
+    /// Fails only if no server could be reached, the others will be retried in the background.
+    pub fn handshake(&mut self, mode: DataMode) -> Result<()> {
+        let mut last_err = None;
+        for server in self.servers.iter_mut() {
+            if let Err(err) = server.handshake(mode) {
+                error!("Handshake with {} failed: {}", server.address, err);
+                server.mode = mode;
+                server.connection_lost(&err);
+                last_err = Some(err);
+            }
+        }
+
+        match last_err {
+            Some(err)
+                if self
+                    .servers
+                    .iter()
+                    .all(|server| server.state != ConnectionState::Connected) =>
+            {
+                Err(err)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    #[inline]
+    pub fn servers(&self) -> &[Server<T>] {
+        &self.servers
+    }
+
+    /// The mode used with the servers, they all use the same.
+    pub fn mode(&self) -> Option<DataMode> {
+        self.servers.first().map(|server| server.mode)
+    }
+
+    /// Counters of every server added together.
+    pub fn stats(&self) -> NetStats {
+        self.servers
+            .iter()
+            .fold(NetStats::default(), |total, server| total + server.stats)
+    }
+
+    /// Data packets of every server whose ACK never came.
+    pub fn lost(&self) -> u64 {
+        self.servers.iter().map(|server| server.acks.lost()).sum()
+    }
+
+    /// Mean of the round-trip times of the servers that answered.
+    pub fn rtt(&self) -> Option<Duration> {
+        let rtts = self
+            .servers
+            .iter()
+            .filter_map(|server| server.rtt.rtt())
+            .collect::<Vec<_>>();
+        if rtts.is_empty() {
+            None
+        } else {
+            Some(rtts.iter().sum::<Duration>() / rtts.len() as u32)
+        }
+    }
+
+    /// Say goodbye to the servers and handshake again with another mode.
+    pub fn switch_mode(&mut self, mode: DataMode) -> Result<()> {
+        for server in self.servers.iter_mut() {
+            if let Err(err) = server.stop(false) {
+                warn!("Failed to say goodbye to {}: {}", server.address, err);
+            }
+            server.stopped = false;
+        }
+        self.handshake(mode)
+    }
+
+    /// Give an order to the servers that are connected, the others miss it. Blocks until
+    /// each server acknowledges it, up to 600ms for a server that doesn't answer.
+    pub fn send_command(&mut self, command: Command) {
+        for server in self.servers.iter_mut() {
+            if server.state != ConnectionState::Connected {
+                continue;
+            }
+
+            if let Err(err) = server.send_command(command) {
+                server.connection_lost(&err);
+            }
+        }
+    }
+
+    pub fn send_current_data(&mut self, analysis: &Analysis, no_ack: bool) -> Result<()> {
+        for server in self.servers.iter_mut() {
+            server.send_current_data(analysis, no_ack)?;
+        }
+        Ok(())
+    }
+
+    pub fn keepalive(&mut self) {
+        for server in self.servers.iter_mut() {
+            server.keepalive();
+        }
+    }
+
+    /// Say goodbye to every server, even if one of them fails.
+    pub fn stop(&mut self, force: bool) -> Result<()> {
+        let mut res = Ok(());
+        for server in self.servers.iter_mut() {
+            if let Err(err) = server.stop(force) {
+                // Don't complain again when dropped
+                server.stopped = true;
+                res = Err(err);
+            }
         }
+        res
     }
 }
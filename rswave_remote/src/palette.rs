@@ -0,0 +1,123 @@
+use crate::spotify::TrackFeatures;
+use anyhow::Result;
+use image::RgbImage;
+
+/// Amount of most significant bits kept per channel when bucketing colors.
+const QUANTIZATION_BITS: u8 = 3;
+/// Minimum manhattan distance between two colors of a palette.
+const MIN_COLOR_DISTANCE: u32 = 96;
+
+/// Download an image, or read it for `file://` urls, and extract its dominant colors.
+pub async fn fetch_palette(url: &str, count: usize) -> Result<Vec<[u8; 3]>> {
+    let image = if let Some(path) = url.strip_prefix("file://") {
+        image::open(path)?.to_rgb8()
+    } else {
+        let bytes = reqwest::get(url).await?.bytes().await?;
+        image::load_from_memory(&bytes)?.to_rgb8()
+    };
+    Ok(dominant_colors(&image, count))
+}
+
+/// Extract up to `count` of the most present colors of an image.
+/// Pixels are bucketed by their most significant bits and each bucket is averaged.
+/// Dark and greyish pixels are ignored because they make poor LED colors.
+pub fn dominant_colors(image: &RgbImage, count: usize) -> Vec<[u8; 3]> {
+    let shift = 8 - QUANTIZATION_BITS;
+    let mut buckets = vec![(0u32, [0u32; 3]); 1 << (QUANTIZATION_BITS * 3)];
+
+    for pixel in image.pixels() {
+        let [r, g, b] = pixel.0;
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        if max < 40 || max - min < 30 {
+            continue;
+        }
+
+        let index = ((r >> shift) as usize) << (QUANTIZATION_BITS * 2)
+            | ((g >> shift) as usize) << QUANTIZATION_BITS
+            | (b >> shift) as usize;
+
+        let (amount, sum) = &mut buckets[index];
+        *amount += 1;
+        sum[0] += r as u32;
+        sum[1] += g as u32;
+        sum[2] += b as u32;
+    }
+
+    let mut buckets = buckets
+        .into_iter()
+        .filter(|(amount, _)| *amount > 0)
+        .map(|(amount, sum)| {
+            (
+                amount,
+                [
+                    (sum[0] / amount) as u8,
+                    (sum[1] / amount) as u8,
+                    (sum[2] / amount) as u8,
+                ],
+            )
+        })
+        .collect::<Vec<_>>();
+    buckets.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+
+    // Take the most present colors that are different enough from each other
+    let mut palette: Vec<[u8; 3]> = Vec::with_capacity(count);
+    for (_, color) in buckets {
+        if palette.len() >= count {
+            break;
+        }
+
+        if palette
+            .iter()
+            .all(|other| color_distance(*other, color) >= MIN_COLOR_DISTANCE)
+        {
+            palette.push(color);
+        }
+    }
+
+    palette
+}
+
+/// A gradient of `count` colors for the character of a track: blue and violet for the calm
+/// ones to orange and red for the energetic ones, the more danceable the wider.
+pub fn features_palette(features: TrackFeatures, count: usize) -> Vec<[u8; 3]> {
+    let energy = features.energy.max(0.0).min(1.0);
+    let danceability = features.danceability.max(0.0).min(1.0);
+    // In degrees
+    let center = 240.0 + 120.0 * energy;
+    let spread = 30.0 + 90.0 * danceability;
+
+    (0..count)
+        .map(|i| {
+            let t = if count > 1 {
+                i as f32 / (count - 1) as f32 - 0.5
+            } else {
+                0.0
+            };
+            hue_to_rgb(center + spread * t)
+        })
+        .collect()
+}
+
+/// Fully saturated and bright color of a hue in degrees.
+fn hue_to_rgb(hue: f32) -> [u8; 3] {
+    let hue = hue.rem_euclid(360.0) / 60.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    let (r, g, b) = match hue as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    let channel = |value: f32| (value * 255.0) as u8;
+    [channel(r), channel(g), channel(b)]
+}
+
+fn color_distance(a: [u8; 3], b: [u8; 3]) -> u32 {
+    a.iter()
+        .zip(b.iter())
+        .map(|(a, b)| (*a as i32 - *b as i32).abs() as u32)
+        .sum()
+}
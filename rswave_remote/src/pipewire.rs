@@ -0,0 +1,140 @@
+use crate::{resample::SAMPLE_RATE, Sample};
+use ::pipewire::{
+    context::Context,
+    keys,
+    main_loop::MainLoop,
+    properties::properties,
+    spa::{
+        param::{
+            audio::{AudioFormat, AudioInfoRaw},
+            ParamType,
+        },
+        pod::{serialize::PodSerializer, Object, Pod, Value},
+        utils::{Direction, SpaTypes},
+    },
+    stream::{Stream, StreamFlags},
+};
+use anyhow::{anyhow, Result};
+use parking_lot::Mutex;
+use ringbuf::Producer;
+use std::{
+    convert::TryInto,
+    io::Cursor,
+    mem,
+    sync::{mpsc, Arc},
+    thread::JoinHandle,
+};
+
+/// Audio source capturing from PipeWire directly, without going through ALSA.
+/// PipeWire converts the audio to 44100Hz stereo for us.
+pub struct PipeWireSource {
+    producer: Arc<Mutex<Option<Producer<Sample>>>>,
+    _thread: JoinHandle<()>,
+}
+
+impl PipeWireSource {
+    /// Capture what `target` plays, a node name or serial (like the stream of a music player
+    /// so notifications don't count), or what the default sink plays otherwise.
+    pub fn new(target: Option<String>) -> Result<Self> {
+        let producer = Arc::new(Mutex::new(None));
+        let stream_producer = producer.clone();
+
+        // The main loop never returns, we just wait for the stream to be connected
+        let (ready_sender, ready_receiver) = mpsc::channel();
+
+        let thread = std::thread::Builder::new()
+            .name("PipeWire Thread".into())
+            .spawn(move || {
+                if let Err(err) = Self::run(target, stream_producer, &ready_sender) {
+                    ready_sender.send(Err(err)).unwrap();
+                }
+            })?;
+
+        ready_receiver
+            .recv()
+            .map_err(|_| anyhow!("PipeWire thread died"))??;
+
+        Ok(Self {
+            producer,
+            _thread: thread,
+        })
+    }
+
+    fn run(
+        target: Option<String>, producer: Arc<Mutex<Option<Producer<Sample>>>>,
+        ready: &mpsc::Sender<Result<()>>,
+    ) -> Result<()> {
+        ::pipewire::init();
+
+        let main_loop = MainLoop::new(None)?;
+        let context = Context::new(&main_loop)?;
+        let core = context.connect(None)?;
+
+        let mut props = properties! {
+            *keys::MEDIA_TYPE => "Audio",
+            *keys::MEDIA_CATEGORY => "Capture",
+            *keys::MEDIA_ROLE => "Music",
+        };
+        match target {
+            Some(target) => props.insert(*keys::TARGET_OBJECT, target),
+            // Record what is played rather than a microphone
+            None => props.insert(*keys::STREAM_CAPTURE_SINK, "true"),
+        }
+
+        let stream = Stream::new(&core, "rswave", props)?;
+        let _listener = stream
+            .add_local_listener_with_user_data(producer)
+            .process(|stream, producer| {
+                let mut buffer = match stream.dequeue_buffer() {
+                    Some(buffer) => buffer,
+                    None => return,
+                };
+                let data = match buffer.datas_mut().first_mut() {
+                    Some(data) => data,
+                    None => return,
+                };
+
+                let size = data.chunk().size() as usize;
+                if let (Some(samples), Some(producer)) = (data.data(), producer.lock().as_mut()) {
+                    // Interleaved f32 little endian
+                    producer.push_iter(
+                        &mut samples[..size.min(samples.len())]
+                            .chunks_exact(mem::size_of::<f32>())
+                            .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()) as Sample),
+                    );
+                }
+            })
+            .register()?;
+
+        let mut format = AudioInfoRaw::new();
+        format.set_format(AudioFormat::F32LE);
+        format.set_rate(SAMPLE_RATE);
+        format.set_channels(2);
+        let format = Value::Object(Object {
+            type_: SpaTypes::ObjectParamFormat.as_raw(),
+            id: ParamType::EnumFormat.as_raw(),
+            properties: format.into(),
+        });
+        let format = PodSerializer::serialize(Cursor::new(Vec::new()), &format)
+            .map_err(|err| anyhow!("Failed to serialize the format: {:?}", err))?
+            .0
+            .into_inner();
+        let mut params = [Pod::from_bytes(&format).ok_or(anyhow!("Invalid format"))?];
+
+        stream.connect(
+            Direction::Input,
+            None,
+            StreamFlags::AUTOCONNECT | StreamFlags::MAP_BUFFERS | StreamFlags::RT_PROCESS,
+            &mut params,
+        )?;
+
+        ready.send(Ok(())).unwrap();
+        main_loop.run();
+        Ok(())
+    }
+
+    /// Replace the buffer to which the samples will be pushed.
+    pub fn attach(&self, producer: Producer<Sample>) {
+        self.producer.lock().replace(producer);
+    }
+}
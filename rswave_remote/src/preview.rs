@@ -0,0 +1,105 @@
+use crate::net::Analysis;
+use std::time::Instant;
+
+/// Brightness lost per second, in percent.
+const GRAVITY: f32 = 150.0;
+const MIN_BRIGHTNESS: f32 = 20.0;
+/// The runner picks a random hue far from the current one, a step of about 0.38 turn
+/// never comes back to the same color.
+const HUE_STEP: u8 = 97;
+
+/// Imitates what the epilepsy runner of the servers does with the data that is sent,
+/// to see the effect without looking at the strip. The random choices are replaced by steps
+/// so it is only an approximation.
+pub struct LedPreview {
+    hue: u8,
+    /// Between 0 and 255.
+    brightness: f32,
+    min_brightness: f32,
+    palette_index: usize,
+    silent: bool,
+    last_update: Instant,
+}
+
+impl Default for LedPreview {
+    fn default() -> Self {
+        Self {
+            hue: 0,
+            brightness: 255.0,
+            min_brightness: MIN_BRIGHTNESS,
+            palette_index: 0,
+            silent: false,
+            last_update: Instant::now(),
+        }
+    }
+}
+
+impl LedPreview {
+    pub fn update(&mut self, analysis: &Analysis) {
+        self.silent = analysis.silent;
+
+        if analysis.beat {
+            self.brightness = 255.0;
+        }
+
+        if analysis.onset {
+            let palette_len = analysis
+                .palette
+                .as_deref()
+                .map_or(0, |palette| palette.len());
+            if palette_len > 1 {
+                self.palette_index = (self.palette_index + 1) % palette_len;
+            } else if let Some(pitch_class) = analysis.pitch_class {
+                self.hue = (pitch_class as u16 * 256 / 12) as u8;
+            } else {
+                self.hue = self.hue.wrapping_add(HUE_STEP);
+            }
+        }
+
+        self.min_brightness = MIN_BRIGHTNESS + (analysis.loudness * 80.0) as f32;
+
+        let now = Instant::now();
+        let delta_time = now.duration_since(self.last_update).as_secs_f32();
+        self.brightness = (self.brightness - GRAVITY * 2.55 * delta_time).max(self.min_brightness);
+        self.last_update = now;
+    }
+
+    /// Color of the strip, black during the standby.
+    pub fn color(&self, palette: Option<&[[u8; 3]]>) -> [u8; 3] {
+        if self.silent {
+            return [0, 0, 0];
+        }
+
+        let [r, g, b] = match palette.and_then(|palette| palette.get(self.palette_index)) {
+            Some(&color) => color,
+            None => hue_to_rgb(self.hue),
+        };
+        let v = self.brightness as u16;
+        [
+            (r as u16 * v / 255) as u8,
+            (g as u16 * v / 255) as u8,
+            (b as u16 * v / 255) as u8,
+        ]
+    }
+
+    #[inline]
+    pub fn is_silent(&self) -> bool {
+        self.silent
+    }
+}
+
+/// Fully saturated color of a hue between 0 and 255.
+fn hue_to_rgb(hue: u8) -> [u8; 3] {
+    // 6 sectors of ~43 hues each
+    let sector = hue as u16 * 6 / 256;
+    let rising = (hue as u16 * 6 % 256) as u8;
+    let falling = 255 - rising;
+    match sector {
+        0 => [255, rising, 0],
+        1 => [falling, 255, 0],
+        2 => [0, 255, rising],
+        3 => [0, falling, 255],
+        4 => [rising, 0, 255],
+        _ => [255, 0, falling],
+    }
+}
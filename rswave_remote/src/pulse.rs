@@ -0,0 +1,45 @@
+use anyhow::{anyhow, Result};
+use std::process::Command;
+
+/// The PulseAudio plugin of ALSA records from this source instead of the default one.
+pub const SOURCE_ENV: &str = "PULSE_SOURCE";
+/// Name of the ALSA device going through PulseAudio (or PipeWire's replacement of it).
+pub const PULSE_DEVICE: &str = "pulse";
+
+/// Name of the source that records what is played by the default sink.
+pub fn default_monitor() -> Result<String> {
+    Ok(format!("{}.monitor", default_sink()?))
+}
+
+fn default_sink() -> Result<String> {
+    // Only available on recent versions
+    if let Some(sink) = pactl(&["get-default-sink"]) {
+        let sink = sink.trim();
+        if !sink.is_empty() {
+            return Ok(sink.to_owned());
+        }
+    }
+
+    pactl(&["info"])
+        .and_then(|info| {
+            info.lines()
+                .find_map(|line| line.strip_prefix("Default Sink:"))
+                .map(|sink| sink.trim().to_owned())
+        })
+        .ok_or(anyhow!("Can't find the default sink, is pactl installed ?"))
+}
+
+fn pactl(args: &[&str]) -> Option<String> {
+    let output = Command::new("pactl")
+        .args(args)
+        // Parse the english output
+        .env("LC_ALL", "C")
+        .output()
+        .ok()?;
+
+    if output.status.success() {
+        String::from_utf8(output.stdout).ok()
+    } else {
+        None
+    }
+}
@@ -0,0 +1,204 @@
+use crate::{net::Analysis, to_f64, Sample};
+use anyhow::{anyhow, Result};
+use rswave_common::packets::BARK_BANDS;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    time::Instant,
+};
+
+const CSV_HEADER: &str = "time,novelty,novelty_peak,beat,onset,pitch_class,loudness,silent,bands";
+
+/// The format is guessed from the extension, CSV unless it is `.jsonl` for one JSON object
+/// per line. A `.json` file would look like a single JSON document, so it is refused.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RecordFormat {
+    Csv,
+    JsonLines,
+}
+
+impl RecordFormat {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("jsonl") => Ok(Self::JsonLines),
+            Some(ext) if ext.eq_ignore_ascii_case("json") => Err(anyhow!(
+                "The recordings are JSON lines, name the file `.jsonl` !"
+            )),
+            _ => Ok(Self::Csv),
+        }
+    }
+}
+
+/// What is sent to the servers for one frame, with the time since the start of the recording.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    /// In seconds.
+    pub time: f64,
+    pub novelty: f64,
+    pub novelty_peak: f64,
+    pub beat: bool,
+    pub onset: bool,
+    pub pitch_class: Option<u8>,
+    pub loudness: f64,
+    pub silent: bool,
+    /// The mel bands, empty if they are disabled.
+    pub bands: Vec<f64>,
+}
+
+impl RecordedFrame {
+    /// The bands are separated by spaces in their column.
+    fn to_csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{}",
+            self.time,
+            self.novelty,
+            self.novelty_peak,
+            self.beat as u8,
+            self.onset as u8,
+            self.pitch_class.map_or(String::new(), |p| p.to_string()),
+            self.loudness,
+            self.silent as u8,
+            self.bands
+                .iter()
+                .map(|band| band.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        )
+    }
+
+    fn from_csv(line: &str) -> Result<Self> {
+        let columns = line.split(',').map(str::trim).collect::<Vec<_>>();
+        if columns.len() != 9 {
+            return Err(anyhow!("Expected 9 columns, got {}", columns.len()));
+        }
+        let flag = |column: &str| column == "1" || column.eq_ignore_ascii_case("true");
+
+        Ok(Self {
+            time: columns[0].parse()?,
+            novelty: columns[1].parse()?,
+            novelty_peak: columns[2].parse()?,
+            beat: flag(columns[3]),
+            onset: flag(columns[4]),
+            pitch_class: match columns[5] {
+                "" => None,
+                pitch_class => Some(pitch_class.parse()?),
+            },
+            loudness: columns[6].parse()?,
+            silent: flag(columns[7]),
+            bands: columns[8]
+                .split_whitespace()
+                .map(str::parse)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Parse one line of a recording, `None` for the ones to skip like the CSV header.
+    pub fn parse(line: &str, format: RecordFormat) -> Result<Option<Self>> {
+        let line = line.trim();
+        if line.is_empty() || line == CSV_HEADER {
+            return Ok(None);
+        }
+
+        let frame: Self = match format {
+            RecordFormat::Csv => Self::from_csv(line)?,
+            RecordFormat::JsonLines => serde_json::from_str(line)?,
+        };
+        // It becomes a Duration, which can't be negative or too big
+        if !(0.0..=u32::MAX as f64).contains(&frame.time) {
+            return Err(anyhow!("Invalid time {}", frame.time));
+        }
+        Ok(Some(frame))
+    }
+
+    /// The palette, the features, the tempo, the zones, the balance and the Bark bands
+    /// aren't recorded.
+    pub fn to_analysis(&self) -> Analysis<'static> {
+        Analysis {
+            novelty: self.novelty,
+            novelty_peak: self.novelty_peak,
+            beat: self.beat,
+            onset: self.onset,
+            pitch_class: self.pitch_class,
+            balance: 0.0,
+            loudness: self.loudness,
+            segments: false,
+            tempo: 0.0,
+            palette: None,
+            features: None,
+            silent: self.silent,
+            zones: Vec::new(),
+            bands: [0; BARK_BANDS],
+        }
+    }
+}
+
+/// Read a whole recording made with [Recorder].
+pub fn read_recording(path: &Path) -> Result<Vec<RecordedFrame>> {
+    let format = RecordFormat::from_path(path)?;
+    let content = std::fs::read_to_string(path)?;
+
+    let mut frames = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        match RecordedFrame::parse(line, format) {
+            Ok(Some(frame)) => frames.push(frame),
+            Ok(None) => {}
+            Err(err) => return Err(anyhow!("Line {} of the recording: {}", i + 1, err)),
+        }
+    }
+    Ok(frames)
+}
+
+/// Writes every analysis to a file while running, to study it afterwards.
+pub struct Recorder {
+    writer: BufWriter<File>,
+    format: RecordFormat,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn new(path: &Path) -> Result<Self> {
+        let format = RecordFormat::from_path(path)?;
+        let mut writer = BufWriter::new(File::create(path)?);
+        if format == RecordFormat::Csv {
+            writeln!(writer, "{}", CSV_HEADER)?;
+        }
+
+        Ok(Self {
+            writer,
+            format,
+            start: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, analysis: &Analysis, bands: &[Sample]) -> Result<()> {
+        let frame = RecordedFrame {
+            time: self.start.elapsed().as_secs_f64(),
+            novelty: analysis.novelty,
+            novelty_peak: analysis.novelty_peak,
+            beat: analysis.beat,
+            onset: analysis.onset,
+            pitch_class: analysis.pitch_class,
+            loudness: analysis.loudness,
+            silent: analysis.silent,
+            bands: bands.iter().copied().map(to_f64).collect(),
+        };
+
+        match self.format {
+            RecordFormat::Csv => writeln!(self.writer, "{}", frame.to_csv())?,
+            RecordFormat::JsonLines => {
+                serde_json::to_writer(&mut self.writer, &frame)?;
+                writeln!(self.writer)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        // Nothing else can be done about it
+        let _ = self.writer.flush();
+    }
+}
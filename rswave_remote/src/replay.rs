@@ -0,0 +1,63 @@
+use crate::{
+    app::App,
+    net::NetHandler,
+    record::{read_recording, RecordedFrame},
+    Opt,
+};
+use anyhow::{anyhow, Result};
+use std::{
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+use tracing::info;
+
+/// Longest sleep between two frames, so `stop` is noticed quickly.
+const IDLE_DELAY: Duration = Duration::from_millis(10);
+
+/// Send a recording to the servers with its original timing, no audio needed.
+/// Returns early when `stop` becomes true.
+pub fn replay(opt: &Opt, path: &Path, stop: &AtomicBool) -> Result<()> {
+    let frames = read_recording(path)?;
+    let duration = match frames.last() {
+        Some(frame) => frame.time,
+        None => return Err(anyhow!("The recording is empty !")),
+    };
+
+    // Send the beats only if there are some
+    let with_beats = frames.iter().any(|frame| frame.beat);
+    let mut net = App::make_net(opt, with_beats)?
+        .ok_or_else(|| anyhow!("Nowhere to replay to, give a server with --address !"))?;
+    info!("Replaying {} frames ({:.0}s)", frames.len(), duration);
+
+    let res = send_frames(&mut net, &frames, opt.no_ack, stop);
+    net.stop(false)?;
+    res
+}
+
+fn send_frames(
+    net: &mut NetHandler, frames: &[RecordedFrame], no_ack: bool, stop: &AtomicBool,
+) -> Result<()> {
+    let start = Instant::now();
+    for frame in frames.iter() {
+        let due = Duration::from_secs_f64(frame.time);
+        loop {
+            if stop.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+
+            let elapsed = start.elapsed();
+            if elapsed >= due {
+                break;
+            }
+            // The recording can have long gaps
+            net.keepalive();
+            std::thread::sleep((due - elapsed).min(IDLE_DELAY));
+        }
+
+        net.send_current_data(&frame.to_analysis(), no_ack)?;
+    }
+
+    info!("End of the recording");
+    Ok(())
+}
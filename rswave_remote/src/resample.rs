@@ -0,0 +1,193 @@
+pub use crate::audio::SAMPLE_RATE;
+use crate::Sample;
+use anyhow::{anyhow, Error, Result};
+use ringbuf::Producer;
+use rubato::{FftFixedIn, Resampler};
+use std::str::FromStr;
+
+/// Frames per channel given to the resampler at once.
+const CHUNK_SIZE: usize = 1024;
+const SUB_CHUNKS: usize = 2;
+
+/// Which channels of the device are analysed, the analysis works in stereo.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChannelSelection {
+    /// Average the even channels into the left one and the odd ones into the right one,
+    /// mono is duplicated.
+    Downmix,
+    /// Take a pair of channels as left and right, starting from 0.
+    Pair(usize, usize),
+}
+
+impl ChannelSelection {
+    fn check(self, channels: usize) -> Result<()> {
+        match self {
+            Self::Pair(left, right) if left.max(right) >= channels => Err(anyhow!(
+                "Can't select channels {} and {}, the device only has {} !",
+                left,
+                right,
+                channels
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn stereo(self, frame: &[Sample]) -> (Sample, Sample) {
+        match self {
+            Self::Downmix if frame.len() == 1 => (frame[0], frame[0]),
+            Self::Downmix => {
+                let average = |channels: &mut dyn Iterator<Item = &Sample>| {
+                    let (sum, count) =
+                        channels.fold((0.0, 0), |(sum, count), sample| (sum + sample, count + 1));
+                    sum / count as Sample
+                };
+                (
+                    average(&mut frame.iter().step_by(2)),
+                    average(&mut frame.iter().skip(1).step_by(2)),
+                )
+            }
+            Self::Pair(left, right) => (frame[left], frame[right]),
+        }
+    }
+}
+
+impl FromStr for ChannelSelection {
+    type Err = Error;
+
+    /// Parses `downmix` or a pair like `2,3`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("downmix") {
+            return Ok(Self::Downmix);
+        }
+
+        let mut pair = s
+            .splitn(2, ',')
+            .map(|channel| channel.trim().parse::<usize>());
+        match (pair.next(), pair.next()) {
+            (Some(Ok(left)), Some(Ok(right))) => Ok(Self::Pair(left, right)),
+            _ => Err(anyhow!("Expected downmix or a pair of channels like 0,1 !")),
+        }
+    }
+}
+
+/// Where the stream puts its interleaved samples, they are converted to stereo and resampled
+/// to [SAMPLE_RATE] if the device runs at another rate.
+pub struct SampleSink {
+    producer: Producer<Sample>,
+    resampler: Option<StereoResampler>,
+    selection: ChannelSelection,
+    frame: Vec<Sample>,
+}
+
+impl SampleSink {
+    pub fn new(
+        producer: Producer<Sample>, sample_rate: u32, channels: usize, selection: ChannelSelection,
+    ) -> Result<Self> {
+        selection.check(channels)?;
+        let resampler = if sample_rate == SAMPLE_RATE {
+            None
+        } else {
+            Some(StereoResampler::new(sample_rate)?)
+        };
+
+        Ok(Self {
+            producer,
+            resampler,
+            selection,
+            frame: vec![0.0; channels],
+        })
+    }
+
+    pub fn push<I: Iterator<Item = Sample>>(&mut self, samples: &mut I) {
+        let Self {
+            producer,
+            resampler,
+            selection,
+            frame,
+        } = self;
+
+        // Nothing to convert
+        if frame.len() == 2 && *selection == ChannelSelection::Downmix {
+            Self::push_stereo(producer, resampler, samples);
+            return;
+        }
+
+        let mut stereo = std::iter::from_fn(|| {
+            for sample in frame.iter_mut() {
+                *sample = samples.next()?;
+            }
+            Some(selection.stereo(&frame[..]))
+        })
+        .flat_map(|(left, right)| std::iter::once(left).chain(std::iter::once(right)));
+        Self::push_stereo(producer, resampler, &mut stereo);
+    }
+
+    fn push_stereo<I: Iterator<Item = Sample>>(
+        producer: &mut Producer<Sample>, resampler: &mut Option<StereoResampler>, samples: &mut I,
+    ) {
+        match resampler.as_mut() {
+            Some(resampler) => resampler.push(samples, producer),
+            None => {
+                producer.push_iter(samples);
+            }
+        }
+    }
+}
+
+struct StereoResampler {
+    inner: FftFixedIn<Sample>,
+    input: Vec<Vec<Sample>>,
+    output: Vec<Vec<Sample>>,
+}
+
+impl StereoResampler {
+    fn new(sample_rate: u32) -> Result<Self> {
+        let inner = FftFixedIn::new(
+            sample_rate as usize,
+            SAMPLE_RATE as usize,
+            CHUNK_SIZE,
+            SUB_CHUNKS,
+            2,
+        )?;
+        let input = inner.input_buffer_allocate(false);
+        let output = inner.output_buffer_allocate(true);
+
+        Ok(Self {
+            inner,
+            input,
+            output,
+        })
+    }
+
+    /// Buffer the samples until there is enough for the resampler, without allocating
+    /// as this runs in the audio callback.
+    fn push<I: Iterator<Item = Sample>>(
+        &mut self, samples: &mut I, producer: &mut Producer<Sample>,
+    ) {
+        while let (Some(left), Some(right)) = (samples.next(), samples.next()) {
+            self.input[0].push(left);
+            self.input[1].push(right);
+
+            if self.input[0].len() < self.inner.input_frames_next() {
+                continue;
+            }
+
+            // Only fails with buffers of the wrong size
+            let (_, written) = self
+                .inner
+                .process_into_buffer(&self.input, &mut self.output, None)
+                .expect("Resampler buffers mismatch !");
+            self.input[0].clear();
+            self.input[1].clear();
+
+            producer.push_iter(
+                &mut self.output[0][..written]
+                    .iter()
+                    .zip(&self.output[1][..written])
+                    .flat_map(|(&left, &right)| {
+                        std::iter::once(left).chain(std::iter::once(right))
+                    }),
+            );
+        }
+    }
+}
@@ -1,48 +1,323 @@
+#[cfg(feature = "source_mpris")]
+use crate::mpris::MprisTracker;
+use crate::{mpd::MpdTracker, palette, Opt, PaletteSource};
 use anyhow::{anyhow, Result};
 use rspotify::{
     client::{ApiError, Spotify},
-    model::{audio::AudioAnalysis, playing::Playing, track::FullTrack},
-    oauth2::{SpotifyClientCredentials, SpotifyOAuth},
+    model::{audio::AudioAnalysis, playing::Playing, search::SearchResult},
+    oauth2::{SpotifyClientCredentials, SpotifyOAuth, TokenInfo},
+    senum::SearchType,
 };
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{error, warn};
 
 const REGULAR_TIMEOUT_THRESHOLD: Duration = Duration::from_secs(5);
+/// Local players are cheap to query, we can afford to ask more often.
+const LOCAL_TIMEOUT_THRESHOLD: Duration = Duration::from_secs(1);
+/// Segment loudness (in dB) considered as silence.
+const SEGMENT_LOUDNESS_FLOOR: f32 = -60.0;
+/// Refresh the user token this long before it actually expires.
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(60);
+/// Amount of colors to extract from the album art or to make from the features.
+const PALETTE_SIZE: usize = 3;
+/// A progress further than this from the estimate is a seek, in ms.
+const SEEK_THRESHOLD_MS: f64 = 2000.0;
+/// A progress that moved less than this since the last poll is a pause, in ms.
+const PAUSE_THRESHOLD_MS: f64 = 50.0;
+/// How fast the player clock is allowed to run compared to ours.
+const MIN_RATE: f64 = 0.95;
+const MAX_RATE: f64 = 1.05;
+/// Weight of a new measure of the rate.
+const RATE_SMOOTHING: f64 = 0.2;
+/// Part of the error of the estimate caught up at each poll, the rest is left to the rate
+/// so the beats aren't skipped.
+const PROGRESS_CORRECTION: f64 = 0.5;
+
+/// How to log in as a spotify user.
+#[derive(Debug, Clone)]
+pub struct SpotifyAuth {
+    pub redirect_uri: String,
+    /// Ignore the cached token.
+    pub fresh: bool,
+    /// Don't assume a browser is available.
+    pub headless: bool,
+    /// Code already obtained by the user, no need to ask for it.
+    pub auth_code: Option<String>,
+}
+
+impl From<&Opt> for SpotifyAuth {
+    fn from(opt: &Opt) -> Self {
+        Self {
+            redirect_uri: opt.spotify_redirect_uri.clone(),
+            fresh: opt.spotify_auth_fresh,
+            headless: opt.spotify_headless,
+            auth_code: opt.auth_code.clone(),
+        }
+    }
+}
+
+/// Get a user token, from the cache if possible.
+/// In headless mode, the authorization URL is printed and the redirect URL
+/// (or just the code) is read from stdin, so it works without a browser
+/// and without the redirect URI being reachable from this machine.
+pub async fn request_token(oauth: &mut SpotifyOAuth, auth: &SpotifyAuth) -> Option<TokenInfo> {
+    if let Some(code) = auth.auth_code.as_ref() {
+        return oauth.get_access_token(code).await;
+    }
+
+    if !auth.headless {
+        return if auth.fresh {
+            rspotify::util::get_token_without_cache(oauth).await
+        } else {
+            rspotify::util::get_token(oauth).await
+        };
+    }
+
+    if !auth.fresh {
+        if let Some(token) = oauth.get_cached_token().await {
+            return Some(token);
+        }
+    }
+
+    println!(
+        "Open this URL on any device and log in:\n\n{}\n",
+        oauth.get_authorize_url(None, None)
+    );
+    println!("Then paste the URL you were redirected to:");
+
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).ok()?;
+    let code = parse_auth_code(input.trim())?;
+
+    oauth.get_access_token(&code).await
+}
+
+/// Extract the code from a redirect URL, a bare code is accepted as is.
+fn parse_auth_code(input: &str) -> Option<String> {
+    let code = match input.find("code=") {
+        Some(start) => input[start + 5..].split('&').next().unwrap_or_default(),
+        None => input,
+    };
+
+    if code.is_empty() {
+        None
+    } else {
+        Some(code.to_string())
+    }
+}
+
+/// A playing track, regardless of where the information comes from.
+#[derive(Debug, Clone)]
+pub struct TrackInfo {
+    /// Identifies the track in its source, used to detect track changes.
+    pub key: String,
+    pub spotify_id: Option<String>,
+    pub name: String,
+    pub artist: String,
+    /// Only known through the spotify API.
+    pub artist_id: Option<String>,
+    /// URI of the playlist, album or artist being played, only known through the spotify API.
+    pub context_uri: Option<String>,
+    pub duration_ms: u32,
+    pub progress_ms: u32,
+    pub album_art_url: Option<String>,
+}
+
+impl TrackInfo {
+    fn from_playing(playing: Playing) -> Option<Self> {
+        let progress_ms = playing.progress_ms.unwrap_or(0);
+        let context_uri = playing.context.map(|context| context.uri);
+        let track = playing.item?;
+        let artist = track.artists.into_iter().next();
+
+        Some(Self {
+            key: track.id.clone().unwrap_or_else(|| track.uri.clone()),
+            spotify_id: track.id,
+            name: track.name,
+            artist_id: artist.as_ref().and_then(|artist| artist.id.clone()),
+            artist: artist.map(|artist| artist.name).unwrap_or_default(),
+            context_uri,
+            duration_ms: track.duration_ms,
+            progress_ms,
+            // Images are sorted widest first, the smallest is plenty for a few colors
+            album_art_url: track.album.images.last().map(|image| image.url.clone()),
+        })
+    }
+}
+
+/// Estimates the progress of the track between the polls of the player. Its clock doesn't
+/// exactly run as fast as ours and the track may be paused, which is measured from one poll
+/// to the next.
+struct ProgressClock {
+    /// Estimated progress in ms at `anchor`.
+    progress_ms: f64,
+    anchor: Instant,
+    /// Speed of the track compared to the local clock.
+    rate: f64,
+    paused: bool,
+    /// Last progress reported and when.
+    last_report: Option<(u32, Instant)>,
+}
+
+impl ProgressClock {
+    fn new() -> Self {
+        Self {
+            progress_ms: 0.0,
+            anchor: Instant::now(),
+            rate: 1.0,
+            paused: false,
+            last_report: None,
+        }
+    }
+
+    fn progress_at(&self, at: Instant) -> f64 {
+        if self.paused {
+            return self.progress_ms;
+        }
+        let elapsed_ms = at.saturating_duration_since(self.anchor).as_secs_f64() * 1000.0;
+        self.progress_ms + elapsed_ms * self.rate
+    }
+
+    fn progress_ms(&self) -> u32 {
+        self.progress_at(Instant::now()).max(0.0) as u32
+    }
+
+    /// Start over with a new track, the drift is kept.
+    fn reset(&mut self, progress_ms: u32, at: Instant) {
+        self.progress_ms = progress_ms as f64;
+        self.anchor = at;
+        self.paused = false;
+        self.last_report = Some((progress_ms, at));
+    }
+
+    /// The player said the track was at `progress_ms` `at` this time.
+    /// Returns true if the estimate had to jump, when seeking for example.
+    fn report(&mut self, progress_ms: u32, at: Instant) -> bool {
+        let (last_ms, last_at) = match self.last_report {
+            Some(last) => last,
+            None => {
+                self.reset(progress_ms, at);
+                return true;
+            }
+        };
+        let elapsed_ms = at.saturating_duration_since(last_at).as_secs_f64() * 1000.0;
+        if elapsed_ms <= 0.0 {
+            return false;
+        }
+        let advanced_ms = progress_ms as f64 - last_ms as f64;
+        self.last_report = Some((progress_ms, at));
+
+        if advanced_ms.abs() < PAUSE_THRESHOLD_MS {
+            self.progress_ms = progress_ms as f64;
+            self.anchor = at;
+            self.paused = true;
+            return false;
+        }
+
+        let error_ms = progress_ms as f64 - self.progress_at(at);
+        if self.paused || error_ms.abs() > SEEK_THRESHOLD_MS {
+            // Resumed or seeked, the measure of the rate is meaningless
+            self.reset(progress_ms, at);
+            return error_ms.abs() > SEEK_THRESHOLD_MS;
+        }
+
+        let measured = (advanced_ms / elapsed_ms).max(MIN_RATE).min(MAX_RATE);
+        self.rate += (measured - self.rate) * RATE_SMOOTHING;
+        self.progress_ms = self.progress_at(at) + error_ms * PROGRESS_CORRECTION;
+        self.anchor = at;
+        false
+    }
+}
+
+/// The character of a track according to spotify.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TrackFeatures {
+    /// Between 0 and 1.
+    pub danceability: f32,
+    /// Between 0 and 1.
+    pub energy: f32,
+}
+
+/// A player giving the current track instead of the spotify API.
+pub enum PlayerSource {
+    #[cfg(feature = "source_mpris")]
+    Mpris(MprisTracker),
+    Mpd(MpdTracker),
+}
+
+impl PlayerSource {
+    fn current_track(&mut self) -> Option<TrackInfo> {
+        match self {
+            #[cfg(feature = "source_mpris")]
+            Self::Mpris(mpris) => mpris.current_track(),
+            Self::Mpd(mpd) => mpd.current_track(),
+        }
+    }
+}
 
 pub struct SpotifyTracker {
-    oauth: SpotifyOAuth,
+    // Only when logged in as a user
+    oauth: Option<SpotifyOAuth>,
+    refresh_token: Option<String>,
+    token_expiry: Instant,
     spotify: Spotify,
+    player: Option<PlayerSource>,
 
     // Current track tracking
+    refresh_threshold: Duration,
     last_track_query: Instant,
     track_end_time: Instant,
-    current_track_cache: Option<Playing>,
+    current_track_cache: Option<TrackInfo>,
+    progress: ProgressClock,
 
     // Track analysis
     audio_analysis: Option<AudioAnalysis>,
+    features: Option<TrackFeatures>,
     last_beat_index: usize,
     is_beat: bool,
+    last_segment_index: usize,
+    loudness: f32,
+
+    // Colors of the track
+    palette_source: PaletteSource,
+    palette: Option<Vec<[u8; 3]>>,
+
+    // Genres of the artist, only fetched when needed
+    fetch_genres: bool,
+    genres: Vec<String>,
 }
 
 impl SpotifyTracker {
-    pub async fn new(client_id: &str, client_secret: &str, no_cache: bool) -> Result<Self> {
-        let mut oauth = SpotifyOAuth::default()
-            .client_id(client_id)
-            .client_secret(client_secret)
-            .redirect_uri("http://localhost/")
-            .scope("user-read-currently-playing")
-            .build();
-
-        // Ask for token
-        let token = if no_cache {
-            rspotify::util::get_token_without_cache(&mut oauth).await
+    /// When a `player` is given, the current track is read from it and the spotify API
+    /// is only used for the analysis, so there is no need to log in as a user.
+    pub async fn new(
+        client_id: &str, client_secret: &str, auth: &SpotifyAuth, player: Option<PlayerSource>,
+    ) -> Result<Self> {
+        let (oauth, token, credentials) = if player.is_some() {
+            // Client credentials are refreshed by rspotify itself
+            let credentials = SpotifyClientCredentials::default()
+                .client_id(client_id)
+                .client_secret(client_secret)
+                .build();
+            (None, None, credentials)
         } else {
-            rspotify::util::get_token(&mut oauth).await
-        }
-        .ok_or(anyhow!("Failed to get spotify token !"))?;
+            let mut oauth = SpotifyOAuth::default()
+                .client_id(client_id)
+                .client_secret(client_secret)
+                .redirect_uri(&auth.redirect_uri)
+                .scope("user-read-currently-playing")
+                .build();
 
-        let credentials = SpotifyClientCredentials::default()
-            .token_info(token)
-            .build();
+            // Ask for token
+            let token = request_token(&mut oauth, auth)
+                .await
+                .ok_or(anyhow!("Failed to get spotify token !"))?;
+
+            let credentials = SpotifyClientCredentials::default()
+                .token_info(token.clone())
+                .build();
+            (Some(oauth), Some(token), credentials)
+        };
 
         let spotify = Spotify::default()
             .client_credentials_manager(credentials)
@@ -50,60 +325,153 @@ impl SpotifyTracker {
 
         Ok(Self {
             oauth,
+            refresh_token: token.as_ref().and_then(|token| token.refresh_token.clone()),
+            token_expiry: token.as_ref().map_or_else(Instant::now, token_expiry),
             spotify,
+            refresh_threshold: if player.is_some() {
+                LOCAL_TIMEOUT_THRESHOLD
+            } else {
+                REGULAR_TIMEOUT_THRESHOLD
+            },
+            player,
             last_track_query: Instant::now() - Duration::from_secs(60),
             track_end_time: Instant::now() + Duration::from_secs(60 * 3),
             current_track_cache: None,
+            progress: ProgressClock::new(),
 
             audio_analysis: None,
+            features: None,
             last_beat_index: 0,
             is_beat: false,
+            last_segment_index: 0,
+            loudness: SEGMENT_LOUDNESS_FLOOR,
+
+            palette_source: PaletteSource::Album,
+            palette: None,
+
+            fetch_genres: false,
+            genres: Vec::new(),
         })
     }
 }
 
+/// When the token will expire, according to the most precise information available.
+fn token_expiry(token: &TokenInfo) -> Instant {
+    let now = Instant::now();
+    let remaining = match token.expires_at {
+        Some(expires_at) => {
+            let unix_now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |now| now.as_secs() as i64);
+            (expires_at - unix_now).max(0) as u64
+        }
+        None => token.expires_in as u64,
+    };
+
+    (now + Duration::from_secs(remaining))
+        .checked_sub(TOKEN_REFRESH_MARGIN)
+        .unwrap_or(now)
+}
+
+// User token handling
+impl SpotifyTracker {
+    /// Refresh the user token if it is about to expire.
+    /// Does nothing when using client credentials.
+    async fn ensure_token(&mut self) {
+        if self.oauth.is_none() || Instant::now() < self.token_expiry {
+            return;
+        }
+
+        match self.fetch_new_token().await {
+            Some(token) => self.use_token(token),
+            None => {
+                // Don't hammer the API, try again later
+                error!("Failed to refresh spotify token !");
+                self.token_expiry = Instant::now() + self.refresh_threshold;
+            }
+        }
+    }
+
+    async fn fetch_new_token(&mut self) -> Option<TokenInfo> {
+        let oauth = self.oauth.as_mut()?;
+        if let Some(refresh_token) = self.refresh_token.as_ref() {
+            if let Some(token) = oauth.refresh_access_token(refresh_token).await {
+                return Some(token);
+            }
+        }
+
+        // The cache may have been updated by someone else
+        oauth.get_cached_token().await
+    }
+
+    fn use_token(&mut self, token: TokenInfo) {
+        self.token_expiry = token_expiry(&token);
+        // Spotify doesn't always send a new refresh token, the old one is still valid then
+        if let Some(refresh_token) = token.refresh_token.clone() {
+            self.refresh_token = Some(refresh_token);
+        }
+
+        let credentials = SpotifyClientCredentials::default()
+            .token_info(token)
+            .build();
+        self.spotify = Spotify::default()
+            .client_credentials_manager(credentials)
+            .build();
+    }
+}
+
 // Current track fetch
 impl SpotifyTracker {
     pub async fn refresh_current_track(&mut self) {
         let now = Instant::now();
         if now >= self.track_end_time
-            || now.duration_since(self.last_track_query) >= REGULAR_TIMEOUT_THRESHOLD
+            || now.duration_since(self.last_track_query) >= self.refresh_threshold
         {
-            // Takes several ms
-            match self.spotify.current_user_playing_track().await {
+            self.ensure_token().await;
+
+            let new_track = if let Some(player) = self.player.as_mut() {
+                Ok(player.current_track())
+            } else {
+                // Takes several ms
+                self.spotify
+                    .current_user_playing_track()
+                    .await
+                    .map(|playing| playing.and_then(TrackInfo::from_playing))
+            };
+
+            // The progress was measured around now, not after fetching the analysis
+            let answered = Instant::now();
+            match new_track {
                 Ok(new_track) => {
-                    let mut refresh_analysis = false;
-
-                    if let Some(Playing {
-                        item:
-                            Some(FullTrack {
-                                id: Some(new_id), ..
-                            }),
-                        ..
-                    }) = new_track.as_ref()
-                    {
-                        if let Some(Playing {
-                            item:
-                                Some(FullTrack {
-                                    id: Some(old_id), ..
-                                }),
-                            ..
-                        }) = self.current_track_cache.as_ref()
-                        {
-                            if new_id != old_id {
-                                refresh_analysis = true;
+                    let refresh_analysis =
+                        match (new_track.as_ref(), self.current_track_cache.as_ref()) {
+                            (Some(new), Some(old)) => new.key != old.key,
+                            (Some(_), None) => true,
+                            (None, _) => {
+                                self.audio_analysis.take();
+                                self.features.take();
+                                self.palette.take();
+                                self.genres.clear();
+                                false
                             }
-                        } else {
-                            refresh_analysis = true;
-                        }
-                    } else {
-                        self.current_track_cache.take();
-                        self.audio_analysis.take();
-                    }
+                        };
 
                     self.current_track_cache = new_track;
+                    let progress_ms = self
+                        .current_track_cache
+                        .as_ref()
+                        .map(|track| track.progress_ms);
+                    if let Some(progress_ms) = progress_ms {
+                        if refresh_analysis {
+                            self.progress.reset(progress_ms, answered);
+                        } else if self.progress.report(progress_ms, answered) {
+                            self.sync_beat_index();
+                        }
+                    }
                     if refresh_analysis {
                         self.refresh_track_analysis().await;
+                        self.refresh_palette().await;
+                        self.refresh_artist_genres().await;
                     }
 
                     self.update_timings_with_current();
@@ -112,23 +480,17 @@ impl SpotifyTracker {
                     let err = err.downcast::<ApiError>().unwrap();
                     match err {
                         ApiError::RateLimited(Some(secs)) => {
-                            eprintln!("Rate limited for {} secs", secs);
+                            warn!("Rate limited for {} secs", secs);
                             let now = Instant::now();
                             self.last_track_query = now;
                             self.track_end_time =
                                 now + REGULAR_TIMEOUT_THRESHOLD - Duration::from_secs(secs as u64);
                         }
-                        ApiError::Unauthorized | _ => {
-                            // Re auth and retry
-                            let token = rspotify::util::get_token(&mut self.oauth).await;
-                            let cred = self
-                                .spotify
-                                .client_credentials_manager
-                                .take()
-                                .unwrap()
-                                .token_info(token.expect("Failed to refresh token"));
-                            self.spotify = Spotify::default().client_credentials_manager(cred);
+                        ApiError::Unauthorized => {
+                            // The token was revoked or expired early, refresh it on the next query
+                            self.token_expiry = now;
                         }
+                        _ => {}
                     }
                 }
             }
@@ -138,49 +500,104 @@ impl SpotifyTracker {
     fn update_timings_with_current(&mut self) {
         let now = Instant::now();
         self.last_track_query = now;
-        if let Some(Playing {
-            item: Some(track),
-            progress_ms: Some(progress_ms),
-            ..
-        }) = self.current_track_cache.as_ref()
-        {
-            self.track_end_time =
-                now + Duration::from_millis((track.duration_ms - progress_ms) as u64);
+        if let Some(track) = self.current_track_cache.as_ref() {
+            self.track_end_time = now
+                + Duration::from_millis(
+                    track
+                        .duration_ms
+                        .saturating_sub(self.progress.progress_ms()) as u64,
+                );
         }
     }
 
     /// Be sure to call [refresh_current_track] before.
     /// Returns the playing track and its real progress in ms.
-    pub fn current_track(&self) -> Option<(&Playing, u32)> {
-        if let Some(playing) = self.current_track_cache.as_ref() {
-            Some((playing, self.compute_real_progress_ms(playing)))
+    pub fn current_track(&self) -> Option<(&TrackInfo, u32)> {
+        if let Some(track) = self.current_track_cache.as_ref() {
+            Some((track, self.progress.progress_ms()))
         } else {
             None
         }
     }
-
-    #[inline]
-    fn compute_real_progress_ms(&self, playing: &Playing) -> u32 {
-        playing.progress_ms.unwrap_or(0)
-            + Instant::now()
-                .duration_since(self.last_track_query)
-                .as_millis() as u32
-    }
 }
 
 // Track analysis fetch
 impl SpotifyTracker {
     async fn refresh_track_analysis(&mut self) {
-        if let Some(Playing {
-            item: Some(FullTrack { id: Some(id), .. }),
-            ..
-        }) = self.current_track_cache.as_ref()
+        self.audio_analysis = None;
+        self.features = None;
+
+        let track = match self.current_track_cache.as_ref() {
+            Some(track) => track,
+            None => return,
+        };
+
+        // Tracks that don't come from spotify need to be looked up first
+        let id = match track.spotify_id.clone() {
+            Some(id) => id,
+            None => match self.search_track_id(track).await {
+                Some(id) => id,
+                None => return,
+            },
+        };
+        // Not to search it again for the genres
+        if let Some(track) = self.current_track_cache.as_mut() {
+            track.spotify_id = Some(id.clone());
+        }
+
+        match self.spotify.audio_analysis(&id).await {
+            Ok(analysis) => {
+                self.audio_analysis = Some(analysis);
+                self.last_beat_index = 0;
+                self.last_segment_index = 0;
+            }
+            Err(err) => error!("Failed to get track analysis: {}", err),
+        }
+
+        match self.spotify.audio_features(&id).await {
+            Ok(features) => {
+                self.features = Some(TrackFeatures {
+                    danceability: features.danceability,
+                    energy: features.energy,
+                })
+            }
+            Err(err) => error!("Failed to get track features: {}", err),
+        }
+    }
+
+    /// Danceability and energy of the current track, if known.
+    pub fn features(&self) -> Option<TrackFeatures> {
+        self.features
+    }
+
+    async fn search_track_id(&self, track: &TrackInfo) -> Option<String> {
+        let query = if track.artist.is_empty() {
+            format!("track:{}", track.name)
+        } else {
+            format!("track:{} artist:{}", track.name, track.artist)
+        };
+
+        match self
+            .spotify
+            .search(&query, SearchType::Track, 1, 0, None, None)
+            .await
         {
-            self.audio_analysis = Some(self.spotify.audio_analysis(id).await.unwrap());
-            self.last_beat_index = 0;
+            Ok(SearchResult::Tracks(page)) => {
+                page.items.into_iter().next().and_then(|found| found.id)
+            }
+            Ok(_) => None,
+            Err(err) => {
+                error!("Failed to search track: {}", err);
+                None
+            }
         }
     }
 
+    /// The track is known to spotify, its beats and tempo are available.
+    pub fn has_analysis(&self) -> bool {
+        self.audio_analysis.is_some()
+    }
+
     pub fn tempo(&self) -> f32 {
         if let Some(analysis) = self.audio_analysis.as_ref() {
             analysis.track.tempo
@@ -191,10 +608,7 @@ impl SpotifyTracker {
 
     pub fn advance_beat(&mut self) {
         if let Some(analysis) = self.audio_analysis.as_ref() {
-            // If there is an analysis, there is a track
-            let progress = self.compute_real_progress_ms(self.current_track_cache.as_ref().unwrap())
-                as f32
-                / 1000.0;
+            let progress = self.progress.progress_ms() as f32 / 1000.0;
 
             let beat = analysis
                 .beats
@@ -217,8 +631,173 @@ impl SpotifyTracker {
         }
     }
 
+    /// After a seek, continue from the next beat without flashing all the ones in between.
+    fn sync_beat_index(&mut self) {
+        if let Some(analysis) = self.audio_analysis.as_ref() {
+            let progress = self.progress.progress_ms() as f32 / 1000.0;
+            self.last_beat_index = analysis
+                .beats
+                .iter()
+                .position(|beat| beat.start >= progress)
+                .unwrap_or_else(|| analysis.beats.len());
+        }
+    }
+
     /// Be sure to call [advance_beat] before to be up to date.
     pub fn is_beat(&self) -> bool {
         self.is_beat
     }
 }
+
+// Track segments
+impl SpotifyTracker {
+    /// Interpolate the loudness of the current segment at the real progress of the track.
+    pub fn advance_segment(&mut self) {
+        let analysis = match self.audio_analysis.as_ref() {
+            Some(analysis) if !analysis.segments.is_empty() => analysis,
+            _ => {
+                self.loudness = SEGMENT_LOUDNESS_FLOOR;
+                return;
+            }
+        };
+
+        let progress = self.progress.progress_ms() as f32 / 1000.0;
+
+        // The track may have been seeked backward
+        if progress
+            < analysis.segments[self.last_segment_index.min(analysis.segments.len() - 1)].start
+        {
+            self.last_segment_index = 0;
+        }
+
+        while let Some(next) = analysis.segments.get(self.last_segment_index + 1) {
+            if next.start > progress {
+                break;
+            }
+            self.last_segment_index += 1;
+        }
+
+        let segment = &analysis.segments[self.last_segment_index];
+        let loudness_end = segment
+            .loudness_end
+            .or_else(|| {
+                analysis
+                    .segments
+                    .get(self.last_segment_index + 1)
+                    .map(|next| next.loudness_start)
+            })
+            .unwrap_or(segment.loudness_start);
+
+        // Loudness rises from its start value to its max, then decays to its end value
+        let elapsed = (progress - segment.start).max(0.0);
+        self.loudness = if elapsed < segment.loudness_max_time {
+            let t = elapsed / segment.loudness_max_time;
+            segment.loudness_start + (segment.loudness_max - segment.loudness_start) * t
+        } else {
+            let decay_time = (segment.duration - segment.loudness_max_time).max(f32::EPSILON);
+            let t = ((elapsed - segment.loudness_max_time) / decay_time).min(1.0);
+            segment.loudness_max + (loudness_end - segment.loudness_max) * t
+        };
+    }
+
+    /// The track analysis has segments, the loudness follows them.
+    pub fn has_segments(&self) -> bool {
+        self.audio_analysis
+            .as_ref()
+            .map_or(false, |analysis| !analysis.segments.is_empty())
+    }
+
+    /// Be sure to call [advance_segment] before to be up to date.
+    /// Returns the loudness of the current segment normalized between 0 and 1.
+    pub fn segment_loudness(&self) -> f32 {
+        ((self.loudness - SEGMENT_LOUDNESS_FLOOR) / -SEGMENT_LOUDNESS_FLOOR)
+            .max(0.0)
+            .min(1.0)
+    }
+}
+
+// Artist genres
+impl SpotifyTracker {
+    /// Fetch the genres of the artist of each track from now on.
+    pub fn enable_genres(&mut self) {
+        self.fetch_genres = true;
+    }
+
+    async fn refresh_artist_genres(&mut self) {
+        self.genres.clear();
+        if !self.fetch_genres {
+            return;
+        }
+
+        let track = match self.current_track_cache.as_ref() {
+            Some(track) => track,
+            None => return,
+        };
+
+        // The artist is only known for the tracks coming from the spotify API
+        let artist_id = match (track.artist_id.clone(), track.spotify_id.as_deref()) {
+            (Some(id), _) => id,
+            (None, Some(track_id)) => match self.spotify.track(track_id).await {
+                Ok(track) => match track.artists.into_iter().next().and_then(|a| a.id) {
+                    Some(id) => id,
+                    None => return,
+                },
+                Err(err) => {
+                    error!("Failed to get the track: {}", err);
+                    return;
+                }
+            },
+            (None, None) => return,
+        };
+
+        match self.spotify.artist(&artist_id).await {
+            Ok(artist) => self.genres = artist.genres,
+            Err(err) => error!("Failed to get the genres of the artist: {}", err),
+        }
+    }
+
+    /// Genres of the artist of the current track, empty if unknown.
+    pub fn genres(&self) -> &[String] {
+        &self.genres
+    }
+}
+
+// Colors of the track
+impl SpotifyTracker {
+    /// Where the palette of the next tracks comes from.
+    pub fn set_palette_source(&mut self, source: PaletteSource) {
+        self.palette_source = source;
+    }
+
+    /// After the track analysis, for the features.
+    async fn refresh_palette(&mut self) {
+        self.palette = None;
+        if self.palette_source == PaletteSource::Features {
+            self.palette = self
+                .features
+                .map(|features| palette::features_palette(features, PALETTE_SIZE));
+            return;
+        }
+
+        let url = match self
+            .current_track_cache
+            .as_ref()
+            .and_then(|track| track.album_art_url.clone())
+        {
+            Some(url) => url,
+            None => return,
+        };
+
+        match palette::fetch_palette(&url, PALETTE_SIZE).await {
+            Ok(colors) if !colors.is_empty() => self.palette = Some(colors),
+            Ok(_) => {}
+            Err(err) => error!("Failed to extract album palette: {}", err),
+        }
+    }
+
+    /// Dominant colors of the current album art if any could be extracted, or the colors
+    /// made from the features.
+    pub fn palette(&self) -> Option<&[[u8; 3]]> {
+        self.palette.as_deref()
+    }
+}
@@ -0,0 +1,137 @@
+use crate::{from_i16, Sample};
+use anyhow::{anyhow, Result};
+use librespot::{
+    connect::spirc::Spirc,
+    core::{
+        authentication::Credentials,
+        config::{ConnectConfig, DeviceType, SessionConfig},
+        session::Session,
+    },
+    playback::{
+        audio_backend::{self, Sink},
+        config::PlayerConfig,
+        decoder::AudioPacket,
+        mixer::{softmixer::SoftMixer, Mixer},
+        player::Player,
+    },
+};
+use parking_lot::Mutex;
+use ringbuf::Producer;
+use std::{io, sync::Arc, thread::JoinHandle};
+
+/// A Spotify Connect device whose decoded audio is fed directly to the analysis.
+/// The audio is still played on the default librespot backend if there is one.
+pub struct SpotifyConnect {
+    producer: Arc<Mutex<Option<Producer<Sample>>>>,
+    _thread: JoinHandle<()>,
+}
+
+impl SpotifyConnect {
+    pub fn new(name: String, username: String, password: String) -> Result<Self> {
+        let producer = Arc::new(Mutex::new(None));
+        let sink_producer = producer.clone();
+
+        // Librespot needs its own runtime, we just wait for the session to be up
+        let (ready_sender, ready_receiver) = std::sync::mpsc::channel();
+
+        let thread = std::thread::Builder::new()
+            .name("Librespot Thread".into())
+            .spawn(move || {
+                let runtime = match tokio1::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                {
+                    Ok(runtime) => runtime,
+                    Err(err) => {
+                        ready_sender.send(Err(anyhow!(err))).unwrap();
+                        return;
+                    }
+                };
+
+                runtime.block_on(async move {
+                    let credentials = Credentials::with_password(username, password);
+                    let session =
+                        match Session::connect(SessionConfig::default(), credentials, None).await {
+                            Ok(session) => session,
+                            Err(err) => {
+                                ready_sender
+                                    .send(Err(anyhow!("Librespot session failed: {}", err)))
+                                    .unwrap();
+                                return;
+                            }
+                        };
+
+                    let (player, _) =
+                        Player::new(PlayerConfig::default(), session.clone(), None, move || {
+                            Box::new(TeeSink {
+                                producer: sink_producer,
+                                playback: audio_backend::find(None).map(|builder| builder(None)),
+                            })
+                        });
+
+                    let connect_config = ConnectConfig {
+                        name,
+                        device_type: DeviceType::Computer,
+                        volume: u16::MAX / 2,
+                        autoplay: false,
+                    };
+                    let (_spirc, spirc_task) = Spirc::new(
+                        connect_config,
+                        session,
+                        player,
+                        Box::new(SoftMixer::open(None)),
+                    );
+
+                    ready_sender.send(Ok(())).unwrap();
+                    spirc_task.await;
+                });
+            })?;
+
+        ready_receiver
+            .recv()
+            .map_err(|_| anyhow!("Librespot thread died !"))??;
+
+        Ok(Self {
+            producer,
+            _thread: thread,
+        })
+    }
+
+    /// Replace the buffer to which the decoded samples will be pushed.
+    pub fn attach(&self, producer: Producer<Sample>) {
+        self.producer.lock().replace(producer);
+    }
+}
+
+struct TeeSink {
+    producer: Arc<Mutex<Option<Producer<Sample>>>>,
+    playback: Option<Box<dyn Sink>>,
+}
+
+impl Sink for TeeSink {
+    fn start(&mut self) -> io::Result<()> {
+        if let Some(playback) = self.playback.as_mut() {
+            playback.start()?;
+        }
+        Ok(())
+    }
+
+    fn stop(&mut self) -> io::Result<()> {
+        if let Some(playback) = self.playback.as_mut() {
+            playback.stop()?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, packet: &AudioPacket) -> io::Result<()> {
+        if let Some(producer) = self.producer.lock().as_mut() {
+            // Librespot always decodes to 44100Hz stereo, in 16 bits
+            producer.push_iter(&mut packet.samples().iter().copied().map(from_i16));
+        }
+
+        if let Some(playback) = self.playback.as_mut() {
+            playback.write(packet)?;
+        }
+        Ok(())
+    }
+}
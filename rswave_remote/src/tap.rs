@@ -0,0 +1,84 @@
+use crate::net::Analysis;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Taps further apart than this start a new tempo, the last one only moves the beats.
+const TAP_TIMEOUT: Duration = Duration::from_secs(2);
+/// Taps averaged to find the tempo.
+const MAX_TAPS: usize = 8;
+/// 300 beats per minute, faster is a bounce of the key.
+const MIN_PERIOD: Duration = Duration::from_millis(200);
+
+/// A tempo tapped by hand from the TUI, for when neither spotify nor the tempo tracking
+/// find the beats.
+pub struct TapTempo {
+    /// The most recent last.
+    taps: VecDeque<Instant>,
+    period: Option<Duration>,
+    next_beat: Option<Instant>,
+    /// A tap not yet sent as a beat.
+    tapped: bool,
+}
+
+impl TapTempo {
+    pub fn new() -> Self {
+        Self {
+            taps: VecDeque::new(),
+            period: None,
+            next_beat: None,
+            tapped: false,
+        }
+    }
+
+    /// The beats are then in phase with this tap.
+    pub fn tap(&mut self, now: Instant) {
+        match self.taps.back() {
+            Some(&last) if now - last < MIN_PERIOD => return,
+            Some(&last) if now - last > TAP_TIMEOUT => self.taps.clear(),
+            _ => {}
+        }
+        self.taps.push_back(now);
+        while self.taps.len() > MAX_TAPS {
+            self.taps.pop_front();
+        }
+
+        if self.taps.len() > 1 {
+            self.period = Some((now - self.taps[0]) / (self.taps.len() - 1) as u32);
+        }
+        if let Some(period) = self.period {
+            self.next_beat = Some(now + period);
+            self.tapped = true;
+        }
+    }
+
+    /// Stop the beats.
+    pub fn forget(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Beats per minute, 0 when nothing was tapped.
+    pub fn tempo(&self) -> f64 {
+        self.period
+            .map_or(0.0, |period| 60.0 / period.as_secs_f64())
+    }
+
+    /// Take over the beat and the tempo of the analysis if nothing else found them.
+    pub fn fill_in(&mut self, analysis: &mut Analysis, now: Instant) {
+        let period = match self.period {
+            Some(period) if analysis.tempo <= 0.0 => period,
+            _ => return,
+        };
+
+        let mut beat = std::mem::take(&mut self.tapped);
+        if let Some(next_beat) = self.next_beat.as_mut() {
+            while *next_beat <= now {
+                *next_beat += period;
+                beat = true;
+            }
+        }
+        analysis.beat |= beat;
+        analysis.tempo = self.tempo();
+    }
+}
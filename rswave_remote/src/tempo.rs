@@ -0,0 +1,190 @@
+use crate::{audio::SAMPLE_RATE, net::Analysis, spotify::SpotifyTracker};
+use std::collections::VecDeque;
+
+/// Seconds of novelty looked at to find the tempo.
+const HISTORY_SECS: f64 = 6.0;
+/// Seconds between two estimations of the tempo.
+const ESTIMATE_SECS: f64 = 1.0;
+/// Tempos considered, in beats per minute.
+const MIN_TEMPO: f64 = 60.0;
+const MAX_TEMPO: f64 = 180.0;
+/// Most music is around there, it settles the octave errors.
+const PREFERRED_TEMPO: f64 = 120.0;
+/// Below this correlation with itself, the novelty has no tempo to speak of.
+const MIN_CONFIDENCE: f64 = 0.1;
+/// Part of the phase error corrected at each estimation, so the beats don't jump around.
+const PHASE_CORRECTION: f64 = 0.5;
+
+/// Finds the tempo and the beats in the novelty when spotify doesn't know the track: the
+/// period comes from the autocorrelation of the recent novelty, and the beats are locked on
+/// its peaks.
+pub struct TempoTracker {
+    /// In seconds.
+    frame_period: f64,
+    /// Recent novelty, one value per frame.
+    history: VecDeque<f64>,
+    /// Frames since the last estimation.
+    since_estimate: usize,
+    /// Beat period in frames, `None` until the novelty is regular enough.
+    period: Option<f64>,
+    /// Frames until the next beat.
+    countdown: f64,
+}
+
+impl TempoTracker {
+    /// The audio is analysed `sample_size` samples at a time.
+    pub fn new(sample_size: usize) -> Self {
+        Self {
+            frame_period: sample_size as f64 / SAMPLE_RATE as f64,
+            history: VecDeque::new(),
+            since_estimate: 0,
+            period: None,
+            countdown: 0.0,
+        }
+    }
+
+    /// Feed the novelty of the frame, and take over the beat and the tempo of the analysis if
+    /// spotify doesn't have the track.
+    pub fn fill_in(&mut self, analysis: &mut Analysis, spotify: Option<&SpotifyTracker>) {
+        let beat = self.update(analysis.novelty);
+        if spotify.map_or(false, |spotify| spotify.has_analysis()) {
+            return;
+        }
+
+        analysis.beat = beat;
+        analysis.tempo = self.tempo();
+    }
+
+    /// Beats per minute, 0 when unknown.
+    pub fn tempo(&self) -> f64 {
+        self.period
+            .map_or(0.0, |period| 60.0 / (period * self.frame_period))
+    }
+
+    /// Returns true on a beat.
+    fn update(&mut self, novelty: f64) -> bool {
+        let size = (HISTORY_SECS / self.frame_period) as usize;
+        self.history.push_back(novelty);
+        while self.history.len() > size {
+            self.history.pop_front();
+        }
+
+        let beat = match self.period {
+            Some(period) => {
+                self.countdown -= 1.0;
+                if self.countdown <= 0.0 {
+                    self.countdown += period;
+                    true
+                } else {
+                    false
+                }
+            }
+            None => false,
+        };
+
+        self.since_estimate += 1;
+        if self.since_estimate as f64 * self.frame_period >= ESTIMATE_SECS
+            && self.history.len() == size
+        {
+            self.since_estimate = 0;
+            self.estimate();
+        }
+        beat
+    }
+
+    fn estimate(&mut self) {
+        let mean = self.history.iter().sum::<f64>() / self.history.len() as f64;
+        let curve = self
+            .history
+            .iter()
+            .map(|novelty| novelty - mean)
+            .collect::<Vec<_>>();
+
+        let energy = Self::autocorrelation(&curve, 0);
+        if energy <= 0.0 {
+            self.period = None;
+            return;
+        }
+
+        let min_lag = (60.0 / (MAX_TEMPO * self.frame_period)).floor().max(1.0) as usize;
+        let max_lag =
+            ((60.0 / (MIN_TEMPO * self.frame_period)).ceil() as usize).min(curve.len() / 2);
+        if min_lag + 2 > max_lag {
+            return;
+        }
+        let correlations = (min_lag - 1..=max_lag + 1)
+            .map(|lag| Self::autocorrelation(&curve, lag) / energy)
+            .collect::<Vec<_>>();
+
+        // Weighted towards the preferred tempo, on a log scale
+        let weight = |lag: usize| {
+            let tempo = 60.0 / (lag as f64 * self.frame_period);
+            let octaves = (tempo / PREFERRED_TEMPO).log2();
+            (-octaves * octaves).exp()
+        };
+        let best = (1..correlations.len() - 1)
+            .max_by(|&a, &b| {
+                let a = correlations[a] * weight(a + min_lag - 1);
+                let b = correlations[b] * weight(b + min_lag - 1);
+                a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .unwrap();
+        if correlations[best] < MIN_CONFIDENCE {
+            self.period = None;
+            return;
+        }
+
+        // The frames are long, refine the lag between them with a parabola
+        let (before, at, after) = (
+            correlations[best - 1],
+            correlations[best],
+            correlations[best + 1],
+        );
+        let curvature = before - 2.0 * at + after;
+        let shift = if curvature < 0.0 {
+            (0.5 * (before - after) / curvature).max(-0.5).min(0.5)
+        } else {
+            0.0
+        };
+        let period = (best + min_lag - 1) as f64 + shift;
+
+        // Lock the phase on the peaks of the novelty, the best offset of a comb over the curve
+        let phase = (0..period.round() as usize)
+            .max_by(|&a, &b| {
+                let a = Self::comb(&curve, period, a);
+                let b = Self::comb(&curve, period, b);
+                a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .unwrap_or(0) as f64;
+        // The last beat was `phase` frames ago, counting this one
+        let countdown = period - phase;
+
+        match self.period {
+            Some(_) => {
+                let mut error = countdown - self.countdown;
+                if error > period / 2.0 {
+                    error -= period;
+                } else if error < -period / 2.0 {
+                    error += period;
+                }
+                self.countdown += error * PHASE_CORRECTION;
+            }
+            None => self.countdown = countdown,
+        }
+        self.period = Some(period);
+    }
+
+    fn autocorrelation(curve: &[f64], lag: usize) -> f64 {
+        curve.iter().zip(&curve[lag..]).map(|(a, b)| a * b).sum()
+    }
+
+    /// Sum of the novelty `offset` frames before the end, then every `period` frames.
+    fn comb(curve: &[f64], period: f64, offset: usize) -> f64 {
+        let last = curve.len() as f64 - 1.0 - offset as f64;
+        (0..)
+            .map(|k| last - k as f64 * period)
+            .take_while(|&index| index >= 0.0)
+            .map(|index| curve[index.round() as usize])
+            .sum()
+    }
+}
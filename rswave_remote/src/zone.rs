@@ -0,0 +1,86 @@
+use crate::{audio::AudioProcessor, to_f64, Sample};
+use anyhow::{anyhow, Error, Result};
+use rswave_common::packets::{ZoneData, MAX_ZONES, MAX_ZONE_NAME};
+use std::{collections::VecDeque, str::FromStr};
+
+/// A range of the spectrum sent to the zone of the servers with the same name.
+#[derive(Clone, Debug)]
+pub struct ZoneBand {
+    pub name: String,
+    /// In Hz.
+    pub low: f64,
+    pub high: f64,
+}
+
+impl FromStr for ZoneBand {
+    type Err = Error;
+
+    /// Parses `name:low-high`, like `floor:20-250`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        let (name, range) = match (parts.next(), parts.next()) {
+            (Some(name), Some(range)) if !name.trim().is_empty() => (name.trim(), range),
+            _ => return Err(anyhow!("Expected a zone like floor:20-250 !")),
+        };
+        if name.len() > MAX_ZONE_NAME {
+            return Err(anyhow!(
+                "The zone names are at most {} bytes long !",
+                MAX_ZONE_NAME
+            ));
+        }
+
+        let mut bounds = range
+            .splitn(2, '-')
+            .map(|bound| bound.trim().parse::<f64>());
+        match (bounds.next(), bounds.next()) {
+            (Some(Ok(low)), Some(Ok(high))) if low < high => Ok(Self {
+                name: name.to_string(),
+                low,
+                high,
+            }),
+            _ => Err(anyhow!("Invalid frequency range `{}` !", range)),
+        }
+    }
+}
+
+/// Computes the novelty of each zone along with its recent peak.
+pub struct ZoneTracker {
+    bands: Vec<ZoneBand>,
+    /// Recent novelty of each band, as long as the short term novelty.
+    curves: Vec<VecDeque<f64>>,
+}
+
+impl ZoneTracker {
+    /// Fails with more zones than a packet can hold.
+    pub fn new(bands: Vec<ZoneBand>) -> Result<Self> {
+        if bands.len() > MAX_ZONES {
+            return Err(anyhow!("At most {} zones can be sent !", MAX_ZONES));
+        }
+        Ok(Self {
+            curves: vec![VecDeque::new(); bands.len()],
+            bands,
+        })
+    }
+
+    /// Be sure to have processed the audio before.
+    pub fn update(&mut self, audio: &AudioProcessor<Sample>) -> Vec<ZoneData> {
+        let size = audio.short_term_novelty_size();
+        self.bands
+            .iter()
+            .zip(self.curves.iter_mut())
+            .map(|(band, curve)| {
+                let value = to_f64(audio.novelty_between(band.low, band.high));
+                curve.push_back(value);
+                while curve.len() > size {
+                    curve.pop_front();
+                }
+
+                ZoneData {
+                    name: band.name.clone(),
+                    value,
+                    peak: curve.iter().copied().fold(0.0, f64::max),
+                }
+            })
+            .collect()
+    }
+}
@@ -1,8 +1,13 @@
 fn main() {
     if cfg!(not(any(
         feature = "controller_ws2811",
-        feature = "controller_gpio"
+        feature = "controller_gpio",
+        feature = "controller_adalight",
+        feature = "controller_hyperion"
     ))) {
         panic!("You need to chose at least one LED controller !")
     }
+
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/rswave.proto").expect("Failed to compile the protobuf !");
 }
@@ -0,0 +1,154 @@
+use anyhow::{anyhow, Result};
+use cichlid::ColorRGB;
+#[cfg(feature = "animation_gif")]
+use std::fs::File;
+use std::{fs, path::Path};
+
+/// Images played one after the other by the animation runner, all of the same size.
+pub struct Animation {
+    width: usize,
+    height: usize,
+    /// Row by row.
+    frames: Vec<Vec<ColorRGB>>,
+    /// Frames per second, at the reference tempo.
+    pub fps: f32,
+    /// Beats per minute at which it plays at `fps`, it follows the beats when there is one.
+    pub tempo: Option<f32>,
+}
+
+impl Animation {
+    /// A GIF (requires the `animation_gif` feature) or a PPM with one frame per row, like the
+    /// images of `--dry-run-output`. The delays of the GIF are ignored, `fps` is used instead.
+    pub fn load(path: &Path, fps: f32, tempo: Option<f32>) -> Result<Self> {
+        if fps <= 0.0 || tempo.map_or(false, |tempo| tempo <= 0.0) {
+            return Err(anyhow!(
+                "The FPS and the tempo of the animation must be positive !"
+            ));
+        }
+
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_lowercase);
+        let (width, height, frames) = match extension.as_deref() {
+            Some("ppm") => Self::load_ppm(path)?,
+            #[cfg(feature = "animation_gif")]
+            Some("gif") => Self::load_gif(path)?,
+            _ => return Err(anyhow!("Unknown animation format for {} !", path.display())),
+        };
+        if frames.is_empty() || width == 0 || height == 0 {
+            return Err(anyhow!("The animation {} is empty !", path.display()));
+        }
+
+        Ok(Self {
+            width,
+            height,
+            frames,
+            fps,
+            tempo,
+        })
+    }
+
+    /// A frame is a single row.
+    fn load_ppm(path: &Path) -> Result<(usize, usize, Vec<Vec<ColorRGB>>)> {
+        let content = fs::read(path)?;
+
+        // The header is `P6 width height 255`, separated by whitespace
+        let mut fields = Vec::new();
+        let mut start = None;
+        let mut data = content.len();
+        for (i, byte) in content.iter().enumerate() {
+            if !byte.is_ascii_whitespace() {
+                start = start.or(Some(i));
+                continue;
+            }
+            if let Some(start) = start.take() {
+                fields.push(&content[start..i]);
+                if fields.len() == 4 {
+                    data = i + 1;
+                    break;
+                }
+            }
+        }
+        let number = |field: &[u8]| {
+            std::str::from_utf8(field)
+                .ok()
+                .and_then(|field| field.parse::<usize>().ok())
+        };
+        let (width, height) = match fields.as_slice() {
+            [b"P6", width, height, b"255"] => (number(width), number(height)),
+            _ => (None, None),
+        };
+        let (width, height) = match (width, height) {
+            (Some(width), Some(height)) => (width, height),
+            _ => {
+                return Err(anyhow!(
+                    "{} isn't a binary PPM with 8 bits colors !",
+                    path.display()
+                ))
+            }
+        };
+
+        let pixels = &content[data..];
+        if pixels.len() < width * height * 3 {
+            return Err(anyhow!("{} is cut short !", path.display()));
+        }
+        let frames = pixels[..width * height * 3]
+            .chunks_exact(width.max(1) * 3)
+            .map(|row| {
+                row.chunks_exact(3)
+                    .map(|rgb| ColorRGB::new(rgb[0], rgb[1], rgb[2]))
+                    .collect()
+            })
+            .collect();
+        Ok((width, 1, frames))
+    }
+
+    #[cfg(feature = "animation_gif")]
+    fn load_gif(path: &Path) -> Result<(usize, usize, Vec<Vec<ColorRGB>>)> {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(File::open(path)?)?;
+        let (width, height) = (decoder.width() as usize, decoder.height() as usize);
+
+        // The frames only draw what changed
+        let mut canvas = vec![ColorRGB::default(); width * height];
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame()? {
+            let (left, top) = (frame.left as usize, frame.top as usize);
+            let frame_width = (frame.width as usize).max(1);
+            let pixels = frame
+                .buffer
+                .chunks_exact(4)
+                .enumerate()
+                .map(|(i, rgba)| (left + i % frame_width, top + i / frame_width, rgba))
+                .filter(|&(x, y, _)| x < width && y < height);
+
+            for (x, y, rgba) in pixels.clone() {
+                // Transparent pixels keep what was below
+                if rgba[3] > 0 {
+                    canvas[y * width + x] = ColorRGB::new(rgba[0], rgba[1], rgba[2]);
+                }
+            }
+            frames.push(canvas.clone());
+
+            if let gif::DisposalMethod::Background = frame.dispose {
+                for (x, y, _) in pixels {
+                    canvas[y * width + x] = ColorRGB::default();
+                }
+            }
+        }
+        Ok((width, height, frames))
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Color of the frame at `x` and `y` between 0 and 1, from the top left corner.
+    pub fn pixel(&self, frame: usize, x: f32, y: f32) -> ColorRGB {
+        let column = (x.max(0.0).min(1.0) * (self.width - 1) as f32).round() as usize;
+        let row = (y.max(0.0).min(1.0) * (self.height - 1) as f32).round() as usize;
+        self.frames[frame % self.frames.len()][row * self.width + column]
+    }
+}
@@ -1,144 +1,1127 @@
+#[cfg(feature = "local_analysis")]
+use crate::local::LocalAnalysis;
 use crate::{
+    animation::Animation,
+    artnet::{ArtNetInput, ConsoleFrames},
+    division::BeatDivider,
+    effects::{RadialPulse, VerticalGradient},
+    jitter::{JitterBuffer, Playback},
+    layer::LayerStack,
+    layout::Layout,
     led_controllers::LedController,
     net::{NetHandler, RemoteData},
+    post,
+    preset::{Preset, Presets},
+    recording::{Recording, Recordings},
     runners::{
-        EpilepsyRunner, NoopRunner, Runner, RunnerEnum, SimpleBeatRunner, StandbyRunner,
-        WhiteRunner,
+        AnimationRunner, EpilepsyRunner, Frame, NoopRunner, ReplayRunner, Runner, RunnerEnum,
+        SimpleBeatRunner, SpatialRunner, SpectrumRunner, StandbyRunner, TrackFeatures, WhiteRunner,
     },
+    schedule::QuietMode,
+    zone::check_zones,
     Opt,
 };
-use anyhow::Result;
-use log::{debug, info};
-use single_value_channel::Updater;
+use anyhow::{anyhow, Result};
+use chrono::Local;
+use cichlid::ColorRGB;
+use rswave_common::packets::{BeatDivision, Command};
 use std::{
+    fs,
+    ops::Range,
+    sync::{mpsc as std_mpsc, Arc, Mutex, MutexGuard},
     thread::JoinHandle,
     time::{Duration, Instant},
 };
+use tokio::{
+    sync::{mpsc, oneshot},
+    task,
+};
+use tracing::{debug, info, info_span, trace_span, warn};
 
-#[derive(Debug, Copy, Clone)]
+/// Amount of events the net task can get ahead of the app.
+const NET_CHANNEL_SIZE: usize = 64;
+/// Amount of frames the local analysis can get ahead of the app.
+const LOCAL_CHANNEL_SIZE: usize = 16;
+/// Amount of runners that [App::common_runner] cycles through, one more with `--animation`.
+const COMMON_RUNNERS: usize = 6;
+/// Frames late in a row before complaining that the LEDs can't keep up.
+const OVERLOAD_FRAMES: u32 = 100;
+/// Time the strobe stays on, then off.
+const STROBE_HALF_PERIOD: Duration = Duration::from_millis(40);
+
+#[derive(Debug, Clone)]
 pub(crate) enum ControllerMessage {
     Standby,
     RandomRunner,
-    Analysis { novelty: f64, is_beat: bool },
-    Noop,
+    /// Show the next runner, even without remote.
+    NextRunner,
+    /// Switch between the standby and the current runner.
+    ToggleStandby,
+    /// Show this runner, even without remote.
+    Runner(usize),
+    Palette(Vec<ColorRGB>),
+    Features(TrackFeatures),
+    Brightness(u8),
+    StandbySpeed(f32),
+    /// Blank the strip and start the current runner over.
+    Reset,
+    /// Show the look saved with this id.
+    Preset(u8),
+    /// Save what is shown with this id, the name is kept if there is none.
+    SavePreset(u8, Option<String>),
+    BeatDivision(BeatDivision),
+    /// A beat for the runners, given by hand.
+    Beat,
+    /// Flash the whole strip in white over the runners for this long.
+    Strobe(Duration),
     Exit,
 }
 
+/// Analysis of one frame of the local audio.
+#[derive(Debug, Copy, Clone)]
+pub struct LocalFrame {
+    /// Relative to its recent peak.
+    pub novelty: f64,
+    pub is_onset: bool,
+    pub pitch_class: Option<u8>,
+    /// From -1 on the left to 1 on the right.
+    pub balance: f64,
+    /// When the samples were analysed, they were heard a bit earlier.
+    pub at: Instant,
+}
+
+/// What the strip shows, kept up to date for the APIs.
+#[derive(Debug, Clone, Default)]
+pub struct Status {
+    /// A remote drives the LEDs.
+    pub connected: bool,
+    /// Index of the common runner, `None` for the standby.
+    pub runner: Option<usize>,
+    pub brightness: u8,
+    pub standby_speed: f32,
+    pub quiet: bool,
+    /// The last preset recalled.
+    pub preset: Option<u8>,
+}
+
+/// Orders given to the app from the outside.
+#[derive(Debug, Clone)]
+pub enum Control {
+    /// Say goodbye to the remotes and return from [App::run].
+    Stop,
+    /// Show the next runner.
+    CycleRunner,
+    /// Switch between the standby and the current runner.
+    ToggleStandby,
+    /// Like the ones of the remotes.
+    Command(Command),
+    /// Save what is shown as the preset with this id, the name is kept if there is none.
+    SavePreset(u8, Option<String>),
+}
+
+/// Amount of common runners, the indexes given to them wrap around it.
+pub fn runner_count(opt: &Opt) -> usize {
+    COMMON_RUNNERS + opt.animation.is_some() as usize
+}
+
 pub struct App<C: LedController + Send + 'static> {
-    _opt: Opt,
-    net: NetHandler,
+    opt: Opt,
+
+    net_task: task::JoinHandle<Result<()>>,
+    net_stop: oneshot::Sender<()>,
+    remote_data: mpsc::Receiver<RemoteData>,
+    connected: bool,
+
+    /// Drives the LEDs when no remote is connected, if enabled.
+    #[cfg(feature = "local_analysis")]
+    local: Option<LocalAnalysis>,
+    local_data: mpsc::Receiver<LocalFrame>,
+    has_local: bool,
+    /// Compares the beats of the remotes with the local analysis, if enabled.
+    beat_diagnostics: Option<BeatDiagnostics>,
+    /// Lets a lighting console take over the runners, if enabled.
+    artnet: Option<ArtNetInput>,
+
+    control_sender: mpsc::Sender<Control>,
+    control: mpsc::Receiver<Control>,
 
     runner_thread: JoinHandle<()>,
-    messenger: Updater<ControllerMessage>,
+    messenger: std_mpsc::Sender<ControllerMessage>,
+    /// One per zone, or a single one for the whole strip.
+    jitter_buffers: Arc<Mutex<Vec<JitterBuffer>>>,
+    /// Shown on start, instead of the standby.
+    initial_preset: Option<u8>,
+    presets: Option<Arc<Mutex<Presets>>>,
+    /// The runner thread saved a preset, the file must be written.
+    presets_saved: mpsc::Receiver<()>,
+    status: Arc<Mutex<Status>>,
 
     _phantom: std::marker::PhantomData<C>,
 }
 
 impl<C: LedController + Send + 'static> App<C> {
+    /// Must be called from within the tokio runtime.
     pub fn new(opt: Opt, controller: C) -> Result<Self> {
-        let net = NetHandler::new(opt.port)?;
-        let (runner_thread, messenger) = Self::make_controller_thread(opt, controller);
+        if !opt.zone.is_empty() {
+            if !C::is_addressable_individually() {
+                return Err(anyhow!("Zones need an addressable strip !"));
+            }
+            check_zones(&opt.zone, controller.led_amount())?;
+        }
+        let layout = opt.layout.as_deref().map(Layout::load).transpose()?;
+        if let Some(layout) = layout.as_ref() {
+            if !C::is_addressable_individually() {
+                return Err(anyhow!("A layout needs an addressable strip !"));
+            }
+            if layout.len() != controller.led_amount() {
+                return Err(anyhow!(
+                    "The layout places {} LEDs but the strip has {} !",
+                    layout.len(),
+                    controller.led_amount()
+                ));
+            }
+        }
+
+        let presets = opt.presets.as_deref().map(Presets::load).transpose()?;
+        let initial_preset = match (opt.preset.as_deref(), presets.as_ref()) {
+            (Some(name), Some(presets)) => Some(
+                presets
+                    .find(name)
+                    .ok_or_else(|| anyhow!("Unknown preset {} !", name))?
+                    .id,
+            ),
+            _ => None,
+        };
+        let presets = presets.map(|presets| Arc::new(Mutex::new(presets)));
+        let status = Arc::new(Mutex::new(Status::default()));
+
+        let net = NetHandler::new(
+            opt.port,
+            opt.transport,
+            opt.peer_policy,
+            opt.psk,
+            opt.led_update_period as u32,
+        )?;
+        let (net_task, net_stop, remote_data) = Self::spawn_net_task(net);
+
+        let jitter_buffers = Arc::new(Mutex::new(
+            (0..opt.zone.len().max(1))
+                .map(|_| JitterBuffer::new(Duration::from_millis(opt.jitter_buffer)))
+                .collect(),
+        ));
+        let artnet = match opt.artnet_universe {
+            Some(universe) => Some(ArtNetInput::new(universe, Self::led_amount(&controller))?),
+            None => None,
+        };
+        let recordings = Recordings::open(&opt, Self::led_amount(&controller))?;
+        let animation = opt
+            .animation
+            .as_deref()
+            .map(|path| Animation::load(path, opt.animation_fps, opt.animation_tempo))
+            .transpose()?
+            .map(Arc::new);
+        let count = runner_count(&opt);
+        if let Some(layer) = opt.layer.iter().find(|layer| layer.runner >= count) {
+            return Err(anyhow!(
+                "No runner {} for a layer, they go from 0 to {} !",
+                layer.runner,
+                count - 1
+            ));
+        }
+        // A single write covers all the saves made in the meantime
+        let (saved_sender, presets_saved) = mpsc::channel(1);
+        let (runner_thread, messenger) = Self::make_controller_thread(
+            opt.clone(),
+            controller,
+            layout,
+            jitter_buffers.clone(),
+            presets.clone(),
+            saved_sender,
+            status.clone(),
+            artnet.as_ref().map(ArtNetInput::frames),
+            recordings,
+            animation,
+        );
+
+        let (local_sender, local_data) = mpsc::channel(LOCAL_CHANNEL_SIZE);
+        #[cfg(feature = "local_analysis")]
+        let local = if opt.local_analysis {
+            Some(LocalAnalysis::new(&opt, local_sender)?)
+        } else {
+            None
+        };
+        #[cfg(feature = "local_analysis")]
+        let has_local = local.is_some();
+        #[cfg(not(feature = "local_analysis"))]
+        let has_local = {
+            drop(local_sender);
+            false
+        };
+        #[cfg(feature = "local_analysis")]
+        let beat_diagnostics = if opt.beat_diagnostics {
+            info!("Beat diagnostics enabled, the latency of the beats is logged regularly");
+            Some(BeatDiagnostics::default())
+        } else {
+            None
+        };
+        #[cfg(not(feature = "local_analysis"))]
+        let beat_diagnostics = None;
+
+        let (control_sender, control) = mpsc::channel(1);
 
         Ok(Self {
-            _opt: opt,
-            net,
+            opt,
+            net_task,
+            net_stop,
+            remote_data,
+            connected: false,
+            #[cfg(feature = "local_analysis")]
+            local,
+            local_data,
+            has_local,
+            beat_diagnostics,
+            artnet,
+            control_sender,
+            control,
             runner_thread,
             messenger,
+            jitter_buffers,
+            initial_preset,
+            presets,
+            presets_saved,
+            status,
             _phantom: Default::default(),
         })
     }
 
+    /// The transports stay blocking ones (UDP, TCP and QUIC alike), so the handler runs on a
+    /// blocking thread of the runtime and forwards what it receives. Its waits check the stop
+    /// signal, so stopping doesn't wait for a packet or the end of a wait.
+    fn spawn_net_task(
+        mut net: NetHandler,
+    ) -> (
+        task::JoinHandle<Result<()>>,
+        oneshot::Sender<()>,
+        mpsc::Receiver<RemoteData>,
+    ) {
+        let (sender, receiver) = mpsc::channel(NET_CHANNEL_SIZE);
+        let (stop_sender, mut stop) = oneshot::channel();
+
+        let handle = task::spawn_blocking(move || {
+            // Stop when asked to or when the app is gone
+            let mut stopped =
+                move || !matches!(stop.try_recv(), Err(oneshot::error::TryRecvError::Empty));
+            while !stopped() {
+                match net.poll()? {
+                    Some(data) => {
+                        if sender.blocking_send(data).is_err() {
+                            break;
+                        }
+                    }
+                    None => net.wait(&mut stopped)?,
+                }
+            }
+
+            net.stop()
+        });
+        debug!("Spawned net task !");
+
+        (handle, stop_sender, receiver)
+    }
+
+    #[allow(clippy::too_many_arguments)]
     fn make_controller_thread(
-        opt: Opt, mut controller: C,
-    ) -> (JoinHandle<()>, Updater<ControllerMessage>) {
-        let (mut receiver, updater) =
-            single_value_channel::channel_starting_with(ControllerMessage::Noop);
+        mut opt: Opt, mut controller: C, layout: Option<Layout>,
+        jitter_buffers: Arc<Mutex<Vec<JitterBuffer>>>, presets: Option<Arc<Mutex<Presets>>>,
+        presets_saved: mpsc::Sender<()>, status: Arc<Mutex<Status>>,
+        console: Option<ConsoleFrames>, recordings: Recordings, animation: Option<Arc<Animation>>,
+    ) -> (JoinHandle<()>, std_mpsc::Sender<ControllerMessage>) {
+        let (sender, receiver) = std_mpsc::channel();
 
         let handle = std::thread::Builder::new()
             .name("Led Runner Thread".into())
             .spawn(move || {
-                let period = Duration::from_millis(opt.led_update_period);
-                let mut runner: RunnerEnum = NoopRunner.into();
+                let _render = info_span!("render").entered();
+                // Also the shortest period, for a slow strip or a busy machine
+                let default_period = Duration::from_millis(opt.led_update_period);
+                let mut palette = Vec::new();
+                let mut features = None;
+                let mut brightness = opt.brightness;
+                let mut preset_id = None;
+                // Whether to show the common runner or the standby, also what to go back to
+                // after the quiet hours
+                let mut common = false;
+                let mut runner_index = 0;
+                let runner_count = runner_count(&opt);
+                let mut quiet = false;
+                // Until when the strobe covers the runners
+                let mut strobe_until = None;
+                // What was shown last before the post and the strobe, to put it back after
+                // the strobe
+                let mut last_rendered = None;
+                // Whether a lighting console drives the LEDs instead of the runners
+                let mut console_active = false;
+                // What the runners would show while the console drives the LEDs
+                let mut hidden = None;
+                let Recordings {
+                    mut recorder,
+                    replay,
+                } = recordings;
+                // When the current frame should have started
+                let mut next_frame = Instant::now();
+                let mut late_frames = 0;
+                let mut last_frame = next_frame;
+                let led_amount = Self::led_amount(&controller);
+                let zones = if opt.zone.is_empty() {
+                    vec![0..led_amount]
+                } else {
+                    opt.zone.iter().map(|zone| zone.leds.clone()).collect()
+                };
+                // One runner per zone
+                let layout = Arc::new(layout.unwrap_or_else(|| Layout::linear(led_amount)));
+                let mut layers = LayerStack::new(
+                    opt.layer
+                        .iter()
+                        .map(|layer| {
+                            let runner =
+                                Self::common_runner(layer.runner, &palette, animation.as_ref());
+                            (runner, layer.opacity)
+                        })
+                        .collect(),
+                    led_amount,
+                );
+                for runner in layers.runners_mut() {
+                    runner.layout(layout.clone());
+                }
+                let layouts = zones
+                    .iter()
+                    .map(|zone| Arc::new(layout.zone(zone.clone())))
+                    .collect::<Vec<_>>();
+                let mut runners = Self::zone_runners(zones.len(), || NoopRunner.into());
+                let mut dividers = zones
+                    .iter()
+                    .map(|_| BeatDivider::new(opt.beat_division))
+                    .collect::<Vec<_>>();
+                // What the zones last showed, they don't all change at every frame
+                let mut strip = vec![ColorRGB::new(0, 0, 0); led_amount];
 
-                loop {
+                'frames: loop {
                     let start = Instant::now();
-                    match receiver.latest_mut() {
-                        msg @ ControllerMessage::Standby => {
-                            runner =
-                                StandbyRunner::new(opt.standby_speed, opt.standby_reverse).into();
-                            *msg = ControllerMessage::Noop;
-                            info!("Runner: standby");
-                        }
-                        msg @ ControllerMessage::RandomRunner => {
-                            runner = EpilepsyRunner::new().into();
-                            // runner = WhiteRunner::new().into();
-                            *msg = ControllerMessage::Noop;
-                            info!("Runner: common");
-                        }
-                        msg @ ControllerMessage::Analysis { .. } => {
-                            if let ControllerMessage::Analysis { novelty, is_beat } = msg {
-                                if *is_beat {
+                    let frame = trace_span!("frame").entered();
+
+                    for msg in receiver.try_iter() {
+                        common = match msg {
+                            ControllerMessage::Standby => false,
+                            ControllerMessage::RandomRunner => true,
+                            ControllerMessage::NextRunner => {
+                                runner_index = (runner_index + 1) % runner_count;
+                                true
+                            }
+                            ControllerMessage::ToggleStandby => !common,
+                            ControllerMessage::Runner(index) => {
+                                runner_index = index % runner_count;
+                                true
+                            }
+                            ControllerMessage::Brightness(new_brightness) => {
+                                info!("Brightness: {}", new_brightness);
+                                brightness = new_brightness;
+                                controller.set_brightness(brightness);
+                                continue;
+                            }
+                            ControllerMessage::StandbySpeed(speed) => {
+                                info!("Standby speed: {}", speed);
+                                opt.standby_speed = speed;
+                                // Only the standby needs to start over
+                                if common {
+                                    continue;
+                                }
+                                common
+                            }
+                            ControllerMessage::Reset => {
+                                if let Err(err) = controller.reset() {
+                                    warn!("Failed to blank the LEDs: {}", err);
+                                }
+                                common
+                            }
+                            ControllerMessage::Palette(new_palette) => {
+                                palette = new_palette;
+                                for runner in runners.iter_mut().chain(layers.runners_mut()) {
+                                    runner.palette(&palette);
+                                }
+                                debug!("New palette: {:?}", palette);
+                                continue;
+                            }
+                            ControllerMessage::Features(new_features) => {
+                                features = Some(new_features);
+                                for runner in runners.iter_mut().chain(layers.runners_mut()) {
+                                    runner.features(new_features);
+                                }
+                                debug!("Track features: {:?}", new_features);
+                                continue;
+                            }
+                            ControllerMessage::Preset(id) => {
+                                let preset = presets.as_ref().and_then(|presets| {
+                                    Self::lock_presets(presets).get(id).cloned()
+                                });
+                                let preset = match preset {
+                                    Some(preset) => preset,
+                                    None => {
+                                        warn!("No preset {}", id);
+                                        continue;
+                                    }
+                                };
+                                info!("Preset {}: {}", preset.id, preset.name);
+                                preset_id = Some(preset.id);
+                                brightness = preset.brightness;
+                                controller.set_brightness(brightness);
+                                opt.standby_speed = preset.standby_speed;
+                                palette = preset.palette;
+                                for runner in layers.runners_mut() {
+                                    runner.palette(&palette);
+                                }
+                                match preset.runner {
+                                    Some(index) => {
+                                        runner_index = index % runner_count;
+                                        true
+                                    }
+                                    None => false,
+                                }
+                            }
+                            ControllerMessage::SavePreset(id, name) => {
+                                let mut presets = match presets.as_ref() {
+                                    Some(presets) => Self::lock_presets(presets),
+                                    None => {
+                                        warn!("Can't save a preset without `--presets`");
+                                        continue;
+                                    }
+                                };
+                                let name = name
+                                    .or_else(|| presets.get(id).map(|saved| saved.name.clone()))
+                                    .unwrap_or_else(|| format!("Preset {}", id));
+                                info!("Saving preset {}: {}", id, name);
+                                let preset = Preset {
+                                    id,
+                                    name,
+                                    runner: if common { Some(runner_index) } else { None },
+                                    brightness,
+                                    standby_speed: opt.standby_speed,
+                                    palette: palette.clone(),
+                                };
+                                presets.set(preset);
+                                // Written by the app, the disk may be slow
+                                let _ = presets_saved.try_send(());
+                                continue;
+                            }
+                            ControllerMessage::BeatDivision(division) => {
+                                info!("Beat division: {:?}", division);
+                                for divider in dividers.iter_mut() {
+                                    divider.set_division(division);
+                                }
+                                continue;
+                            }
+                            ControllerMessage::Beat => {
+                                for runner in runners.iter_mut().chain(layers.runners_mut()) {
                                     runner.beat();
                                 }
-                                runner.novelty(*novelty);
+                                continue;
+                            }
+                            ControllerMessage::Strobe(duration) => {
+                                strobe_until = Some(Instant::now() + duration);
+                                continue;
+                            }
+                            ControllerMessage::Exit => {
+                                // Don't leave the strip frozen on the last frame
+                                if let Err(err) = controller.reset() {
+                                    warn!("Failed to blank the LEDs: {}", err);
+                                }
+                                break 'frames;
                             }
-                            *msg = ControllerMessage::Noop;
+                        };
+
+                        if !quiet {
+                            runners = Self::current_runners(
+                                &opt,
+                                common,
+                                runner_index,
+                                &palette,
+                                features,
+                                &layouts,
+                                replay.as_ref(),
+                                animation.as_ref(),
+                            );
+                        }
+                    }
+
+                    let now_quiet = opt
+                        .quiet_hours
+                        .map_or(false, |hours| hours.contains(Local::now().time()));
+                    if now_quiet != quiet {
+                        quiet = now_quiet;
+                        runners = if quiet {
+                            info!("Quiet hours: {:?}", opt.quiet_mode);
+                            match opt.quiet_mode {
+                                QuietMode::Off => {
+                                    if let Err(err) = controller.reset() {
+                                        warn!("Failed to blank the LEDs: {}", err);
+                                    }
+                                    Self::zone_runners(zones.len(), || NoopRunner.into())
+                                }
+                                QuietMode::Dim => Self::zone_runners(zones.len(), || {
+                                    Self::standby_runner(&opt)
+                                        .with_brightness(opt.quiet_brightness)
+                                        .into()
+                                }),
+                            }
+                        } else {
+                            info!("End of quiet hours");
+                            Self::current_runners(
+                                &opt,
+                                common,
+                                runner_index,
+                                &palette,
+                                features,
+                                &layouts,
+                                replay.as_ref(),
+                                animation.as_ref(),
+                            )
+                        };
+                    }
+
+                    {
+                        // Cheap enough to do at every frame
+                        let mut status = status.lock().expect("Status poisoned !");
+                        status.runner = if common { Some(runner_index) } else { None };
+                        status.brightness = brightness;
+                        status.standby_speed = opt.standby_speed;
+                        status.quiet = quiet;
+                        status.preset = preset_id;
+                    }
+
+                    let mut playbacks = jitter_buffers
+                        .lock()
+                        .expect("Jitter buffer poisoned !")
+                        .iter_mut()
+                        .map(|buffer| buffer.playback(start))
+                        .collect::<Vec<_>>();
+                    for (playback, divider) in playbacks.iter_mut().zip(dividers.iter_mut()) {
+                        if let Some(playback) = playback {
+                            playback.is_beat = divider.update(playback.is_beat, start);
+                        }
+                    }
+                    // The layers follow the first zone
+                    if let Some(playback) = playbacks[0].as_ref() {
+                        for runner in layers.runners_mut() {
+                            Self::feed(runner, playback);
+                        }
+                    }
+                    for (runner, playback) in runners.iter_mut().zip(playbacks) {
+                        if let Some(playback) = playback {
+                            Self::feed(runner, &playback);
                         }
-                        ControllerMessage::Exit => break,
-                        ControllerMessage::Noop => {}
                     }
 
-                    if runner.run_once() {
-                        runner.display(&mut controller).unwrap();
+                    let delta_time = start - last_frame;
+                    let mut rendered = if opt.zone.is_empty() {
+                        runners[0].run_once(delta_time, led_amount)
+                    } else {
+                        Self::render_zones(&mut runners, &zones, &mut strip, delta_time)
+                    };
+                    if !layers.is_empty() && !quiet {
+                        rendered = layers.composite(rendered, delta_time);
+                    }
+                    if let Some(console) = console.as_ref() {
+                        let active = console.is_active(start);
+                        if active != console_active {
+                            console_active = active;
+                            if active {
+                                info!("A lighting console took over the LEDs");
+                                hidden = last_rendered.take();
+                            } else {
+                                info!("The lighting console handed the LEDs back");
+                                // The runners may have nothing new to show
+                                rendered = rendered.or_else(|| hidden.take());
+                                if rendered.is_none() {
+                                    if let Err(err) = controller.reset() {
+                                        warn!("Failed to blank the LEDs: {}", err);
+                                    }
+                                }
+                            }
+                        }
+                        if active {
+                            let runners_frame =
+                                std::mem::replace(&mut rendered, console.take_frame());
+                            if runners_frame.is_some() {
+                                hidden = runners_frame;
+                            }
+                        }
                     }
+                    if rendered.is_some() {
+                        last_rendered = rendered.clone();
+                    }
+                    let strobe = match strobe_until {
+                        Some(until) if start < until && !quiet => {
+                            let phase = (until - start).as_millis()
+                                / STROBE_HALF_PERIOD.as_millis();
+                            Some(if phase % 2 == 0 {
+                                ColorRGB::new(255, 255, 255)
+                            } else {
+                                ColorRGB::new(0, 0, 0)
+                            })
+                        }
+                        Some(_) => {
+                            // Don't leave the strip on the strobe if the runners show nothing new
+                            strobe_until = None;
+                            rendered = rendered.or_else(|| last_rendered.clone());
+                            if rendered.is_none() {
+                                if let Err(err) = controller.reset() {
+                                    warn!("Failed to blank the LEDs: {}", err);
+                                }
+                            }
+                            None
+                        }
+                        None => None,
+                    };
+                    // Before the post, the replay goes through it again
+                    if let (Some(rendered), Some(writer)) = (rendered.as_ref(), recorder.as_mut()) {
+                        if let Err(err) = writer.record(rendered, start) {
+                            warn!("Failed to record, stopping: {}", err);
+                            recorder = None;
+                        }
+                    }
+                    // The console knows what it wants
+                    if !opt.post.is_empty() && !console_active {
+                        rendered = rendered.map(|frame| post::process(frame, &opt.post));
+                    }
+                    if let Some(color) = strobe {
+                        rendered = Some(Frame::Uniform(color));
+                    }
+                    match rendered {
+                        Some(rendered) => rendered.display(&mut controller).unwrap(),
+                        None if controller.needs_refresh() => controller.commit().unwrap(),
+                        None => {}
+                    }
+                    last_frame = start;
 
-                    // Wait for the rest of the period
-                    std::thread::sleep(period - Instant::now().duration_since(start));
+                    drop(frame);
+                    let period = runners
+                        .iter()
+                        .filter_map(|runner| runner.frame_period())
+                        .chain(layers.frame_period().filter(|_| !quiet))
+                        .chain(strobe_until.map(|_| STROBE_HALF_PERIOD))
+                        .min()
+                        .unwrap_or(default_period)
+                        .max(default_period);
+                    next_frame += period;
+                    let now = Instant::now();
+                    match next_frame.checked_duration_since(now) {
+                        Some(remaining) => {
+                            if late_frames >= OVERLOAD_FRAMES {
+                                info!("The LEDs are keeping up again");
+                            }
+                            late_frames = 0;
+                            std::thread::sleep(remaining);
+                        }
+                        None => {
+                            late_frames += 1;
+                            if late_frames == OVERLOAD_FRAMES {
+                                warn!(
+                                    "The LEDs can't keep up with a period of {:?}, frames are skipped",
+                                    period
+                                );
+                            }
+                            // Catch up on a small delay, but don't rush through all the
+                            // frames that were missed
+                            if now.duration_since(next_frame) > period {
+                                next_frame = now;
+                            }
+                        }
+                    }
                 }
 
+                if let Some(recorder) = recorder {
+                    if let Err(err) = recorder.finish() {
+                        warn!("Failed to finish the recording: {}", err);
+                    }
+                }
                 info!("Runner thread exit");
             })
             .expect("Failed to create runner thread !");
         debug!("Spawned runner thread !");
 
-        (handle, updater)
+        (handle, sender)
+    }
+
+    /// Give the analysis to the runner.
+    fn feed(runner: &mut RunnerEnum, playback: &Playback) {
+        if playback.is_beat {
+            runner.beat();
+        }
+        runner.pitch(playback.pitch_class);
+        runner.balance(playback.balance);
+        if playback.is_onset {
+            runner.onset();
+        }
+        runner.novelty(playback.novelty);
+        if let Some(loudness) = playback.loudness {
+            runner.loudness(loudness);
+        }
+        if let Some(bands) = playback.bands.as_ref() {
+            runner.bands(bands);
+        }
+    }
+
+    /// Non addressable strips get a single color.
+    fn led_amount(controller: &C) -> usize {
+        if C::is_addressable_individually() {
+            controller.led_amount()
+        } else {
+            1
+        }
+    }
+
+    fn standby_runner(opt: &Opt) -> StandbyRunner {
+        StandbyRunner::new(opt.standby_speed, opt.standby_reverse)
     }
 
-    pub fn run(&mut self) -> Result<()> {
-        // Wait for remote
-        if !self.net.is_connected() {
-            self.messenger.update(ControllerMessage::Standby)?;
-            self.net.wait_for_remote_blocking()?;
-            self.net.handshake()?;
+    fn zone_runners(zones: usize, runner: impl Fn() -> RunnerEnum) -> Vec<RunnerEnum> {
+        (0..zones).map(|_| runner()).collect()
+    }
+
+    /// The same runner for every zone, each one with the layout of its zone.
+    #[allow(clippy::too_many_arguments)]
+    fn current_runners(
+        opt: &Opt, common: bool, runner_index: usize, palette: &[ColorRGB],
+        features: Option<TrackFeatures>, layouts: &[Arc<Layout>], replay: Option<&Arc<Recording>>,
+        animation: Option<&Arc<Animation>>,
+    ) -> Vec<RunnerEnum> {
+        let zones = layouts.len();
+        let mut runners = if common {
+            info!("Runner: common #{}", runner_index);
+            Self::zone_runners(zones, || {
+                Self::common_runner(runner_index, palette, animation)
+            })
+        } else if let Some(replay) = replay {
+            info!("Runner: replay");
+            Self::zone_runners(zones, || ReplayRunner::new(replay.clone()).into())
+        } else {
+            info!("Runner: standby");
+            Self::zone_runners(zones, || Self::standby_runner(opt).into())
+        };
+        for (runner, layout) in runners.iter_mut().zip(layouts) {
+            runner.layout(layout.clone());
         }
+        if let Some(features) = features {
+            for runner in runners.iter_mut() {
+                runner.features(features);
+            }
+        }
+        runners
+    }
 
-        // Set a runner
-        self.messenger.update(ControllerMessage::RandomRunner)?;
+    fn common_runner(
+        index: usize, palette: &[ColorRGB], animation: Option<&Arc<Animation>>,
+    ) -> RunnerEnum {
+        let count = COMMON_RUNNERS + animation.is_some() as usize;
+        let mut runner: RunnerEnum = match (index % count, animation) {
+            (0, _) => EpilepsyRunner::new().into(),
+            (1, _) => SimpleBeatRunner::new().into(),
+            (2, _) => WhiteRunner::new().into(),
+            (3, _) => SpectrumRunner::new().into(),
+            (4, _) => SpatialRunner::new(RadialPulse::new()).into(),
+            (6, Some(animation)) => AnimationRunner::new(animation.clone()).into(),
+            _ => SpatialRunner::new(VerticalGradient::new()).into(),
+        };
+        runner.palette(palette);
+        runner
+    }
+
+    /// Render every zone in its part of `strip`, nothing if none of them changed.
+    fn render_zones(
+        runners: &mut [RunnerEnum], zones: &[Range<usize>], strip: &mut [ColorRGB],
+        delta_time: Duration,
+    ) -> Option<Frame> {
+        let mut changed = false;
+        for (runner, zone) in runners.iter_mut().zip(zones) {
+            match runner.run_once(delta_time, zone.len()) {
+                Some(Frame::Uniform(color)) => {
+                    for led in strip[zone.clone()].iter_mut() {
+                        *led = color;
+                    }
+                }
+                Some(Frame::Individual(colors)) => {
+                    for (led, color) in strip[zone.clone()].iter_mut().zip(colors) {
+                        *led = color;
+                    }
+                }
+                None => continue,
+            }
+            changed = true;
+        }
+
+        if changed {
+            Some(Frame::Individual(strip.to_vec()))
+        } else {
+            None
+        }
+    }
+
+    /// Give orders to the app from another task.
+    pub fn control(&self) -> mpsc::Sender<Control> {
+        self.control_sender.clone()
+    }
+
+    /// Drive the LEDs with whatever the remotes send, until told to stop.
+    pub async fn run(&mut self) -> Result<()> {
+        self.standby()?;
+        if let Some(id) = self.initial_preset {
+            self.messenger.send(ControllerMessage::Preset(id))?;
+        }
 
-        // Wait for next packet
         loop {
-            match self.net.recv()? {
-                RemoteData::Analysis { novelty, is_beat } => {
-                    self.messenger
-                        .update(ControllerMessage::Analysis { novelty, is_beat })?;
+            tokio::select! {
+                data = self.remote_data.recv() => match data {
+                    Some(data) => self.handle_remote_data(data)?,
+                    // The net task failed, its error is reported when stopping
+                    None => return Ok(()),
+                },
+                Some(frame) = self.local_data.recv() => {
+                    if let Some(diagnostics) = self.beat_diagnostics.as_mut() {
+                        diagnostics.novelty(frame.novelty, frame.at);
+                    }
+
+                    // The remotes know better
+                    if !self.connected {
+                        for buffer in self.lock_jitter_buffers().iter_mut() {
+                            buffer.push(
+                                Playback {
+                                    novelty: frame.novelty,
+                                    is_beat: false,
+                                    is_onset: frame.is_onset,
+                                    pitch_class: frame.pitch_class,
+                                    balance: frame.balance,
+                                    loudness: None,
+                                    bands: None,
+                                },
+                                None,
+                            );
+                        }
+                    }
                 }
-                RemoteData::Goodbye { .. } => {
-                    // Ignore force flag
-                    self.net.stop()?;
-                    break;
+                Some(control) = self.control.recv() => match control {
+                    Control::Stop => return Ok(()),
+                    Control::CycleRunner => self.messenger.send(ControllerMessage::NextRunner)?,
+                    Control::ToggleStandby => {
+                        self.messenger.send(ControllerMessage::ToggleStandby)?
+                    }
+                    Control::Command(command) => {
+                        self.messenger.send(Self::command_message(command))?
+                    }
+                    Control::SavePreset(id, name) => {
+                        self.messenger.send(ControllerMessage::SavePreset(id, name))?
+                    }
+                },
+                Some(()) = self.presets_saved.recv() => {
+                    Self::write_presets(self.presets.as_deref()).await
                 }
             }
         }
+    }
+
+    fn handle_remote_data(&mut self, data: RemoteData) -> Result<()> {
+        match data {
+            RemoteData::Connected => {
+                // Set a runner, also when another remote takes over
+                self.set_connected(true);
+                self.messenger.send(ControllerMessage::RandomRunner)?;
+            }
+            RemoteData::Analysis {
+                novelty,
+                is_beat,
+                is_onset,
+                pitch_class,
+                balance,
+                loudness,
+                at,
+                zones,
+                bands,
+            } => {
+                if is_beat {
+                    self.diagnose_beat(at);
+                }
 
-        // Remote has disconnected
+                for (i, buffer) in self.lock_jitter_buffers().iter_mut().enumerate() {
+                    // The zones the remote doesn't know about get the whole novelty
+                    let novelty = self
+                        .opt
+                        .zone
+                        .get(i)
+                        .and_then(|zone| zones.iter().find(|(name, _)| *name == zone.name))
+                        .map_or(novelty, |&(_, novelty)| novelty);
+                    let data = Playback {
+                        novelty,
+                        is_beat,
+                        is_onset,
+                        pitch_class,
+                        balance,
+                        loudness,
+                        bands,
+                    };
+                    buffer.push(data, at);
+                }
+            }
+            RemoteData::Beat { tempo, at } => {
+                self.diagnose_beat(at);
+                for buffer in self.lock_jitter_buffers().iter_mut() {
+                    buffer.pulse(tempo, at);
+                }
+            }
+            RemoteData::Palette(colors) => {
+                self.messenger.send(ControllerMessage::Palette(colors))?;
+            }
+            RemoteData::Features(features) => {
+                self.messenger.send(ControllerMessage::Features(features))?;
+            }
+            RemoteData::Heartbeat => {}
+            RemoteData::Command(command) => {
+                debug!("Command: {:?}", command);
+                self.messenger.send(Self::command_message(command))?;
+            }
+            RemoteData::Standby(silent) => {
+                // The music is paused, the remote is still there
+                info!(
+                    "Remote audio {}",
+                    if silent { "stopped" } else { "resumed" }
+                );
+                self.messenger.send(if silent {
+                    ControllerMessage::Standby
+                } else {
+                    ControllerMessage::RandomRunner
+                })?;
+            }
+            RemoteData::Timeout => {
+                // Go back to standby and wait for a remote, the net task logged which one
+                self.standby()?;
+            }
+            RemoteData::Goodbye { .. } => {
+                // Ignore force flag
+                self.standby()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The beat is shown `at` this time, or after the delay of the jitter buffer.
+    fn diagnose_beat(&mut self, at: Option<Instant>) {
+        if self.beat_diagnostics.is_some() {
+            let delay = self.lock_jitter_buffers()[0].delay();
+            if let Some(diagnostics) = self.beat_diagnostics.as_mut() {
+                diagnostics.beat(at.unwrap_or_else(|| Instant::now() + delay));
+            }
+        }
+    }
+
+    fn command_message(command: Command) -> ControllerMessage {
+        match command {
+            Command::Brightness(brightness) => ControllerMessage::Brightness(brightness),
+            Command::Runner(index) => ControllerMessage::Runner(index as usize),
+            Command::StandbySpeed(speed) => ControllerMessage::StandbySpeed(speed),
+            Command::Reset => ControllerMessage::Reset,
+            Command::Preset(id) => ControllerMessage::Preset(id),
+            Command::SavePreset(id) => ControllerMessage::SavePreset(id, None),
+            Command::BeatDivision(division) => ControllerMessage::BeatDivision(division),
+            Command::Beat => ControllerMessage::Beat,
+            Command::Color([r, g, b]) => ControllerMessage::Palette(vec![ColorRGB::new(r, g, b)]),
+            Command::Strobe(duration_ms) => {
+                ControllerMessage::Strobe(Duration::from_millis(duration_ms as u64))
+            }
+        }
+    }
+
+    /// No remote left, the local analysis takes over if there is one.
+    fn standby(&mut self) -> Result<()> {
+        self.set_connected(false);
+        for buffer in self.lock_jitter_buffers().iter_mut() {
+            buffer.clear();
+        }
+        self.messenger.send(if self.has_local {
+            ControllerMessage::RandomRunner
+        } else {
+            ControllerMessage::Standby
+        })?;
         Ok(())
     }
 
-    pub fn stop(self) -> Result<()> {
-        self.messenger.update(ControllerMessage::Exit)?;
+    fn set_connected(&mut self, connected: bool) {
+        self.connected = connected;
+        self.status.lock().expect("Status poisoned !").connected = connected;
+    }
+
+    #[inline]
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// What the strip shows, for the APIs.
+    pub fn status(&self) -> Arc<Mutex<Status>> {
+        self.status.clone()
+    }
+
+    /// Only with `--presets`.
+    pub fn presets(&self) -> Option<Arc<Mutex<Presets>>> {
+        self.presets.clone()
+    }
+
+    /// Write the presets saved by the runner thread on a blocking thread.
+    async fn write_presets(presets: Option<&Mutex<Presets>>) {
+        let (path, content) = match presets {
+            Some(presets) => Self::lock_presets(presets).file(),
+            None => return,
+        };
+        let res = task::spawn_blocking(move || fs::write(path, content)).await;
+        match res {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => warn!("Failed to save the presets: {}", err),
+            Err(err) => warn!("Failed to save the presets: {}", err),
+        }
+    }
+
+    fn lock_presets(presets: &Mutex<Presets>) -> MutexGuard<Presets> {
+        presets.lock().expect("Presets poisoned !")
+    }
+
+    fn lock_jitter_buffers(&self) -> MutexGuard<Vec<JitterBuffer>> {
+        self.jitter_buffers
+            .lock()
+            .expect("Jitter buffer poisoned !")
+    }
+
+    pub async fn stop(mut self) -> Result<()> {
+        // Unblocks the local analysis if it is waiting for us
+        drop(self.local_data);
+
+        // Already gone if it failed
+        let _ = self.net_stop.send(());
+        let net_res = self
+            .net_task
+            .await
+            .map_err(|err| anyhow!("Net task panicked: {}", err))?;
+
+        self.messenger.send(ControllerMessage::Exit)?;
         self.runner_thread
             .join()
             .expect("Failed to join runner thread !");
-        Ok(())
+        if self.presets_saved.try_recv().is_ok() {
+            Self::write_presets(self.presets.as_deref()).await;
+        }
+
+        #[cfg(feature = "local_analysis")]
+        if let Some(local) = self.local {
+            local.stop()?;
+        }
+        if let Some(artnet) = self.artnet {
+            artnet.stop()?;
+        }
+        net_res
     }
 }
@@ -0,0 +1,180 @@
+use crate::runners::Frame;
+use anyhow::{anyhow, Result};
+use cichlid::ColorRGB;
+use std::{
+    io::ErrorKind,
+    net::UdpSocket,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+use tracing::{debug, info, info_span};
+
+/// Port of Art-Net, the consoles don't send to any other.
+pub const ARTNET_PORT: u16 = 6454;
+/// The highest universe, they are 15 bits.
+pub const MAX_UNIVERSE: u16 = 0x7FFF;
+/// 510 of the 512 channels of a universe, RGB for each LED.
+const LEDS_PER_UNIVERSE: usize = 170;
+const HEADER: &[u8] = b"Art-Net\0";
+const OP_DMX: u16 = 0x5000;
+/// Header, opcode, version, sequence, physical, universe and length.
+const DMX_OFFSET: usize = 18;
+/// How often to check if the app is stopping.
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+/// The consoles send at least every 4 seconds even when nothing changes, without DMX for
+/// longer than that the runners take over again.
+const RELEASE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What the console last sent.
+struct Console {
+    colors: Vec<ColorRGB>,
+    last_packet: Option<Instant>,
+    /// Not yet shown.
+    changed: bool,
+}
+
+/// The LEDs driven by a lighting console, handed to the runner thread.
+#[derive(Clone)]
+pub struct ConsoleFrames {
+    console: Arc<Mutex<Console>>,
+}
+
+impl ConsoleFrames {
+    /// Whether the console drives the LEDs instead of the runners.
+    pub fn is_active(&self, now: Instant) -> bool {
+        self.lock().last_packet.map_or(false, |last| {
+            now.saturating_duration_since(last) < RELEASE_TIMEOUT
+        })
+    }
+
+    /// What the console sent since the last call, if anything.
+    pub fn take_frame(&self) -> Option<Frame> {
+        let mut console = self.lock();
+        if !std::mem::take(&mut console.changed) {
+            return None;
+        }
+        Some(Frame::Individual(console.colors.clone()))
+    }
+
+    fn lock(&self) -> MutexGuard<Console> {
+        self.console.lock().expect("Console poisoned !")
+    }
+}
+
+/// Listens for Art-Net, the strip is mapped from `universe` on over as many universes
+/// as needed.
+pub struct ArtNetInput {
+    thread: JoinHandle<Result<()>>,
+    stop: Arc<AtomicBool>,
+    frames: ConsoleFrames,
+}
+
+impl ArtNetInput {
+    pub fn new(universe: u16, led_amount: usize) -> Result<Self> {
+        if universe > MAX_UNIVERSE {
+            return Err(anyhow!(
+                "The Art-Net universe must be below {} !",
+                MAX_UNIVERSE + 1
+            ));
+        }
+        let socket = UdpSocket::bind(("0.0.0.0", ARTNET_PORT))?;
+        socket.set_read_timeout(Some(READ_TIMEOUT))?;
+        let universes = (led_amount + LEDS_PER_UNIVERSE - 1) / LEDS_PER_UNIVERSE;
+        info!(
+            "Listening for Art-Net on universes {} to {}",
+            universe,
+            universe as usize + universes.max(1) - 1
+        );
+
+        let frames = ConsoleFrames {
+            console: Arc::new(Mutex::new(Console {
+                colors: vec![ColorRGB::default(); led_amount],
+                last_packet: None,
+                changed: false,
+            })),
+        };
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread_frames = frames.clone();
+        let thread = std::thread::Builder::new()
+            .name("Art-Net Thread".into())
+            .spawn(move || {
+                let _artnet = info_span!("artnet").entered();
+                Self::run(socket, universe, thread_frames, thread_stop)
+            })?;
+
+        Ok(Self {
+            thread,
+            stop,
+            frames,
+        })
+    }
+
+    pub fn frames(&self) -> ConsoleFrames {
+        self.frames.clone()
+    }
+
+    fn run(
+        socket: UdpSocket, first_universe: u16, frames: ConsoleFrames, stop: Arc<AtomicBool>,
+    ) -> Result<()> {
+        let mut buf = [0; DMX_OFFSET + 512];
+        while !stop.load(Ordering::Relaxed) {
+            let len = match socket.recv(&mut buf) {
+                Ok(len) => len,
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    continue
+                }
+                Err(err) => return Err(err.into()),
+            };
+            let (universe, data) = match Self::parse(&buf[..len]) {
+                Some(dmx) => dmx,
+                None => continue,
+            };
+            let offset = match universe.checked_sub(first_universe) {
+                Some(index) => index as usize * LEDS_PER_UNIVERSE,
+                None => continue,
+            };
+
+            let mut console = frames.lock();
+            if offset >= console.colors.len() {
+                continue;
+            }
+            for (led, channels) in console.colors[offset..]
+                .iter_mut()
+                .zip(data.chunks_exact(3))
+            {
+                *led = ColorRGB::new(channels[0], channels[1], channels[2]);
+            }
+            console.last_packet = Some(Instant::now());
+            console.changed = true;
+        }
+
+        debug!("Art-Net thread exit");
+        Ok(())
+    }
+
+    /// The universe and the channels of an ArtDmx packet, the other packets are ignored.
+    fn parse(packet: &[u8]) -> Option<(u16, &[u8])> {
+        if packet.len() < DMX_OFFSET || !packet.starts_with(HEADER) {
+            return None;
+        }
+        if u16::from_le_bytes([packet[8], packet[9]]) != OP_DMX {
+            return None;
+        }
+        let universe = u16::from_le_bytes([packet[14], packet[15]]) & MAX_UNIVERSE;
+        let len = u16::from_be_bytes([packet[16], packet[17]]) as usize;
+        let data = &packet[DMX_OFFSET..];
+        Some((universe, &data[..len.min(data.len())]))
+    }
+
+    pub fn stop(self) -> Result<()> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.thread
+            .join()
+            .map_err(|_| anyhow!("Art-Net thread panicked !"))?
+    }
+}
@@ -0,0 +1,140 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+use tracing::info;
+
+/// Beats further than this from a peak of the local novelty are not matched.
+const WINDOW: Duration = Duration::from_millis(300);
+/// Width of a bar of the histogram, in milliseconds.
+const BIN_MS: i64 = 20;
+/// Beats matched or not between two reports.
+const REPORT_EVERY: usize = 32;
+/// Below this, a local maximum of the novelty is just noise.
+const PEAK_THRESHOLD: f64 = 0.6;
+/// Longest bar of the histogram.
+const BAR_WIDTH: usize = 40;
+
+/// Measures how far the beats of the remote are from the peaks of the novelty of the
+/// microphone, to know how late or early the LEDs are compared to the sound in the room.
+#[derive(Default)]
+pub struct BeatDiagnostics {
+    /// Last two frames of the local novelty, to spot the local maximums.
+    frames: [(f64, Option<Instant>); 2],
+    peaks: VecDeque<Instant>,
+    /// When the beats are shown, waiting for the peaks around them.
+    beats: VecDeque<Instant>,
+    /// Peak minus beat of each matched beat, in milliseconds.
+    offsets: Vec<i64>,
+    unmatched: usize,
+}
+
+impl BeatDiagnostics {
+    /// A frame of the local analysis, analysed `at` this time.
+    pub fn novelty(&mut self, novelty: f64, at: Instant) {
+        let [(before, _), (last, last_at)] = self.frames;
+        if let Some(last_at) = last_at {
+            if last >= PEAK_THRESHOLD && last > before && last >= novelty {
+                self.peaks.push_back(last_at);
+            }
+        }
+        self.frames = [self.frames[1], (novelty, Some(at))];
+
+        self.settle(at);
+    }
+
+    /// A beat of the remote, shown on the strip `at` this time.
+    pub fn beat(&mut self, at: Instant) {
+        self.beats.push_back(at);
+    }
+
+    /// Match the beats old enough to have all their candidate peaks.
+    fn settle(&mut self, now: Instant) {
+        while let Some(&beat) = self.beats.front() {
+            if beat + WINDOW > now {
+                break;
+            }
+            self.beats.pop_front();
+
+            let closest = self
+                .peaks
+                .iter()
+                .map(|&peak| Self::offset_ms(beat, peak))
+                .min_by_key(|offset| offset.abs())
+                .filter(|offset| offset.abs() <= WINDOW.as_millis() as i64);
+            match closest {
+                Some(offset) => self.offsets.push(offset),
+                None => self.unmatched += 1,
+            }
+
+            if (self.offsets.len() + self.unmatched) % REPORT_EVERY == 0 {
+                self.report();
+            }
+        }
+
+        // Old peaks can't match anything anymore
+        while let Some(&peak) = self.peaks.front() {
+            match self.beats.front() {
+                Some(&beat) if peak + WINDOW >= beat => break,
+                None if peak + WINDOW >= now => break,
+                _ => {
+                    self.peaks.pop_front();
+                }
+            }
+        }
+    }
+
+    fn offset_ms(beat: Instant, peak: Instant) -> i64 {
+        if peak >= beat {
+            (peak - beat).as_millis() as i64
+        } else {
+            -((beat - peak).as_millis() as i64)
+        }
+    }
+
+    /// Log the histogram of everything measured so far.
+    fn report(&self) {
+        let total = self.offsets.len() + self.unmatched;
+        info!(
+            "Beat latency over {} beats, {} without a peak close enough",
+            total, self.unmatched
+        );
+        if self.offsets.is_empty() {
+            return;
+        }
+
+        let window = WINDOW.as_millis() as i64;
+        let bins = (2 * window / BIN_MS + 1) as usize;
+        let mut histogram = vec![0usize; bins];
+        for offset in self.offsets.iter() {
+            histogram[((offset + window) / BIN_MS) as usize] += 1;
+        }
+
+        let highest = histogram.iter().copied().max().unwrap_or(1);
+        for (i, count) in histogram.iter().enumerate() {
+            info!(
+                "{:>+5}ms {:<width$} {}",
+                i as i64 * BIN_MS - window,
+                "#".repeat(count * BAR_WIDTH / highest),
+                count,
+                width = BAR_WIDTH
+            );
+        }
+
+        let mut sorted = self.offsets.clone();
+        sorted.sort_unstable();
+        let median = sorted[sorted.len() / 2];
+        if median >= 0 {
+            info!(
+                "Median of {}ms: the LEDs are ahead of the sound, raise --jitter-buffer by as much",
+                median
+            );
+        } else {
+            info!(
+                "Median of {}ms: the LEDs are behind the sound, lower --jitter-buffer or the \
+                 latency of the remote by as much",
+                median
+            );
+        }
+    }
+}
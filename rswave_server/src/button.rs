@@ -0,0 +1,69 @@
+use crate::app::Control;
+use anyhow::{anyhow, Error, Result};
+use rppal::gpio::{Gpio, InputPin, Trigger};
+use std::{
+    str::FromStr,
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+use tracing::debug;
+
+/// Presses closer than this are bounces of the same press.
+const DEBOUNCE_DELAY: Duration = Duration::from_millis(200);
+
+/// What a press of the button does.
+#[derive(Copy, Clone, Debug)]
+pub enum ButtonAction {
+    /// Switch to the next runner.
+    Cycle,
+    /// Switch between the standby and the current runner.
+    Standby,
+}
+
+impl ButtonAction {
+    fn control(self) -> Control {
+        match self {
+            Self::Cycle => Control::CycleRunner,
+            Self::Standby => Control::ToggleStandby,
+        }
+    }
+}
+
+impl FromStr for ButtonAction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "cycle" => Ok(Self::Cycle),
+            "standby" => Ok(Self::Standby),
+            _ => Err(anyhow!("Unknown button action !")),
+        }
+    }
+}
+
+/// A push button between a GPIO pin and the ground, the presses are given to the app
+/// as long as this is alive.
+pub struct Button {
+    _pin: InputPin,
+}
+
+impl Button {
+    pub fn new(pin: u8, action: ButtonAction, control: mpsc::Sender<Control>) -> Result<Self> {
+        let mut pin = Gpio::new()?.get(pin)?.into_input_pullup();
+
+        let mut last_press: Option<Instant> = None;
+        pin.set_async_interrupt(Trigger::FallingEdge, move |_| {
+            let now = Instant::now();
+            if last_press.map_or(false, |last| now.duration_since(last) < DEBOUNCE_DELAY) {
+                return;
+            }
+            last_press = Some(now);
+
+            debug!("Button pressed: {:?}", action);
+            // Drop the press if the app is busy, or gone
+            let _ = control.try_send(action.control());
+        })?;
+
+        Ok(Self { _pin: pin })
+    }
+}
@@ -0,0 +1,55 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Timestamps remembered to estimate the clock of the remote, a few seconds of packets.
+const OFFSET_WINDOW: usize = 128;
+
+/// Converts the timestamps of a remote to the time of the server, to show the data when
+/// the remote asked for it.
+pub struct RemoteClock {
+    /// How long after the timestamp the data must be shown, if at all.
+    delay: Option<Duration>,
+    /// The local times are counted from here.
+    epoch: Instant,
+    /// Local arrival time minus remote timestamp of the last packets, in microseconds.
+    /// The smallest is the packet that was the least delayed by the network.
+    offsets: VecDeque<i64>,
+}
+
+impl RemoteClock {
+    /// A `delay_ms` of zero shows the data on arrival.
+    pub fn new(delay_ms: u32) -> Self {
+        Self {
+            delay: Some(Duration::from_millis(delay_ms as u64))
+                .filter(|delay| *delay > Duration::from_millis(0)),
+            epoch: Instant::now(),
+            offsets: VecDeque::with_capacity(OFFSET_WINDOW),
+        }
+    }
+
+    /// When to show the data of a packet with this timestamp that just arrived.
+    /// The network delay of the fastest packets is not accounted for, it is about the same
+    /// for every server on a LAN.
+    pub fn schedule(&mut self, timestamp_us: u64) -> Option<Instant> {
+        let delay = self.delay?;
+
+        let now = Instant::now();
+        let local_us = now.duration_since(self.epoch).as_micros() as i64;
+        if self.offsets.len() >= OFFSET_WINDOW {
+            self.offsets.pop_front();
+        }
+        self.offsets.push_back(local_us - timestamp_us as i64);
+
+        let offset = *self.offsets.iter().min().unwrap();
+        let sent_us = timestamp_us as i64 + offset;
+        let sent = if sent_us > 0 {
+            self.epoch + Duration::from_micros(sent_us as u64)
+        } else {
+            now
+        };
+        // Too late already, better now than never
+        Some((sent + delay).max(now))
+    }
+}
@@ -0,0 +1,51 @@
+use crate::Opt;
+use anyhow::{anyhow, Result};
+use daemonize::Daemonize;
+use rswave_common::logging;
+use std::fs::OpenOptions;
+use syslog::Facility;
+
+/// Detach from the terminal and setup the logs accordingly, must be called before any other
+/// thread is spawned (including the tokio runtime).
+pub fn daemonize(opt: &Opt) -> Result<()> {
+    let mut daemon = Daemonize::new()
+        .pid_file(&opt.pid_file)
+        .working_directory("/");
+
+    let log_file = match opt.log_file.as_ref() {
+        Some(log_file) => {
+            let log_file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(log_file)?;
+            // Panics are printed on stderr
+            daemon = daemon.stderr(log_file.try_clone()?);
+            Some(log_file)
+        }
+        None => None,
+    };
+
+    daemon
+        .start()
+        .map_err(|err| anyhow!("Failed to daemonize: {}", err))?;
+
+    match log_file {
+        Some(log_file) => logging::init(opt.log_format, Some(log_file)),
+        // The events are forwarded to `log` when there is no subscriber
+        None => syslog::init(
+            Facility::LOG_DAEMON,
+            log::LevelFilter::Info,
+            Some(env!("CARGO_PKG_NAME")),
+        )
+        .map_err(|err| anyhow!("Failed to connect to syslog: {}", err))?,
+    }
+
+    Ok(())
+}
+
+/// Remove the pidfile, the process is about to exit.
+pub fn cleanup(opt: &Opt) {
+    if let Err(err) = std::fs::remove_file(&opt.pid_file) {
+        tracing::warn!("Failed to remove pidfile: {}", err);
+    }
+}
@@ -0,0 +1,198 @@
+//! A D-Bus service to control a server running on the same machine, with `busctl` for
+//! example.
+
+use crate::app::{Control, Status};
+use ::dbus::blocking::{stdintf::org_freedesktop_dbus::RequestNameReply, Connection};
+use anyhow::{anyhow, Error, Result};
+use dbus_crossroads::{Context, Crossroads, MethodErr};
+use rswave_common::packets::Command;
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc as std_mpsc, Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+pub const BUS_NAME: &str = "org.rswave.Server1";
+const OBJECT_PATH: &str = "/org/rswave/Server1";
+
+#[derive(Copy, Clone, Debug)]
+pub enum Bus {
+    Session,
+    System,
+}
+
+impl FromStr for Bus {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "session" => Ok(Self::Session),
+            "system" => Ok(Self::System),
+            _ => Err(anyhow!("Unknown bus !")),
+        }
+    }
+}
+
+struct Server {
+    status: Arc<Mutex<Status>>,
+    control: mpsc::Sender<Control>,
+    /// Given back when powered on again.
+    last_brightness: u8,
+}
+
+impl Server {
+    fn send(&self, command: Command) -> Result<(), MethodErr> {
+        self.control
+            .blocking_send(Control::Command(command))
+            .map_err(|_| MethodErr::failed("The server is stopping"))
+    }
+
+    fn status(&self) -> Status {
+        self.status.lock().expect("Status poisoned !").clone()
+    }
+}
+
+/// Serves the interface as long as this is alive.
+pub struct DbusApi {
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl DbusApi {
+    pub fn new(
+        bus: Bus, brightness: u8, status: Arc<Mutex<Status>>, control: mpsc::Sender<Control>,
+    ) -> Result<Self> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let (ready_tx, ready_rx) = std_mpsc::channel();
+
+        // The connection is a blocking one
+        let thread = thread::spawn(move || {
+            let connection = match connect(bus) {
+                Ok(connection) => {
+                    let _ = ready_tx.send(Ok(()));
+                    connection
+                }
+                Err(err) => {
+                    let _ = ready_tx.send(Err(err));
+                    return;
+                }
+            };
+
+            let mut cr = Crossroads::new();
+            let token = cr.register(BUS_NAME, |b| {
+                b.method(
+                    "SetPower",
+                    ("on",),
+                    (),
+                    |_: &mut Context, server: &mut Server, (on,): (bool,)| {
+                        let current = server.status().brightness;
+                        if !on && current > 0 {
+                            server.last_brightness = current;
+                        }
+                        server.send(Command::Brightness(if on {
+                            server.last_brightness
+                        } else {
+                            0
+                        }))
+                    },
+                );
+                b.method(
+                    "SetBrightness",
+                    ("brightness",),
+                    (),
+                    |_: &mut Context, server: &mut Server, (brightness,): (u8,)| {
+                        server.send(Command::Brightness(brightness))
+                    },
+                );
+                b.method(
+                    "SetEffect",
+                    ("index",),
+                    (),
+                    |_: &mut Context, server: &mut Server, (index,): (u32,)| {
+                        if index > u8::MAX as u32 {
+                            return Err(MethodErr::invalid_arg(&index));
+                        }
+                        server.send(Command::Runner(index as u8))
+                    },
+                );
+                b.property("Power")
+                    .get(|_, server: &mut Server| Ok(server.status().brightness > 0))
+                    .emits_changed_false();
+                b.property("Brightness")
+                    .get(|_, server: &mut Server| Ok(server.status().brightness))
+                    .emits_changed_false();
+                // -1 for the standby
+                b.property("Effect")
+                    .get(|_, server: &mut Server| {
+                        Ok(server.status().runner.map_or(-1, |index| index as i32))
+                    })
+                    .emits_changed_false();
+                b.property("Connected")
+                    .get(|_, server: &mut Server| Ok(server.status().connected))
+                    .emits_changed_false();
+            });
+            cr.insert(
+                OBJECT_PATH,
+                &[token],
+                Server {
+                    status,
+                    control,
+                    last_brightness: brightness,
+                },
+            );
+
+            use ::dbus::channel::MatchingReceiver;
+            connection.start_receive(
+                ::dbus::message::MatchRule::new_method_call(),
+                Box::new(move |msg, conn| {
+                    let _ = cr.handle_message(msg, conn);
+                    true
+                }),
+            );
+
+            while !thread_stop.load(Ordering::Relaxed) {
+                if let Err(err) = connection.process(Duration::from_millis(500)) {
+                    error!("The D-Bus API stopped: {}", err);
+                    break;
+                }
+            }
+        });
+
+        ready_rx
+            .recv()
+            .map_err(|_| anyhow!("The D-Bus thread died"))??;
+        info!("D-Bus API available as {}", BUS_NAME);
+
+        Ok(Self {
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+fn connect(bus: Bus) -> Result<Connection> {
+    let connection = match bus {
+        Bus::Session => Connection::new_session()?,
+        Bus::System => Connection::new_system()?,
+    };
+    match connection.request_name(BUS_NAME, false, true, true)? {
+        RequestNameReply::PrimaryOwner | RequestNameReply::AlreadyOwner => Ok(connection),
+        _ => Err(anyhow!("{} is already taken on the bus", BUS_NAME)),
+    }
+}
+
+impl Drop for DbusApi {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
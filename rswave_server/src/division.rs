@@ -0,0 +1,61 @@
+use rswave_common::packets::BeatDivision;
+use std::time::{Duration, Instant};
+
+/// Beats further apart than this aren't doubled, the music probably stopped in between.
+const MAX_BEAT_PERIOD: Duration = Duration::from_secs(2);
+
+/// Drops or adds beats before they reach the runners, see [BeatDivision].
+pub struct BeatDivider {
+    division: BeatDivision,
+    /// Beats seen, to skip every other one.
+    count: u64,
+    last_beat: Option<Instant>,
+    /// When to add the beat halfway to the next one.
+    extra_beat: Option<Instant>,
+}
+
+impl BeatDivider {
+    pub fn new(division: BeatDivision) -> Self {
+        Self {
+            division,
+            count: 0,
+            last_beat: None,
+            extra_beat: None,
+        }
+    }
+
+    pub fn set_division(&mut self, division: BeatDivision) {
+        self.division = division;
+        self.extra_beat = None;
+    }
+
+    /// Whether the runners should see a beat `now`, `is_beat` being what the remote said.
+    pub fn update(&mut self, is_beat: bool, now: Instant) -> bool {
+        if !is_beat {
+            return match self.extra_beat {
+                Some(at) if now >= at => {
+                    self.extra_beat = None;
+                    true
+                }
+                _ => false,
+            };
+        }
+
+        // Guess when the next beat comes from the previous one
+        let period = self
+            .last_beat
+            .map(|last| now - last)
+            .filter(|&period| period < MAX_BEAT_PERIOD);
+        self.last_beat = Some(now);
+        self.count += 1;
+
+        match self.division {
+            BeatDivision::Half => self.count % 2 == 1,
+            BeatDivision::Normal => true,
+            BeatDivision::Double => {
+                self.extra_beat = period.map(|period| now + period / 2);
+                true
+            }
+        }
+    }
+}
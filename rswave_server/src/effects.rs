@@ -0,0 +1,167 @@
+//! Effects computed from where each LED is in space, for the installations that aren't a
+//! single straight strip. They give a color to any point at any time, the runner asks them
+//! for the point of each LED.
+
+use crate::{
+    layout::Layout,
+    runners::{dim, gradient},
+};
+use cichlid::{ColorRGB, HSV};
+use enum_dispatch::enum_dispatch;
+use std::collections::VecDeque;
+
+/// What the effects know of the music when a frame is rendered.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Scene {
+    /// Seconds since the effect started.
+    pub time: f32,
+    /// Relative to its recent peak.
+    pub novelty: f32,
+}
+
+#[enum_dispatch]
+pub enum EffectEnum {
+    RadialPulse,
+    VerticalGradient,
+}
+
+#[enum_dispatch(EffectEnum)]
+pub trait Effect {
+    /// The LEDs moved, before the first frame.
+    fn layout(&mut self, _layout: &Layout) {}
+    /// Colors to use instead of the rainbow, when there are some.
+    fn palette(&mut self, _palette: &[ColorRGB]) {}
+    fn beat(&mut self, _time: f32) {}
+    /// Move the effect forward, once per frame before asking for the colors.
+    fn update(&mut self, _scene: &Scene) {}
+    /// Color at `(x, y)`, as given by [Layout::normalized].
+    fn color(&self, x: f32, y: f32, scene: &Scene) -> ColorRGB;
+}
+
+// Radial pulse
+// <editor-fold>
+/// Rings of light leaving the center of the installation on every beat.
+pub struct RadialPulse {
+    center: (f32, f32),
+    /// When each ring started and its color, the oldest first.
+    rings: VecDeque<(f32, ColorRGB)>,
+    next_hue: u8,
+    /// Used in turn for the rings when not empty.
+    palette: Vec<ColorRGB>,
+    next_color: usize,
+    /// Distance travelled per second.
+    speed: f32,
+    width: f32,
+}
+
+impl RadialPulse {
+    /// Rings further than this are out of any layout.
+    const MAX_RADIUS: f32 = 1.5;
+
+    pub fn new() -> Self {
+        Self {
+            center: (0.5, 0.5),
+            rings: VecDeque::new(),
+            next_hue: 0,
+            palette: Vec::new(),
+            next_color: 0,
+            speed: 1.2,
+            width: 0.08,
+        }
+    }
+}
+
+impl Effect for RadialPulse {
+    fn layout(&mut self, layout: &Layout) {
+        self.center = layout.normalized_center();
+    }
+
+    fn palette(&mut self, palette: &[ColorRGB]) {
+        self.palette = palette.to_vec();
+    }
+
+    fn beat(&mut self, time: f32) {
+        let color = if self.palette.is_empty() {
+            HSV::new(self.next_hue, 255, 255).to_rgb_rainbow()
+        } else {
+            self.next_color = (self.next_color + 1) % self.palette.len();
+            self.palette[self.next_color]
+        };
+        self.rings.push_back((time, color));
+        self.next_hue = self.next_hue.wrapping_add(40);
+    }
+
+    fn update(&mut self, scene: &Scene) {
+        while let Some(&(start, _)) = self.rings.front() {
+            if (scene.time - start) * self.speed <= Self::MAX_RADIUS {
+                break;
+            }
+            self.rings.pop_front();
+        }
+    }
+
+    fn color(&self, x: f32, y: f32, scene: &Scene) -> ColorRGB {
+        let distance = ((x - self.center.0).powi(2) + (y - self.center.1).powi(2)).sqrt();
+
+        // The brightest ring at this distance, they fade as they grow
+        let (value, color) = self
+            .rings
+            .iter()
+            .map(|&(start, color)| {
+                let radius = (scene.time - start) * self.speed;
+                let value = (1.0 - (distance - radius).abs() / self.width).max(0.0)
+                    * (1.0 - radius / Self::MAX_RADIUS).max(0.0);
+                (value, color)
+            })
+            .fold((0.0, ColorRGB::default()), |brightest, ring| {
+                if ring.0 > brightest.0 {
+                    ring
+                } else {
+                    brightest
+                }
+            });
+        dim(color, (value * 255.0) as u8)
+    }
+}
+// </editor-fold>
+
+// Vertical gradient
+// <editor-fold>
+/// A rainbow from the top to the bottom slowly drifting, brighter with the novelty.
+pub struct VerticalGradient {
+    /// Part of the color wheel from the top to the bottom.
+    spread: f32,
+    /// Turns of the color wheel per second.
+    drift: f32,
+    /// Goes back and forth along it instead of around the color wheel.
+    palette: Vec<ColorRGB>,
+}
+
+impl VerticalGradient {
+    pub fn new() -> Self {
+        Self {
+            spread: 0.5,
+            drift: 0.1,
+            palette: Vec::new(),
+        }
+    }
+}
+
+impl Effect for VerticalGradient {
+    fn palette(&mut self, palette: &[ColorRGB]) {
+        self.palette = palette.to_vec();
+    }
+
+    fn color(&self, _: f32, y: f32, scene: &Scene) -> ColorRGB {
+        let turns = y * self.spread + scene.time * self.drift;
+        let value = 0.25 + 0.75 * scene.novelty.max(0.0).min(1.0);
+        if self.palette.len() > 1 {
+            let position = 1.0 - (2.0 * turns.fract() - 1.0).abs();
+            return dim(gradient(&self.palette, position), (value * 255.0) as u8);
+        }
+
+        let hue = (turns.fract() * 256.0) as u8;
+        HSV::new(hue, 255, (value * 255.0) as u8).to_rgb_rainbow()
+    }
+}
+// </editor-fold>
@@ -0,0 +1,193 @@
+//! A gRPC service to control the server from bigger software with typed clients, see
+//! `proto/rswave.proto`.
+
+use crate::{
+    app::{Control, Status},
+    preset::Presets,
+    zone::Zone,
+};
+use anyhow::Result;
+use rswave_common::packets::Command;
+use std::sync::{Arc, Mutex};
+use tokio::{net::TcpListener, sync::mpsc, task};
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::{transport::Server, Request, Response};
+use tracing::{error, info};
+
+mod proto {
+    tonic::include_proto!("rswave");
+}
+
+use proto::{
+    led_service_server::{LedService, LedServiceServer},
+    BrightnessRequest, Empty, PresetRequest, PresetsReply, RunnerRequest, SavePresetRequest,
+    StatusReply, ZonesReply,
+};
+
+type RpcResult<T> = Result<Response<T>, tonic::Status>;
+
+struct Service {
+    led_count: usize,
+    zones: Vec<Zone>,
+    status: Arc<Mutex<Status>>,
+    presets: Option<Arc<Mutex<Presets>>>,
+    control: mpsc::Sender<Control>,
+}
+
+impl Service {
+    async fn send(&self, control: Control) -> RpcResult<Empty> {
+        self.control
+            .send(control)
+            .await
+            .map_err(|_| tonic::Status::unavailable("The server is stopping"))?;
+        Ok(Response::new(Empty {}))
+    }
+
+    fn presets(&self) -> Result<&Mutex<Presets>, tonic::Status> {
+        self.presets
+            .as_deref()
+            .ok_or_else(|| tonic::Status::failed_precondition("No preset file, see --presets"))
+    }
+}
+
+#[tonic::async_trait]
+impl LedService for Service {
+    async fn get_status(&self, _: Request<Empty>) -> RpcResult<StatusReply> {
+        let status = self.status.lock().expect("Status poisoned !").clone();
+        Ok(Response::new(StatusReply {
+            connected: status.connected,
+            standby: status.runner.is_none(),
+            runner: status.runner.unwrap_or(0) as u32,
+            brightness: status.brightness as u32,
+            standby_speed: status.standby_speed,
+            quiet: status.quiet,
+            preset: status.preset.map_or(-1, i32::from),
+            led_count: self.led_count as u32,
+        }))
+    }
+
+    async fn set_runner(&self, request: Request<RunnerRequest>) -> RpcResult<Empty> {
+        let index = request.into_inner().index;
+        if index > u8::MAX as u32 {
+            return Err(tonic::Status::invalid_argument("The index goes up to 255"));
+        }
+        self.send(Control::Command(Command::Runner(index as u8)))
+            .await
+    }
+
+    async fn set_brightness(&self, request: Request<BrightnessRequest>) -> RpcResult<Empty> {
+        let brightness = request.into_inner().brightness.min(u8::MAX as u32) as u8;
+        self.send(Control::Command(Command::Brightness(brightness)))
+            .await
+    }
+
+    async fn list_presets(&self, _: Request<Empty>) -> RpcResult<PresetsReply> {
+        let presets = match self.presets.as_deref() {
+            Some(presets) => presets.lock().expect("Presets poisoned !").list().to_vec(),
+            None => Vec::new(),
+        };
+
+        Ok(Response::new(PresetsReply {
+            presets: presets
+                .into_iter()
+                .map(|preset| proto::Preset {
+                    id: preset.id as u32,
+                    name: preset.name,
+                    standby: preset.runner.is_none(),
+                    runner: preset.runner.unwrap_or(0) as u32,
+                    brightness: preset.brightness as u32,
+                    standby_speed: preset.standby_speed,
+                    palette: preset
+                        .palette
+                        .iter()
+                        .map(|color| {
+                            (color.r as u32) << 16 | (color.g as u32) << 8 | color.b as u32
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }))
+    }
+
+    async fn recall_preset(&self, request: Request<PresetRequest>) -> RpcResult<Empty> {
+        let id = request.into_inner().id;
+        let exists = id <= u8::MAX as u32
+            && self
+                .presets()?
+                .lock()
+                .expect("Presets poisoned !")
+                .get(id as u8)
+                .is_some();
+        if !exists {
+            return Err(tonic::Status::not_found(format!("No preset {}", id)));
+        }
+
+        self.send(Control::Command(Command::Preset(id as u8))).await
+    }
+
+    async fn save_preset(&self, request: Request<SavePresetRequest>) -> RpcResult<Empty> {
+        self.presets()?;
+        let request = request.into_inner();
+        if request.id > u8::MAX as u32 {
+            return Err(tonic::Status::invalid_argument("The id goes up to 255"));
+        }
+
+        let name = Some(request.name).filter(|name| !name.is_empty());
+        self.send(Control::SavePreset(request.id as u8, name)).await
+    }
+
+    async fn list_zones(&self, _: Request<Empty>) -> RpcResult<ZonesReply> {
+        Ok(Response::new(ZonesReply {
+            zones: self
+                .zones
+                .iter()
+                .map(|zone| proto::Zone {
+                    name: zone.name.clone(),
+                    start: zone.leds.start as u32,
+                    end: zone.leds.end as u32,
+                })
+                .collect(),
+        }))
+    }
+}
+
+/// Serves the API as long as this is alive.
+pub struct GrpcApi {
+    task: task::JoinHandle<()>,
+}
+
+impl GrpcApi {
+    /// Must be called from within the tokio runtime.
+    pub async fn new(
+        port: u16, led_count: usize, zones: Vec<Zone>, status: Arc<Mutex<Status>>,
+        presets: Option<Arc<Mutex<Presets>>>, control: mpsc::Sender<Control>,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+        info!("gRPC API listening on port {}", port);
+
+        let service = Service {
+            led_count,
+            zones,
+            status,
+            presets,
+            control,
+        };
+        let task = task::spawn(async move {
+            let res = Server::builder()
+                .add_service(LedServiceServer::new(service))
+                .serve_with_incoming(TcpListenerStream::new(listener))
+                .await;
+            if let Err(err) = res {
+                error!("The gRPC API stopped: {}", err);
+            }
+        });
+
+        Ok(Self { task })
+    }
+}
+
+impl Drop for GrpcApi {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
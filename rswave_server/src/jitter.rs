@@ -0,0 +1,203 @@
+use rswave_common::packets::BARK_BANDS;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// How long a pulse lasts when the tempo is unknown.
+const DEFAULT_PULSE: Duration = Duration::from_millis(250);
+/// Samples further apart than this are a gap in the stream, not its cadence.
+const MAX_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Copy, Clone)]
+struct Sample {
+    arrival: Instant,
+    data: Playback,
+}
+
+/// What the runner should see at a given time, also what is pushed.
+#[derive(Debug, Copy, Clone)]
+pub struct Playback {
+    pub novelty: f64,
+    /// A beat was played since the last playback.
+    pub is_beat: bool,
+    /// An onset was detected since the last playback.
+    pub is_onset: bool,
+    pub pitch_class: Option<u8>,
+    /// From -1 on the left to 1 on the right.
+    pub balance: f64,
+    pub loudness: Option<f64>,
+    /// Energy of the Bark bands between 0 and 1, only in the bands mode.
+    pub bands: Option<[f32; BARK_BANDS]>,
+}
+
+/// Delays the analysis a bit and interpolates between the samples, so packets arriving
+/// irregularly or slowly still give a smooth curve at the LED update rate.
+pub struct JitterBuffer {
+    delay: Duration,
+    samples: VecDeque<Sample>,
+    last_playback: Option<Instant>,
+    /// Average time between two pushes.
+    interval: Option<Duration>,
+    last_push: Option<Instant>,
+    /// The remote says when to play the samples, they are already delayed.
+    scheduled: bool,
+}
+
+impl JitterBuffer {
+    /// A `delay` of zero always plays the latest sample, otherwise it is raised to the
+    /// interval between the samples so there is always one ahead to interpolate towards.
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            samples: VecDeque::new(),
+            last_playback: None,
+            interval: None,
+            last_push: None,
+            scheduled: false,
+        }
+    }
+
+    /// The delay actually applied, depends on the rate at which the samples arrive.
+    pub fn delay(&self) -> Duration {
+        if self.delay == Duration::from_millis(0) || self.scheduled {
+            return Duration::from_millis(0);
+        }
+        self.interval
+            .map_or(self.delay, |interval| self.delay.max(interval))
+    }
+
+    /// The sample is played `at` the given time instead of after the delay, if there is one.
+    pub fn push(&mut self, data: Playback, at: Option<Instant>) {
+        let now = Instant::now();
+        self.scheduled = at.is_some();
+        if let Some(elapsed) = self.last_push.map(|last| now.duration_since(last)) {
+            if elapsed <= MAX_INTERVAL {
+                self.interval = Some(match self.interval {
+                    Some(interval) => (interval * 7 + elapsed) / 8,
+                    None => elapsed,
+                });
+            }
+        }
+        self.last_push = Some(now);
+
+        self.push_sample(at.unwrap_or(now), data);
+    }
+
+    fn push_sample(&mut self, at: Instant, data: Playback) {
+        // The end of a pulse that is cut short
+        while self
+            .samples
+            .back()
+            .map_or(false, |sample| sample.arrival > at)
+        {
+            self.samples.pop_back();
+        }
+
+        self.samples.push_back(Sample { arrival: at, data });
+    }
+
+    /// Make up the novelty around a beat when only the beats are received, it jumps to 1 and
+    /// falls back to 0 within half a beat.
+    pub fn pulse(&mut self, tempo: Option<f64>, at: Option<Instant>) {
+        self.scheduled = at.is_some();
+        // The beats are too far apart to tell the cadence, the pulse has its own end anyway
+        self.push_sample(
+            at.unwrap_or_else(Instant::now),
+            Playback {
+                novelty: 1.0,
+                is_beat: true,
+                is_onset: true,
+                pitch_class: None,
+                balance: 0.0,
+                loudness: None,
+                bands: None,
+            },
+        );
+
+        let length = tempo.map_or(DEFAULT_PULSE, |tempo| {
+            Duration::from_secs_f64(30.0 / tempo.max(1.0))
+        });
+        let mut end = *self.samples.back().unwrap();
+        end.arrival += length;
+        end.data.novelty = 0.0;
+        end.data.is_beat = false;
+        end.data.is_onset = false;
+        self.samples.push_back(end);
+    }
+
+    /// Forget everything, for when the remote goes away.
+    pub fn clear(&mut self) {
+        self.samples.clear();
+        self.last_playback = None;
+        self.interval = None;
+        self.last_push = None;
+        self.scheduled = false;
+    }
+
+    /// Returns nothing while the buffer is still filling up.
+    pub fn playback(&mut self, now: Instant) -> Option<Playback> {
+        let target = now.checked_sub(self.delay()).unwrap_or(now);
+
+        // Beats and onsets are events, they must not be skipped even if no frame lands on them
+        let last_playback = self.last_playback;
+        let (is_beat, is_onset) = self
+            .samples
+            .iter()
+            .filter(|sample| last_playback.map_or(true, |last| sample.arrival > last))
+            .take_while(|sample| sample.arrival <= target)
+            .fold((false, false), |(beat, onset), sample| {
+                (beat || sample.data.is_beat, onset || sample.data.is_onset)
+            });
+
+        // Keep the last sample before the target to interpolate from it
+        while self.samples.len() > 1 && self.samples[1].arrival <= target {
+            self.samples.pop_front();
+        }
+
+        let from = *self.samples.front()?;
+        if from.arrival > target {
+            return None;
+        }
+        self.last_playback = Some(target);
+
+        let to = match self.samples.get(1) {
+            Some(to) => to,
+            // Nothing new, hold the last value
+            None => {
+                return Some(Playback {
+                    is_beat,
+                    is_onset,
+                    ..from.data
+                })
+            }
+        };
+
+        let t = target.duration_since(from.arrival).as_secs_f64()
+            / to.arrival.duration_since(from.arrival).as_secs_f64();
+        let (from, to) = (from.data, to.data);
+        let lerp = |a: f64, b: f64| a + (b - a) * t;
+
+        Some(Playback {
+            novelty: lerp(from.novelty, to.novelty),
+            is_beat,
+            is_onset,
+            // Can't be interpolated
+            pitch_class: from.pitch_class,
+            balance: lerp(from.balance, to.balance),
+            loudness: match (from.loudness, to.loudness) {
+                (Some(a), Some(b)) => Some(lerp(a, b)),
+                (loudness, _) => loudness,
+            },
+            bands: match (from.bands, to.bands) {
+                (Some(mut a), Some(b)) => {
+                    for (a, b) in a.iter_mut().zip(b.iter()) {
+                        *a = lerp(*a as f64, *b as f64) as f32;
+                    }
+                    Some(a)
+                }
+                (bands, _) => bands,
+            },
+        })
+    }
+}
@@ -0,0 +1,121 @@
+use crate::runners::{Frame, Runner, RunnerEnum};
+use anyhow::{anyhow, Error};
+use cichlid::ColorRGB;
+use std::{str::FromStr, time::Duration};
+
+/// A common runner shown over the current one.
+#[derive(Clone, Debug)]
+pub struct Layer {
+    /// Index of the common runner.
+    pub runner: usize,
+    /// Between 0 and 1.
+    pub opacity: f32,
+}
+
+impl FromStr for Layer {
+    type Err = Error;
+
+    /// Parses `runner:opacity`, like `2:0.5`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        let runner = parts.next().unwrap_or("").trim();
+        let opacity = parts.next().unwrap_or("1").trim();
+
+        Ok(Self {
+            runner: runner
+                .parse()
+                .map_err(|_| anyhow!("Invalid runner `{}` !", runner))?,
+            opacity: match opacity.parse::<f32>() {
+                Ok(opacity) if (0.0..=1.0).contains(&opacity) => opacity,
+                _ => return Err(anyhow!("The opacity must be between 0 and 1 !")),
+            },
+        })
+    }
+}
+
+/// Runners drawn over the whole strip in order, on top of what the current runners show.
+/// Their black is transparent, so a flash can go over an ambient runner.
+pub struct LayerStack {
+    /// With their opacity and what they last showed.
+    layers: Vec<(RunnerEnum, f32, Vec<ColorRGB>)>,
+    /// What the current runners last showed.
+    base: Vec<ColorRGB>,
+}
+
+impl LayerStack {
+    pub fn new(layers: Vec<(RunnerEnum, f32)>, led_amount: usize) -> Self {
+        Self {
+            layers: layers
+                .into_iter()
+                .map(|(runner, opacity)| (runner, opacity, vec![ColorRGB::default(); led_amount]))
+                .collect(),
+            base: vec![ColorRGB::default(); led_amount],
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    /// To give them the analysis and the palette like the other runners.
+    pub fn runners_mut(&mut self) -> impl Iterator<Item = &mut RunnerEnum> {
+        self.layers.iter_mut().map(|(runner, _, _)| runner)
+    }
+
+    /// Time between two frames that suits the layers.
+    pub fn frame_period(&self) -> Option<Duration> {
+        self.layers
+            .iter()
+            .filter_map(|(runner, _, _)| runner.frame_period())
+            .min()
+    }
+
+    /// Put the layers over the frame of the current runners, `None` if nothing changed.
+    pub fn composite(&mut self, base: Option<Frame>, delta_time: Duration) -> Option<Frame> {
+        let led_amount = self.base.len();
+        let mut changed = Self::store(base, &mut self.base);
+        for (runner, _, last) in self.layers.iter_mut() {
+            changed |= Self::store(runner.run_once(delta_time, led_amount), last);
+        }
+        if !changed {
+            return None;
+        }
+
+        let mut strip = self.base.clone();
+        for (_, opacity, colors) in self.layers.iter() {
+            for (led, color) in strip.iter_mut().zip(colors) {
+                *led = blend(*led, *color, *opacity);
+            }
+        }
+        Some(Frame::Individual(strip))
+    }
+
+    /// Returns whether there was a new frame.
+    fn store(frame: Option<Frame>, last: &mut [ColorRGB]) -> bool {
+        match frame {
+            Some(Frame::Uniform(color)) => {
+                for led in last.iter_mut() {
+                    *led = color;
+                }
+            }
+            Some(Frame::Individual(colors)) => {
+                for (led, color) in last.iter_mut().zip(colors) {
+                    *led = color;
+                }
+            }
+            None => return false,
+        }
+        true
+    }
+}
+
+/// `over` on top of `under`, as opaque as it is bright.
+fn blend(under: ColorRGB, over: ColorRGB, opacity: f32) -> ColorRGB {
+    let alpha = opacity * over.r.max(over.g).max(over.b) as f32 / u8::MAX as f32;
+    let mix = |under: u8, over: u8| (under as f32 * (1.0 - alpha) + over as f32 * alpha) as u8;
+    ColorRGB::new(
+        mix(under.r, over.r),
+        mix(under.g, over.g),
+        mix(under.b, over.b),
+    )
+}
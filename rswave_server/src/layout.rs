@@ -0,0 +1,156 @@
+use anyhow::{anyhow, Result};
+use std::{fs, ops::Range, path::Path};
+
+/// Where each LED is in space, in any unit as long as it is the same on both axes.
+#[derive(Clone, Debug)]
+pub struct Layout {
+    positions: Vec<(f32, f32)>,
+    /// Top left and bottom right corners of the box around the LEDs.
+    bounds: ((f32, f32), (f32, f32)),
+}
+
+impl Layout {
+    fn new(positions: Vec<(f32, f32)>) -> Self {
+        let bounds = positions.iter().fold(
+            (
+                (f32::INFINITY, f32::INFINITY),
+                (f32::NEG_INFINITY, f32::NEG_INFINITY),
+            ),
+            |((min_x, min_y), (max_x, max_y)), &(x, y)| {
+                ((min_x.min(x), min_y.min(y)), (max_x.max(x), max_y.max(y)))
+            },
+        );
+        Self { positions, bounds }
+    }
+
+    /// The LEDs one unit apart on a straight line, what a strip without layout file is.
+    pub fn linear(led_amount: usize) -> Self {
+        Self::new((0..led_amount).map(|led| (led as f32, 0.0)).collect())
+    }
+
+    /// Each line places the next LEDs of the strip, `#` starts a comment:
+    /// - `x y` for a single LED,
+    /// - `run x1 y1 x2 y2 count` for `count` LEDs evenly spread from the first point to the
+    /// second one, both included,
+    /// - `matrix x y columns rows` for rows of LEDs one unit apart starting at the top left
+    /// corner, add `serpentine` when every other row is wired backwards.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+
+        let mut positions = Vec::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            Self::parse_line(line, &mut positions)
+                .map_err(|err| anyhow!("Line {} of the layout: {}", i + 1, err))?;
+        }
+
+        if positions.is_empty() {
+            return Err(anyhow!("The layout has no LED"));
+        }
+        Ok(Self::new(positions))
+    }
+
+    fn parse_line(line: &str, positions: &mut Vec<(f32, f32)>) -> Result<()> {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let number = |word: &str| {
+            word.parse::<f32>()
+                .map_err(|_| anyhow!("Invalid number `{}`", word))
+        };
+        let count = |word: &str| {
+            word.parse::<usize>()
+                .map_err(|_| anyhow!("Invalid count `{}`", word))
+        };
+
+        match words.as_slice() {
+            ["run", x1, y1, x2, y2, leds] => {
+                let (x1, y1, x2, y2) = (number(x1)?, number(y1)?, number(x2)?, number(y2)?);
+                let leds = count(leds)?;
+                for led in 0..leds {
+                    let t = if leds > 1 {
+                        led as f32 / (leds - 1) as f32
+                    } else {
+                        0.0
+                    };
+                    positions.push((x1 + (x2 - x1) * t, y1 + (y2 - y1) * t));
+                }
+            }
+            ["run", ..] => return Err(anyhow!("Expected `run x1 y1 x2 y2 count`")),
+            ["matrix", x, y, columns, rows, rest @ ..] => {
+                let serpentine = match rest {
+                    [] => false,
+                    ["serpentine"] => true,
+                    _ => return Err(anyhow!("Expected `matrix x y columns rows [serpentine]`")),
+                };
+                let (x, y) = (number(x)?, number(y)?);
+                let (columns, rows) = (count(columns)?, count(rows)?);
+                for row in 0..rows {
+                    for column in 0..columns {
+                        let column = if serpentine && row % 2 == 1 {
+                            columns - 1 - column
+                        } else {
+                            column
+                        };
+                        positions.push((x + column as f32, y + row as f32));
+                    }
+                }
+            }
+            ["matrix", ..] => {
+                return Err(anyhow!("Expected `matrix x y columns rows [serpentine]`"))
+            }
+            [x, y] => positions.push((number(x)?, number(y)?)),
+            _ => return Err(anyhow!("Expected `x y`, `run` or `matrix`")),
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// As given in the file.
+    pub fn position(&self, led: usize) -> (f32, f32) {
+        self.positions[led]
+    }
+
+    /// The top left and bottom right corners of the box around the LEDs.
+    pub fn bounds(&self) -> ((f32, f32), (f32, f32)) {
+        self.bounds
+    }
+
+    /// Between 0 and 1 along the longest side of the box around the LEDs, the other side
+    /// keeps its proportions.
+    pub fn normalized(&self, led: usize) -> (f32, f32) {
+        let ((min_x, min_y), (max_x, max_y)) = self.bounds;
+        let size = (max_x - min_x).max(max_y - min_y);
+        if size <= 0.0 {
+            return (0.0, 0.0);
+        }
+
+        let (x, y) = self.position(led);
+        ((x - min_x) / size, (y - min_y) / size)
+    }
+
+    /// Middle of the box around the LEDs, like [Layout::normalized].
+    pub fn normalized_center(&self) -> (f32, f32) {
+        let ((min_x, min_y), (max_x, max_y)) = self.bounds;
+        let size = (max_x - min_x).max(max_y - min_y);
+        if size <= 0.0 {
+            return (0.0, 0.0);
+        }
+
+        ((max_x - min_x) / size / 2.0, (max_y - min_y) / size / 2.0)
+    }
+
+    /// The LEDs of a zone of the strip.
+    pub fn zone(&self, leds: Range<usize>) -> Self {
+        Self::new(self.positions[leds].to_vec())
+    }
+}
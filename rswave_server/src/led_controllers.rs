@@ -1,9 +1,43 @@
+#[cfg(feature = "controller_adalight")]
+use anyhow::anyhow;
 use anyhow::Result;
 use cichlid::ColorRGB;
 #[cfg(feature = "controller_gpio")]
 use rppal::gpio::{Gpio, OutputPin};
 #[cfg(feature = "controller_ws2811")]
 use rs_ws281x::{ChannelBuilder, ControllerBuilder, RawColor, StripType};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+#[cfg(feature = "controller_hyperion")]
+use std::{
+    io::{self, Read},
+    net::{TcpStream, ToSocketAddrs},
+};
+use tracing::info;
+#[cfg(any(feature = "controller_adalight", feature = "controller_hyperion"))]
+use tracing::{debug, warn};
+
+/// Map a brightness level to the one that looks like it to the eye with the CIE 1931 lightness
+/// curve, so half the level looks half as bright. Only zero turns the LEDs off.
+pub fn perceived_brightness(level: u8) -> u8 {
+    let lightness = level as f64 / u8::MAX as f64 * 100.0;
+    let luminance = if lightness <= 8.0 {
+        lightness / 903.3
+    } else {
+        ((lightness + 16.0) / 116.0).powi(3)
+    };
+
+    let brightness = (luminance * u8::MAX as f64).round() as u8;
+    if level > 0 {
+        brightness.max(1)
+    } else {
+        0
+    }
+}
 
 pub trait LedController {
     fn is_addressable_individually() -> bool;
@@ -14,6 +48,15 @@ pub trait LedController {
     fn commit(&mut self) -> Result<()>;
 
     fn reset(&mut self) -> Result<()>;
+
+    /// Change the overall brightness, ignored by the strips that can't be dimmed.
+    /// The level is perceptual, see [perceived_brightness].
+    fn set_brightness(&mut self, _brightness: u8) {}
+
+    /// Whether the same frame must still be committed on every update.
+    fn needs_refresh(&self) -> bool {
+        false
+    }
 }
 
 // Controller WS2811
@@ -50,7 +93,7 @@ impl ControllerWs2811 {
                     .count(led_count as i32)
                     .strip_type(StripType::Ws2811Gbr)
                     .invert(false)
-                    .brightness(brightness)
+                    .brightness(perceived_brightness(brightness))
                     .build(),
             )
             .build()?;
@@ -103,6 +146,11 @@ impl LedController for ControllerWs2811 {
         }
         self.commit()
     }
+
+    fn set_brightness(&mut self, brightness: u8) {
+        self.inner
+            .set_brightness(Self::LED_CHANNEL, perceived_brightness(brightness));
+    }
 }
 // </editor-fold>
 
@@ -192,3 +240,706 @@ impl LedController for ControllerGpio {
     }
 }
 // <editor-fold>
+
+// Adalight controller
+// <editor-fold>
+/// Streams the frames to a microcontroller over a serial port with the Adalight protocol,
+/// like the Arduino sketches of Adafruit or the ESP firmwares for ambilights.
+#[cfg(feature = "controller_adalight")]
+pub struct ControllerAdalight {
+    path: String,
+    baud_rate: u32,
+    /// `None` while the adapter is unplugged or stuck.
+    port: Option<Box<dyn serialport::SerialPort>>,
+    last_attempt: Instant,
+    leds: Vec<ColorRGB>,
+    brightness: u8,
+    message: Vec<u8>,
+}
+
+#[cfg(feature = "controller_adalight")]
+impl ControllerAdalight {
+    const WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+    /// Time between two attempts to open the port again when the adapter is gone.
+    const REOPEN_DELAY: Duration = Duration::from_secs(1);
+
+    pub fn new(path: &str, baud_rate: u32, led_count: usize, brightness: u8) -> Result<Self> {
+        if led_count == 0 || led_count > u16::MAX as usize + 1 {
+            return Err(anyhow!("Adalight supports 1 to 65536 LEDs !"));
+        }
+
+        let port = Self::open(path, baud_rate)?;
+        info!("Opened {} at {} bauds", path, baud_rate);
+
+        Ok(Self {
+            path: path.to_owned(),
+            baud_rate,
+            port: Some(port),
+            last_attempt: Instant::now(),
+            leds: vec![ColorRGB::new(0, 0, 0); led_count],
+            brightness: perceived_brightness(brightness),
+            message: Vec::with_capacity(6 + led_count * 3),
+        })
+    }
+
+    fn open(path: &str, baud_rate: u32) -> serialport::Result<Box<dyn serialport::SerialPort>> {
+        serialport::new(path, baud_rate)
+            .timeout(Self::WRITE_TIMEOUT)
+            .open()
+    }
+
+    /// Write the message, the port is dropped and opened again later if it fails.
+    fn send(&mut self) {
+        if self.port.is_none() {
+            if self.last_attempt.elapsed() < Self::REOPEN_DELAY {
+                return;
+            }
+            self.last_attempt = Instant::now();
+            match Self::open(&self.path, self.baud_rate) {
+                Ok(port) => {
+                    info!("Reopened {}", self.path);
+                    self.port = Some(port);
+                }
+                Err(err) => {
+                    debug!("{} is still unavailable: {}", self.path, err);
+                    return;
+                }
+            }
+        }
+
+        let port = self.port.as_mut().unwrap();
+        if let Err(err) = port.write_all(&self.message) {
+            warn!("Lost {}: {}", self.path, err);
+            self.port = None;
+            self.last_attempt = Instant::now();
+        }
+    }
+}
+
+#[cfg(feature = "controller_adalight")]
+impl LedController for ControllerAdalight {
+    fn is_addressable_individually() -> bool {
+        true
+    }
+
+    fn led_amount(&self) -> usize {
+        self.leds.len()
+    }
+
+    fn set_all(&mut self, color: ColorRGB) {
+        for led in self.leds.iter_mut() {
+            *led = color;
+        }
+    }
+
+    fn set_all_individual(&mut self, colors: &[ColorRGB]) {
+        for (led, color) in self.leds.iter_mut().zip(colors) {
+            *led = *color;
+        }
+    }
+
+    fn set_individual(&mut self, i: usize, color: ColorRGB) {
+        self.leds[i] = color;
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        // "Ada", the amount of LEDs minus one in big endian and a checksum of it
+        let [hi, lo] = ((self.leds.len() - 1) as u16).to_be_bytes();
+        self.message.clear();
+        self.message
+            .extend_from_slice(&[b'A', b'd', b'a', hi, lo, hi ^ lo ^ 0x55]);
+
+        let brightness = self.brightness as u16;
+        for color in self.leds.iter() {
+            self.message.extend_from_slice(&[
+                (color.r as u16 * brightness / 255) as u8,
+                (color.g as u16 * brightness / 255) as u8,
+                (color.b as u16 * brightness / 255) as u8,
+            ]);
+        }
+
+        self.send();
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.set_all(ColorRGB::new(0, 0, 0));
+        self.commit()
+    }
+
+    fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = perceived_brightness(brightness);
+    }
+}
+// </editor-fold>
+
+// Hyperion controller
+// <editor-fold>
+/// Forwards the frames to a Hyperion or HyperHDR instance through its JSON API, as a source
+/// with its own priority like a grabber. The LEDs themselves are configured in Hyperion.
+#[cfg(feature = "controller_hyperion")]
+pub struct ControllerHyperion {
+    address: String,
+    priority: u8,
+    /// `None` while Hyperion is unreachable.
+    stream: Option<TcpStream>,
+    last_attempt: Instant,
+    leds: Vec<ColorRGB>,
+    brightness: u8,
+}
+
+#[cfg(feature = "controller_hyperion")]
+impl ControllerHyperion {
+    /// Time between two connection attempts when Hyperion is unreachable.
+    const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+    /// The frames are sent from the runner thread, so a connection attempt or a write to a
+    /// stuck Hyperion can't hold it for more than a few frames.
+    const CONNECT_TIMEOUT: Duration = Duration::from_millis(100);
+    const WRITE_TIMEOUT: Duration = Duration::from_millis(50);
+    /// Hyperion falls back to its other sources if the frames stop for this long.
+    const COLOR_DURATION_MS: u64 = 1000;
+    const ORIGIN: &'static str = "rswave";
+
+    pub fn new(address: String, priority: u8, led_count: usize, brightness: u8) -> Result<Self> {
+        let stream = Self::connect(&address)?;
+        info!("Connected to Hyperion at {}", address);
+
+        Ok(Self {
+            address,
+            priority,
+            stream: Some(stream),
+            last_attempt: Instant::now(),
+            leds: vec![ColorRGB::new(0, 0, 0); led_count.max(1)],
+            brightness: perceived_brightness(brightness),
+        })
+    }
+
+    /// Send a command, the connection is dropped and tried again later if it fails.
+    fn send(&mut self, command: serde_json::Value) -> Result<()> {
+        if self.stream.is_none() {
+            if self.last_attempt.elapsed() < Self::RECONNECT_DELAY {
+                return Ok(());
+            }
+            self.last_attempt = Instant::now();
+            match Self::connect(&self.address) {
+                Ok(stream) => {
+                    info!("Reconnected to Hyperion");
+                    self.stream = Some(stream);
+                }
+                Err(err) => {
+                    debug!("Hyperion is still unreachable: {}", err);
+                    return Ok(());
+                }
+            }
+        }
+
+        let stream = self.stream.as_mut().unwrap();
+        let mut message = command.to_string();
+        message.push('\n');
+        let res = stream
+            .write_all(message.as_bytes())
+            .and_then(|_| Self::drain_replies(stream));
+        if let Err(err) = res {
+            warn!("Lost the connection to Hyperion: {}", err);
+            self.stream = None;
+            self.last_attempt = Instant::now();
+        }
+        Ok(())
+    }
+
+    fn connect(address: &str) -> io::Result<TcpStream> {
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, "No address to connect to");
+        for address in address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, Self::CONNECT_TIMEOUT) {
+                Ok(stream) => {
+                    stream.set_nodelay(true)?;
+                    stream.set_write_timeout(Some(Self::WRITE_TIMEOUT))?;
+                    return Ok(stream);
+                }
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+
+    /// Hyperion answers every command, nothing useful in there but it must be read.
+    fn drain_replies(stream: &mut TcpStream) -> io::Result<()> {
+        stream.set_nonblocking(true)?;
+        let mut buffer = [0; 1024];
+        let res = loop {
+            match stream.read(&mut buffer) {
+                Ok(0) => break Err(io::ErrorKind::ConnectionAborted.into()),
+                Ok(_) => {}
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+                Err(err) => break Err(err),
+            }
+        };
+        stream.set_nonblocking(false)?;
+        res
+    }
+}
+
+#[cfg(feature = "controller_hyperion")]
+impl LedController for ControllerHyperion {
+    fn is_addressable_individually() -> bool {
+        true
+    }
+
+    fn led_amount(&self) -> usize {
+        self.leds.len()
+    }
+
+    fn set_all(&mut self, color: ColorRGB) {
+        for led in self.leds.iter_mut() {
+            *led = color;
+        }
+    }
+
+    fn set_all_individual(&mut self, colors: &[ColorRGB]) {
+        for (led, color) in self.leds.iter_mut().zip(colors) {
+            *led = *color;
+        }
+    }
+
+    fn set_individual(&mut self, i: usize, color: ColorRGB) {
+        self.leds[i] = color;
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        let brightness = self.brightness as u16;
+        // Hyperion spreads the colors over its own LEDs, one after the other
+        let mut colors = Vec::with_capacity(self.leds.len() * 3);
+        for color in self.leds.iter() {
+            colors.extend_from_slice(&[
+                (color.r as u16 * brightness / 255) as u8,
+                (color.g as u16 * brightness / 255) as u8,
+                (color.b as u16 * brightness / 255) as u8,
+            ]);
+        }
+
+        self.send(serde_json::json!({
+            "command": "color",
+            "color": colors,
+            "priority": self.priority,
+            "duration": Self::COLOR_DURATION_MS,
+            "origin": Self::ORIGIN,
+        }))
+    }
+
+    /// Give the LEDs back to the other sources of Hyperion.
+    fn reset(&mut self) -> Result<()> {
+        self.set_all(ColorRGB::new(0, 0, 0));
+        self.send(serde_json::json!({
+            "command": "clear",
+            "priority": self.priority,
+        }))
+    }
+
+    fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = perceived_brightness(brightness);
+    }
+
+    fn needs_refresh(&self) -> bool {
+        // Or Hyperion would drop the color after its duration
+        true
+    }
+}
+// </editor-fold>
+
+// Dry run controller
+// <editor-fold>
+/// Pretends to be an addressable strip, the frames are logged or written as images instead.
+pub struct ControllerDryRun {
+    leds: Vec<ColorRGB>,
+    brightness: u8,
+    /// Where to write one image per second of animation, a row per frame.
+    output: Option<PathBuf>,
+    rows: Vec<Vec<ColorRGB>>,
+    images: usize,
+    last_dump: Instant,
+}
+
+impl ControllerDryRun {
+    const DUMP_PERIOD: Duration = Duration::from_secs(1);
+    /// LEDs shown in the logs.
+    const LOGGED_LEDS: usize = 8;
+
+    pub fn new(led_count: usize, brightness: u8, output: Option<PathBuf>) -> Result<Self> {
+        if let Some(output) = output.as_ref() {
+            std::fs::create_dir_all(output)?;
+            info!("Writing the frames to {}", output.display());
+        }
+
+        Ok(Self {
+            leds: vec![ColorRGB::new(0, 0, 0); led_count.max(1)],
+            brightness: perceived_brightness(brightness),
+            output,
+            rows: Vec::new(),
+            images: 0,
+            last_dump: Instant::now(),
+        })
+    }
+
+    /// Binary PPM, the simplest image format there is.
+    fn write_image(&mut self, output: &Path) -> Result<()> {
+        let path = output.join(format!("frames_{:05}.ppm", self.images));
+        let mut file = BufWriter::new(File::create(path)?);
+        write!(file, "P6\n{} {}\n255\n", self.leds.len(), self.rows.len())?;
+        for color in self.rows.iter().flatten() {
+            file.write_all(&[color.r, color.g, color.b])?;
+        }
+        file.flush()?;
+
+        self.images += 1;
+        Ok(())
+    }
+
+    fn log_frame(&self) {
+        let shown = self
+            .leds
+            .iter()
+            .take(Self::LOGGED_LEDS)
+            .map(|color| format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let more = if self.leds.len() > Self::LOGGED_LEDS {
+            " ..."
+        } else {
+            ""
+        };
+        info!("Frame: {}{}", shown, more);
+    }
+}
+
+impl LedController for ControllerDryRun {
+    fn is_addressable_individually() -> bool {
+        true
+    }
+
+    fn led_amount(&self) -> usize {
+        self.leds.len()
+    }
+
+    fn set_all(&mut self, color: ColorRGB) {
+        for led in self.leds.iter_mut() {
+            *led = color;
+        }
+    }
+
+    fn set_all_individual(&mut self, colors: &[ColorRGB]) {
+        for (led, color) in self.leds.iter_mut().zip(colors) {
+            *led = *color;
+        }
+    }
+
+    fn set_individual(&mut self, i: usize, color: ColorRGB) {
+        self.leds[i] = color;
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        // Like the hardware brightness of the WS2811
+        let brightness = self.brightness as u16;
+        self.rows.push(
+            self.leds
+                .iter()
+                .map(|color| {
+                    ColorRGB::new(
+                        (color.r as u16 * brightness / 255) as u8,
+                        (color.g as u16 * brightness / 255) as u8,
+                        (color.b as u16 * brightness / 255) as u8,
+                    )
+                })
+                .collect(),
+        );
+
+        if self.last_dump.elapsed() < Self::DUMP_PERIOD {
+            return Ok(());
+        }
+        self.last_dump = Instant::now();
+
+        match self.output.clone() {
+            Some(output) => self.write_image(&output)?,
+            None => self.log_frame(),
+        }
+        self.rows.clear();
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.set_all(ColorRGB::new(0, 0, 0));
+        self.commit()
+    }
+
+    fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = perceived_brightness(brightness);
+    }
+}
+// </editor-fold>
+
+// Dithering controller
+// <editor-fold>
+/// Applies the brightness itself instead of the strip, and carries what is lost by rounding
+/// to the next frames. Dim colors then average to their exact value over a few frames instead
+/// of jumping between the few levels left.
+pub struct ControllerDithering<C: LedController> {
+    inner: C,
+    brightness: u8,
+    leds: Vec<ColorRGB>,
+    /// What was rounded off for each channel of each LED.
+    errors: Vec<[f32; 3]>,
+    output: Vec<ColorRGB>,
+}
+
+impl<C: LedController> ControllerDithering<C> {
+    pub fn new(mut inner: C, brightness: u8) -> Self {
+        // Only the software brightness is applied
+        inner.set_brightness(u8::MAX);
+        let led_amount = inner.led_amount().max(1);
+
+        Self {
+            inner,
+            brightness: perceived_brightness(brightness),
+            leds: vec![ColorRGB::new(0, 0, 0); led_amount],
+            errors: vec![[0.0; 3]; led_amount],
+            output: vec![ColorRGB::new(0, 0, 0); led_amount],
+        }
+    }
+}
+
+impl<C: LedController> LedController for ControllerDithering<C> {
+    fn is_addressable_individually() -> bool {
+        C::is_addressable_individually()
+    }
+
+    fn led_amount(&self) -> usize {
+        self.inner.led_amount()
+    }
+
+    fn set_all(&mut self, color: ColorRGB) {
+        for led in self.leds.iter_mut() {
+            *led = color;
+        }
+    }
+
+    fn set_all_individual(&mut self, colors: &[ColorRGB]) {
+        for (led, color) in self.leds.iter_mut().zip(colors) {
+            *led = *color;
+        }
+    }
+
+    fn set_individual(&mut self, i: usize, color: ColorRGB) {
+        self.leds[i] = color;
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        let scale = self.brightness as f32 / u8::MAX as f32;
+        let dither = |channel: u8, error: &mut f32| {
+            let value = channel as f32 * scale + *error;
+            let rounded = value.floor();
+            *error = value - rounded;
+            rounded as u8
+        };
+        for ((output, led), error) in self
+            .output
+            .iter_mut()
+            .zip(self.leds.iter())
+            .zip(self.errors.iter_mut())
+        {
+            *output = ColorRGB::new(
+                dither(led.r, &mut error[0]),
+                dither(led.g, &mut error[1]),
+                dither(led.b, &mut error[2]),
+            );
+        }
+
+        if C::is_addressable_individually() {
+            self.inner.set_all_individual(&self.output);
+        } else {
+            self.inner.set_all(self.output[0]);
+        }
+        self.inner.commit()
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        // The refreshes would show the old frame again otherwise
+        for ((led, output), error) in self
+            .leds
+            .iter_mut()
+            .zip(self.output.iter_mut())
+            .zip(self.errors.iter_mut())
+        {
+            *led = ColorRGB::new(0, 0, 0);
+            *output = ColorRGB::new(0, 0, 0);
+            *error = [0.0; 3];
+        }
+        self.inner.reset()
+    }
+
+    fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = perceived_brightness(brightness);
+    }
+
+    fn needs_refresh(&self) -> bool {
+        true
+    }
+}
+// </editor-fold>
+
+// Composite controller
+// <editor-fold>
+/// One of the strips of a [ControllerComposite].
+pub enum CompositeOutput {
+    #[cfg(feature = "controller_ws2811")]
+    Ws2811(ControllerWs2811),
+    /// Takes a single place on the logical strip.
+    #[cfg(feature = "controller_gpio")]
+    Gpio(ControllerGpio),
+    #[cfg(feature = "controller_adalight")]
+    Adalight(ControllerAdalight),
+}
+
+impl CompositeOutput {
+    fn led_amount(&self) -> usize {
+        match self {
+            #[cfg(feature = "controller_ws2811")]
+            Self::Ws2811(controller) => controller.led_amount(),
+            #[cfg(feature = "controller_gpio")]
+            Self::Gpio(controller) => controller.led_amount(),
+            #[cfg(feature = "controller_adalight")]
+            Self::Adalight(controller) => controller.led_amount(),
+        }
+    }
+
+    /// Show `colors`, which are as many as the LEDs of the output.
+    fn set_all_individual(&mut self, colors: &[ColorRGB]) {
+        match self {
+            #[cfg(feature = "controller_ws2811")]
+            Self::Ws2811(controller) => controller.set_all_individual(colors),
+            #[cfg(feature = "controller_gpio")]
+            Self::Gpio(controller) => controller.set_all(colors[0]),
+            #[cfg(feature = "controller_adalight")]
+            Self::Adalight(controller) => controller.set_all_individual(colors),
+        }
+    }
+
+    fn set_all(&mut self, color: ColorRGB) {
+        match self {
+            #[cfg(feature = "controller_ws2811")]
+            Self::Ws2811(controller) => controller.set_all(color),
+            #[cfg(feature = "controller_gpio")]
+            Self::Gpio(controller) => controller.set_all(color),
+            #[cfg(feature = "controller_adalight")]
+            Self::Adalight(controller) => controller.set_all(color),
+        }
+    }
+
+    fn set_individual(&mut self, i: usize, color: ColorRGB) {
+        match self {
+            #[cfg(feature = "controller_ws2811")]
+            Self::Ws2811(controller) => controller.set_individual(i, color),
+            #[cfg(feature = "controller_gpio")]
+            Self::Gpio(controller) => controller.set_individual(i, color),
+            #[cfg(feature = "controller_adalight")]
+            Self::Adalight(controller) => controller.set_individual(i, color),
+        }
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        match self {
+            #[cfg(feature = "controller_ws2811")]
+            Self::Ws2811(controller) => controller.commit(),
+            #[cfg(feature = "controller_gpio")]
+            Self::Gpio(controller) => controller.commit(),
+            #[cfg(feature = "controller_adalight")]
+            Self::Adalight(controller) => controller.commit(),
+        }
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        match self {
+            #[cfg(feature = "controller_ws2811")]
+            Self::Ws2811(controller) => controller.reset(),
+            #[cfg(feature = "controller_gpio")]
+            Self::Gpio(controller) => controller.reset(),
+            #[cfg(feature = "controller_adalight")]
+            Self::Adalight(controller) => controller.reset(),
+        }
+    }
+
+    fn set_brightness(&mut self, brightness: u8) {
+        match self {
+            #[cfg(feature = "controller_ws2811")]
+            Self::Ws2811(controller) => controller.set_brightness(brightness),
+            #[cfg(feature = "controller_gpio")]
+            Self::Gpio(controller) => controller.set_brightness(brightness),
+            #[cfg(feature = "controller_adalight")]
+            Self::Adalight(controller) => controller.set_brightness(brightness),
+        }
+    }
+}
+
+/// Several strips shown as one long strip, one after the other in the given order.
+pub struct ControllerComposite {
+    outputs: Vec<CompositeOutput>,
+}
+
+impl ControllerComposite {
+    pub fn new(outputs: Vec<CompositeOutput>) -> Self {
+        Self { outputs }
+    }
+}
+
+impl LedController for ControllerComposite {
+    fn is_addressable_individually() -> bool {
+        true
+    }
+
+    fn led_amount(&self) -> usize {
+        self.outputs.iter().map(CompositeOutput::led_amount).sum()
+    }
+
+    fn set_all(&mut self, color: ColorRGB) {
+        for output in self.outputs.iter_mut() {
+            output.set_all(color);
+        }
+    }
+
+    fn set_all_individual(&mut self, colors: &[ColorRGB]) {
+        let mut offset = 0;
+        for output in self.outputs.iter_mut() {
+            let end = offset + output.led_amount();
+            output.set_all_individual(&colors[offset..end]);
+            offset = end;
+        }
+    }
+
+    fn set_individual(&mut self, mut i: usize, color: ColorRGB) {
+        for output in self.outputs.iter_mut() {
+            if i < output.led_amount() {
+                return output.set_individual(i, color);
+            }
+            i -= output.led_amount();
+        }
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        for output in self.outputs.iter_mut() {
+            output.commit()?;
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        for output in self.outputs.iter_mut() {
+            output.reset()?;
+        }
+        Ok(())
+    }
+
+    fn set_brightness(&mut self, brightness: u8) {
+        for output in self.outputs.iter_mut() {
+            output.set_brightness(brightness);
+        }
+    }
+}
+// </editor-fold>
@@ -1,33 +1,108 @@
+#[cfg(feature = "button")]
+use crate::button::ButtonAction;
+#[cfg(feature = "dbus_api")]
+use crate::dbus::Bus;
+use crate::{
+    layer::Layer,
+    net::PeerPolicy,
+    post::PostProcess,
+    schedule::{QuietHours, QuietMode},
+    zone::Zone,
+};
 use anyhow::anyhow;
-use std::str::FromStr;
+use rswave_common::{
+    auth::Psk, logging::LogFormat, packets::BeatDivision, transport::TransportKind,
+};
+use std::{path::PathBuf, str::FromStr};
 use structopt::StructOpt;
 
+pub mod animation;
 pub mod app;
+pub mod artnet;
+pub mod beat_sync;
+#[cfg(feature = "button")]
+pub mod button;
+pub mod clock;
+#[cfg(feature = "daemon")]
+pub mod daemon;
+#[cfg(feature = "dbus_api")]
+pub mod dbus;
+pub mod division;
+pub mod effects;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod jitter;
+pub mod layer;
+pub mod layout;
 pub mod led_controllers;
+#[cfg(feature = "local_analysis")]
+pub mod local;
 pub mod net;
+pub mod post;
+pub mod preset;
+pub mod recording;
 pub mod runners;
+pub mod schedule;
+pub mod test_pattern;
+#[cfg(feature = "wled")]
+pub mod wled;
+pub mod zone;
 
-#[derive(Copy, Clone, Debug, StructOpt)]
+#[derive(Clone, Debug, StructOpt)]
 pub struct Opt {
     /// Port to use.
     #[structopt(short, long, default_value = "20200")]
     pub port: u16,
 
-    /// Set overall brightness.
+    /// Set overall brightness, 128 looks half as bright as 255.
     #[structopt(short, long, default_value = "255")]
     pub brightness: u8,
 
+    /// Apply the brightness with temporal dithering instead of on the strip, so dim colors
+    /// stay smooth. Needs a short `--led-update-period` to not flicker.
+    #[structopt(long)]
+    pub dithering: bool,
+
     /// Reset the LED strip and exit.
     #[structopt(short, long)]
     pub reset: bool,
 
+    /// Light the LEDs one by one with colors that tell their index and exit, to check
+    /// `--led-count` and find dead LEDs.
+    #[structopt(long)]
+    pub test_pattern: bool,
+
+    /// Don't sweep red, green and blue across the strip on start.
+    #[structopt(long)]
+    pub no_self_test: bool,
+
+    /// Don't touch the LEDs, log the frames or write them as images with `--dry-run-output`.
+    #[structopt(long)]
+    pub dry_run: bool,
+
+    /// Write one PPM image per second of animation in this directory, one row per frame,
+    /// instead of logging the frames.
+    #[structopt(long, requires = "dry-run")]
+    pub dry_run_output: Option<PathBuf>,
+
     /// Led strip type, will default to WS2811.
-    /// Possible values: ws2811, gpio.
+    /// Possible values: ws2811, gpio, adalight (requires the `controller_adalight` feature),
+    /// hyperion (requires the `controller_hyperion` feature).
     #[structopt(short, long, default_value = "ws2811")]
     pub led_type: LedStripType,
 
+    /// Drive several strips as a single one, in this order, instead of `--led-type`.
+    /// For example `ws2811:60,adalight:144,gpio`, the GPIO strip taking a single place at the
+    /// end. An addressable strip without its own count uses `--led-count`.
+    #[structopt(long, use_delimiter = true)]
+    pub composite: Vec<CompositePart>,
+
     /// Amount of LEDs on the strip (only used with an addressable strip).
-    #[structopt(short = "c", long, required_if("led_type", "ws2811"))]
+    #[structopt(
+        short = "c",
+        long,
+        required_ifs(&[("led_type", "ws2811"), ("led_type", "adalight")])
+    )]
     pub led_count: Option<usize>,
 
     /// Frequency in Hz to use for the PWM pins, only used with GPIO led type.
@@ -46,10 +121,92 @@ pub struct Opt {
     #[structopt(long, default_value = "25", required_if("led_type", "gpio"))]
     pub pin_blue: u8,
 
-    /// Delay during LED updates in milliseconds.
+    /// Serial port of the microcontroller in Adalight led type.
+    #[structopt(long, default_value = "/dev/ttyUSB0")]
+    pub serial_port: String,
+
+    /// Speed of the serial port in Adalight led type, must match the sketch of the
+    /// microcontroller.
+    #[structopt(long, default_value = "115200")]
+    pub baud_rate: u32,
+
+    /// Address of the JSON API of Hyperion in Hyperion led type.
+    #[structopt(long, default_value = "localhost:19444")]
+    pub hyperion_address: String,
+
+    /// Priority of the frames sent to Hyperion, lower values win over its other sources.
+    #[structopt(long, default_value = "50")]
+    pub hyperion_priority: u8,
+
+    /// Shortest delay between LED updates in milliseconds, the runners may ask for a longer one
+    /// but never a shorter one.
     #[structopt(long, default_value = "10")]
     pub led_update_period: u64,
 
+    /// Delay in milliseconds applied to the analysis to smooth out irregular packet arrivals,
+    /// 0 to disable. Raised to the interval between the packets when they come slower,
+    /// so the LEDs are still updated smoothly in between.
+    #[structopt(long, default_value = "30")]
+    pub jitter_buffer: u64,
+
+    /// A part of the strip with its own runner, `name:start-end` with the end excluded like
+    /// `floor:0-60`. Can be given several times, the remotes may send each zone its own data.
+    #[structopt(long)]
+    pub zone: Vec<Zone>,
+
+    /// File giving the position of each LED in space, for the effects drawing shapes
+    /// instead of following the strip. See the README for its format.
+    #[structopt(long)]
+    pub layout: Option<PathBuf>,
+
+    /// A common runner shown over the current one on the whole strip, `runner:opacity` like
+    /// `2:0.5` for the white flashes at half opacity. Can be given several times, the black
+    /// of the layers is transparent.
+    #[structopt(long)]
+    pub layer: Vec<Layer>,
+
+    /// Change the frames of every runner before they are shown, applied in the order given:
+    /// `blur:radius`, `mirror`, `reverse`, `floor:value` to turn off the LEDs dimmer than
+    /// that, and `pixelate:size`.
+    #[structopt(long)]
+    pub post: Vec<PostProcess>,
+
+    /// Which beats reach the runners: half for every other beat, normal, or double for
+    /// another beat halfway between them. The remotes can change it.
+    #[structopt(long, default_value = "normal")]
+    pub beat_division: BeatDivision,
+
+    /// Let a lighting console drive the LEDs over Art-Net, from this universe on with 170 LEDs
+    /// per universe, shown as is without `--post`. The runners take over again a few seconds
+    /// after it stops sending.
+    #[structopt(long)]
+    pub artnet_universe: Option<u16>,
+
+    /// Play this animation as a 7th common runner, a GIF (requires the `animation_gif` feature)
+    /// or a PPM with one frame per row like the images of `--dry-run-output`. It is stretched
+    /// over the strip, or over the `--layout` for a matrix.
+    #[structopt(long)]
+    pub animation: Option<PathBuf>,
+
+    /// Frames per second of the animation.
+    #[structopt(long, default_value = "30")]
+    pub animation_fps: f32,
+
+    /// Tempo in beats per minute at which the animation plays at `--animation-fps`, it then
+    /// goes faster or slower with the beats.
+    #[structopt(long)]
+    pub animation_tempo: Option<f32>,
+
+    /// Write every frame shown to this file with its time, to replay it with `--replay`.
+    /// The frames are taken before `--post` and the strobe.
+    #[structopt(long)]
+    pub record: Option<PathBuf>,
+
+    /// Play a file written with `--record` in a loop instead of the standby rainbow, as a
+    /// show that needs no audio.
+    #[structopt(long)]
+    pub replay: Option<PathBuf>,
+
     /// Controls the speed of the rainbow during the standby mode.
     #[structopt(long, default_value = "1.0")]
     pub standby_speed: f32,
@@ -58,12 +215,115 @@ pub struct Opt {
     /// This effect will only be visible on addressable LED strips.
     #[structopt(long)]
     pub standby_reverse: bool,
+
+    /// Local time range during which the strip calms down, even if a remote is connected.
+    /// For example `23:00-07:00`.
+    #[structopt(long)]
+    pub quiet_hours: Option<QuietHours>,
+
+    /// What to do during the quiet hours.
+    /// Possible values: off, dim.
+    #[structopt(long, default_value = "off")]
+    pub quiet_mode: QuietMode,
+
+    /// Brightness of the standby rainbow during the quiet hours with the dim mode.
+    #[structopt(long, default_value = "100")]
+    pub quiet_brightness: u8,
+
+    /// File where the presets are kept, created when the first one is saved by a remote or
+    /// an API.
+    #[structopt(long)]
+    pub presets: Option<PathBuf>,
+
+    /// Show this preset on start, by id or by name.
+    #[structopt(long, requires = "presets")]
+    pub preset: Option<String>,
+
+    /// GPIO pin of a push button wired to the ground, to change the effects without remote.
+    #[cfg(feature = "button")]
+    #[structopt(long)]
+    pub button_pin: Option<u8>,
+
+    /// What a press of the button does.
+    /// Possible values: cycle (the runners), standby (toggle it).
+    #[cfg(feature = "button")]
+    #[structopt(long, default_value = "cycle")]
+    pub button_action: ButtonAction,
+
+    /// Analyse the audio of a microphone plugged into this machine to drive the LEDs when no
+    /// remote is connected (requires the `local_analysis` feature).
+    #[cfg(feature = "local_analysis")]
+    #[structopt(long)]
+    pub local_analysis: bool,
+
+    /// A pattern to help take the right device for the local analysis, the default input
+    /// device is used otherwise.
+    #[cfg(feature = "local_analysis")]
+    #[structopt(long)]
+    pub local_device: Option<String>,
+
+    /// Log how far the beats of the remote are from the peaks heard by the local analysis,
+    /// as a histogram, to know how much delay to compensate.
+    #[cfg(feature = "local_analysis")]
+    #[structopt(long, requires = "local-analysis")]
+    pub beat_diagnostics: bool,
+
+    /// Serve a part of the JSON API of WLED on this port so its apps and Home Assistant can
+    /// control the LEDs, they usually expect port 80 (requires the `wled` feature).
+    #[cfg(feature = "wled")]
+    #[structopt(long)]
+    pub wled_port: Option<u16>,
+
+    /// Serve a gRPC API on this port, see `proto/rswave.proto` (requires the `grpc` feature).
+    #[cfg(feature = "grpc")]
+    #[structopt(long)]
+    pub grpc_port: Option<u16>,
+
+    /// Publish org.rswave.Server1 on this D-Bus bus to control the LEDs with `busctl`.
+    /// Possible values: session, system (requires the `dbus_api` feature).
+    #[cfg(feature = "dbus_api")]
+    #[structopt(long)]
+    pub dbus: Option<Bus>,
+
+    /// How to talk with the remote.
+    /// Possible values: udp, tcp, quic (requires the `transport_quic` feature).
+    #[structopt(long, default_value = "udp")]
+    pub transport: TransportKind,
+
+    /// Passphrase shared with the remote, any remote that doesn't know it will be rejected.
+    #[structopt(long, env = "RSWAVE_PSK", hide_env_values = true)]
+    pub psk: Option<Psk>,
+
+    /// Which remote drives the LEDs when several are connected.
+    /// Possible values: latest, first, exclusive (refuse the others).
+    #[structopt(long, default_value = "latest")]
+    pub peer_policy: PeerPolicy,
+
+    /// How to print the logs, the verbosity is set with `RUST_LOG`.
+    /// Possible values: text, json.
+    #[structopt(long, default_value = "text")]
+    pub log_format: LogFormat,
+
+    /// Fork into the background, for setups without a service manager (requires the `daemon`
+    /// feature).
+    #[structopt(long)]
+    pub daemonize: bool,
+
+    /// Where to write the PID when running as a daemon.
+    #[structopt(long, default_value = "/run/rswave_server.pid")]
+    pub pid_file: PathBuf,
+
+    /// Write the logs to this file when running as a daemon instead of sending them to syslog.
+    #[structopt(long)]
+    pub log_file: Option<PathBuf>,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum LedStripType {
     Ws2811,
     Gpio,
+    Adalight,
+    Hyperion,
 }
 
 impl FromStr for LedStripType {
@@ -73,7 +333,36 @@ impl FromStr for LedStripType {
         match s.to_lowercase().as_str() {
             "ws2811" => Ok(Self::Ws2811),
             "gpio" => Ok(Self::Gpio),
+            "adalight" => Ok(Self::Adalight),
+            "hyperion" => Ok(Self::Hyperion),
             _ => Err(anyhow!("Unknown led strip type !")),
         }
     }
 }
+
+/// One of the strips of `--composite`, like `ws2811:60`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CompositePart {
+    pub led_type: LedStripType,
+    /// How many LEDs this strip has, if not `--led-count`.
+    pub led_count: Option<usize>,
+}
+
+impl FromStr for CompositePart {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        let led_type = parts.next().unwrap_or("").trim().parse()?;
+        let led_count = match parts.next().map(|count| count.trim().parse::<usize>()) {
+            None => None,
+            Some(Ok(count)) if count > 0 => Some(count),
+            Some(_) => return Err(anyhow!("Invalid LED count in `{}` !", s)),
+        };
+
+        Ok(Self {
+            led_type,
+            led_count,
+        })
+    }
+}
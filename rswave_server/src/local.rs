@@ -0,0 +1,156 @@
+use crate::{app::LocalFrame, Opt};
+use anyhow::{anyhow, Result};
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    Device, Sample, SampleFormat, Stream, StreamConfig,
+};
+use ringbuf::{Consumer, Producer, RingBuffer};
+use rswave_common::audio::AudioProcessor;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+use tracing::{info, info_span, warn};
+
+/// How long to wait for the buffer to fill up.
+const IDLE_DELAY: Duration = Duration::from_millis(10);
+
+/// Analyse the audio of a microphone plugged into the Pi, the same way the remote does,
+/// to drive the LEDs without any remote.
+pub struct LocalAnalysis {
+    thread: JoinHandle<Result<()>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl LocalAnalysis {
+    /// The analysis of each frame is sent with `sender`.
+    pub fn new(opt: &Opt, sender: mpsc::Sender<LocalFrame>) -> Result<Self> {
+        let device = Self::find_device(opt.local_device.as_deref())?;
+        info!(
+            "Local analysis with {}",
+            device.name().unwrap_or_else(|_| "unknown device".into())
+        );
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = std::thread::Builder::new()
+            .name("Local Analysis Thread".into())
+            .spawn(move || {
+                let _analysis = info_span!("local_analysis").entered();
+                Self::run(device, sender, thread_stop)
+            })?;
+
+        Ok(Self { thread, stop })
+    }
+
+    fn find_device(hint: Option<&str>) -> Result<Device> {
+        let host = cpal::default_host();
+        match hint {
+            Some(hint) => host
+                .input_devices()?
+                .find(|device| device.name().map(|n| n.contains(hint)).unwrap_or(false))
+                .ok_or(anyhow!("Can't find a device satisfying the hint")),
+            None => host
+                .default_input_device()
+                .ok_or(anyhow!("No default device found")),
+        }
+    }
+
+    fn run(device: Device, sender: mpsc::Sender<LocalFrame>, stop: Arc<AtomicBool>) -> Result<()> {
+        let mut processor = AudioProcessor::<f64>::default();
+        let sample_size = processor.sample_size();
+
+        let (prod, mut cons) = RingBuffer::<f64>::new(sample_size * 4).split();
+        // The stream isn't `Send`, it lives here
+        let stream = Self::open_stream(&device, prod)?;
+        stream.play()?;
+
+        while !stop.load(Ordering::Relaxed) {
+            if !Self::process_next(&mut processor, &mut cons) {
+                std::thread::sleep(IDLE_DELAY);
+                continue;
+            }
+
+            let peak = processor.novelty_peak_short_term();
+            let novelty = if peak > 0.0 {
+                processor.novelty() / peak
+            } else {
+                0.0
+            };
+            let frame = LocalFrame {
+                novelty,
+                is_onset: processor.is_onset(),
+                pitch_class: processor.dominant_pitch_class(),
+                balance: processor.balance(),
+                at: Instant::now(),
+            };
+            if sender.blocking_send(frame).is_err() {
+                // Nobody to analyse for
+                break;
+            }
+        }
+
+        stream.pause()?;
+        Ok(())
+    }
+
+    /// Returns false if there isn't enough samples yet.
+    fn process_next(processor: &mut AudioProcessor, consumer: &mut Consumer<f64>) -> bool {
+        // 2 channels
+        if consumer.len() < processor.sample_size() * 2 {
+            return false;
+        }
+
+        consumer.pop_slice(processor.input());
+        processor.process();
+        true
+    }
+
+    fn open_stream(device: &Device, prod: Producer<f64>) -> Result<Stream> {
+        let config = device.default_input_config()?;
+        if config.sample_rate().0 != 44100 {
+            warn!(
+                "Sample rate of {}Hz instead of 44100Hz, the analysis may be off",
+                config.sample_rate().0
+            );
+        }
+
+        let format = config.sample_format();
+        let config: StreamConfig = config.into();
+        Ok(match format {
+            SampleFormat::I16 => Self::build_stream::<i16>(device, &config, prod)?,
+            SampleFormat::U16 => Self::build_stream::<u16>(device, &config, prod)?,
+            SampleFormat::F32 => Self::build_stream::<f32>(device, &config, prod)?,
+        })
+    }
+
+    /// USB microphones are often mono, the processor expects interleaved stereo.
+    fn build_stream<T: Sample>(
+        device: &Device, config: &StreamConfig, mut prod: Producer<f64>,
+    ) -> Result<Stream> {
+        let channels = config.channels as usize;
+        Ok(device.build_input_stream(
+            config,
+            move |data: &[T], _| {
+                for frame in data.chunks_exact(channels) {
+                    let left = frame[0].to_f32() as f64;
+                    let right = frame.get(1).map_or(left, |right| right.to_f32() as f64);
+                    prod.push_iter(&mut [left, right].iter().copied());
+                }
+            },
+            |err| warn!("CPAL Error: {:?}", err),
+        )?)
+    }
+
+    pub fn stop(self) -> Result<()> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.thread
+            .join()
+            .map_err(|_| anyhow!("Local analysis thread panicked"))?
+    }
+}
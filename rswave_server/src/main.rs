@@ -1,55 +1,275 @@
-use log::{debug, info};
+use anyhow::anyhow;
+#[cfg(feature = "button")]
+use rswave_server::button::Button;
+#[cfg(feature = "controller_adalight")]
+use rswave_server::led_controllers::ControllerAdalight;
 #[cfg(feature = "controller_gpio")]
 use rswave_server::led_controllers::ControllerGpio;
+#[cfg(feature = "controller_hyperion")]
+use rswave_server::led_controllers::ControllerHyperion;
 #[cfg(feature = "controller_ws2811")]
 use rswave_server::led_controllers::ControllerWs2811;
-use rswave_server::{app::App, led_controllers::LedController, LedStripType, Opt};
+use rswave_server::{
+    app::{App, Control},
+    led_controllers::{
+        CompositeOutput, ControllerComposite, ControllerDithering, ControllerDryRun, LedController,
+    },
+    LedStripType, Opt,
+};
 use structopt::StructOpt;
+use tracing::{debug, error, info};
 
 fn main() -> anyhow::Result<()> {
-    env_logger::init();
-    info!("Starting...");
-
     // Parse cmdline
     let opt: Opt = Opt::from_args();
 
+    // Forking must happen before the runtime spawns its threads
+    if opt.daemonize {
+        #[cfg(feature = "daemon")]
+        rswave_server::daemon::daemonize(&opt)?;
+        #[cfg(not(feature = "daemon"))]
+        return Err(anyhow!("Daemon mode is not supported by this build !"));
+    } else {
+        rswave_common::logging::init(opt.log_format, None);
+    }
+    info!("Starting...");
+
+    let res = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(select_controller(opt.clone()));
+
+    #[cfg(feature = "daemon")]
+    if opt.daemonize {
+        rswave_server::daemon::cleanup(&opt);
+    }
+    res
+}
+
+async fn select_controller(opt: Opt) -> anyhow::Result<()> {
+    if opt.dry_run {
+        info!("Dry run, the LEDs won't be touched");
+        let controller = ControllerDryRun::new(
+            opt.led_count.unwrap_or(1),
+            opt.brightness,
+            opt.dry_run_output.clone(),
+        )?;
+        return run_app(opt, controller).await;
+    }
+
+    if !opt.composite.is_empty() {
+        info!("Choosed a composite of {:?}", opt.composite);
+        let mut outputs = Vec::new();
+        for (i, part) in opt.composite.iter().enumerate() {
+            let led_type = part.led_type;
+            // They would fight over the same pins
+            if opt.composite[..i]
+                .iter()
+                .any(|other| other.led_type == led_type)
+            {
+                return Err(anyhow!("{:?} can only be used once !", led_type));
+            }
+            let led_count = part.led_count.or(opt.led_count);
+
+            outputs.push(match led_type {
+                #[cfg(feature = "controller_ws2811")]
+                LedStripType::Ws2811 => CompositeOutput::Ws2811(ControllerWs2811::new(
+                    led_count.ok_or_else(|| anyhow!("The WS2811 strip needs a LED count !"))?,
+                    opt.brightness,
+                )?),
+                #[cfg(feature = "controller_gpio")]
+                LedStripType::Gpio => CompositeOutput::Gpio(ControllerGpio::new(
+                    opt.pwm_freq,
+                    opt.pin_red,
+                    opt.pin_green,
+                    opt.pin_blue,
+                )?),
+                #[cfg(feature = "controller_adalight")]
+                LedStripType::Adalight => CompositeOutput::Adalight(ControllerAdalight::new(
+                    &opt.serial_port,
+                    opt.baud_rate,
+                    led_count.ok_or_else(|| anyhow!("The Adalight strip needs a LED count !"))?,
+                    opt.brightness,
+                )?),
+                #[allow(unreachable_patterns)]
+                _ => {
+                    return Err(anyhow!(
+                        "LED type {:?} can't be part of a composite in this build !",
+                        led_type
+                    ))
+                }
+            });
+        }
+        return run_app(opt, ControllerComposite::new(outputs)).await;
+    }
+
     match opt.led_type {
         LedStripType::Ws2811 => {
             info!("Choosed led type WS2811");
             #[cfg(not(feature = "controller_ws2811"))]
-            eprintln!("LED type WS2811 is not supported by this build !");
+            error!("LED type WS2811 is not supported by this build !");
             #[cfg(feature = "controller_ws2811")]
-            run_app(
-                opt,
-                ControllerWs2811::new(opt.led_count.unwrap(), opt.brightness)?,
-            )?;
+            {
+                let controller = ControllerWs2811::new(opt.led_count.unwrap(), opt.brightness)?;
+                run_app(opt, controller).await?;
+            }
         }
         LedStripType::Gpio => {
             info!("Choosed led type GPIO");
             #[cfg(not(feature = "controller_gpio"))]
-            eprintln!("LED type GPIO is not supported by this build !");
+            error!("LED type GPIO is not supported by this build !");
             #[cfg(feature = "controller_gpio")]
-            run_app(
-                opt,
-                ControllerGpio::new(opt.pwm_freq, opt.pin_red, opt.pin_green, opt.pin_blue)?,
-            )?;
+            {
+                let controller =
+                    ControllerGpio::new(opt.pwm_freq, opt.pin_red, opt.pin_green, opt.pin_blue)?;
+                run_app(opt, controller).await?;
+            }
+        }
+        LedStripType::Adalight => {
+            info!("Choosed led type Adalight");
+            #[cfg(not(feature = "controller_adalight"))]
+            error!("LED type Adalight is not supported by this build !");
+            #[cfg(feature = "controller_adalight")]
+            {
+                let controller = ControllerAdalight::new(
+                    &opt.serial_port,
+                    opt.baud_rate,
+                    opt.led_count.unwrap(),
+                    opt.brightness,
+                )?;
+                run_app(opt, controller).await?;
+            }
+        }
+        LedStripType::Hyperion => {
+            info!("Choosed led type Hyperion");
+            #[cfg(not(feature = "controller_hyperion"))]
+            error!("LED type Hyperion is not supported by this build !");
+            #[cfg(feature = "controller_hyperion")]
+            {
+                let controller = ControllerHyperion::new(
+                    opt.hyperion_address.clone(),
+                    opt.hyperion_priority,
+                    opt.led_count.unwrap_or(1),
+                    opt.brightness,
+                )?;
+                run_app(opt, controller).await?;
+            }
         }
     }
 
     Ok(())
 }
 
-fn run_app<C: LedController + Send + 'static>(opt: Opt, mut controller: C) -> anyhow::Result<()> {
+async fn run_app<C: LedController + Send + 'static>(
+    opt: Opt, mut controller: C,
+) -> anyhow::Result<()> {
     if opt.reset {
         debug!("Reset and exit");
         controller.reset()?;
         return Ok(());
     }
 
-    let mut app = App::new(opt, controller)?;
-    loop {
-        app.run()?;
-        // TODO: listen for key inputs
+    if opt.test_pattern {
+        debug!("Test pattern and exit");
+        return rswave_server::test_pattern::run(&mut controller).await;
+    }
+
+    if !opt.no_self_test {
+        rswave_server::test_pattern::self_test(&mut controller).await?;
+    }
+
+    if opt.dithering {
+        debug!("Dithering enabled");
+        let brightness = opt.brightness;
+        start_app(opt, ControllerDithering::new(controller, brightness)).await
+    } else {
+        start_app(opt, controller).await
+    }
+}
+
+async fn start_app<C: LedController + Send + 'static>(
+    opt: Opt, controller: C,
+) -> anyhow::Result<()> {
+    // Not --led-count, a composite adds up its strips
+    #[cfg(any(feature = "wled", feature = "grpc"))]
+    let led_count = controller.led_amount();
+    let mut app = App::new(opt.clone(), controller)?;
+
+    #[cfg(feature = "button")]
+    let _button = opt
+        .button_pin
+        .map(|pin| Button::new(pin, opt.button_action, app.control()))
+        .transpose()?;
+
+    #[cfg(feature = "wled")]
+    let _wled = match opt.wled_port {
+        Some(port) => Some(
+            rswave_server::wled::WledApi::new(
+                port,
+                led_count,
+                rswave_server::app::runner_count(&opt),
+                opt.brightness,
+                app.status(),
+                app.control(),
+            )
+            .await?,
+        ),
+        None => None,
+    };
+
+    #[cfg(feature = "grpc")]
+    let _grpc = match opt.grpc_port {
+        Some(port) => Some(
+            rswave_server::grpc::GrpcApi::new(
+                port,
+                led_count,
+                opt.zone.clone(),
+                app.status(),
+                app.presets(),
+                app.control(),
+            )
+            .await?,
+        ),
+        None => None,
+    };
+
+    #[cfg(feature = "dbus_api")]
+    let _dbus = opt
+        .dbus
+        .map(|bus| {
+            rswave_server::dbus::DbusApi::new(bus, opt.brightness, app.status(), app.control())
+        })
+        .transpose()?;
+
+    let control = app.control();
+    tokio::spawn(async move {
+        match wait_for_termination().await {
+            Ok(()) => info!("Termination requested, shutting down..."),
+            Err(err) => error!("Failed to wait for termination signals: {}", err),
+        }
+        // The app is already gone otherwise
+        let _ = control.send(Control::Stop).await;
+    });
+
+    // TODO: listen for key inputs
+    let res = app.run().await;
+    app.stop().await?;
+    res
+}
+
+/// Completes on Ctrl+C, or when the service manager asks us to stop.
+async fn wait_for_termination() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            res = tokio::signal::ctrl_c() => res,
+            _ = terminate.recv() => Ok(()),
+        }
     }
-    app.stop()
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
 }
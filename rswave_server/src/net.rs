@@ -1,201 +1,724 @@
+use crate::{clock::RemoteClock, runners::TrackFeatures};
 use anyhow::{anyhow, Result};
-use log::{debug, error, info};
+use cichlid::ColorRGB;
 use rswave_common::{
+    auth::Psk,
+    codec::PacketCodec,
     packets::{
-        AckPacket, DataMode, HelloPacket, NoveltyBeatsModePacket, NoveltyModePacket, SetModePacket,
+        AbortReason, AckPacket, BandsModePacket, BeatsOnlyModePacket, Command, DataMode,
+        HelloPacket, NoveltyBeatsModePacket, NoveltyModeData, NoveltyModePacket, PaletteData,
+        SetModePacket, BARK_BANDS, MAX_PALETTE_COLORS,
     },
     rkyv::{
-        archived_value, check_archive,
-        de::deserializers::AllocDeserializer,
-        ser::{serializers::WriteSerializer, Serializer},
-        Aligned, Deserialize, Serialize,
+        de::deserializers::AllocDeserializer, ser::serializers::WriteSerializer, Archived,
+        Deserialize, Serialize,
     },
-    MAGIC,
+    sequence::SequenceTracker,
+    transport::{Transport, TransportKind, TransportSocket},
+    MAGIC, PEER_TIMEOUT, PROTOCOL_VERSION,
 };
 use std::{
-    io::ErrorKind,
-    net::{SocketAddr, UdpSocket},
-    time::Duration,
+    collections::VecDeque,
+    io::{self, ErrorKind},
+    net::SocketAddr,
+    str::FromStr,
+    time::{Duration, Instant},
 };
+use tracing::{debug, debug_span, error, info, instrument, warn};
+
+/// Log the packet statistics every this many data packets.
+const STATS_LOG_INTERVAL: u64 = 1000;
+/// Amount of hello nonces remembered to detect replays.
+const NONCE_HISTORY: usize = 64;
+/// Maximum time blocked in a wait, so the caller gets the control back regularly.
+const MAX_WAIT: Duration = Duration::from_millis(100);
+/// The sockets can't be woken up from another thread, so a wait checks this often if it
+/// should give up.
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+/// Hellos answered per second at most, checking them is expensive with a PSK.
+const MAX_HELLOS_PER_SECOND: usize = 10;
 
 #[derive(Debug)]
 pub enum RemoteData {
-    Analysis { novelty: f64, is_beat: bool },
-    Goodbye { force: bool },
+    /// A new remote is now the one driving the LEDs.
+    Connected,
+    Analysis {
+        novelty: f64,
+        is_beat: bool,
+        is_onset: bool,
+        pitch_class: Option<u8>,
+        /// From -1 on the left to 1 on the right.
+        balance: f64,
+        loudness: Option<f64>,
+        /// When to show it, as soon as possible if `None`.
+        at: Option<Instant>,
+        /// Novelty of the zones with these names, the others get `novelty`.
+        zones: Vec<(String, f64)>,
+        /// Energy of the Bark bands between 0 and 1, only in the bands mode.
+        bands: Option<[f32; BARK_BANDS]>,
+    },
+    Palette(Vec<ColorRGB>),
+    Features(TrackFeatures),
+    /// In the beats only mode, nothing is sent between the beats.
+    Beat {
+        /// Beats per minute.
+        tempo: Option<f64>,
+        at: Option<Instant>,
+    },
+    Heartbeat,
+    /// An order given from the remote.
+    Command(Command),
+    /// The audio of the remote went silent or came back.
+    Standby(bool),
+    /// Nothing was received for too long, the remote is considered gone.
+    Timeout,
+    Goodbye {
+        force: bool,
+    },
 }
 
-pub struct NetHandler {
-    socket: UdpSocket,
-    current_peer: Option<SocketAddr>,
-    mode: DataMode,
-    serialize_scratch: Option<Vec<u8>>,
-    deserialize_scratch: Aligned<[u8; 128]>,
+/// Which remote drives the LEDs when several are connected.
+#[derive(Copy, Clone, Debug)]
+pub enum PeerPolicy {
+    /// The first remote to connect keeps control until it leaves.
+    First,
+    /// A newly connected remote takes control right away.
+    Latest,
+    /// Only one remote at a time, the others are refused until it leaves.
+    Exclusive,
+}
+
+impl FromStr for PeerPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "first" => Ok(Self::First),
+            "latest" => Ok(Self::Latest),
+            "exclusive" => Ok(Self::Exclusive),
+            _ => Err(anyhow!("Unknown peer policy !")),
+        }
+    }
+}
+
+struct Peer {
+    address: SocketAddr,
+    /// Unknown until the handshake is over.
+    mode: Option<DataMode>,
+    sequence: SequenceTracker,
+    clock: RemoteClock,
+    connected_at: Instant,
+    last_seen: Instant,
+}
+
+impl Peer {
+    fn log_stats(&self) {
+        info!(
+            "Packets received from {}: {}, lost: {} ({:.2}%), out of order: {}",
+            self.address,
+            self.sequence.received(),
+            self.sequence.lost(),
+            self.sequence.loss_ratio() * 100.0,
+            self.sequence.out_of_order()
+        );
+    }
+}
+
+pub struct NetHandler<T: Transport = TransportSocket> {
+    socket: T,
+    peers: Vec<Peer>,
+    /// The peer whose data is forwarded.
+    active_peer: Option<SocketAddr>,
+    policy: PeerPolicy,
+    codec: PacketCodec,
     is_stopped: bool,
+    psk: Option<Psk>,
+    seen_nonces: VecDeque<u64>,
+    /// When the last hellos were received, to limit them.
+    recent_hellos: VecDeque<Instant>,
+    led_update_period_ms: u32,
 }
 
 impl NetHandler {
-    pub fn new(port: u16) -> Result<Self> {
-        let socket = UdpSocket::bind(SocketAddr::new([0, 0, 0, 0].into(), port))?;
-        socket.set_nonblocking(false)?;
+    /// With a `psk`, only remotes that sign their hello with the same key are accepted.
+    /// The remotes are told to send at most once per `led_update_period_ms`.
+    pub fn new(
+        port: u16, transport: TransportKind, policy: PeerPolicy, psk: Option<Psk>,
+        led_update_period_ms: u32,
+    ) -> Result<Self> {
+        let socket = TransportSocket::server(transport, port)?;
+        Self::with_transport(socket, policy, psk, led_update_period_ms)
+    }
+}
+
+impl<T: Transport> NetHandler<T> {
+    /// Same as [NetHandler::new] but with an already listening transport.
+    pub fn with_transport(
+        mut socket: T, policy: PeerPolicy, psk: Option<Psk>, led_update_period_ms: u32,
+    ) -> Result<Self> {
+        socket.set_nonblocking(true)?;
 
         Ok(Self {
             socket,
-            current_peer: None,
-            mode: DataMode::Novelty,
-            serialize_scratch: None,
-            deserialize_scratch: Aligned([0; 128]),
+            peers: Vec::new(),
+            active_peer: None,
+            policy,
+            codec: PacketCodec::default(),
             is_stopped: false,
+            psk,
+            seen_nonces: VecDeque::with_capacity(NONCE_HISTORY),
+            recent_hellos: VecDeque::with_capacity(MAX_HELLOS_PER_SECOND),
+            led_update_period_ms,
         })
     }
 
     pub fn is_connected(&self) -> bool {
-        self.current_peer.is_some()
+        self.active_peer.is_some()
+    }
+
+    /// Check the signature of the hello and that it isn't a replay of an old one.
+    fn authenticate(&mut self, hello: &HelloPacket) -> bool {
+        let psk = match self.psk.as_ref() {
+            Some(psk) => psk,
+            None => return true,
+        };
+
+        if !hello.verify(psk) || self.seen_nonces.contains(&hello.nonce) {
+            return false;
+        }
+
+        if self.seen_nonces.len() >= NONCE_HISTORY {
+            self.seen_nonces.pop_front();
+        }
+        self.seen_nonces.push_back(hello.nonce);
+        true
     }
 
-    pub fn wait_for_remote_blocking(&mut self) -> Result<()> {
-        if self.current_peer.is_some() {
-            debug!("Already connected, skip");
+    /// First step of the handshake, answer the hello of an unknown peer.
+    #[instrument(level = "debug", name = "handshake", skip(self))]
+    fn accept(&mut self, address: SocketAddr) -> Result<()> {
+        let now = Instant::now();
+        let hello = self
+            .codec
+            .check::<HelloPacket>()
+            .ok()
+            .and_then(|hello| hello.deserialize(&mut AllocDeserializer).ok());
+
+        let mut hello = match hello {
+            Some(hello) => hello,
+            None => {
+                // Mostly the late packets of a peer that timed out, it was already logged
+                debug!("Ignored invalid hello from {}", address);
+                return Ok(());
+            }
+        };
+
+        // Garbage doesn't count, it would lock the real remotes out
+        while let Some(&received) = self.recent_hellos.front() {
+            if now.duration_since(received) < Duration::from_secs(1) {
+                break;
+            }
+            self.recent_hellos.pop_front();
+        }
+        if self.recent_hellos.len() >= MAX_HELLOS_PER_SECOND {
+            warn!("Too many hellos, refused {}", address);
+            return self.refuse(address, AbortReason::RateLimited);
+        }
+        self.recent_hellos.push_back(now);
+
+        if hello.version != PROTOCOL_VERSION {
+            warn!(
+                "Refused {}, protocol version {} instead of {}",
+                address, hello.version, PROTOCOL_VERSION
+            );
+            return self.refuse(address, AbortReason::BadVersion);
+        }
+
+        if !self.authenticate(&hello) {
+            warn!("Rejected unauthenticated hello from {}", address);
             return Ok(());
         }
 
-        self.socket.set_nonblocking(true)?;
-        let res = loop {
-            match self.socket.recv_from(self.deserialize_scratch.as_mut()) {
-                Ok((_, peer)) => {
-                    self.current_peer = Some(peer);
-                    self.socket.connect(peer)?;
-                    break Ok(());
-                }
-                Err(err) if err.kind() == ErrorKind::WouldBlock => {}
-                Err(err) => break Err(anyhow!(err)),
+        if let PeerPolicy::Exclusive = self.policy {
+            if !self.peers.is_empty() {
+                warn!("Refused {}, another remote is connected", address);
+                return self.refuse(address, AbortReason::Busy);
             }
+        }
 
-            // Wait for a bit and retry
-            std::thread::sleep(Duration::from_millis(500));
-        };
-        self.socket.set_nonblocking(false)?;
+        debug!("Starting handshake with {}...", address);
+        hello.led_update_period_ms = self.led_update_period_ms;
+        self.serialize_send(&hello, address, true)?;
 
-        info!("New peer: {}", self.current_peer.as_ref().unwrap());
+        self.peers.push(Peer {
+            address,
+            mode: None,
+            sequence: SequenceTracker::default(),
+            clock: RemoteClock::new(hello.sync_delay_ms),
+            connected_at: now,
+            last_seen: now,
+        });
+        Ok(())
+    }
 
-        res
+    /// Whether the last packet is a hello, the other packets are never that small and start
+    /// with the tag of their variant instead of the magic.
+    fn is_hello(&self) -> bool {
+        self.codec.received_len() == std::mem::size_of::<Archived<HelloPacket>>()
+            && self
+                .codec
+                .check::<HelloPacket>()
+                .map_or(false, |hello| hello.magic == MAGIC)
     }
 
-    pub fn handshake(&mut self) -> Result<()> {
-        // Hello has already been recv when waiting for a remote.
+    /// Answer a hello with the reason why the remote can't connect.
+    fn refuse(&mut self, address: SocketAddr, reason: AbortReason) -> Result<()> {
+        self.serialize_send(&AckPacket::Abort(reason), address, true)
+    }
 
-        debug!("Starting handshake...");
+    /// Last step of the handshake, the peer can now be elected.
+    fn set_mode(&mut self, index: usize) -> Result<()> {
+        let mode = self
+            .codec
+            .check::<SetModePacket>()
+            .map_err(|err| anyhow!("Check archive failed, unknown mode ?: {}", err))?;
+        let mode: SetModePacket = mode.deserialize(&mut AllocDeserializer)?;
 
-        // Hello
-        let hello = unsafe { archived_value::<HelloPacket>(self.deserialize_scratch.as_ref(), 0) };
-        let hello = hello.deserialize(&mut AllocDeserializer).unwrap();
-        self.serialize_send(&hello)?;
+        let peer = &mut self.peers[index];
+        debug!("Mode of {}: {:?}", peer.address, mode);
+        peer.mode = Some(mode.mode);
+        info!("New peer: {}", peer.address);
 
-        // SetMode
-        self.socket.recv(self.deserialize_scratch.as_mut())?;
-        let mode = unsafe { archived_value::<SetModePacket>(self.deserialize_scratch.as_ref(), 0) };
-        let mode: SetModePacket = mode.deserialize(&mut AllocDeserializer).unwrap();
-        debug!("Mode: {:?}", mode);
-        self.mode = mode.mode;
+        let address = peer.address;
+        self.serialize_send(&AckPacket::Control(mode.sequence), address, true)
+    }
 
-        debug!("Handshake successful");
+    /// The sequence of the packet if it sets the mode the peer already has.
+    fn repeated_mode(&self, mode: DataMode) -> Option<u32> {
+        let packet: SetModePacket = self
+            .codec
+            .check::<SetModePacket>()
+            .ok()?
+            .deserialize(&mut AllocDeserializer)
+            .ok()?;
+        Some(packet.sequence).filter(|_| packet.mode == mode)
+    }
 
-        Ok(())
+    fn remove_peer(&mut self, index: usize) {
+        let peer = self.peers.remove(index);
+        peer.log_stats();
+        self.socket.forget(peer.address);
     }
 
-    fn serialize_send(&mut self, item: &impl Serialize<WriteSerializer<Vec<u8>>>) -> Result<()> {
-        if let Some(scratch) = &mut self.serialize_scratch {
-            scratch.clear();
-        } else {
-            self.serialize_scratch = Some(Vec::new());
+    /// Choose the active peer according to the policy.
+    /// Returns the event to report if it changed, `lost` being the reason if there is none left.
+    fn elect(&mut self, lost: RemoteData) -> Option<RemoteData> {
+        let ready = self.peers.iter().filter(|peer| peer.mode.is_some());
+        let elected = match self.policy {
+            PeerPolicy::First | PeerPolicy::Exclusive => ready.min_by_key(|peer| peer.connected_at),
+            PeerPolicy::Latest => ready.max_by_key(|peer| peer.connected_at),
         }
+        .map(|peer| peer.address);
 
-        let mut serializer = WriteSerializer::new(self.serialize_scratch.take().unwrap());
-        serializer.serialize_value(item)?;
+        if elected == self.active_peer {
+            return None;
+        }
 
-        let buff = serializer.into_inner();
-        self.socket.send(&buff)?;
+        self.active_peer = elected;
+        match elected {
+            Some(address) => {
+                info!("Active peer: {}", address);
+                Some(RemoteData::Connected)
+            }
+            None => Some(lost),
+        }
+    }
 
-        self.serialize_scratch.replace(buff);
+    /// Control packets should be `reliable`, when the transport supports it.
+    fn serialize_send(
+        &mut self, item: &impl Serialize<WriteSerializer<Vec<u8>>>, peer: SocketAddr,
+        reliable: bool,
+    ) -> Result<()> {
+        let datagram = self.codec.encode(item)?;
+        self.socket.send_to(datagram, peer, reliable)?;
         Ok(())
     }
 
-    pub fn recv(&mut self) -> Result<RemoteData> {
-        let len = self.socket.recv(self.deserialize_scratch.as_mut())?;
+    /// Receive datagrams until one is intact, corrupted ones are simply dropped.
+    /// Returns the sender of the packet, left in the codec.
+    fn recv_checked(&mut self) -> io::Result<SocketAddr> {
+        loop {
+            match self.codec.recv_from(&mut self.socket)? {
+                (address, true) => return Ok(address),
+                (address, false) => warn!("Dropped a corrupted datagram from {}", address),
+            }
+        }
+    }
+
+    /// Forget the peers we didn't hear from for too long.
+    fn check_timeouts(&mut self) -> Option<RemoteData> {
+        let mut index = 0;
+        while index < self.peers.len() {
+            if self.peers[index].last_seen.elapsed() >= PEER_TIMEOUT {
+                error!(
+                    "No news from {} for {:?}, disconnect",
+                    self.peers[index].address, PEER_TIMEOUT
+                );
+                self.remove_peer(index);
+            } else {
+                index += 1;
+            }
+        }
+
+        self.elect(RemoteData::Timeout)
+    }
+
+    /// Block until there may be something to [poll], a packet or a peer timing out,
+    /// but no longer than a fraction of a second. Returns early once `stopped` says so.
+    pub fn wait(&mut self, mut stopped: impl FnMut() -> bool) -> Result<()> {
+        let now = Instant::now();
+        let timeout = self
+            .peers
+            .iter()
+            .map(|peer| (peer.last_seen + PEER_TIMEOUT).saturating_duration_since(now))
+            .min()
+            .map_or(MAX_WAIT, |timeout| timeout.min(MAX_WAIT));
+        let deadline = now + timeout;
 
-        let res = match self.mode {
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left == Duration::from_millis(0) || stopped() {
+                return Ok(());
+            }
+            if self.socket.wait_readable(left.min(STOP_CHECK_INTERVAL))? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Process everything that was received without blocking, until the active peer
+    /// has something to say. Handshakes and packets of the other peers are handled
+    /// in the meantime. Returns nothing once there is nothing left to read.
+    pub fn poll(&mut self) -> Result<Option<RemoteData>> {
+        loop {
+            if let Some(event) = self.check_timeouts() {
+                return Ok(Some(event));
+            }
+
+            let address = match self.recv_checked() {
+                Ok(address) => address,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(None),
+                Err(err) => return Err(anyhow!(err)),
+            };
+            let _packet =
+                debug_span!("packet", peer = %address, len = self.codec.received_len()).entered();
+
+            let index = match self.peers.iter().position(|peer| peer.address == address) {
+                Some(index) => index,
+                None => {
+                    self.accept(address)?;
+                    continue;
+                }
+            };
+            if self.is_hello() {
+                // The remote restarted, the new connection replaces the old one if accepted
+                let old = self.peers.remove(index);
+                self.accept(address)?;
+                if self.peers.iter().any(|peer| peer.address == address) {
+                    info!("{} connected again", address);
+                    old.log_stats();
+                } else {
+                    self.peers.insert(index, old);
+                }
+
+                match self.elect(RemoteData::Goodbye { force: false }) {
+                    Some(event) => return Ok(Some(event)),
+                    None => continue,
+                }
+            }
+            self.peers[index].last_seen = Instant::now();
+
+            let mode = match self.peers[index].mode {
+                Some(mode) => mode,
+                None => {
+                    if let Err(err) = self.set_mode(index) {
+                        error!("Handshake with {} failed: {}", address, err);
+                        self.refuse(address, AbortReason::UnsupportedMode)?;
+                        self.remove_peer(index);
+                    }
+
+                    match self.elect(RemoteData::Goodbye { force: true }) {
+                        Some(event) => return Ok(Some(event)),
+                        None => continue,
+                    }
+                }
+            };
+
+            let (ack, data) = match Self::parse(&self.codec, mode, &mut self.peers[index].clock) {
+                Ok(parsed) => parsed,
+                Err(err) => {
+                    if let Some(sequence) = self.repeated_mode(mode) {
+                        // Our ACK of the mode was lost, the remote is fine
+                        debug!("Mode of {} received again", address);
+                        self.serialize_send(&AckPacket::Control(sequence), address, true)?;
+                        continue;
+                    }
+
+                    error!("Send ACK Abort to {}: {}", address, err);
+                    let packet = AckPacket::Abort(AbortReason::InvalidPacket);
+                    self.serialize_send(&packet, address, true)?;
+                    self.remove_peer(index);
+
+                    match self.elect(RemoteData::Goodbye { force: true }) {
+                        Some(event) => return Ok(Some(event)),
+                        None => continue,
+                    }
+                }
+            };
+
+            match ack {
+                Some(AckPacket::Ok(sequence)) => {
+                    let peer = &mut self.peers[index];
+                    peer.sequence.record(sequence);
+                    if peer.sequence.received() % STATS_LOG_INTERVAL == 0 {
+                        peer.log_stats();
+                    }
+
+                    // The data is sent continuously anyway
+                    self.serialize_send(&AckPacket::Ok(sequence), address, false)?;
+                }
+                Some(ack) => self.serialize_send(&ack, address, true)?,
+                None => {}
+            }
+
+            if let RemoteData::Goodbye { .. } = data {
+                let packet = AckPacket::Quit;
+                self.serialize_send(&packet, address, true)?;
+                self.remove_peer(index);
+
+                match self.elect(data) {
+                    Some(event) => return Ok(Some(event)),
+                    None => continue,
+                }
+            }
+
+            if self.active_peer == Some(address) {
+                return Ok(Some(data));
+            }
+        }
+    }
+
+    /// Returns the data and the ACK to answer with, only data and control packets have one.
+    fn parse(
+        codec: &PacketCodec, mode: DataMode, clock: &mut RemoteClock,
+    ) -> Result<(Option<AckPacket>, RemoteData)> {
+        match mode {
             DataMode::Novelty => {
-                let packet = check_archive::<NoveltyModePacket>(
-                    &self.deserialize_scratch.as_ref()[..len],
-                    0,
-                )
-                .map_err(|err| anyhow!("Check archive failed: {}", err))?;
-                let packet: NoveltyModePacket = packet.deserialize(&mut AllocDeserializer)?;
+                let packet = codec
+                    .check::<NoveltyModePacket>()
+                    .map_err(|err| anyhow!("Check archive failed: {}", err))?;
 
                 match packet {
-                    NoveltyModePacket::Data(data) => Ok(RemoteData::Analysis {
-                        novelty: data.value / data.peak,
-                        is_beat: false,
-                    }),
-                    NoveltyModePacket::Goodbye(goodbye) if goodbye.magic == MAGIC => {
-                        Ok(RemoteData::Goodbye {
+                    Archived::<NoveltyModePacket>::Data(data) => Ok((
+                        Some(AckPacket::Ok(data.sequence)),
+                        RemoteData::Analysis {
+                            novelty: data.normalized_novelty(),
+                            is_beat: false,
+                            is_onset: data.onset,
+                            pitch_class: data.pitch_class(),
+                            balance: data.balance(),
+                            loudness: None,
+                            at: clock.schedule(data.timestamp_us),
+                            zones: Self::zones(data),
+                            bands: None,
+                        },
+                    )),
+                    Archived::<NoveltyModePacket>::Palette(palette) => Ok(Self::palette(palette)),
+                    Archived::<NoveltyModePacket>::Heartbeat => Ok((None, RemoteData::Heartbeat)),
+                    Archived::<NoveltyModePacket>::Standby(standby) => Ok((
+                        Some(AckPacket::Control(standby.sequence)),
+                        RemoteData::Standby(standby.silent),
+                    )),
+                    Archived::<NoveltyModePacket>::Command(command) => Ok((
+                        Some(AckPacket::Control(command.sequence)),
+                        RemoteData::Command((&command.command).into()),
+                    )),
+                    Archived::<NoveltyModePacket>::Goodbye(goodbye) if goodbye.is_valid() => Ok((
+                        None,
+                        RemoteData::Goodbye {
                             force: goodbye.force,
-                        })
-                    }
+                        },
+                    )),
                     _ => Err(anyhow!("Abort !")),
                 }
             }
             DataMode::NoveltyBeats => {
-                // TODO: don't deserialize, use the archive
+                let packet = codec
+                    .check::<NoveltyBeatsModePacket>()
+                    .map_err(|err| anyhow!("Check archive failed: {}", err))?;
 
-                let packet = check_archive::<NoveltyBeatsModePacket>(
-                    &self.deserialize_scratch.as_ref()[..len],
-                    0,
-                )
-                .map_err(|err| anyhow!("Check archive failed: {}", err))?;
-                let packet: NoveltyBeatsModePacket = packet.deserialize(&mut AllocDeserializer)?;
+                match packet {
+                    Archived::<NoveltyBeatsModePacket>::Data(data) => Ok((
+                        Some(AckPacket::Ok(data.novelty.sequence)),
+                        RemoteData::Analysis {
+                            novelty: data.novelty.normalized_novelty(),
+                            is_beat: data.beat,
+                            is_onset: data.novelty.onset,
+                            pitch_class: data.novelty.pitch_class(),
+                            balance: data.novelty.balance(),
+                            loudness: Some(data.loudness),
+                            at: clock.schedule(data.novelty.timestamp_us),
+                            zones: Self::zones(&data.novelty),
+                            bands: None,
+                        },
+                    )),
+                    Archived::<NoveltyBeatsModePacket>::Palette(palette) => {
+                        Ok(Self::palette(palette))
+                    }
+                    Archived::<NoveltyBeatsModePacket>::Features(features) => Ok((
+                        Some(AckPacket::Control(features.sequence)),
+                        RemoteData::Features(TrackFeatures {
+                            danceability: features.danceability,
+                            energy: features.energy,
+                        }),
+                    )),
+                    Archived::<NoveltyBeatsModePacket>::Heartbeat => {
+                        Ok((None, RemoteData::Heartbeat))
+                    }
+                    Archived::<NoveltyBeatsModePacket>::Standby(standby) => Ok((
+                        Some(AckPacket::Control(standby.sequence)),
+                        RemoteData::Standby(standby.silent),
+                    )),
+                    Archived::<NoveltyBeatsModePacket>::Command(command) => Ok((
+                        Some(AckPacket::Control(command.sequence)),
+                        RemoteData::Command((&command.command).into()),
+                    )),
+                    Archived::<NoveltyBeatsModePacket>::Goodbye(goodbye) if goodbye.is_valid() => {
+                        Ok((
+                            None,
+                            RemoteData::Goodbye {
+                                force: goodbye.force,
+                            },
+                        ))
+                    }
+                    _ => Err(anyhow!("Abort !")),
+                }
+            }
+            DataMode::BeatsOnly => {
+                let packet = codec
+                    .check::<BeatsOnlyModePacket>()
+                    .map_err(|err| anyhow!("Check archive failed: {}", err))?;
 
                 match packet {
-                    NoveltyBeatsModePacket::Data(data) => Ok(RemoteData::Analysis {
-                        novelty: data.novelty.value / data.novelty.peak,
-                        is_beat: data.beat,
-                    }),
-                    NoveltyBeatsModePacket::Goodbye(goodbye) if goodbye.magic == MAGIC => {
-                        Ok(RemoteData::Goodbye {
-                            force: goodbye.force,
-                        })
+                    Archived::<BeatsOnlyModePacket>::Beat(beat) => Ok((
+                        Some(AckPacket::Ok(beat.sequence)),
+                        RemoteData::Beat {
+                            tempo: Some(beat.tempo).filter(|&tempo| tempo > 0.0),
+                            at: clock.schedule(beat.timestamp_us),
+                        },
+                    )),
+                    Archived::<BeatsOnlyModePacket>::Palette(palette) => Ok(Self::palette(palette)),
+                    Archived::<BeatsOnlyModePacket>::Heartbeat => Ok((None, RemoteData::Heartbeat)),
+                    Archived::<BeatsOnlyModePacket>::Standby(standby) => Ok((
+                        Some(AckPacket::Control(standby.sequence)),
+                        RemoteData::Standby(standby.silent),
+                    )),
+                    Archived::<BeatsOnlyModePacket>::Command(command) => Ok((
+                        Some(AckPacket::Control(command.sequence)),
+                        RemoteData::Command((&command.command).into()),
+                    )),
+                    Archived::<BeatsOnlyModePacket>::Goodbye(goodbye) if goodbye.is_valid() => {
+                        Ok((
+                            None,
+                            RemoteData::Goodbye {
+                                force: goodbye.force,
+                            },
+                        ))
                     }
                     _ => Err(anyhow!("Abort !")),
                 }
             }
-        };
+            DataMode::Bands => {
+                let packet = codec
+                    .check::<BandsModePacket>()
+                    .map_err(|err| anyhow!("Check archive failed: {}", err))?;
 
-        if res.is_ok() {
-            let packet = AckPacket::Ok;
-            self.serialize_send(&packet)?;
-        } else {
-            error!("Send ACK Abort");
-            let packet = AckPacket::Abort;
-            self.serialize_send(&packet)?;
-            self.current_peer = None;
+                match packet {
+                    Archived::<BandsModePacket>::Data(data) => Ok((
+                        Some(AckPacket::Ok(data.novelty.sequence)),
+                        RemoteData::Analysis {
+                            novelty: data.novelty.normalized_novelty(),
+                            is_beat: data.beat,
+                            is_onset: data.novelty.onset,
+                            pitch_class: data.novelty.pitch_class(),
+                            balance: data.novelty.balance(),
+                            loudness: None,
+                            at: clock.schedule(data.novelty.timestamp_us),
+                            zones: Self::zones(&data.novelty),
+                            bands: Some(data.bands()),
+                        },
+                    )),
+                    Archived::<BandsModePacket>::Palette(palette) => Ok(Self::palette(palette)),
+                    Archived::<BandsModePacket>::Heartbeat => Ok((None, RemoteData::Heartbeat)),
+                    Archived::<BandsModePacket>::Standby(standby) => Ok((
+                        Some(AckPacket::Control(standby.sequence)),
+                        RemoteData::Standby(standby.silent),
+                    )),
+                    Archived::<BandsModePacket>::Command(command) => Ok((
+                        Some(AckPacket::Control(command.sequence)),
+                        RemoteData::Command((&command.command).into()),
+                    )),
+                    Archived::<BandsModePacket>::Goodbye(goodbye) if goodbye.is_valid() => Ok((
+                        None,
+                        RemoteData::Goodbye {
+                            force: goodbye.force,
+                        },
+                    )),
+                    _ => Err(anyhow!("Abort !")),
+                }
+            }
         }
+    }
 
-        res
+    /// The same in every mode.
+    fn palette(palette: &Archived<PaletteData>) -> (Option<AckPacket>, RemoteData) {
+        (
+            Some(AckPacket::Control(palette.sequence)),
+            RemoteData::Palette(
+                palette
+                    .colors()
+                    .iter()
+                    .take(MAX_PALETTE_COLORS)
+                    .map(|&[r, g, b]| ColorRGB::new(r, g, b))
+                    .collect(),
+            ),
+        )
     }
 
+    fn zones(data: &Archived<NoveltyModeData>) -> Vec<(String, f64)> {
+        data.zones()
+            .map(|(name, novelty)| (name.to_string(), novelty))
+            .collect()
+    }
+
+    /// Tell every peer that we are leaving.
     pub fn stop(&mut self) -> Result<()> {
-        let ack = AckPacket::Quit;
-        self.serialize_send(&ack)?;
-        self.current_peer = None;
+        while let Some(address) = self.peers.last().map(|peer| peer.address) {
+            let ack = AckPacket::Quit;
+            self.serialize_send(&ack, address, true)?;
+            self.remove_peer(self.peers.len() - 1);
+        }
+        self.active_peer = None;
         self.is_stopped = true;
 
         Ok(())
     }
 }
 
-impl Drop for NetHandler {
+impl<T: Transport> Drop for NetHandler<T> {
     fn drop(&mut self) {
         if !self.is_stopped {
-            eprintln!("Forgot to stop NetHandler !");
+            warn!("Forgot to stop NetHandler !");
         }
     }
 }
@@ -0,0 +1,112 @@
+use crate::runners::Frame;
+use anyhow::{anyhow, Error};
+use cichlid::ColorRGB;
+use std::str::FromStr;
+
+/// Changes the frames of any runner before they are shown.
+#[derive(Clone, Debug)]
+pub enum PostProcess {
+    /// Average of the LEDs around, this far on each side.
+    Blur(usize),
+    /// The second half of the strip shows the first one backwards.
+    Mirror,
+    /// The strip backwards.
+    Reverse,
+    /// LEDs dimmer than this are turned off, some strips flicker at low values.
+    Floor(u8),
+    /// Groups of this many LEDs show their average color.
+    Pixelate(usize),
+}
+
+impl FromStr for PostProcess {
+    type Err = Error;
+
+    /// Parses `name` or `name:value`, like `blur:2`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        let name = parts.next().unwrap_or("").trim();
+        let value = parts.next().map(str::trim);
+        let number = |default: usize| -> Result<usize, Error> {
+            match value {
+                Some(value) => value
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid value `{}` for {} !", value, name)),
+                None => Ok(default),
+            }
+        };
+
+        Ok(match name {
+            "blur" => Self::Blur(number(1)?),
+            "mirror" => Self::Mirror,
+            "reverse" => Self::Reverse,
+            "floor" => Self::Floor(number(8)?.min(u8::MAX as usize) as u8),
+            "pixelate" => Self::Pixelate(number(4)?.max(1)),
+            _ => return Err(anyhow!("Unknown post-processing `{}` !", name)),
+        })
+    }
+}
+
+impl PostProcess {
+    fn apply(&self, colors: &mut [ColorRGB]) {
+        match *self {
+            Self::Blur(radius) => {
+                let source = colors.to_vec();
+                for (i, led) in colors.iter_mut().enumerate() {
+                    let start = i.saturating_sub(radius);
+                    let end = (i + radius + 1).min(source.len());
+                    *led = average(&source[start..end]);
+                }
+            }
+            Self::Mirror => {
+                let len = colors.len();
+                for i in 0..len / 2 {
+                    colors[len - 1 - i] = colors[i];
+                }
+            }
+            Self::Reverse => colors.reverse(),
+            Self::Floor(floor) => {
+                for led in colors.iter_mut() {
+                    if led.r.max(led.g).max(led.b) < floor {
+                        *led = ColorRGB::default();
+                    }
+                }
+            }
+            Self::Pixelate(size) => {
+                for group in colors.chunks_mut(size) {
+                    let color = average(group);
+                    for led in group.iter_mut() {
+                        *led = color;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Run the frame through the chain, in order.
+pub fn process(frame: Frame, chain: &[PostProcess]) -> Frame {
+    match frame {
+        // Stays the same on every LED whatever the chain does
+        Frame::Uniform(color) => {
+            let mut colors = [color];
+            for post in chain {
+                post.apply(&mut colors);
+            }
+            Frame::Uniform(colors[0])
+        }
+        Frame::Individual(mut colors) => {
+            for post in chain {
+                post.apply(&mut colors);
+            }
+            Frame::Individual(colors)
+        }
+    }
+}
+
+fn average(colors: &[ColorRGB]) -> ColorRGB {
+    let len = colors.len().max(1) as u32;
+    let (r, g, b) = colors.iter().fold((0, 0, 0), |(r, g, b), color| {
+        (r + color.r as u32, g + color.g as u32, b + color.b as u32)
+    });
+    ColorRGB::new((r / len) as u8, (g / len) as u8, (b / len) as u8)
+}
@@ -0,0 +1,149 @@
+use anyhow::{anyhow, Result};
+use cichlid::ColorRGB;
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+/// First line of the file, also tells what the columns are.
+const HEADER: &str = "# id,name,runner,brightness,standby_speed,palette";
+
+/// A look of the strip, saved to be recalled later.
+#[derive(Clone, Debug)]
+pub struct Preset {
+    pub id: u8,
+    pub name: String,
+    /// Index of the common runner, `None` for the standby.
+    pub runner: Option<usize>,
+    pub brightness: u8,
+    pub standby_speed: f32,
+    pub palette: Vec<ColorRGB>,
+}
+
+impl Preset {
+    /// The colors are written in hex and separated by spaces in their column.
+    fn to_line(&self) -> String {
+        format!(
+            "{},{},{},{},{},{}",
+            self.id,
+            // There is no escaping
+            self.name.replace(',', " "),
+            self.runner
+                .map_or_else(|| "standby".to_string(), |index| index.to_string()),
+            self.brightness,
+            self.standby_speed,
+            self.palette
+                .iter()
+                .map(|color| format!("{:02x}{:02x}{:02x}", color.r, color.g, color.b))
+                .collect::<Vec<_>>()
+                .join(" ")
+        )
+    }
+
+    fn from_line(line: &str) -> Result<Self> {
+        let columns = line.split(',').map(str::trim).collect::<Vec<_>>();
+        if columns.len() != 6 {
+            return Err(anyhow!("Expected 6 columns, got {}", columns.len()));
+        }
+
+        Ok(Self {
+            id: columns[0].parse()?,
+            name: columns[1].to_string(),
+            runner: match columns[2] {
+                runner if runner.eq_ignore_ascii_case("standby") => None,
+                runner => Some(runner.parse()?),
+            },
+            brightness: columns[3].parse()?,
+            standby_speed: columns[4].parse()?,
+            palette: columns[5]
+                .split_whitespace()
+                .map(|color| {
+                    let rgb = u32::from_str_radix(color.trim_start_matches('#'), 16)
+                        .map_err(|_| anyhow!("Invalid color `{}`", color))?;
+                    Ok(ColorRGB::new(
+                        (rgb >> 16) as u8,
+                        (rgb >> 8) as u8,
+                        rgb as u8,
+                    ))
+                })
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+/// The presets of a file, written back every time one is saved.
+pub struct Presets {
+    path: PathBuf,
+    presets: Vec<Preset>,
+}
+
+impl Presets {
+    /// There is simply no preset if the file doesn't exist yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut presets = Vec::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            match Preset::from_line(line) {
+                Ok(preset) => presets.push(preset),
+                Err(err) => return Err(anyhow!("Line {} of the presets: {}", i + 1, err)),
+            }
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            presets,
+        })
+    }
+
+    pub fn list(&self) -> &[Preset] {
+        &self.presets
+    }
+
+    pub fn get(&self, id: u8) -> Option<&Preset> {
+        self.presets.iter().find(|preset| preset.id == id)
+    }
+
+    /// By id or by name.
+    pub fn find(&self, preset: &str) -> Option<&Preset> {
+        match preset.parse() {
+            Ok(id) => self.get(id),
+            Err(_) => self
+                .presets
+                .iter()
+                .find(|candidate| candidate.name.eq_ignore_ascii_case(preset)),
+        }
+    }
+
+    /// Replace the preset with the same id, the file is written apart with [Presets::file].
+    pub fn set(&mut self, preset: Preset) {
+        match self.presets.iter_mut().find(|saved| saved.id == preset.id) {
+            Some(saved) => *saved = preset,
+            None => {
+                self.presets.push(preset);
+                self.presets.sort_by_key(|preset| preset.id);
+            }
+        }
+    }
+
+    /// The path and the content of the file, to write it without holding the presets.
+    pub fn file(&self) -> (PathBuf, String) {
+        let mut content = String::from(HEADER);
+        for preset in self.presets.iter() {
+            content.push('\n');
+            content.push_str(&preset.to_line());
+        }
+        content.push('\n');
+        (self.path.clone(), content)
+    }
+}
@@ -0,0 +1,118 @@
+use crate::{runners::Frame, Opt};
+use anyhow::{anyhow, Result};
+use cichlid::ColorRGB;
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::info;
+
+/// Start of the file, the format may change.
+const MAGIC: &[u8] = b"RSWREC1\n";
+
+/// Writes the frames shown on the strip to a file, before the post processing, each one
+/// with the milliseconds since the first one. The file starts with the amount of LEDs, then
+/// every frame has its time and the RGB of each LED.
+pub struct Recorder {
+    file: BufWriter<File>,
+    led_amount: usize,
+    start: Option<Instant>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, led_amount: usize) -> Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        file.write_all(&(led_amount as u32).to_le_bytes())?;
+        Ok(Self {
+            file,
+            led_amount,
+            start: None,
+        })
+    }
+
+    pub fn record(&mut self, frame: &Frame, at: Instant) -> Result<()> {
+        let start = *self.start.get_or_insert(at);
+        let time = (at - start).as_millis() as u32;
+        self.file.write_all(&time.to_le_bytes())?;
+
+        let colors = match frame {
+            Frame::Uniform(color) => vec![*color; self.led_amount],
+            Frame::Individual(colors) => colors.clone(),
+        };
+        for i in 0..self.led_amount {
+            let color = colors.get(i).copied().unwrap_or_default();
+            self.file.write_all(&[color.r, color.g, color.b])?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// Frames written by a [Recorder], with the time they were shown at.
+pub struct Recording {
+    pub frames: Vec<(Duration, Vec<ColorRGB>)>,
+}
+
+impl Recording {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read(path)?;
+        if !content.starts_with(MAGIC) || content.len() < MAGIC.len() + 4 {
+            return Err(anyhow!("{} isn't a recording !", path.display()));
+        }
+        let (amount, content) = content[MAGIC.len()..].split_at(4);
+        let led_amount = u32::from_le_bytes([amount[0], amount[1], amount[2], amount[3]]) as usize;
+        if led_amount == 0 {
+            return Err(anyhow!("The recording has no LEDs !"));
+        }
+
+        // The last frame is cut if the server was killed while recording
+        let frames = content
+            .chunks_exact(4 + led_amount * 3)
+            .map(|frame| {
+                let (time, colors) = frame.split_at(4);
+                let time = u32::from_le_bytes([time[0], time[1], time[2], time[3]]);
+                let colors = colors
+                    .chunks_exact(3)
+                    .map(|rgb| ColorRGB::new(rgb[0], rgb[1], rgb[2]))
+                    .collect();
+                (Duration::from_millis(time as u64), colors)
+            })
+            .collect::<Vec<_>>();
+        if frames.is_empty() {
+            return Err(anyhow!("The recording has no frames !"));
+        }
+        Ok(Self { frames })
+    }
+}
+
+/// What `--record` and `--replay` ask for, opened before the runner thread starts so that
+/// a wrong path stops the app.
+pub struct Recordings {
+    pub recorder: Option<Recorder>,
+    pub replay: Option<Arc<Recording>>,
+}
+
+impl Recordings {
+    pub fn open(opt: &Opt, led_amount: usize) -> Result<Self> {
+        let replay = opt.replay.as_deref().map(Recording::load).transpose()?;
+        if let Some(replay) = replay.as_ref() {
+            info!("Replaying {} frames", replay.frames.len());
+        }
+        Ok(Self {
+            recorder: opt
+                .record
+                .as_deref()
+                .map(|path| Recorder::create(path, led_amount))
+                .transpose()?,
+            replay: replay.map(Arc::new),
+        })
+    }
+}
@@ -1,9 +1,16 @@
-use crate::led_controllers::LedController;
+use crate::{
+    animation::Animation,
+    effects::{Effect, EffectEnum, Scene},
+    layout::Layout,
+    led_controllers::{perceived_brightness, LedController},
+    recording::Recording,
+};
 use anyhow::Result;
 use cichlid::{prelude::RainbowFillSingleCycle, ColorRGB, HSV};
 use enum_dispatch::enum_dispatch;
-use log::debug;
-use std::time::Instant;
+use rswave_common::packets::BARK_BANDS;
+use std::{sync::Arc, time::Duration};
+use tracing::debug;
 
 #[enum_dispatch]
 pub enum RunnerEnum {
@@ -12,14 +19,100 @@ pub enum RunnerEnum {
     WhiteRunner,
     SimpleBeatRunner,
     EpilepsyRunner,
+    SpectrumRunner,
+    SpatialRunner,
+    ReplayRunner,
+    AnimationRunner,
+}
+
+/// What a runner shows on the strip.
+#[derive(Debug, Clone)]
+pub enum Frame {
+    /// The same color on every LED.
+    Uniform(ColorRGB),
+    /// One color per LED.
+    Individual(Vec<ColorRGB>),
+}
+
+impl Frame {
+    pub fn display<C: LedController>(&self, controller: &mut C) -> Result<()> {
+        match self {
+            Self::Uniform(color) => controller.set_all(*color),
+            Self::Individual(colors) if C::is_addressable_individually() => {
+                controller.set_all_individual(colors)
+            }
+            // Can't do better than the first one
+            Self::Individual(colors) => {
+                controller.set_all(colors.first().copied().unwrap_or_default())
+            }
+        }
+        controller.commit()
+    }
+}
+
+/// How danceable and energetic the track is, between 0 and 1.
+#[derive(Debug, Copy, Clone)]
+pub struct TrackFeatures {
+    pub danceability: f32,
+    pub energy: f32,
 }
 
 #[enum_dispatch(RunnerEnum)]
 pub trait Runner {
     fn beat(&mut self) {}
+    /// A note or a hit was detected in the audio, more frequent than beats.
+    fn onset(&mut self) {}
+    /// Dominant pitch class of the audio (0 being C), if one stands out.
+    fn pitch(&mut self, _pitch_class: Option<u8>) {}
+    /// Stereo balance of the audio, from -1 on the left to 1 on the right.
+    fn balance(&mut self, _balance: f64) {}
     fn novelty(&mut self, _novelty: f64) {}
-    fn run_once(&mut self) -> bool;
-    fn display<C: LedController>(&self, controller: &mut C) -> Result<()>;
+    /// Loudness envelope of the track between 0 and 1, only available with spotify.
+    fn loudness(&mut self, _loudness: f64) {}
+    /// Colors to use in priority, can be empty.
+    fn palette(&mut self, _palette: &[ColorRGB]) {}
+    /// Character of the track, only available with spotify.
+    fn features(&mut self, _features: TrackFeatures) {}
+    /// Where the LEDs given to the runner are in space, before the first frame. They are on
+    /// a line without `--layout`.
+    fn layout(&mut self, _layout: Arc<Layout>) {}
+    /// Energy of the Bark bands from the lowest, between 0 and 1. Only available when the
+    /// remote sends them.
+    fn bands(&mut self, _bands: &[f32; BARK_BANDS]) {}
+    /// Move the animation forward by `delta_time` and render it for `led_amount` LEDs,
+    /// `None` if the frame didn't change.
+    fn run_once(&mut self, delta_time: Duration, led_amount: usize) -> Option<Frame>;
+    /// Time between two frames that suits the runner, `--led-update-period` is still the
+    /// shortest one.
+    fn frame_period(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Spread the 12 pitch classes around the color wheel.
+fn pitch_hue(pitch_class: u8) -> u8 {
+    (pitch_class as u16 * 256 / 12) as u8
+}
+
+/// Gain of a LED at `position` on the strip, from -1 on the left to 1 on the right, for the
+/// stereo `balance`. The side the music comes from stays at full brightness.
+fn pan(position: f32, balance: f32) -> f32 {
+    (1.0 + position * balance).max(0.0).min(1.0)
+}
+
+/// Color at `position` between 0 and 1 of the gradient going through the colors of the
+/// palette, evenly spread. Black for an empty palette.
+pub fn gradient(palette: &[ColorRGB], position: f32) -> ColorRGB {
+    if palette.len() <= 1 {
+        return palette.first().copied().unwrap_or_default();
+    }
+
+    let index = position.max(0.0).min(1.0) * (palette.len() - 1) as f32;
+    let low = (index as usize).min(palette.len() - 2);
+    let t = index - low as f32;
+    let (from, to) = (palette[low], palette[low + 1]);
+    let mix = |from: u8, to: u8| (from as f32 * (1.0 - t) + to as f32 * t) as u8;
+    ColorRGB::new(mix(from.r, to.r), mix(from.g, to.g), mix(from.b, to.b))
 }
 
 fn hue_randomizer(mut color: HSV) -> HSV {
@@ -40,14 +133,14 @@ fn hue_randomizer(mut color: HSV) -> HSV {
 pub struct NoopRunner;
 
 impl Runner for NoopRunner {
-    fn run_once(&mut self) -> bool {
+    fn run_once(&mut self, _: Duration, _: usize) -> Option<Frame> {
         // no-op
-        false
+        None
     }
 
-    fn display<C: LedController>(&self, _: &mut C) -> Result<()> {
-        // no-op
-        Ok(())
+    fn frame_period(&self) -> Option<Duration> {
+        // Still wake up for the messages
+        Some(Duration::from_millis(50))
     }
 }
 // </editor-fold>
@@ -57,8 +150,10 @@ impl Runner for NoopRunner {
 pub struct StandbyRunner {
     current_color: HSV,
     speed: f32,
+    /// Factor of the speed to match the track.
+    pace: f32,
     reverse: bool,
-    last_update: Instant,
+    brightness: u8,
 }
 
 impl StandbyRunner {
@@ -67,28 +162,37 @@ impl StandbyRunner {
         Self {
             current_color: HSV::new(0, 255, 255),
             speed,
+            pace: 1.0,
             reverse,
-            last_update: Instant::now(),
+            brightness: u8::MAX,
         }
     }
+
+    /// Dim the rainbow, on top of the brightness of the strip.
+    pub fn with_brightness(mut self, brightness: u8) -> Self {
+        self.brightness = perceived_brightness(brightness);
+        self
+    }
+}
+
+pub fn dim(color: ColorRGB, brightness: u8) -> ColorRGB {
+    let scale = |channel: u8| (channel as u16 * brightness as u16 / u8::MAX as u16) as u8;
+    ColorRGB::new(scale(color.r), scale(color.g), scale(color.b))
 }
 
 impl Runner for StandbyRunner {
-    fn run_once(&mut self) -> bool {
-        let now = Instant::now();
-        let delta_time = now.duration_since(self.last_update).as_secs_f32();
+    fn features(&mut self, features: TrackFeatures) {
+        // Between 0.6 and 1.4, the standby stays calm
+        self.pace = 0.6 + 0.4 * features.danceability + 0.4 * features.energy;
+    }
 
-        let hue_shift = (delta_time * self.speed * u8::MAX as f32) as u8;
+    fn run_once(&mut self, delta_time: Duration, led_amount: usize) -> Option<Frame> {
+        let hue_shift = (delta_time.as_secs_f32() * self.speed * self.pace * u8::MAX as f32) as u8;
         self.current_color.h = self.current_color.h.wrapping_add(hue_shift);
         self.current_color.maximize_brightness();
 
-        self.last_update = now;
-        true
-    }
-
-    fn display<C: LedController>(&self, controller: &mut C) -> Result<()> {
-        if C::is_addressable_individually() {
-            let mut rainbow = vec![ColorRGB::default(); controller.led_amount()];
+        if led_amount > 1 {
+            let mut rainbow = vec![ColorRGB::default(); led_amount];
             if self.reverse {
                 rainbow
                     .iter_mut()
@@ -99,12 +203,21 @@ impl Runner for StandbyRunner {
                     .iter_mut()
                     .rainbow_fill_single_cycle(self.current_color.h);
             }
-            controller.set_all_individual(&rainbow);
+            for color in rainbow.iter_mut() {
+                *color = dim(*color, self.brightness);
+            }
+            Some(Frame::Individual(rainbow))
         } else {
-            controller.set_all(self.current_color.to_rgb_rainbow());
+            Some(Frame::Uniform(dim(
+                self.current_color.to_rgb_rainbow(),
+                self.brightness,
+            )))
         }
+    }
 
-        controller.commit()
+    fn frame_period(&self) -> Option<Duration> {
+        // The rainbow moves slowly enough
+        Some(Duration::from_millis(33))
     }
 }
 // </editor-fold>
@@ -114,7 +227,7 @@ impl Runner for StandbyRunner {
 pub struct WhiteRunner {
     value: f32,
     gravity: f32,
-    last_update: Instant,
+    balance: f32,
 }
 
 impl WhiteRunner {
@@ -122,7 +235,7 @@ impl WhiteRunner {
         Self {
             value: 0.0,
             gravity: 500.0,
-            last_update: Instant::now(),
+            balance: 0.0,
         }
     }
 }
@@ -132,18 +245,27 @@ impl Runner for WhiteRunner {
         self.value = 255.0;
     }
 
-    fn run_once(&mut self) -> bool {
-        let now = Instant::now();
-        let delta_time = now.duration_since(self.last_update).as_secs_f32();
-        self.value = (self.value - self.gravity * delta_time).max(0.0);
-        self.last_update = now;
-        true
+    fn balance(&mut self, balance: f64) {
+        self.balance = balance as f32;
     }
 
-    fn display<C: LedController>(&self, controller: &mut C) -> Result<()> {
-        let col = self.value as u8;
-        controller.set_all(ColorRGB::new(col, col, col));
-        controller.commit()
+    fn run_once(&mut self, delta_time: Duration, led_amount: usize) -> Option<Frame> {
+        self.value = (self.value - self.gravity * delta_time.as_secs_f32()).max(0.0);
+
+        if led_amount <= 1 || self.balance == 0.0 {
+            let col = self.value as u8;
+            return Some(Frame::Uniform(ColorRGB::new(col, col, col)));
+        }
+
+        // The flash leans towards the side the music comes from
+        let colors = (0..led_amount)
+            .map(|led| {
+                let position = 2.0 * led as f32 / (led_amount - 1) as f32 - 1.0;
+                let col = (self.value * pan(position, self.balance)) as u8;
+                ColorRGB::new(col, col, col)
+            })
+            .collect();
+        Some(Frame::Individual(colors))
     }
 }
 // </editor-fold>
@@ -153,6 +275,7 @@ impl Runner for WhiteRunner {
 pub struct SimpleBeatRunner {
     current_color: HSV,
     hue_increment: u8,
+    pitch_class: Option<u8>,
     need_update: bool,
 }
 
@@ -161,6 +284,7 @@ impl SimpleBeatRunner {
         Self {
             current_color: HSV::new(0, 255, 255),
             hue_increment: u8::MAX / 6,
+            pitch_class: None,
             need_update: true,
         }
     }
@@ -168,28 +292,31 @@ impl SimpleBeatRunner {
 
 impl Runner for SimpleBeatRunner {
     fn beat(&mut self) {
-        self.current_color.h = loop {
-            let new_hue = rand::random();
-            if (new_hue as i16 - self.current_color.h as i16).abs() > 50 {
-                break new_hue;
-            }
+        self.current_color.h = match self.pitch_class {
+            // Follow the harmony
+            Some(pitch_class) => pitch_hue(pitch_class),
+            None => loop {
+                let new_hue = rand::random();
+                if (new_hue as i16 - self.current_color.h as i16).abs() > 50 {
+                    break new_hue;
+                }
+            },
         };
         self.need_update = true;
     }
 
-    fn run_once(&mut self) -> bool {
+    fn pitch(&mut self, pitch_class: Option<u8>) {
+        self.pitch_class = pitch_class;
+    }
+
+    fn run_once(&mut self, _: Duration, _: usize) -> Option<Frame> {
         if self.need_update {
             self.need_update = false;
-            true
+            Some(Frame::Uniform(self.current_color.to_rgb_rainbow()))
         } else {
-            false
+            None
         }
     }
-
-    fn display<C: LedController>(&self, controller: &mut C) -> Result<()> {
-        controller.set_all(self.current_color.to_rgb_rainbow());
-        controller.commit()
-    }
 }
 // </editor-fold>
 
@@ -198,7 +325,10 @@ impl Runner for SimpleBeatRunner {
 pub struct EpilepsyRunner {
     current_color: HSV,
     gravity: f32,
-    last_update: Instant,
+    min_brightness: u8,
+    palette: Vec<ColorRGB>,
+    palette_index: usize,
+    pitch_class: Option<u8>,
 }
 
 impl EpilepsyRunner {
@@ -206,7 +336,10 @@ impl EpilepsyRunner {
         Self {
             current_color: HSV::new(0, 255, 255),
             gravity: 150.0,
-            last_update: Instant::now(),
+            min_brightness: 20,
+            palette: Vec::new(),
+            palette_index: 0,
+            pitch_class: None,
         }
     }
 }
@@ -216,26 +349,379 @@ impl Runner for EpilepsyRunner {
         self.current_color.maximize_brightness();
     }
 
-    fn novelty(&mut self, novelty: f64) {
-        if novelty > 0.3 {
+    fn onset(&mut self) {
+        if self.palette.len() > 1 {
+            let offset = rand::random::<usize>() % (self.palette.len() - 1) + 1;
+            self.palette_index = (self.palette_index + offset) % self.palette.len();
+        } else if let Some(pitch_class) = self.pitch_class {
+            self.current_color.h = pitch_hue(pitch_class);
+        } else {
             self.current_color = hue_randomizer(self.current_color);
         }
     }
 
-    fn run_once(&mut self) -> bool {
-        let now = Instant::now();
-        let delta_time = now.duration_since(self.last_update).as_secs_f32();
-        let brightness = (self.current_color.v as f32 / 2.55 - self.gravity * delta_time).max(0.0);
-        self.current_color.v = ((brightness * 2.55) as u8).max(20);
+    fn pitch(&mut self, pitch_class: Option<u8>) {
+        self.pitch_class = pitch_class;
+    }
+
+    fn palette(&mut self, palette: &[ColorRGB]) {
+        self.palette = palette.to_vec();
+        self.palette_index = 0;
+    }
 
-        self.last_update = now;
-        true
+    fn loudness(&mut self, loudness: f64) {
+        // Keep some light during loud parts so swells don't fall back to black
+        self.min_brightness = 20 + (loudness * 80.0) as u8;
     }
 
-    fn display<C: LedController>(&self, controller: &mut C) -> Result<()> {
-        controller.set_all(self.current_color.to_rgb_spectrum());
-        controller.commit()
+    fn run_once(&mut self, delta_time: Duration, _: usize) -> Option<Frame> {
+        let brightness =
+            (self.current_color.v as f32 / 2.55 - self.gravity * delta_time.as_secs_f32()).max(0.0);
+        self.current_color.v = ((brightness * 2.55) as u8).max(self.min_brightness);
+
+        Some(Frame::Uniform(match self.palette.get(self.palette_index) {
+            // Use the brightness of the current color on the palette color
+            Some(color) => dim(*color, self.current_color.v),
+            None => self.current_color.to_rgb_spectrum(),
+        }))
+    }
+
+    fn frame_period(&self) -> Option<Duration> {
+        // The flashes must be sharp
+        Some(Duration::from_millis(8))
+    }
+}
+
+// </editor-fold>
+
+// Spectrum runner
+// <editor-fold>
+pub struct SpectrumRunner {
+    /// Shown level of each band, they fall slower than they rise.
+    levels: [f32; BARK_BANDS],
+    /// Whether the remote sends the bands, the novelty fills the strip otherwise.
+    has_bands: bool,
+    novelty: f32,
+    gravity: f32,
+    /// Replaces the rainbow with a gradient when it has several colors.
+    palette: Vec<ColorRGB>,
+}
+
+impl SpectrumRunner {
+    pub fn new() -> Self {
+        Self {
+            levels: [0.0; BARK_BANDS],
+            has_bands: false,
+            novelty: 0.0,
+            gravity: 2.0,
+            palette: Vec::new(),
+        }
+    }
+
+    /// From red for the bass to violet for the treble, without going back to red.
+    fn hue(position: f32) -> u8 {
+        (position.max(0.0).min(1.0) * 200.0) as u8
+    }
+
+    /// Level of the bands at `position` between 0 and 1, interpolated between them.
+    fn level(&self, position: f32) -> f32 {
+        let index = position * (BARK_BANDS - 1) as f32;
+        let low = (index as usize).min(BARK_BANDS - 1);
+        let high = (low + 1).min(BARK_BANDS - 1);
+        let t = index - low as f32;
+        self.levels[low] * (1.0 - t) + self.levels[high] * t
+    }
+}
+
+impl Runner for SpectrumRunner {
+    fn novelty(&mut self, novelty: f64) {
+        self.novelty = self.novelty.max(novelty.max(0.0).min(1.0) as f32);
+    }
+
+    fn bands(&mut self, bands: &[f32; BARK_BANDS]) {
+        self.has_bands = true;
+        for (level, &energy) in self.levels.iter_mut().zip(bands.iter()) {
+            *level = level.max(energy);
+        }
+    }
+
+    fn palette(&mut self, palette: &[ColorRGB]) {
+        self.palette = palette.to_vec();
+    }
+
+    fn run_once(&mut self, delta_time: Duration, led_amount: usize) -> Option<Frame> {
+        let fall = self.gravity * delta_time.as_secs_f32();
+        for level in self.levels.iter_mut() {
+            *level = (*level - fall).max(0.0);
+        }
+        self.novelty = (self.novelty - fall).max(0.0);
+
+        let palette = &self.palette;
+        let color = |position: f32, level: f32| {
+            if palette.len() > 1 {
+                dim(gradient(palette, position), (level * 255.0) as u8)
+            } else {
+                HSV::new(Self::hue(position), 255, (level * 255.0) as u8).to_rgb_rainbow()
+            }
+        };
+
+        if led_amount <= 1 {
+            // The loudest band
+            let (band, level) = self.levels.iter().copied().enumerate().fold(
+                (0, self.novelty),
+                |loudest, (band, level)| {
+                    if level > loudest.1 {
+                        (band, level)
+                    } else {
+                        loudest
+                    }
+                },
+            );
+            return Some(Frame::Uniform(color(
+                band as f32 / (BARK_BANDS - 1) as f32,
+                level,
+            )));
+        }
+
+        let lit = (self.novelty * led_amount as f32) as usize;
+        let colors = (0..led_amount)
+            .map(|led| {
+                let position = led as f32 / (led_amount - 1) as f32;
+                if self.has_bands {
+                    color(position, self.level(position))
+                } else if led < lit {
+                    // Like a VU meter
+                    color(position, 1.0)
+                } else {
+                    ColorRGB::new(0, 0, 0)
+                }
+            })
+            .collect();
+        Some(Frame::Individual(colors))
+    }
+
+    fn frame_period(&self) -> Option<Duration> {
+        Some(Duration::from_millis(16))
+    }
+}
+// </editor-fold>
+
+// Spatial runner
+// <editor-fold>
+/// Shows an effect computed from the position of the LEDs, see `--layout`.
+pub struct SpatialRunner {
+    effect: EffectEnum,
+    /// Made up on a line if none is given before the first frame.
+    layout: Option<Arc<Layout>>,
+    scene: Scene,
+}
+
+impl SpatialRunner {
+    pub fn new(effect: impl Into<EffectEnum>) -> Self {
+        Self {
+            effect: effect.into(),
+            layout: None,
+            scene: Scene::default(),
+        }
+    }
+}
+
+impl Runner for SpatialRunner {
+    fn beat(&mut self) {
+        self.effect.beat(self.scene.time);
+    }
+
+    fn novelty(&mut self, novelty: f64) {
+        self.scene.novelty = novelty as f32;
+    }
+
+    fn palette(&mut self, palette: &[ColorRGB]) {
+        self.effect.palette(palette);
+    }
+
+    fn layout(&mut self, layout: Arc<Layout>) {
+        self.effect.layout(&layout);
+        self.layout = Some(layout);
+    }
+
+    fn run_once(&mut self, delta_time: Duration, led_amount: usize) -> Option<Frame> {
+        self.scene.time += delta_time.as_secs_f32();
+        self.effect.update(&self.scene);
+
+        if led_amount <= 1 {
+            return Some(Frame::Uniform(self.effect.color(0.5, 0.5, &self.scene)));
+        }
+        if self
+            .layout
+            .as_ref()
+            .map_or(true, |layout| layout.len() != led_amount)
+        {
+            self.layout(Arc::new(Layout::linear(led_amount)));
+        }
+
+        let layout = self.layout.as_ref()?;
+        let colors = (0..led_amount)
+            .map(|led| {
+                let (x, y) = layout.normalized(led);
+                self.effect.color(x, y, &self.scene)
+            })
+            .collect();
+        Some(Frame::Individual(colors))
+    }
+
+    fn frame_period(&self) -> Option<Duration> {
+        Some(Duration::from_millis(16))
+    }
+}
+// </editor-fold>
+
+// Replay runner
+// <editor-fold>
+/// Plays a recording in a loop, stretched over the LEDs it is given.
+pub struct ReplayRunner {
+    recording: Arc<Recording>,
+    elapsed: Duration,
+    /// Index of the next frame to show.
+    next: usize,
+}
+
+impl ReplayRunner {
+    pub fn new(recording: Arc<Recording>) -> Self {
+        Self {
+            recording,
+            elapsed: Duration::default(),
+            next: 0,
+        }
     }
 }
 
+impl Runner for ReplayRunner {
+    fn run_once(&mut self, delta_time: Duration, led_amount: usize) -> Option<Frame> {
+        let frames = &self.recording.frames;
+        if self.next >= frames.len() {
+            // Start over
+            self.elapsed = Duration::default();
+            self.next = 0;
+        } else {
+            self.elapsed += delta_time;
+        }
+
+        let mut shown = None;
+        while let Some((at, colors)) = frames.get(self.next) {
+            if *at > self.elapsed {
+                break;
+            }
+            shown = Some(colors);
+            self.next += 1;
+        }
+
+        let colors = shown?;
+        if colors.is_empty() {
+            return None;
+        }
+        Some(Frame::Individual(
+            (0..led_amount)
+                .map(|led| colors[led * colors.len() / led_amount])
+                .collect(),
+        ))
+    }
+}
+// </editor-fold>
+
+// Animation runner
+// <editor-fold>
+/// Beats closer or further apart than these don't change the speed of the animation.
+const ANIMATION_BEAT_PERIODS: (Duration, Duration) =
+    (Duration::from_millis(200), Duration::from_secs(2));
+
+/// Plays an animation stretched over the box around the LEDs, a strip shows the middle row.
+pub struct AnimationRunner {
+    animation: Arc<Animation>,
+    /// Made up on a line if none is given before the first frame.
+    layout: Option<Arc<Layout>>,
+    /// In frames.
+    position: f32,
+    /// Factor of the FPS to follow the tempo.
+    speed: f32,
+    since_beat: Duration,
+    shown: Option<usize>,
+}
+
+impl AnimationRunner {
+    pub fn new(animation: Arc<Animation>) -> Self {
+        Self {
+            animation,
+            layout: None,
+            position: 0.0,
+            speed: 1.0,
+            since_beat: Duration::default(),
+            shown: None,
+        }
+    }
+}
+
+impl Runner for AnimationRunner {
+    fn beat(&mut self) {
+        let (min, max) = ANIMATION_BEAT_PERIODS;
+        if let Some(tempo) = self.animation.tempo {
+            if self.since_beat > min && self.since_beat < max {
+                let bpm = 60.0 / self.since_beat.as_secs_f32();
+                self.speed = (bpm / tempo).max(0.25).min(4.0);
+            }
+        }
+        self.since_beat = Duration::default();
+    }
+
+    fn layout(&mut self, layout: Arc<Layout>) {
+        self.layout = Some(layout);
+        self.shown = None;
+    }
+
+    fn run_once(&mut self, delta_time: Duration, led_amount: usize) -> Option<Frame> {
+        self.since_beat += delta_time;
+        let frame_count = self.animation.frame_count() as f32;
+        self.position += delta_time.as_secs_f32() * self.animation.fps * self.speed;
+        self.position %= frame_count;
+        let frame = self.position as usize;
+
+        if self
+            .layout
+            .as_ref()
+            .map_or(true, |layout| layout.len() != led_amount)
+        {
+            self.layout(Arc::new(Layout::linear(led_amount)));
+        }
+        if self.shown == Some(frame) {
+            return None;
+        }
+        self.shown = Some(frame);
+
+        if led_amount <= 1 {
+            return Some(Frame::Uniform(self.animation.pixel(frame, 0.5, 0.5)));
+        }
+        let layout = self.layout.as_ref()?;
+        let (center_x, center_y) = layout.normalized_center();
+        let scale = |position: f32, center: f32| {
+            if center > 0.0 {
+                position / (center * 2.0)
+            } else {
+                0.5
+            }
+        };
+        let colors = (0..led_amount)
+            .map(|led| {
+                let (x, y) = layout.normalized(led);
+                self.animation
+                    .pixel(frame, scale(x, center_x), scale(y, center_y))
+            })
+            .collect();
+        Some(Frame::Individual(colors))
+    }
+
+    fn frame_period(&self) -> Option<Duration> {
+        Some(Duration::from_secs_f32(
+            1.0 / (self.animation.fps * self.speed),
+        ))
+    }
+}
 // </editor-fold>
@@ -0,0 +1,62 @@
+use anyhow::{anyhow, Error};
+use chrono::NaiveTime;
+use std::str::FromStr;
+
+/// A time range during which the strip must calm down, can span over midnight.
+#[derive(Copy, Clone, Debug)]
+pub struct QuietHours {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl QuietHours {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            // Over midnight
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+impl FromStr for QuietHours {
+    type Err = Error;
+
+    /// Parses `HH:MM-HH:MM`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut bounds = s.splitn(2, '-').map(|bound| {
+            NaiveTime::parse_from_str(bound.trim(), "%H:%M")
+                .map_err(|_| anyhow!("Invalid time `{}`, expected HH:MM !", bound))
+        });
+
+        match (bounds.next(), bounds.next()) {
+            (Some(start), Some(end)) => Ok(Self {
+                start: start?,
+                end: end?,
+            }),
+            _ => Err(anyhow!("Invalid quiet hours, expected HH:MM-HH:MM !")),
+        }
+    }
+}
+
+/// What the strip does during the quiet hours.
+#[derive(Copy, Clone, Debug)]
+pub enum QuietMode {
+    /// Turn the LEDs off.
+    Off,
+    /// Dimmed standby rainbow.
+    Dim,
+}
+
+impl FromStr for QuietMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(Self::Off),
+            "dim" => Ok(Self::Dim),
+            _ => Err(anyhow!("Unknown quiet mode !")),
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use crate::led_controllers::LedController;
+use anyhow::Result;
+use cichlid::ColorRGB;
+use std::time::Duration;
+use tracing::{debug, info};
+
+/// Time before the next LED lights up, the previous ones stay lit.
+const STEP: Duration = Duration::from_millis(150);
+/// How long the whole strip stays lit at the end.
+const HOLD: Duration = Duration::from_secs(5);
+
+/// Every 10th LED is white and every 5th yellow, so they can be counted easily.
+/// The others go red, green, blue.
+fn index_color(number: usize) -> (ColorRGB, &'static str) {
+    if number % 10 == 0 {
+        (ColorRGB::new(255, 255, 255), "white")
+    } else if number % 5 == 0 {
+        (ColorRGB::new(255, 255, 0), "yellow")
+    } else {
+        match number % 3 {
+            1 => (ColorRGB::new(255, 0, 0), "red"),
+            2 => (ColorRGB::new(0, 255, 0), "green"),
+            _ => (ColorRGB::new(0, 0, 255), "blue"),
+        }
+    }
+}
+
+/// Light the LEDs one after the other and log what should be seen, to find the right
+/// `--led-count` and the dead LEDs. The strip is blanked at the end.
+pub async fn run<C: LedController>(controller: &mut C) -> Result<()> {
+    controller.reset()?;
+
+    if !C::is_addressable_individually() {
+        info!("The strip isn't addressable, showing red, green then blue");
+        for number in 1..=3 {
+            let (color, name) = index_color(number);
+            info!("Expecting the whole strip in {}", name);
+            controller.set_all(color);
+            controller.commit()?;
+            tokio::time::sleep(HOLD / 3).await;
+        }
+        return controller.reset();
+    }
+
+    let led_amount = controller.led_amount();
+    info!(
+        "Lighting {} LEDs one by one, every 10th is white and every 5th yellow",
+        led_amount
+    );
+    let mut frame = vec![ColorRGB::new(0, 0, 0); led_amount];
+    for i in 0..led_amount {
+        let (color, name) = index_color(i + 1);
+        info!("LED {}: {}", i + 1, name);
+        frame[i] = color;
+        controller.set_all_individual(&frame);
+        controller.commit()?;
+        tokio::time::sleep(STEP).await;
+    }
+
+    info!(
+        "The whole strip should be lit, a dark LED in the middle is dead. Dark LEDs at the end \
+         mean that the strip has more than {} LEDs, LEDs logged past its end that it has less",
+        led_amount
+    );
+    tokio::time::sleep(HOLD).await;
+    controller.reset()
+}
+
+/// Fill the strip in red, green then blue in a second or so, to see right away that the
+/// controller, the color order and the count are right.
+pub async fn self_test<C: LedController>(controller: &mut C) -> Result<()> {
+    const COLOR_DURATION: Duration = Duration::from_millis(400);
+    const FRAME: Duration = Duration::from_millis(10);
+
+    let led_amount = if C::is_addressable_individually() {
+        controller.led_amount()
+    } else {
+        1
+    };
+    let frames = (COLOR_DURATION.as_millis() / FRAME.as_millis()) as usize;
+
+    debug!("Self test");
+    if led_amount <= 1 {
+        // Nothing to fill, each color is shown as a whole
+        for number in 1..=3 {
+            let (color, _) = index_color(number);
+            controller.set_all(color);
+            controller.commit()?;
+            tokio::time::sleep(COLOR_DURATION).await;
+        }
+        return controller.reset();
+    }
+    for number in 1..=3 {
+        let (color, _) = index_color(number);
+        let mut frame = vec![ColorRGB::new(0, 0, 0); led_amount];
+        for i in 1..=frames {
+            let lit = led_amount * i / frames;
+            for led in frame.iter_mut().take(lit) {
+                *led = color;
+            }
+            controller.set_all_individual(&frame);
+            controller.commit()?;
+            tokio::time::sleep(FRAME).await;
+        }
+    }
+    controller.reset()
+}
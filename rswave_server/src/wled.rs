@@ -0,0 +1,353 @@
+//! A small part of the JSON API of WLED, enough for its apps and the WLED integration of
+//! Home Assistant to turn the LEDs on and off, dim them and pick the effect.
+
+use crate::app::{Control, Status};
+use anyhow::{anyhow, Result};
+use rswave_common::packets::Command;
+use serde_json::{json, Value};
+use std::sync::{
+    atomic::{AtomicU8, Ordering},
+    Arc, Mutex,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    task,
+};
+use tracing::{debug, info, warn};
+
+/// Names of the common runners, in the order of the app. The last one only exists with
+/// `--animation`.
+static EFFECTS: [&str; 7] = [
+    "Epilepsy",
+    "Simple Beat",
+    "White",
+    "Spectrum",
+    "Radial Pulse",
+    "Vertical Gradient",
+    "Animation",
+];
+/// The requests are tiny, anything bigger is refused.
+const MAX_REQUEST_SIZE: usize = 16 * 1024;
+/// Version of WLED the clients are told about.
+const WLED_VERSION: &str = "0.13.0";
+/// WLED numbers its presets from 1 to this.
+const MAX_PRESET: u8 = 250;
+
+/// What the clients see, taken from the status of the app.
+#[derive(Debug, Clone)]
+struct State {
+    on: bool,
+    brightness: u8,
+    effect: usize,
+    /// The last one recalled.
+    preset: Option<u8>,
+}
+
+/// Shared by the requests.
+struct Shared {
+    status: Arc<Mutex<Status>>,
+    /// WLED keeps the brightness while off, the app only knows it's zero.
+    last_brightness: AtomicU8,
+}
+
+/// Serves the API as long as this is alive.
+pub struct WledApi {
+    task: task::JoinHandle<()>,
+}
+
+impl WledApi {
+    /// Must be called from within the tokio runtime. `runner_count` is the amount of common
+    /// runners of the app, `brightness` the one to turn on with if it starts off.
+    pub async fn new(
+        port: u16, led_count: usize, runner_count: usize, brightness: u8,
+        status: Arc<Mutex<Status>>, control: mpsc::Sender<Control>,
+    ) -> Result<Self> {
+        let effects = &EFFECTS[..runner_count.min(EFFECTS.len())];
+        let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+        info!("WLED API listening on port {}", port);
+
+        let shared = Arc::new(Shared {
+            status,
+            last_brightness: AtomicU8::new(brightness.max(1)),
+        });
+        let task = task::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer)) => {
+                        let shared = shared.clone();
+                        let control = control.clone();
+                        task::spawn(async move {
+                            let res = handle(stream, led_count, effects, &shared, &control).await;
+                            if let Err(err) = res {
+                                debug!("WLED request of {} failed: {}", peer, err);
+                            }
+                        });
+                    }
+                    Err(err) => warn!("Failed to accept a WLED client: {}", err),
+                }
+            }
+        });
+
+        Ok(Self { task })
+    }
+}
+
+impl Drop for WledApi {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Answer a single request, the connection is closed afterwards.
+async fn handle(
+    mut stream: TcpStream, led_count: usize, effects: &[&str], shared: &Shared,
+    control: &mpsc::Sender<Control>,
+) -> Result<()> {
+    let (method, path, body) = read_request(&mut stream).await?;
+    debug!("WLED request: {} {}", method, path);
+
+    let (status, response) = match (method.as_str(), path.as_str()) {
+        ("GET", "/json") => (
+            "200 OK",
+            json!({
+                "state": state_json(&snapshot(shared), led_count),
+                "info": info_json(led_count, effects),
+                "effects": effects,
+                "palettes": [],
+            }),
+        ),
+        ("GET", "/json/state") => ("200 OK", state_json(&snapshot(shared), led_count)),
+        ("GET", "/json/info") => ("200 OK", info_json(led_count, effects)),
+        ("GET", "/json/effects") => ("200 OK", json!(effects)),
+        ("GET", "/json/palettes") => ("200 OK", json!([])),
+        ("POST", "/json") | ("POST", "/json/state") => match serde_json::from_slice(&body) {
+            Ok(update) => {
+                let state = apply_update(&update, effects, shared, control).await?;
+                // Asked to answer with the new state, the app may not have caught up yet
+                if update["v"].as_bool() == Some(true) {
+                    ("200 OK", state_json(&state, led_count))
+                } else {
+                    ("200 OK", json!({ "success": true }))
+                }
+            }
+            Err(_) => ("400 Bad Request", json!({ "error": 9 })),
+        },
+        _ => ("404 Not Found", json!({ "error": 3 })),
+    };
+
+    let response = response.to_string();
+    stream
+        .write_all(
+            format!(
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+                 Connection: close\r\n\r\n{}",
+                status,
+                response.len(),
+                response
+            )
+            .as_bytes(),
+        )
+        .await?;
+    Ok(())
+}
+
+/// The method, the path without the query and the body.
+async fn read_request(stream: &mut TcpStream) -> Result<(String, String, Vec<u8>)> {
+    let mut request = Vec::new();
+    let mut chunk = [0; 1024];
+    let head_end = loop {
+        if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+        if request.len() > MAX_REQUEST_SIZE {
+            return Err(anyhow!("Request too big"));
+        }
+
+        let len = stream.read(&mut chunk).await?;
+        if len == 0 {
+            return Err(anyhow!("Connection closed in the middle of the request"));
+        }
+        request.extend_from_slice(&chunk[..len]);
+    };
+
+    let head = String::from_utf8_lossy(&request[..head_end]).to_string();
+    let mut lines = head.lines();
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or_default().to_uppercase();
+    let path = request_line
+        .next()
+        .unwrap_or_default()
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches('/')
+        .to_string();
+
+    let content_length = lines
+        .filter_map(|line| {
+            let mut header = line.splitn(2, ':');
+            Some((header.next()?, header.next()?))
+        })
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .map(|(_, value)| value.trim().parse::<usize>())
+        .transpose()?
+        .unwrap_or(0);
+    if content_length > MAX_REQUEST_SIZE {
+        return Err(anyhow!("Request too big"));
+    }
+
+    let mut body = request[head_end..].to_vec();
+    while body.len() < content_length {
+        let len = stream.read(&mut chunk).await?;
+        if len == 0 {
+            return Err(anyhow!("Connection closed in the middle of the body"));
+        }
+        body.extend_from_slice(&chunk[..len]);
+    }
+    body.truncate(content_length);
+
+    Ok((method, path, body))
+}
+
+/// Change the state like WLED would and tell the app about it, returns the new state.
+async fn apply_update(
+    update: &Value, effects: &[&str], shared: &Shared, control: &mpsc::Sender<Control>,
+) -> Result<State> {
+    let mut controls = Vec::new();
+    let mut state = snapshot(shared);
+    let previous = state.clone();
+
+    match &update["on"] {
+        Value::Bool(on) => state.on = *on,
+        // Toggle
+        Value::String(on) if on == "t" => state.on = !state.on,
+        _ => {}
+    }
+    match update["bri"].as_u64() {
+        // Like WLED, the brightness is kept for when it's turned on again
+        Some(0) => state.on = false,
+        Some(brightness) => {
+            state.brightness = brightness.min(u8::MAX as u64) as u8;
+            shared
+                .last_brightness
+                .store(state.brightness, Ordering::Relaxed);
+        }
+        None => {}
+    }
+
+    // There is only one segment, the whole strip
+    let effect = match &update["seg"] {
+        Value::Array(segments) => segments.iter().find_map(|segment| segment["fx"].as_u64()),
+        segment => segment["fx"].as_u64(),
+    };
+    if let Some(effect) = effect.filter(|effect| (*effect as usize) < effects.len()) {
+        state.effect = effect as usize;
+        controls.push(Control::Command(Command::Runner(effect as u8)));
+    }
+
+    if state.on != previous.on || state.brightness != previous.brightness {
+        controls.push(Control::Command(Command::Brightness(if state.on {
+            state.brightness
+        } else {
+            0
+        })));
+    }
+
+    if let Some(id) = preset_id(&update["psave"]) {
+        let name = update["n"].as_str().map(str::to_string);
+        controls.push(Control::SavePreset(id, name));
+    }
+    // Recalled last, it sets the brightness and the effect too
+    if let Some(id) = preset_id(&update["ps"]) {
+        state.preset = Some(id);
+        controls.push(Control::Command(Command::Preset(id)));
+    }
+
+    for message in controls {
+        control
+            .send(message)
+            .await
+            .map_err(|_| anyhow!("The app is gone"))?;
+    }
+    Ok(state)
+}
+
+fn preset_id(value: &Value) -> Option<u8> {
+    value
+        .as_u64()
+        .filter(|&id| id > 0 && id <= MAX_PRESET as u64)
+        .map(|id| id as u8)
+}
+
+/// The state as the app sees it, copied so no lock is held across an await.
+fn snapshot(shared: &Shared) -> State {
+    let status = shared.status.lock().expect("Status poisoned !").clone();
+    let on = status.brightness > 0;
+    if on {
+        shared
+            .last_brightness
+            .store(status.brightness, Ordering::Relaxed);
+    }
+
+    State {
+        on,
+        brightness: if on {
+            status.brightness
+        } else {
+            shared.last_brightness.load(Ordering::Relaxed)
+        },
+        // The standby has no effect of its own
+        effect: status.runner.unwrap_or(0),
+        preset: status.preset,
+    }
+}
+
+fn state_json(state: &State, led_count: usize) -> Value {
+    json!({
+        "on": state.on,
+        "bri": state.brightness,
+        "transition": 0,
+        "ps": state.preset.map_or(-1, i32::from),
+        "pl": -1,
+        "mainseg": 0,
+        "seg": [{
+            "id": 0,
+            "start": 0,
+            "stop": led_count,
+            "len": led_count,
+            "on": true,
+            "bri": 255,
+            "fx": state.effect,
+            "sx": 128,
+            "ix": 128,
+            "pal": 0,
+            "sel": true,
+            "rev": false,
+            "col": [[255, 255, 255], [0, 0, 0], [0, 0, 0]],
+        }],
+    })
+}
+
+fn info_json(led_count: usize, effects: &[&str]) -> Value {
+    json!({
+        "ver": WLED_VERSION,
+        "name": "rswave",
+        "brand": "WLED",
+        "product": "rswave",
+        "arch": std::env::consts::ARCH,
+        "mac": "000000000000",
+        "leds": {
+            "count": led_count,
+            "rgbw": false,
+            "wv": false,
+            "pwr": 0,
+            "maxpwr": 0,
+            "maxseg": 1,
+        },
+        "fxcount": effects.len(),
+        "palcount": 0,
+        "live": false,
+    })
+}
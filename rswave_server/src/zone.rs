@@ -0,0 +1,61 @@
+use anyhow::{anyhow, Error};
+use std::{ops::Range, str::FromStr};
+
+/// A named part of the strip with its own runner, the remotes can send it its own data.
+#[derive(Clone, Debug)]
+pub struct Zone {
+    pub name: String,
+    /// Indexes of its LEDs, the end excluded.
+    pub leds: Range<usize>,
+}
+
+impl FromStr for Zone {
+    type Err = Error;
+
+    /// Parses `name:start-end`, like `floor:0-60`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        let (name, range) = match (parts.next(), parts.next()) {
+            (Some(name), Some(range)) if !name.trim().is_empty() => (name.trim(), range),
+            _ => return Err(anyhow!("Expected a zone like floor:0-60 !")),
+        };
+
+        let mut bounds = range
+            .splitn(2, '-')
+            .map(|bound| bound.trim().parse::<usize>());
+        match (bounds.next(), bounds.next()) {
+            (Some(Ok(start)), Some(Ok(end))) if start < end => Ok(Self {
+                name: name.to_string(),
+                leds: start..end,
+            }),
+            _ => Err(anyhow!("Invalid LED range `{}` !", range)),
+        }
+    }
+}
+
+/// Check that the zones fit on a strip of `led_amount` LEDs without overlapping.
+pub fn check_zones(zones: &[Zone], led_amount: usize) -> Result<(), Error> {
+    for (i, zone) in zones.iter().enumerate() {
+        if zone.leds.end > led_amount {
+            return Err(anyhow!(
+                "The zone {} goes past the {} LEDs of the strip !",
+                zone.name,
+                led_amount
+            ));
+        }
+
+        for other in &zones[..i] {
+            if other.name == zone.name {
+                return Err(anyhow!("The zone {} is given twice !", zone.name));
+            }
+            if zone.leds.start < other.leds.end && other.leds.start < zone.leds.end {
+                return Err(anyhow!(
+                    "The zones {} and {} overlap !",
+                    other.name,
+                    zone.name
+                ));
+            }
+        }
+    }
+    Ok(())
+}